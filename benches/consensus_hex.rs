@@ -0,0 +1,93 @@
+//! Peak allocation and time of deserializing a 2MB block from its hex with
+//! [`ConsensusEncoded`] and [`ConsensusEncodedLazy`]. Run with
+//! `cargo bench --bench consensus_hex`.
+use bip300301::{ConsensusEncoded, ConsensusEncodedLazy};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+/// Tracks the bytes allocated at any time, and the maximum since the last
+/// [`CountingAllocator::reset_peak`].
+struct CountingAllocator {
+    current: AtomicUsize,
+    peak: AtomicUsize,
+}
+
+impl CountingAllocator {
+    fn reset_peak(&self) -> usize {
+        let current = self.current.load(Ordering::SeqCst);
+        self.peak.store(current, Ordering::SeqCst);
+        current
+    }
+
+    fn peak(&self) -> usize {
+        self.peak.load(Ordering::SeqCst)
+    }
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            let current = self.current.fetch_add(layout.size(), Ordering::SeqCst) + layout.size();
+            self.peak.fetch_max(current, Ordering::SeqCst);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        self.current.fetch_sub(layout.size(), Ordering::SeqCst);
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator {
+    current: AtomicUsize::new(0),
+    peak: AtomicUsize::new(0),
+};
+
+/// A block of about 2MB, with a single transaction of 200 outputs.
+fn fixture_block() -> bitcoin::Block {
+    let mut block = bitcoin::blockdata::constants::genesis_block(bitcoin::Network::Regtest);
+    let output = bitcoin::TxOut {
+        value: 0,
+        script_pubkey: bitcoin::ScriptBuf::from(vec![0x6a; 10_000]),
+    };
+    block.txdata[0].output = vec![output; 200];
+    block
+}
+
+/// Runs `f` and prints the bytes it allocated on top of what was already
+/// allocated, at most, and how long it took.
+fn measure<T>(name: &str, f: impl FnOnce() -> T) -> T {
+    let before = ALLOCATOR.reset_peak();
+    let start = Instant::now();
+    let value = f();
+    let elapsed = start.elapsed();
+    let peak = ALLOCATOR.peak() - before;
+    println!(
+        "{name:<24} peak {:>6.2} MB  {:>8.2} ms",
+        peak as f64 / 1e6,
+        elapsed.as_secs_f64() * 1e3
+    );
+    value
+}
+
+fn main() {
+    let block = fixture_block();
+    let json = serde_json::to_string(&ConsensusEncoded(block.clone())).unwrap();
+    println!(
+        "block {:.2} MB, JSON {:.2} MB",
+        bitcoin::consensus::encode::serialize(&block).len() as f64 / 1e6,
+        json.len() as f64 / 1e6
+    );
+    let eager = measure("eager", || {
+        serde_json::from_str::<ConsensusEncoded<bitcoin::Block>>(&json).unwrap()
+    });
+    let lazy = measure("lazy, not decoded", || {
+        serde_json::from_str::<ConsensusEncodedLazy<bitcoin::Block>>(&json).unwrap()
+    });
+    let decoded = measure("lazy, decoded", || lazy.decode().unwrap());
+    assert_eq!(decoded, eager.0);
+}
@@ -0,0 +1,311 @@
+use crate::client::{
+    Block, Ctip, FailedWithdrawal, Header, MempoolEntry, SidechainId, SpentWithdrawal,
+    WithdrawalStatus,
+};
+use crate::Error;
+use bitcoin::consensus::Decodable;
+use jsonrpsee::core::client::ClientT;
+use jsonrpsee::core::params::{ArrayParams, BatchRequestBuilder};
+
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    BlockHash,
+    BlockHeader,
+    BlockRaw,
+    Block,
+    MempoolEntry,
+    RawTransaction,
+    SpentWithdrawals,
+    FailedWithdrawals,
+    SidechainCtip,
+    WithdrawalStatus,
+}
+
+#[derive(Debug)]
+pub enum BatchItemResult {
+    BlockHash(bitcoin::BlockHash),
+    BlockHeader(Header),
+    BlockRaw(bitcoin::Block),
+    Block(Block),
+    MempoolEntry(MempoolEntry),
+    RawTransaction(bitcoin::Transaction),
+    SpentWithdrawals(Vec<SpentWithdrawal>),
+    FailedWithdrawals(Vec<FailedWithdrawal>),
+    SidechainCtip(Ctip),
+    WithdrawalStatus(Vec<WithdrawalStatus>),
+}
+
+/// Read-only requests sent to the node in a single round-trip.
+///
+/// Results are returned in the order the requests were added, and a failure
+/// of one request does not affect the others.
+#[derive(Debug, Default)]
+pub struct Batch {
+    requests: Vec<(Kind, &'static str, Vec<serde_json::Value>)>,
+}
+
+impl Batch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.requests.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.requests.is_empty()
+    }
+
+    fn push(mut self, kind: Kind, method: &'static str, params: Vec<serde_json::Value>) -> Self {
+        self.requests.push((kind, method, params));
+        self
+    }
+
+    pub fn get_block_hash(self, height: usize) -> Self {
+        self.push(Kind::BlockHash, "getblockhash", vec![height.into()])
+    }
+
+    pub fn get_block_header(self, block_hash: bitcoin::BlockHash) -> Self {
+        self.push(
+            Kind::BlockHeader,
+            "getblockheader",
+            vec![block_hash.to_string().into()],
+        )
+    }
+
+    /// Fetches the block at verbosity 0 and decodes it.
+    pub fn get_block_raw(self, block_hash: bitcoin::BlockHash) -> Self {
+        self.push(
+            Kind::BlockRaw,
+            "getblock",
+            vec![block_hash.to_string().into(), 0.into()],
+        )
+    }
+
+    /// Fetches the block at verbosity 1.
+    pub fn get_block(self, block_hash: bitcoin::BlockHash) -> Self {
+        self.push(
+            Kind::Block,
+            "getblock",
+            vec![block_hash.to_string().into(), 1.into()],
+        )
+    }
+
+    pub fn get_mempool_entry(self, txid: bitcoin::Txid) -> Self {
+        self.push(
+            Kind::MempoolEntry,
+            "getmempoolentry",
+            vec![txid.to_string().into()],
+        )
+    }
+
+    pub fn get_raw_transaction(
+        self,
+        txid: bitcoin::Txid,
+        block_hash: Option<bitcoin::BlockHash>,
+    ) -> Self {
+        let mut params = vec![txid.to_string().into(), false.into()];
+        if let Some(block_hash) = block_hash {
+            params.push(block_hash.to_string().into());
+        }
+        self.push(Kind::RawTransaction, "getrawtransaction", params)
+    }
+
+    pub fn list_spent_withdrawals(self) -> Self {
+        self.push(Kind::SpentWithdrawals, "listspentwithdrawals", vec![])
+    }
+
+    pub fn list_failed_withdrawals(self) -> Self {
+        self.push(Kind::FailedWithdrawals, "listfailedwithdrawals", vec![])
+    }
+
+    /// Fails if the sidechain has no CTIP, see
+    /// [`crate::ctip::get_sidechain_ctip`].
+    pub fn list_sidechain_ctip(self, sidechain: SidechainId) -> Self {
+        self.push(
+            Kind::SidechainCtip,
+            "listsidechainctip",
+            vec![sidechain.0.into()],
+        )
+    }
+
+    pub fn list_withdrawal_status(self, sidechain: SidechainId) -> Self {
+        self.push(
+            Kind::WithdrawalStatus,
+            "listwithdrawalstatus",
+            vec![sidechain.0.into()],
+        )
+    }
+
+    /// Sends all requests in a single batch. The outer error is returned if
+    /// the batch as a whole failed, or the node did not answer every request,
+    /// the inner errors for individual requests.
+    pub async fn send<C>(&self, client: &C) -> Result<Vec<Result<BatchItemResult, Error>>, Error>
+    where
+        C: ClientT + Sync,
+    {
+        if self.requests.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut batch = BatchRequestBuilder::new();
+        for (_, method, params) in &self.requests {
+            let mut array_params = ArrayParams::new();
+            for param in params {
+                array_params.insert(param)?;
+            }
+            batch.insert(method, array_params)?;
+        }
+        let responses: Vec<_> = client
+            .batch_request::<serde_json::Value>(batch)
+            .await?
+            .into_iter()
+            .collect();
+        if responses.len() != self.requests.len() {
+            return Err(Error::BatchResponseLength {
+                expected: self.requests.len(),
+                actual: responses.len(),
+            });
+        }
+        let results = self
+            .requests
+            .iter()
+            .zip(responses)
+            .map(|((kind, _, _), response)| match response {
+                Ok(value) => decode(*kind, value),
+                Err(err) => Err(Error::Jsonrpsee(jsonrpsee::core::Error::Call(
+                    err.into_owned(),
+                ))),
+            })
+            .collect();
+        Ok(results)
+    }
+}
+
+fn decode_hex<T: Decodable>(value: serde_json::Value) -> Result<T, Error> {
+    let hex: String = serde_json::from_value(value)?;
+    let bytes = hex::decode(hex)?;
+    Ok(T::consensus_decode(&mut std::io::Cursor::new(bytes))?)
+}
+
+fn decode(kind: Kind, value: serde_json::Value) -> Result<BatchItemResult, Error> {
+    let result = match kind {
+        Kind::BlockHash => BatchItemResult::BlockHash(serde_json::from_value(value)?),
+        Kind::BlockHeader => BatchItemResult::BlockHeader(serde_json::from_value(value)?),
+        Kind::BlockRaw => BatchItemResult::BlockRaw(decode_hex(value)?),
+        Kind::Block => BatchItemResult::Block(serde_json::from_value(value)?),
+        Kind::MempoolEntry => BatchItemResult::MempoolEntry(serde_json::from_value(value)?),
+        Kind::RawTransaction => BatchItemResult::RawTransaction(decode_hex(value)?),
+        Kind::SpentWithdrawals => BatchItemResult::SpentWithdrawals(serde_json::from_value(value)?),
+        Kind::FailedWithdrawals => {
+            BatchItemResult::FailedWithdrawals(serde_json::from_value(value)?)
+        }
+        Kind::SidechainCtip => BatchItemResult::SidechainCtip(serde_json::from_value(value)?),
+        Kind::WithdrawalStatus => BatchItemResult::WithdrawalStatus(serde_json::from_value(value)?),
+    };
+    Ok(result)
+}
+
+#[cfg(all(test, feature = "client"))]
+mod tests {
+    use super::*;
+    use crate::client::ChainClient;
+    use crate::mock::{block_on, MockChain, MockClient};
+    use bitcoin::hashes::Hash as _;
+    use serde_json::json;
+
+    fn node(chain: &MockChain) -> MockClient {
+        let chain = chain.clone();
+        MockClient::new(move |method, params| match method {
+            "getrawtransaction" => Ok(json!("not a transaction")),
+            _ => chain.handle(method, params).unwrap(),
+        })
+    }
+
+    fn header(result: Result<BatchItemResult, Error>) -> Header {
+        match result {
+            Ok(BatchItemResult::BlockHeader(header)) => header,
+            result => panic!("{result:?}"),
+        }
+    }
+
+    #[test]
+    fn batched_headers_match_single_calls() {
+        let chain = MockChain::new();
+        let mut hashes = vec![chain.tip()];
+        hashes.extend(chain.mine(99));
+
+        let batched = node(&chain);
+        let batch = hashes
+            .iter()
+            .fold(Batch::new(), |batch, hash| batch.get_block_header(*hash));
+        let batched_headers: Vec<_> = block_on(batch.send(&batched))
+            .unwrap()
+            .into_iter()
+            .map(header)
+            .collect();
+
+        let single = node(&chain);
+        let single_headers: Vec<_> = block_on(async {
+            let mut headers = vec![];
+            for hash in &hashes {
+                headers.push(single.getblockheader(hash).await.unwrap());
+            }
+            headers
+        });
+
+        assert_eq!(batched_headers, single_headers);
+        assert_eq!(batched.calls(), single.calls());
+        assert_eq!(batched.round_trips(), 1);
+        assert_eq!(single.round_trips(), 100);
+    }
+
+    #[test]
+    fn failed_items_do_not_fail_the_batch() {
+        let chain = MockChain::new();
+        let genesis = chain.tip();
+        chain.mine(2);
+        let batch = Batch::new()
+            .get_block_header(genesis)
+            .get_block_header(bitcoin::BlockHash::all_zeros())
+            .get_raw_transaction(bitcoin::Txid::all_zeros(), None)
+            .get_block_hash(2);
+        let client = node(&chain);
+        let results = block_on(batch.send(&client)).unwrap();
+        assert_eq!(results.len(), 4);
+        let mut results = results.into_iter();
+        assert_eq!(header(results.next().unwrap()).hash, genesis);
+        // Rejected by the node
+        let err = results.next().unwrap().unwrap_err();
+        assert!(
+            matches!(&err, Error::Jsonrpsee(jsonrpsee::core::Error::Call(err)) if err.code() == -5),
+            "{err:?}"
+        );
+        // Answered, but not with a transaction
+        let err = results.next().unwrap().unwrap_err();
+        assert!(matches!(err, Error::Hex(_)), "{err:?}");
+        assert!(matches!(
+            results.next().unwrap(),
+            Ok(BatchItemResult::BlockHash(hash)) if hash == chain.tip()
+        ));
+        assert_eq!(client.round_trips(), 1);
+    }
+
+    #[test]
+    fn missing_responses_fail_the_batch() {
+        let chain = MockChain::new();
+        let batch = Batch::new().get_block_hash(0).get_block_header(chain.tip());
+        let client = node(&chain).with_batch_limit(1);
+        let err = block_on(batch.send(&client)).unwrap_err();
+        assert!(
+            matches!(
+                err,
+                Error::BatchResponseLength {
+                    expected: 2,
+                    actual: 1
+                }
+            ),
+            "{err:?}"
+        );
+    }
+}
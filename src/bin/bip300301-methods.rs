@@ -0,0 +1,20 @@
+//! Prints the RPC methods covered by the client traits, as a table or, with
+//! `--json`, as a JSON array.
+use bip300301::introspection;
+
+fn main() {
+    let json = match std::env::args().nth(1).as_deref() {
+        None => false,
+        Some("--json") => true,
+        Some(_) => {
+            eprintln!("usage: bip300301-methods [--json]");
+            std::process::exit(2);
+        }
+    };
+    let methods = introspection::methods();
+    if json {
+        println!("{}", serde_json::to_string_pretty(methods).unwrap());
+    } else {
+        print!("{}", introspection::format_table(methods));
+    }
+}
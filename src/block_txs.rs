@@ -0,0 +1,348 @@
+//! Going through the transactions of a block one at a time, without holding
+//! all of them decoded in memory.
+#[cfg(feature = "client")]
+use crate::client::ChainClient as _;
+use crate::Error;
+use bitcoin::consensus::encode;
+use bitcoin::consensus::Decodable as _;
+#[cfg(feature = "client")]
+use futures::Stream;
+#[cfg(feature = "client")]
+use jsonrpsee::core::client::ClientT;
+
+/// Size of a serialized block header.
+const HEADER_LEN: usize = 80;
+
+fn truncated() -> encode::Error {
+    encode::Error::ParseFailed("unexpected end of block")
+}
+
+fn skip(bytes: &[u8], pos: &mut usize, len: u64) -> Result<(), encode::Error> {
+    let end = usize::try_from(len)
+        .ok()
+        .and_then(|len| pos.checked_add(len))
+        .filter(|end| *end <= bytes.len())
+        .ok_or_else(truncated)?;
+    *pos = end;
+    Ok(())
+}
+
+fn read_varint(bytes: &[u8], pos: &mut usize) -> Result<u64, encode::Error> {
+    let mut reader = bytes.get(*pos..).ok_or_else(truncated)?;
+    let len = reader.len();
+    let bitcoin::VarInt(value) = bitcoin::VarInt::consensus_decode(&mut reader)?;
+    *pos += len - reader.len();
+    Ok(value)
+}
+
+/// Length of the serialized transaction at the start of `bytes`, found by
+/// skipping over its fields without decoding them.
+fn tx_len(bytes: &[u8]) -> Result<usize, encode::Error> {
+    let mut pos = 0;
+    skip(bytes, &mut pos, 4)?;
+    // Marker and flag of the segwit serialization
+    let segwit = bytes.get(4..6) == Some(&[0, 1]);
+    if segwit {
+        skip(bytes, &mut pos, 2)?;
+    }
+    let inputs = read_varint(bytes, &mut pos)?;
+    for _ in 0..inputs {
+        skip(bytes, &mut pos, 36)?;
+        let script_len = read_varint(bytes, &mut pos)?;
+        skip(bytes, &mut pos, script_len + 4)?;
+    }
+    let outputs = read_varint(bytes, &mut pos)?;
+    for _ in 0..outputs {
+        skip(bytes, &mut pos, 8)?;
+        let script_len = read_varint(bytes, &mut pos)?;
+        skip(bytes, &mut pos, script_len)?;
+    }
+    if segwit {
+        for _ in 0..inputs {
+            let items = read_varint(bytes, &mut pos)?;
+            for _ in 0..items {
+                let item_len = read_varint(bytes, &mut pos)?;
+                skip(bytes, &mut pos, item_len)?;
+            }
+        }
+    }
+    skip(bytes, &mut pos, 4)?;
+    Ok(pos)
+}
+
+/// Iterator over the transactions of a serialized block and their index in
+/// it. Only the serialized block is kept, and every transaction is decoded
+/// when it is reached.
+pub struct BlockTransactions {
+    bytes: Vec<u8>,
+    /// Start of the next transaction.
+    pos: usize,
+    tx_count: u64,
+    next: usize,
+}
+
+impl BlockTransactions {
+    pub fn new(block: Vec<u8>) -> Result<Self, Error> {
+        let mut pos = 0;
+        skip(&block, &mut pos, HEADER_LEN as u64)?;
+        let tx_count = read_varint(&block, &mut pos)?;
+        Ok(BlockTransactions {
+            bytes: block,
+            pos,
+            tx_count,
+            next: 0,
+        })
+    }
+
+    pub fn header(&self) -> Result<bitcoin::block::Header, Error> {
+        Ok(bitcoin::consensus::deserialize(&self.bytes[..HEADER_LEN])?)
+    }
+
+    /// Number of transactions the block claims to have, read from its
+    /// serialization. Decoding may still fail before that many were
+    /// yielded.
+    pub fn tx_count_hint(&self) -> usize {
+        self.tx_count as usize
+    }
+
+    /// Serialization of the next transaction, without decoding it.
+    fn next_raw(&mut self) -> Option<Result<(usize, &[u8]), Error>> {
+        if self.next as u64 >= self.tx_count {
+            return None;
+        }
+        let rest = &self.bytes[self.pos..];
+        let len = match tx_len(rest) {
+            Ok(len) => len,
+            Err(err) => {
+                // Nothing after a malformed transaction can be found
+                self.tx_count = 0;
+                return Some(Err(err.into()));
+            }
+        };
+        let index = self.next;
+        self.next += 1;
+        self.pos += len;
+        Some(Ok((index, &rest[..len])))
+    }
+
+    /// Only decodes the transactions whose serialization `predicate`
+    /// returns `true` for, e.g. [`contains_fragment`].
+    pub fn matching<P>(self, predicate: P) -> MatchingTransactions<P>
+    where
+        P: FnMut(&[u8]) -> bool,
+    {
+        MatchingTransactions {
+            transactions: self,
+            predicate,
+        }
+    }
+}
+
+impl Iterator for BlockTransactions {
+    type Item = Result<(usize, bitcoin::Transaction), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (index, raw) = match self.next_raw()? {
+            Ok(raw) => raw,
+            Err(err) => return Some(Err(err)),
+        };
+        Some(
+            bitcoin::consensus::deserialize(raw)
+                .map(|transaction| (index, transaction))
+                .map_err(Error::from),
+        )
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some((self.tx_count as usize).saturating_sub(self.next)))
+    }
+}
+
+/// Returned by [`BlockTransactions::matching`].
+pub struct MatchingTransactions<P> {
+    transactions: BlockTransactions,
+    predicate: P,
+}
+
+impl<P> Iterator for MatchingTransactions<P>
+where
+    P: FnMut(&[u8]) -> bool,
+{
+    type Item = Result<(usize, bitcoin::Transaction), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (index, raw) = match self.transactions.next_raw()? {
+                Ok(raw) => raw,
+                Err(err) => return Some(Err(err)),
+            };
+            if !(self.predicate)(raw) {
+                continue;
+            }
+            return Some(
+                bitcoin::consensus::deserialize(raw)
+                    .map(|transaction| (index, transaction))
+                    .map_err(Error::from),
+            );
+        }
+    }
+}
+
+/// Predicate for [`BlockTransactions::matching`] that checks whether the
+/// serialized transaction contains `fragment`, e.g. a script. This can
+/// match transactions that don't use `fragment` as a whole script, so
+/// matches should be checked after decoding.
+pub fn contains_fragment(fragment: &[u8]) -> impl Fn(&[u8]) -> bool + '_ {
+    move |raw| fragment.is_empty() || raw.windows(fragment.len()).any(|window| window == fragment)
+}
+
+#[cfg(feature = "client")]
+/// Fetches the serialized block with `getblock` at verbosity 0.
+pub async fn fetch_block_transactions<C>(
+    client: &C,
+    block_hash: &bitcoin::BlockHash,
+) -> Result<BlockTransactions, Error>
+where
+    C: ClientT + Sync,
+{
+    let block = client.getblockhex(block_hash, 0).await?;
+    BlockTransactions::new(hex::decode(block.as_hex())?)
+}
+
+#[cfg(feature = "client")]
+/// Streams the transactions of a block with their index in it. See
+/// [`BlockTransactions`].
+pub fn block_tx_iter<'a, C>(
+    client: &'a C,
+    block_hash: bitcoin::BlockHash,
+) -> impl Stream<Item = Result<(usize, bitcoin::Transaction), Error>> + 'a
+where
+    C: ClientT + Sync,
+{
+    block_txs_matching(client, block_hash, |_: &[u8]| true)
+}
+
+#[cfg(feature = "client")]
+/// Like [`block_tx_iter`], but skips transactions whose serialization
+/// `predicate` returns `false` for without decoding them.
+pub fn block_txs_matching<'a, C, P>(
+    client: &'a C,
+    block_hash: bitcoin::BlockHash,
+    predicate: P,
+) -> impl Stream<Item = Result<(usize, bitcoin::Transaction), Error>> + 'a
+where
+    C: ClientT + Sync,
+    P: FnMut(&[u8]) -> bool + 'a,
+{
+    enum State<'a, C, P> {
+        Fetch(&'a C, P),
+        Decode(MatchingTransactions<P>),
+        Done,
+    }
+    let state = State::Fetch(client, predicate);
+    futures::stream::unfold(state, move |state| async move {
+        let mut transactions = match state {
+            State::Fetch(client, predicate) => {
+                match fetch_block_transactions(client, &block_hash).await {
+                    Ok(transactions) => transactions.matching(predicate),
+                    Err(err) => return Some((Err(err), State::Done)),
+                }
+            }
+            State::Decode(transactions) => transactions,
+            State::Done => return None,
+        };
+        let item = transactions.next()?;
+        Some((item, State::Decode(transactions)))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::blockdata::constants::genesis_block;
+    use bitcoin::hashes::Hash as _;
+
+    fn transaction(script: &[u8], witness: bool) -> bitcoin::Transaction {
+        let mut input = bitcoin::TxIn {
+            previous_output: bitcoin::OutPoint::new(bitcoin::Txid::all_zeros(), 1),
+            script_sig: bitcoin::ScriptBuf::from_bytes(vec![0x51; 300]),
+            ..Default::default()
+        };
+        if witness {
+            input.witness = bitcoin::Witness::from_slice(&[vec![1; 72], vec![2; 33]]);
+        }
+        bitcoin::Transaction {
+            version: 2,
+            lock_time: bitcoin::absolute::LockTime::ZERO,
+            input: vec![input],
+            output: vec![bitcoin::TxOut {
+                value: 1000,
+                script_pubkey: bitcoin::ScriptBuf::from_bytes(script.to_vec()),
+            }],
+        }
+    }
+
+    /// Block with the genesis coinbase, a legacy transaction and a segwit
+    /// transaction paying to `[0x6a, 0xaa, 0xbb]`.
+    fn block() -> bitcoin::Block {
+        let mut block = genesis_block(bitcoin::Network::Regtest);
+        block.txdata.push(transaction(&[0x51], false));
+        block.txdata.push(transaction(&[0x6a, 0xaa, 0xbb], true));
+        block
+    }
+
+    #[test]
+    fn yields_every_transaction_in_order() {
+        let block = block();
+        let transactions = BlockTransactions::new(bitcoin::consensus::serialize(&block)).unwrap();
+        assert_eq!(transactions.header().unwrap(), block.header);
+        assert_eq!(transactions.tx_count_hint(), 3);
+        let yielded: Vec<_> = transactions.map(Result::unwrap).collect();
+        let expected: Vec<_> = block.txdata.into_iter().enumerate().collect();
+        assert_eq!(yielded, expected);
+    }
+
+    #[test]
+    fn matching_skips_other_transactions() {
+        let block = block();
+        let transactions = BlockTransactions::new(bitcoin::consensus::serialize(&block)).unwrap();
+        let matching: Vec<_> = transactions
+            .matching(contains_fragment(&[0xaa, 0xbb]))
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(matching, vec![(2, block.txdata[2].clone())]);
+    }
+
+    #[test]
+    fn truncated_block() {
+        assert!(BlockTransactions::new(vec![0; HEADER_LEN - 1]).is_err());
+        let mut bytes = bitcoin::consensus::serialize(&block());
+        bytes.truncate(bytes.len() - 10);
+        let yielded: Vec<_> = BlockTransactions::new(bytes).unwrap().collect();
+        assert_eq!(yielded.len(), 3);
+        assert!(yielded[..2].iter().all(Result::is_ok));
+        assert!(yielded[2].is_err());
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn streams_block_from_the_node() {
+        use crate::mock::{block_on, MockClient};
+        use futures::StreamExt as _;
+
+        let block = block();
+        let hex = bitcoin::consensus::encode::serialize_hex(&block);
+        let client = MockClient::new(move |method, params| {
+            assert_eq!(method, "getblock");
+            assert_eq!(params[1], 0);
+            Ok(serde_json::json!(hex))
+        });
+        let yielded: Vec<_> = block_on(
+            block_tx_iter(&client, block.block_hash())
+                .map(Result::unwrap)
+                .collect(),
+        );
+        let expected: Vec<_> = block.txdata.into_iter().enumerate().collect();
+        assert_eq!(yielded, expected);
+    }
+}
@@ -0,0 +1,230 @@
+//! Synchronous wrapper of [`Drivechain`], for small tools that only make a
+//! few calls and don't otherwise need a tokio runtime.
+//!
+//! Calls run on a current-thread runtime that is created by the first call
+//! and shared by every [`Client`]. They fail with
+//! [`Error::BlockingInRuntime`] when made from within a tokio runtime, where
+//! blocking would stall it; use the async API there.
+use crate::client::{
+    Block, BlockTemplate, BlockTemplateRequest, ChainClient, Ctip, Deposit, DrivechainClient,
+    FailedWithdrawal, Header, RawMempoolVerbose, RawMempoolWithSequence, SidechainId,
+    SpentWithdrawal, WithdrawalStatus,
+};
+use crate::{Drivechain, Error};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::OnceLock;
+use tokio::runtime::Runtime;
+
+static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+
+fn runtime() -> Result<&'static Runtime, Error> {
+    if let Some(runtime) = RUNTIME.get() {
+        return Ok(runtime);
+    }
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    // Another thread may have created one meanwhile, in which case ours is
+    // dropped
+    let _ = RUNTIME.set(runtime);
+    Ok(RUNTIME.get().expect("runtime was just set"))
+}
+
+fn block_on<F, T>(future: F) -> Result<T, Error>
+where
+    F: Future<Output = Result<T, Error>>,
+{
+    if tokio::runtime::Handle::try_current().is_ok() {
+        return Err(Error::BlockingInRuntime);
+    }
+    runtime()?.block_on(future)
+}
+
+/// Blocking version of the most used methods of [`Drivechain`].
+#[derive(Clone)]
+pub struct Client {
+    pub drivechain: Drivechain,
+}
+
+impl Client {
+    /// Like [`Drivechain::new`].
+    pub fn new(
+        sidechain_number: SidechainId,
+        main_addr: SocketAddr,
+        user: &str,
+        password: &str,
+    ) -> Result<Self, Error> {
+        // The HTTP client must be built in a runtime context
+        let _guard = runtime()?.enter();
+        let drivechain = Drivechain::new(sidechain_number, main_addr, user, password)?;
+        Ok(Self { drivechain })
+    }
+
+    pub fn getblockcount(&self) -> Result<usize, Error> {
+        block_on(async { Ok(self.drivechain.client.getblockcount().await?) })
+    }
+
+    pub fn getbestblockhash(&self) -> Result<bitcoin::BlockHash, Error> {
+        block_on(async { Ok(self.drivechain.client.getbestblockhash().await?) })
+    }
+
+    pub fn getblockheader(&self, block_hash: &bitcoin::BlockHash) -> Result<Header, Error> {
+        block_on(async { Ok(self.drivechain.client.getblockheader(block_hash).await?) })
+    }
+
+    /// The block with the txids of its transactions.
+    pub fn get_block(&self, block_hash: &bitcoin::BlockHash) -> Result<Block, Error> {
+        block_on(self.drivechain.get_block(block_hash))
+    }
+
+    pub fn get_block_raw(&self, block_hash: &bitcoin::BlockHash) -> Result<bitcoin::Block, Error> {
+        block_on(self.drivechain.get_block_raw(block_hash))
+    }
+
+    pub fn get_raw_mempool(&self) -> Result<Vec<bitcoin::Txid>, Error> {
+        block_on(self.drivechain.get_raw_mempool())
+    }
+
+    pub fn get_raw_mempool_verbose(&self) -> Result<RawMempoolVerbose, Error> {
+        block_on(async { Ok(self.drivechain.client.getrawmempoolverbose(true).await?) })
+    }
+
+    pub fn get_raw_mempool_with_sequence(&self) -> Result<RawMempoolWithSequence, Error> {
+        block_on(async {
+            Ok(self
+                .drivechain
+                .client
+                .getrawmempoolwithsequence(false, true)
+                .await?)
+        })
+    }
+
+    pub fn send_raw_transaction(&self, tx_hex: &str) -> Result<bitcoin::Txid, Error> {
+        block_on(self.drivechain.send_raw_transaction(tx_hex))
+    }
+
+    pub fn getblocktemplate(
+        &self,
+        template_request: &BlockTemplateRequest,
+    ) -> Result<BlockTemplate, Error> {
+        block_on(async {
+            Ok(self
+                .drivechain
+                .client
+                .getblocktemplate(template_request)
+                .await?)
+        })
+    }
+
+    /// Returns `None` if the block was accepted, or the reason it was
+    /// rejected.
+    pub fn submit_block(&self, block: &bitcoin::Block) -> Result<Option<String>, Error> {
+        let hexdata = bitcoin::consensus::encode::serialize_hex(block);
+        block_on(async { Ok(self.drivechain.client.submitblock(&hexdata).await?) })
+    }
+
+    /// Deposits to the sidechain, like `listsidechaindepositsbyblock`.
+    pub fn list_deposits(
+        &self,
+        end: Option<bitcoin::BlockHash>,
+        start: Option<bitcoin::BlockHash>,
+    ) -> Result<Vec<Deposit>, Error> {
+        let sidechain = self.drivechain.sidechain_number;
+        block_on(async {
+            Ok(self
+                .drivechain
+                .client
+                .listsidechaindepositsbyblock(sidechain, end, start)
+                .await?)
+        })
+    }
+
+    /// Withdrawal bundles of the sidechain, like `listwithdrawalstatus`.
+    pub fn list_withdrawal_status(&self) -> Result<Vec<WithdrawalStatus>, Error> {
+        let sidechain = self.drivechain.sidechain_number;
+        block_on(async {
+            Ok(self
+                .drivechain
+                .client
+                .listwithdrawalstatus(sidechain)
+                .await?)
+        })
+    }
+
+    /// Spent withdrawal bundles of every sidechain.
+    pub fn list_spent_withdrawals(&self) -> Result<Vec<SpentWithdrawal>, Error> {
+        block_on(async { Ok(self.drivechain.client.listspentwithdrawals().await?) })
+    }
+
+    /// Failed withdrawal bundles of every sidechain.
+    pub fn list_failed_withdrawals(&self) -> Result<Vec<FailedWithdrawal>, Error> {
+        block_on(async { Ok(self.drivechain.client.listfailedwithdrawals().await?) })
+    }
+
+    /// See [`Drivechain::get_sidechain_ctip`].
+    pub fn get_sidechain_ctip(&self) -> Result<Option<Ctip>, Error> {
+        block_on(self.drivechain.get_sidechain_ctip())
+    }
+}
+
+impl From<Drivechain> for Client {
+    fn from(drivechain: Drivechain) -> Self {
+        Self { drivechain }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{serve, MockClient};
+    use bitcoin::hashes::Hash as _;
+    use serde_json::json;
+
+    fn node() -> MockClient {
+        MockClient::new(|method, _| {
+            Ok(match method {
+                "getblockcount" => json!(101),
+                "getbestblockhash" => json!(bitcoin::BlockHash::all_zeros()),
+                _ => unreachable!("{method}"),
+            })
+        })
+    }
+
+    #[test]
+    fn calls_outside_a_runtime() {
+        let (addr, node) = serve(node());
+        let client = Client::new(SidechainId(0), addr, "user", "password").unwrap();
+        assert_eq!(client.getblockcount().unwrap(), 101);
+        // Clients share the runtime
+        let other = Client::new(SidechainId(1), addr, "user", "password").unwrap();
+        assert_eq!(
+            other.getbestblockhash().unwrap(),
+            bitcoin::BlockHash::all_zeros()
+        );
+        assert_eq!(client.getblockcount().unwrap(), 101);
+        assert_eq!(
+            node.methods(),
+            ["getblockcount", "getbestblockhash", "getblockcount"]
+        );
+    }
+
+    #[test]
+    fn fails_inside_a_runtime() {
+        let (addr, node) = serve(node());
+        let client = Client::new(SidechainId(0), addr, "user", "password").unwrap();
+        crate::mock::block_on(async {
+            assert!(matches!(
+                client.getblockcount(),
+                Err(Error::BlockingInRuntime)
+            ));
+            assert!(matches!(
+                client.get_sidechain_ctip(),
+                Err(Error::BlockingInRuntime)
+            ));
+        });
+        assert!(node.methods().is_empty());
+        // Outside of it again, the call goes through
+        assert_eq!(client.getblockcount().unwrap(), 101);
+    }
+}
@@ -0,0 +1,467 @@
+use crate::client::{ChainClient, DrivechainClient, SidechainId};
+use crate::Error;
+use futures::future::BoxFuture;
+use futures::Stream;
+use jsonrpsee::core::client::ClientT;
+use jsonrpsee::http_client::HttpClient;
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Error code of `verifybmm` if the block has no BMM commitment of the
+/// sidechain to the critical hash, `RPC_INVALID_PARAMETER`.
+const RPC_BMM_NOT_FOUND: i32 = -8;
+
+/// The bytes of the previous mainchain block hash that a BMM request commits
+/// to: the last 4 bytes of the block hash as displayed, i.e. its last 8 hex
+/// characters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PrevBytes(pub [u8; 4]);
+
+impl PrevBytes {
+    pub fn from_block_hash(block_hash: &bitcoin::BlockHash) -> Self {
+        // Block hashes are stored in reverse byte order relative to how they
+        // are displayed, so the last displayed bytes come first.
+        let bytes: &[u8] = block_hash.as_ref();
+        PrevBytes([bytes[3], bytes[2], bytes[1], bytes[0]])
+    }
+
+    pub fn matches(&self, block_hash: &bitcoin::BlockHash) -> bool {
+        *self == Self::from_block_hash(block_hash)
+    }
+}
+
+impl From<&bitcoin::BlockHash> for PrevBytes {
+    fn from(block_hash: &bitcoin::BlockHash) -> Self {
+        Self::from_block_hash(block_hash)
+    }
+}
+
+impl fmt::Display for PrevBytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&hex::encode(self.0))
+    }
+}
+
+impl FromStr for PrevBytes {
+    type Err = hex::FromHexError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut bytes = [0; 4];
+        hex::decode_to_slice(s, &mut bytes)?;
+        Ok(PrevBytes(bytes))
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for PrevBytes {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl serde::Serialize for PrevBytes {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+/// How [`AutoBidder`] raises the bid after it was outbid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BidIncrement {
+    Absolute(bitcoin::Amount),
+    /// Percent of the previous bid, rounded down, but at least 1 sat.
+    Percent(u64),
+}
+
+impl BidIncrement {
+    pub fn apply(&self, bid: bitcoin::Amount) -> bitcoin::Amount {
+        match *self {
+            BidIncrement::Absolute(increment) => bid + increment,
+            BidIncrement::Percent(percent) => {
+                let increment = bid.to_sat().saturating_mul(percent) / 100;
+                bid + bitcoin::Amount::from_sat(increment.max(1))
+            }
+        }
+    }
+}
+
+/// Returns the h* to commit to in the next mainchain block.
+pub type CriticalHashProvider =
+    Arc<dyn Fn() -> BoxFuture<'static, Result<bitcoin::BlockHash, Error>> + Send + Sync>;
+
+#[derive(Clone)]
+pub struct AutoBidderConfig {
+    pub initial_bid: bitcoin::Amount,
+    pub max_bid: bitcoin::Amount,
+    pub increment: BidIncrement,
+    /// Interval in which the mainchain tip is checked for new blocks.
+    pub poll_interval: Duration,
+    pub critical_hash_provider: CriticalHashProvider,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BidEvent {
+    /// A BMM request was created for the block after `prev_block_hash`.
+    Submitted {
+        prev_block_hash: bitcoin::BlockHash,
+        critical_hash: bitcoin::BlockHash,
+        amount: bitcoin::Amount,
+    },
+    Included {
+        block_hash: bitcoin::BlockHash,
+        critical_hash: bitcoin::BlockHash,
+        amount: bitcoin::Amount,
+    },
+    /// `block_hash`, the block the request was for, does not include it. If
+    /// that block was disconnected, `block_hash` is the tip.
+    Outbid {
+        block_hash: bitcoin::BlockHash,
+        amount: bitcoin::Amount,
+    },
+    /// The bid can't be raised any further, and stays at `max_bid`.
+    MaxBidReached { max_bid: bitcoin::Amount },
+}
+
+struct LiveBid {
+    prev_block_hash: bitcoin::BlockHash,
+    /// Height of the block the request is for.
+    height: usize,
+    critical_hash: bitcoin::BlockHash,
+    amount: bitcoin::Amount,
+}
+
+/// Submits a BMM request for every mainchain block, and raises the bid
+/// after it was outbid, up to a maximum.
+///
+/// Every request commits to the tip it was made for, so a request that
+/// missed its block can't be included in any later one. At most one request
+/// is made per mainchain tip.
+pub struct AutoBidder<C = HttpClient> {
+    client: C,
+    sidechain: SidechainId,
+    config: AutoBidderConfig,
+    next_bid: bitcoin::Amount,
+    tip: Option<bitcoin::BlockHash>,
+    live: Option<LiveBid>,
+    events: VecDeque<BidEvent>,
+}
+
+impl<C> AutoBidder<C>
+where
+    C: ClientT + Sync,
+{
+    pub fn new(client: C, sidechain: SidechainId, config: AutoBidderConfig) -> Self {
+        AutoBidder {
+            client,
+            sidechain,
+            next_bid: std::cmp::min(config.initial_bid, config.max_bid),
+            config,
+            tip: None,
+            live: None,
+            events: VecDeque::new(),
+        }
+    }
+
+    /// Bid for the next request.
+    pub fn next_bid(&self) -> bitcoin::Amount {
+        self.next_bid
+    }
+
+    /// Bids until the stream is dropped. The first request is made for the
+    /// current tip right away.
+    pub fn run(self) -> impl Stream<Item = Result<BidEvent, Error>> {
+        futures::stream::unfold(self, |mut bidder| async move {
+            let event = bidder.next_event().await;
+            Some((event, bidder))
+        })
+    }
+
+    async fn next_event(&mut self) -> Result<BidEvent, Error> {
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Ok(event);
+            }
+            let tip = self.client.getbestblockhash().await?;
+            if Some(tip) == self.tip {
+                tokio::time::sleep(self.config.poll_interval).await;
+                continue;
+            }
+            if let Some(live) = self.live.take() {
+                self.check_inclusion(live, tip).await?;
+            }
+            self.submit(tip).await?;
+            self.tip = Some(tip);
+        }
+    }
+
+    /// Block of the main chain ending in `tip` that the request of `live`
+    /// was for, i.e. the child of `live.prev_block_hash`, or `None` if that
+    /// block is no longer in the main chain.
+    async fn bid_block(
+        &self,
+        live: &LiveBid,
+        tip: bitcoin::BlockHash,
+    ) -> Result<Option<bitcoin::BlockHash>, Error> {
+        let mut block_hash = tip;
+        let mut header = self.client.getblockheader(&block_hash).await?;
+        while header.height > live.height {
+            let Some(prev) = header.previousblockhash else {
+                return Ok(None);
+            };
+            block_hash = prev;
+            header = self.client.getblockheader(&block_hash).await?;
+        }
+        if header.height == live.height && header.previousblockhash == Some(live.prev_block_hash) {
+            Ok(Some(block_hash))
+        } else {
+            Ok(None)
+        }
+    }
+
+    async fn check_inclusion(
+        &mut self,
+        live: LiveBid,
+        tip: bitcoin::BlockHash,
+    ) -> Result<(), Error> {
+        let block_hash = self.bid_block(&live, tip).await?;
+        let included = match block_hash {
+            Some(block_hash) => match self
+                .client
+                .verifybmm(&block_hash, &live.critical_hash, self.sidechain)
+                .await
+            {
+                Ok(_) => true,
+                Err(jsonrpsee::core::Error::Call(err)) if err.code() == RPC_BMM_NOT_FOUND => false,
+                Err(err) => return Err(err.into()),
+            },
+            None => false,
+        };
+        let block_hash = block_hash.unwrap_or(tip);
+        if included {
+            self.events.push_back(BidEvent::Included {
+                block_hash,
+                critical_hash: live.critical_hash,
+                amount: live.amount,
+            });
+            self.next_bid = std::cmp::min(self.config.initial_bid, self.config.max_bid);
+            return Ok(());
+        }
+        self.events.push_back(BidEvent::Outbid {
+            block_hash,
+            amount: live.amount,
+        });
+        let bumped = self.config.increment.apply(live.amount);
+        if bumped > self.config.max_bid {
+            self.events.push_back(BidEvent::MaxBidReached {
+                max_bid: self.config.max_bid,
+            });
+            self.next_bid = self.config.max_bid;
+        } else {
+            self.next_bid = bumped;
+        }
+        Ok(())
+    }
+
+    async fn submit(&mut self, tip: bitcoin::BlockHash) -> Result<(), Error> {
+        let critical_hash = (self.config.critical_hash_provider)().await?;
+        let height = self.client.getblockheader(&tip).await?.height + 1;
+        self.client
+            .createbmmcriticaldatatx(
+                self.next_bid.into(),
+                height as u32,
+                &critical_hash,
+                self.sidechain,
+                PrevBytes::from_block_hash(&tip),
+            )
+            .await?;
+        self.live = Some(LiveBid {
+            prev_block_hash: tip,
+            height,
+            critical_hash,
+            amount: self.next_bid,
+        });
+        self.events.push_back(BidEvent::Submitted {
+            prev_block_hash: tip,
+            critical_hash,
+            amount: self.next_bid,
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{block_on, rpc_error, MockChain, MockClient};
+    use bitcoin::hashes::Hash as _;
+    use futures::StreamExt as _;
+    use std::collections::HashSet;
+    use std::sync::Mutex;
+
+    const CRITICAL_HASH: [u8; 32] = [7; 32];
+
+    fn amount(sats: u64) -> bitcoin::Amount {
+        bitcoin::Amount::from_sat(sats)
+    }
+
+    /// Node whose blocks in `included` have our BMM commitment.
+    fn client(chain: &MockChain, included: &Arc<Mutex<HashSet<bitcoin::BlockHash>>>) -> MockClient {
+        let (chain, included) = (chain.clone(), included.clone());
+        MockClient::new(move |method, params| {
+            if let Some(result) = chain.handle(method, params) {
+                return result;
+            }
+            match method {
+                "createbmmcriticaldatatx" => Ok(serde_json::json!({})),
+                "verifybmm" => {
+                    let block_hash: bitcoin::BlockHash =
+                        serde_json::from_value(params[0].clone()).unwrap();
+                    if included.lock().unwrap().contains(&block_hash) {
+                        Ok(serde_json::json!({}))
+                    } else {
+                        Err(rpc_error(RPC_BMM_NOT_FOUND, "h* not found in block"))
+                    }
+                }
+                _ => Err(rpc_error(-32601, "Method not found")),
+            }
+        })
+    }
+
+    fn bidder(client: MockClient) -> AutoBidder<MockClient> {
+        let critical_hash = bitcoin::BlockHash::from_byte_array(CRITICAL_HASH);
+        let config = AutoBidderConfig {
+            initial_bid: amount(1000),
+            max_bid: amount(1800),
+            increment: BidIncrement::Absolute(amount(500)),
+            poll_interval: Duration::from_millis(1),
+            critical_hash_provider: Arc::new(move || Box::pin(async move { Ok(critical_hash) })),
+        };
+        AutoBidder::new(client, SidechainId(0), config)
+    }
+
+    fn submitted(prev_block_hash: bitcoin::BlockHash, sats: u64) -> BidEvent {
+        BidEvent::Submitted {
+            prev_block_hash,
+            critical_hash: bitcoin::BlockHash::from_byte_array(CRITICAL_HASH),
+            amount: amount(sats),
+        }
+    }
+
+    fn outbid(block_hash: bitcoin::BlockHash, sats: u64) -> BidEvent {
+        BidEvent::Outbid {
+            block_hash,
+            amount: amount(sats),
+        }
+    }
+
+    fn included(block_hash: bitcoin::BlockHash, sats: u64) -> BidEvent {
+        BidEvent::Included {
+            block_hash,
+            critical_hash: bitcoin::BlockHash::from_byte_array(CRITICAL_HASH),
+            amount: amount(sats),
+        }
+    }
+
+    async fn next<S>(events: &mut S) -> BidEvent
+    where
+        S: Stream<Item = Result<BidEvent, Error>> + Unpin,
+    {
+        events.next().await.unwrap().unwrap()
+    }
+
+    #[test]
+    fn outbid_outbid_included() {
+        let chain = MockChain::new();
+        let included_in = Arc::new(Mutex::new(HashSet::new()));
+        let genesis = chain.tip();
+        block_on(async {
+            let mut events = Box::pin(bidder(client(&chain, &included_in)).run());
+            assert_eq!(next(&mut events).await, submitted(genesis, 1000));
+            let [first] = chain.mine(1)[..] else { panic!() };
+            assert_eq!(next(&mut events).await, outbid(first, 1000));
+            assert_eq!(next(&mut events).await, submitted(first, 1500));
+            let [second] = chain.mine(1)[..] else {
+                panic!()
+            };
+            assert_eq!(next(&mut events).await, outbid(second, 1500));
+            assert_eq!(
+                next(&mut events).await,
+                BidEvent::MaxBidReached {
+                    max_bid: amount(1800)
+                }
+            );
+            assert_eq!(next(&mut events).await, submitted(second, 1800));
+            let third = chain.mine_with(vec![]);
+            included_in.lock().unwrap().insert(third);
+            assert_eq!(next(&mut events).await, included(third, 1800));
+            assert_eq!(next(&mut events).await, submitted(third, 1000));
+        });
+    }
+
+    #[test]
+    fn included_in_a_block_before_the_tip() {
+        let chain = MockChain::new();
+        let included_in = Arc::new(Mutex::new(HashSet::new()));
+        let genesis = chain.tip();
+        let client = client(&chain, &included_in);
+        block_on(async {
+            let mut events = Box::pin(bidder(client).run());
+            assert_eq!(next(&mut events).await, submitted(genesis, 1000));
+            let [first, second] = chain.mine(2)[..] else {
+                panic!()
+            };
+            included_in.lock().unwrap().insert(first);
+            assert_eq!(next(&mut events).await, included(first, 1000));
+            assert_eq!(next(&mut events).await, submitted(second, 1000));
+        });
+    }
+
+    #[test]
+    fn bid_block_disconnected() {
+        let chain = MockChain::new();
+        let included_in = Arc::new(Mutex::new(HashSet::new()));
+        let [first] = chain.mine(1)[..] else { panic!() };
+        block_on(async {
+            let mut events = Box::pin(bidder(client(&chain, &included_in)).run());
+            assert_eq!(next(&mut events).await, submitted(first, 1000));
+            chain.disconnect(1);
+            let [_, tip] = chain.mine(2)[..] else {
+                panic!()
+            };
+            assert_eq!(next(&mut events).await, outbid(tip, 1000));
+        });
+    }
+
+    #[test]
+    fn other_verifybmm_errors_are_returned() {
+        let chain = MockChain::new();
+        let inner = chain.clone();
+        let client = MockClient::new(move |method, params| {
+            if let Some(result) = inner.handle(method, params) {
+                return result;
+            }
+            match method {
+                "createbmmcriticaldatatx" => Ok(serde_json::json!({})),
+                _ => Err(rpc_error(-32603, "Failed to read block from disk")),
+            }
+        });
+        block_on(async {
+            let mut events = Box::pin(bidder(client).run());
+            assert!(events.next().await.unwrap().is_ok());
+            chain.mine(1);
+            assert!(matches!(
+                events.next().await,
+                Some(Err(Error::Jsonrpsee(_)))
+            ));
+        });
+    }
+}
@@ -0,0 +1,275 @@
+use hashlink::LinkedHashMap;
+use jsonrpsee::core::async_trait;
+use jsonrpsee::core::client::{BatchResponse, ClientT};
+use jsonrpsee::core::params::BatchRequestBuilder;
+use jsonrpsee::core::traits::ToRpcParams;
+use serde::de::DeserializeOwned;
+use serde_json::value::RawValue;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// Methods whose responses are determined by the block hash they are called
+/// with. Anything not in this list is never cached.
+///
+/// Only the hex responses of [`VERBOSE_METHODS`] are cached.
+pub const CACHEABLE_METHODS: &[&str] = &[
+    "getblock",
+    "getblockfilter",
+    "getblockheader",
+    "gettxoutproof",
+];
+
+/// Methods whose verbose responses include `confirmations` and
+/// `nextblockhash`, which change as blocks are connected, and so are never
+/// cached.
+pub const VERBOSE_METHODS: &[&str] = &["getblock", "getblockheader"];
+
+struct Entry {
+    response: Box<RawValue>,
+    /// Height of the block the response is about, if known.
+    height: Option<u64>,
+}
+
+#[derive(Default)]
+struct State {
+    entries: LinkedHashMap<String, Entry>,
+    /// Heights of blocks seen in verbose responses, keyed by block hash, so
+    /// that cached hex responses can be attributed to a height.
+    heights: LinkedHashMap<String, u64>,
+}
+
+/// Client wrapper that keeps an LRU cache of responses to
+/// [`CACHEABLE_METHODS`], keyed by method and parameters.
+///
+/// Cached entries must be invalidated by the caller on reorgs, see
+/// [`CachedClient::invalidate_descendants_of`].
+#[derive(Clone)]
+pub struct CachedClient<C> {
+    inner: C,
+    capacity: usize,
+    cache: Arc<Mutex<State>>,
+}
+
+impl<C> CachedClient<C> {
+    pub fn new(inner: C, capacity: usize) -> Self {
+        CachedClient {
+            inner,
+            capacity,
+            cache: Arc::new(Mutex::new(State::default())),
+        }
+    }
+
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+
+    pub fn len(&self) -> usize {
+        self.cache.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&self) {
+        let mut cache = self.cache.lock().unwrap();
+        cache.entries.clear();
+        cache.heights.clear();
+    }
+
+    /// Removes all cached responses for blocks above `block_hash`, as well as
+    /// responses that can't be attributed to a height. If the height of
+    /// `block_hash` isn't known from the cache, everything is removed.
+    pub fn invalidate_descendants_of(&self, block_hash: &bitcoin::BlockHash) {
+        let mut cache = self.cache.lock().unwrap();
+        let Some(&fork_height) = cache.heights.get(&block_hash.to_string()) else {
+            cache.entries.clear();
+            cache.heights.clear();
+            return;
+        };
+        cache
+            .entries
+            .retain(|_, entry| matches!(entry.height, Some(height) if height <= fork_height));
+        cache.heights.retain(|_, height| *height <= fork_height);
+    }
+
+    fn get(&self, key: &str) -> Option<Box<RawValue>> {
+        let mut cache = self.cache.lock().unwrap();
+        let entry = cache.entries.to_back(key)?;
+        Some(entry.response.clone())
+    }
+
+    /// Caches `response`, unless it is a verbose response of one of
+    /// [`VERBOSE_METHODS`], in which case only the height of its block is
+    /// kept.
+    fn insert(
+        &self,
+        method: &str,
+        params: Option<&RawValue>,
+        key: String,
+        response: Box<RawValue>,
+    ) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut cache = self.cache.lock().unwrap();
+        if VERBOSE_METHODS.contains(&method) && !response.get().starts_with('"') {
+            #[derive(serde::Deserialize)]
+            struct BlockHeight {
+                hash: String,
+                height: u64,
+            }
+            if let Ok(block) = serde_json::from_str::<BlockHeight>(response.get()) {
+                cache.heights.replace(block.hash, block.height);
+                while cache.heights.len() > self.capacity {
+                    cache.heights.pop_front();
+                }
+            }
+            return;
+        }
+        let height = params
+            .and_then(|params| serde_json::from_str::<Vec<serde_json::Value>>(params.get()).ok())
+            .and_then(|params| params.first()?.as_str().map(str::to_owned))
+            .and_then(|block_hash| cache.heights.get(&block_hash).copied());
+        cache.entries.replace(key, Entry { response, height });
+        while cache.entries.len() > self.capacity {
+            cache.entries.pop_front();
+        }
+    }
+}
+
+pub(crate) struct RawParams(pub(crate) Option<Box<RawValue>>);
+
+impl ToRpcParams for RawParams {
+    fn to_rpc_params(self) -> Result<Option<Box<RawValue>>, jsonrpsee::core::Error> {
+        Ok(self.0)
+    }
+}
+
+#[async_trait]
+impl<C> ClientT for CachedClient<C>
+where
+    C: ClientT + Send + Sync,
+{
+    async fn notification<Params>(
+        &self,
+        method: &str,
+        params: Params,
+    ) -> Result<(), jsonrpsee::core::Error>
+    where
+        Params: ToRpcParams + Send,
+    {
+        self.inner.notification(method, params).await
+    }
+
+    async fn request<R, Params>(
+        &self,
+        method: &str,
+        params: Params,
+    ) -> Result<R, jsonrpsee::core::Error>
+    where
+        R: DeserializeOwned,
+        Params: ToRpcParams + Send,
+    {
+        if !CACHEABLE_METHODS.contains(&method) {
+            return self.inner.request(method, params).await;
+        }
+        let params = params.to_rpc_params()?;
+        let key = match &params {
+            Some(params) => format!("{method}{}", params.get()),
+            None => method.to_owned(),
+        };
+        let response = match self.get(&key) {
+            Some(response) => response,
+            None => {
+                let response: Box<RawValue> = self
+                    .inner
+                    .request(method, RawParams(params.clone()))
+                    .await?;
+                self.insert(method, params.as_deref(), key, response.clone());
+                response
+            }
+        };
+        Ok(serde_json::from_str(response.get())?)
+    }
+
+    async fn batch_request<'a, R>(
+        &self,
+        batch: BatchRequestBuilder<'a>,
+    ) -> Result<BatchResponse<'a, R>, jsonrpsee::core::Error>
+    where
+        R: DeserializeOwned + fmt::Debug + 'a,
+    {
+        self.inner.batch_request(batch).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CachedClient;
+    use crate::mock::{block_on, MockClient};
+    use jsonrpsee::core::client::ClientT;
+    use jsonrpsee::rpc_params;
+    use serde_json::{json, Value};
+
+    const BLOCK_A: &str = "000000000000000000000000000000000000000000000000000000000000000a";
+    const BLOCK_B: &str = "000000000000000000000000000000000000000000000000000000000000000b";
+
+    fn client() -> CachedClient<MockClient> {
+        let inner = MockClient::new(|method, params| {
+            let hash = params[0].as_str().unwrap();
+            let height = if hash == BLOCK_A { 10 } else { 11 };
+            Ok(match (method, params.get(1)) {
+                ("getblock", Some(verbosity)) if verbosity == &json!(0) => json!("00"),
+                ("getblock", _) => json!({
+                    "hash": hash,
+                    "height": height,
+                    "confirmations": 1,
+                }),
+                _ => Value::Null,
+            })
+        });
+        CachedClient::new(inner, 16)
+    }
+
+    #[test]
+    fn verbose_blocks_are_not_cached() {
+        let client = client();
+        block_on(async {
+            for _ in 0..2 {
+                let _: Value = client
+                    .request("getblock", rpc_params![BLOCK_A, 1])
+                    .await
+                    .unwrap();
+                let _: Value = client
+                    .request("getblock", rpc_params![BLOCK_A, 0])
+                    .await
+                    .unwrap();
+            }
+        });
+        assert_eq!(client.inner().methods().len(), 3);
+        assert_eq!(client.len(), 1);
+    }
+
+    #[test]
+    fn invalidates_descendants_by_height() {
+        let client = client();
+        block_on(async {
+            for hash in [BLOCK_A, BLOCK_B] {
+                let _: Value = client
+                    .request("getblock", rpc_params![hash, 1])
+                    .await
+                    .unwrap();
+                let _: Value = client
+                    .request("getblock", rpc_params![hash, 0])
+                    .await
+                    .unwrap();
+            }
+        });
+        assert_eq!(client.len(), 2);
+        client.invalidate_descendants_of(&BLOCK_A.parse().unwrap());
+        assert_eq!(client.len(), 1);
+        client.invalidate_descendants_of(&BLOCK_B.parse().unwrap());
+        assert!(client.is_empty());
+    }
+}
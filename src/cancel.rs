@@ -0,0 +1,163 @@
+//! Cancelling long-running helpers, and bounding how long they may take.
+//!
+//! Helpers that loop or issue many requests have a `_with_limits` variant
+//! that takes [`Limits`], checks them between requests, and races requests
+//! in flight against them, so dropping the future is not needed to stop
+//! them. Helpers that build up a result return it as a [`Partial`], with
+//! what they completed before [`Error::Cancelled`] or
+//! [`Error::DeadlineExceeded`], e.g. the headers disconnected by
+//! [`crate::headers::LazyHeaderCache::sync_with_limits`] so far. Pollers
+//! return the error.
+use crate::Error;
+use futures::future::Either;
+use std::future::Future;
+use std::time::Duration;
+use tokio::time::Instant;
+pub use tokio_util::sync::CancellationToken;
+
+/// Cancellation token and overall timeout of a helper. The default never
+/// cancels or times out.
+#[derive(Debug, Clone, Default)]
+pub struct Limits {
+    pub cancel: Option<CancellationToken>,
+    /// Counted from when the helper starts.
+    pub timeout: Option<Duration>,
+}
+
+impl Limits {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_cancellation(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub(crate) fn start(&self) -> Running {
+        Running {
+            cancel: self.cancel.clone(),
+            deadline: self.timeout.map(|timeout| Instant::now() + timeout),
+        }
+    }
+}
+
+/// Result of a helper that may have been stopped by its [`Limits`].
+#[derive(Debug)]
+pub struct Partial<T> {
+    /// The complete result, or what was completed before `stopped`.
+    pub value: T,
+    /// [`Error::Cancelled`] or [`Error::DeadlineExceeded`] if the helper
+    /// was stopped. Helpers that keep what they completed on any error,
+    /// such as [`crate::priority::PriorityManager::cleanup`], return that
+    /// error here too.
+    pub stopped: Option<Error>,
+}
+
+impl<T> Partial<T> {
+    /// `value` with the error `result` stopped with, or the error if it is
+    /// not [`Error::Cancelled`] or [`Error::DeadlineExceeded`].
+    pub(crate) fn new(value: T, result: Result<(), Error>) -> Result<Self, Error> {
+        match result {
+            Ok(()) => Ok(Partial {
+                value,
+                stopped: None,
+            }),
+            Err(err @ (Error::Cancelled | Error::DeadlineExceeded { .. })) => Ok(Partial {
+                value,
+                stopped: Some(err),
+            }),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.stopped.is_none()
+    }
+
+    /// The value if the helper was not stopped, otherwise the error.
+    pub fn into_result(self) -> Result<T, Error> {
+        match self.stopped {
+            None => Ok(self.value),
+            Some(err) => Err(err),
+        }
+    }
+}
+
+/// [`Limits`] of a helper that started.
+#[derive(Debug, Clone)]
+pub(crate) struct Running {
+    cancel: Option<CancellationToken>,
+    deadline: Option<Instant>,
+}
+
+impl Running {
+    /// Fails if cancelled or past the deadline. `completed` and
+    /// `remaining_estimate` are the progress reported in
+    /// [`Error::DeadlineExceeded`].
+    pub(crate) fn check(
+        &self,
+        completed: usize,
+        remaining_estimate: Option<usize>,
+    ) -> Result<(), Error> {
+        if self
+            .cancel
+            .as_ref()
+            .is_some_and(|cancel| cancel.is_cancelled())
+        {
+            return Err(Error::Cancelled);
+        }
+        if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            return Err(Error::DeadlineExceeded {
+                completed,
+                remaining_estimate,
+            });
+        }
+        Ok(())
+    }
+
+    /// Like [`Running::check`] before and while `request` runs.
+    pub(crate) async fn race<F, T>(
+        &self,
+        request: F,
+        completed: usize,
+        remaining_estimate: Option<usize>,
+    ) -> Result<T, Error>
+    where
+        F: Future<Output = Result<T, Error>>,
+    {
+        self.check(completed, remaining_estimate)?;
+        let cancelled = async {
+            match &self.cancel {
+                Some(cancel) => cancel.cancelled().await,
+                None => std::future::pending().await,
+            }
+        };
+        let request = async {
+            match self.deadline {
+                Some(deadline) => {
+                    tokio::time::timeout_at(deadline, request)
+                        .await
+                        .map_err(|_| Error::DeadlineExceeded {
+                            completed,
+                            remaining_estimate,
+                        })?
+                }
+                None => request.await,
+            }
+        };
+        futures::pin_mut!(cancelled, request);
+        match futures::future::select(request, cancelled).await {
+            Either::Left((result, _)) => result,
+            Either::Right(((), _)) => Err(Error::Cancelled),
+        }
+    }
+}
@@ -0,0 +1,484 @@
+//! Recording mainchain RPC sessions to a file and replaying them, to run
+//! integration tests without a node.
+//!
+//! A cassette is a JSON lines file. The first line is a header with the
+//! format version, every further line one [`Interaction`]. Only methods,
+//! params and results are recorded, so credentials sent in HTTP headers
+//! never end up in a cassette. Params of [`REDACTED_METHODS`] are replaced
+//! with [`REDACTED`].
+//!
+//! Batches are not supported, and are rejected by both clients.
+use crate::Error;
+use jsonrpsee::core::async_trait;
+use jsonrpsee::core::client::{BatchResponse, ClientT};
+use jsonrpsee::core::params::BatchRequestBuilder;
+use jsonrpsee::core::traits::ToRpcParams;
+use jsonrpsee::types::ErrorObjectOwned;
+use serde::de::DeserializeOwned;
+use serde_json::value::RawValue;
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+pub const CASSETTE_VERSION: u32 = 1;
+
+/// Methods whose params are secret, e.g. wallet passphrases.
+pub const REDACTED_METHODS: &[&str] = &[
+    "encryptwallet",
+    "walletpassphrase",
+    "walletpassphrasechange",
+];
+
+/// Replaces the params of [`REDACTED_METHODS`] in cassettes.
+pub const REDACTED: &str = "<redacted>";
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+struct CassetteHeader {
+    version: u32,
+}
+
+/// Error returned by the node, or a description of a transport error.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct RecordedError {
+    /// `None` for errors that did not come from the node.
+    pub code: Option<i32>,
+    pub message: String,
+    pub data: Option<serde_json::Value>,
+}
+
+impl RecordedError {
+    fn new(err: &jsonrpsee::core::Error) -> Self {
+        match err {
+            jsonrpsee::core::Error::Call(err) => RecordedError {
+                code: Some(err.code()),
+                message: err.message().to_owned(),
+                data: err
+                    .data()
+                    .and_then(|data| serde_json::from_str(data.get()).ok()),
+            },
+            err => RecordedError {
+                code: None,
+                message: err.to_string(),
+                data: None,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Interaction {
+    pub method: String,
+    /// `null` if the request had no params.
+    pub params: serde_json::Value,
+    /// `None` for notifications.
+    pub response: Option<serde_json::Value>,
+    pub error: Option<RecordedError>,
+}
+
+impl Interaction {
+    fn result<R>(&self) -> Result<R, jsonrpsee::core::Error>
+    where
+        R: DeserializeOwned,
+    {
+        match (&self.response, &self.error) {
+            (
+                _,
+                Some(RecordedError {
+                    code: Some(code),
+                    message,
+                    data,
+                }),
+            ) => Err(ErrorObjectOwned::owned(*code, message.clone(), data.clone()).into()),
+            (_, Some(error)) => Err(jsonrpsee::core::Error::Custom(error.message.clone())),
+            (Some(response), None) => Ok(R::deserialize(response)?),
+            (None, None) => Ok(R::deserialize(serde_json::Value::Null)?),
+        }
+    }
+}
+
+fn params_value(
+    method: &str,
+    params: &Option<Box<RawValue>>,
+) -> Result<serde_json::Value, jsonrpsee::core::Error> {
+    if REDACTED_METHODS.contains(&method) {
+        return Ok(serde_json::Value::String(REDACTED.to_owned()));
+    }
+    match params {
+        Some(params) => Ok(serde_json::from_str(params.get())?),
+        None => Ok(serde_json::Value::Null),
+    }
+}
+
+fn batches_unsupported() -> jsonrpsee::core::Error {
+    jsonrpsee::core::Error::Custom("batches are not supported by cassettes".to_owned())
+}
+
+/// Client wrapper that appends every request and its result to a cassette.
+pub struct RecordingClient<C> {
+    inner: C,
+    cassette: Mutex<File>,
+}
+
+impl<C> RecordingClient<C> {
+    /// Creates the cassette at `path`, replacing any existing file.
+    pub fn new(inner: C, path: impl AsRef<Path>) -> Result<Self, Error> {
+        let mut cassette = File::create(path)?;
+        let header = CassetteHeader {
+            version: CASSETTE_VERSION,
+        };
+        writeln!(cassette, "{}", serde_json::to_string(&header).unwrap())?;
+        Ok(RecordingClient {
+            inner,
+            cassette: Mutex::new(cassette),
+        })
+    }
+
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+
+    fn record(&self, interaction: &Interaction) -> Result<(), jsonrpsee::core::Error> {
+        let line = serde_json::to_string(interaction)?;
+        writeln!(self.cassette.lock().unwrap(), "{line}")
+            .map_err(|err| jsonrpsee::core::Error::Custom(format!("writing cassette: {err}")))
+    }
+}
+
+#[async_trait]
+impl<C> ClientT for RecordingClient<C>
+where
+    C: ClientT + Send + Sync,
+{
+    async fn notification<Params>(
+        &self,
+        method: &str,
+        params: Params,
+    ) -> Result<(), jsonrpsee::core::Error>
+    where
+        Params: ToRpcParams + Send,
+    {
+        let params = params.to_rpc_params()?;
+        let recorded_params = params_value(method, &params)?;
+        let result = self
+            .inner
+            .notification(method, crate::cache::RawParams(params))
+            .await;
+        let interaction = Interaction {
+            method: method.to_owned(),
+            params: recorded_params,
+            response: None,
+            error: result.as_ref().err().map(RecordedError::new),
+        };
+        self.record(&interaction)?;
+        result
+    }
+
+    async fn request<R, Params>(
+        &self,
+        method: &str,
+        params: Params,
+    ) -> Result<R, jsonrpsee::core::Error>
+    where
+        R: DeserializeOwned,
+        Params: ToRpcParams + Send,
+    {
+        let params = params.to_rpc_params()?;
+        let recorded_params = params_value(method, &params)?;
+        let result: Result<serde_json::Value, _> = self
+            .inner
+            .request(method, crate::cache::RawParams(params))
+            .await;
+        let interaction = Interaction {
+            method: method.to_owned(),
+            params: recorded_params,
+            response: result.as_ref().ok().cloned(),
+            error: result.as_ref().err().map(RecordedError::new),
+        };
+        self.record(&interaction)?;
+        Ok(R::deserialize(result?)?)
+    }
+
+    async fn batch_request<'a, R>(
+        &self,
+        _batch: BatchRequestBuilder<'a>,
+    ) -> Result<BatchResponse<'a, R>, jsonrpsee::core::Error>
+    where
+        R: DeserializeOwned + fmt::Debug + 'a,
+    {
+        Err(batches_unsupported())
+    }
+}
+
+/// Decides whether a recorded interaction answers a request, given the
+/// method and params of the request.
+pub type Matcher = Arc<dyn Fn(&Interaction, &str, &serde_json::Value) -> bool + Send + Sync>;
+
+/// Matches method and params exactly.
+pub fn exact_matcher() -> Matcher {
+    Arc::new(|interaction, method, params| {
+        interaction.method == method && interaction.params == *params
+    })
+}
+
+/// Matches method and params exactly, except for the params of `method` at
+/// `positions`, e.g. timeouts that differ between runs.
+pub fn ignoring_params(method: &'static str, positions: &'static [usize]) -> Matcher {
+    Arc::new(move |interaction, request_method, params| {
+        if interaction.method != request_method {
+            return false;
+        }
+        if request_method != method {
+            return interaction.params == *params;
+        }
+        match (&interaction.params, params) {
+            (serde_json::Value::Array(recorded), serde_json::Value::Array(params)) => {
+                recorded.len() == params.len()
+                    && recorded.iter().zip(params).enumerate().all(
+                        |(position, (recorded, param))| {
+                            positions.contains(&position) || recorded == param
+                        },
+                    )
+            }
+            (recorded, params) => recorded == params,
+        }
+    })
+}
+
+/// Client that answers requests from a cassette recorded by a
+/// [`RecordingClient`], without a node.
+///
+/// Requests without a matching interaction fail with an error describing
+/// the closest recorded interaction. Dropping the client while recorded
+/// interactions are left panics, unless the thread is already panicking.
+pub struct ReplayClient {
+    interactions: Mutex<VecDeque<Interaction>>,
+    matcher: Matcher,
+    strict_order: bool,
+}
+
+impl ReplayClient {
+    pub fn from_cassette(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let invalid = |line, reason: String| Error::InvalidCassette { line, reason };
+        let mut lines = BufReader::new(File::open(path)?).lines();
+        let header: CassetteHeader = match lines.next() {
+            Some(line) => {
+                serde_json::from_str(&line?).map_err(|err| invalid(1, err.to_string()))?
+            }
+            None => return Err(invalid(1, "missing header".to_owned())),
+        };
+        if header.version != CASSETTE_VERSION {
+            return Err(invalid(
+                1,
+                format!(
+                    "version {} is not supported, expected {CASSETTE_VERSION}",
+                    header.version
+                ),
+            ));
+        }
+        let mut interactions = VecDeque::new();
+        for (index, line) in lines.enumerate() {
+            let interaction =
+                serde_json::from_str(&line?).map_err(|err| invalid(index + 2, err.to_string()))?;
+            interactions.push_back(interaction);
+        }
+        Ok(ReplayClient {
+            interactions: Mutex::new(interactions),
+            matcher: exact_matcher(),
+            strict_order: false,
+        })
+    }
+
+    pub fn with_matcher(mut self, matcher: Matcher) -> Self {
+        self.matcher = matcher;
+        self
+    }
+
+    /// Only answers requests with the next recorded interaction, e.g. to
+    /// test that retries happen in the recorded order. By default, the first
+    /// unused matching interaction answers a request.
+    pub fn strict_order(mut self) -> Self {
+        self.strict_order = true;
+        self
+    }
+
+    /// Interactions that have not been replayed yet.
+    pub fn remaining(&self) -> Vec<Interaction> {
+        self.interactions.lock().unwrap().iter().cloned().collect()
+    }
+
+    fn take(
+        &self,
+        method: &str,
+        params: &serde_json::Value,
+    ) -> Result<Interaction, jsonrpsee::core::Error> {
+        let mut interactions = self.interactions.lock().unwrap();
+        let position = if self.strict_order {
+            interactions
+                .front()
+                .filter(|interaction| (self.matcher)(interaction, method, params))
+                .map(|_| 0)
+        } else {
+            interactions
+                .iter()
+                .position(|interaction| (self.matcher)(interaction, method, params))
+        };
+        if let Some(position) = position {
+            return Ok(interactions.remove(position).unwrap());
+        }
+        let closest = if self.strict_order {
+            interactions.front()
+        } else {
+            interactions
+                .iter()
+                .find(|interaction| interaction.method == method)
+                .or(interactions.front())
+        };
+        let mut message = format!("no recorded interaction matches `{method}`\n+ params: {params}");
+        if let Some(closest) = closest {
+            message += &format!(
+                "\n- closest recorded: `{}`\n- params: {}",
+                closest.method, closest.params
+            );
+        }
+        Err(jsonrpsee::core::Error::Custom(message))
+    }
+}
+
+impl Drop for ReplayClient {
+    fn drop(&mut self) {
+        let interactions = self.interactions.get_mut().unwrap();
+        if !interactions.is_empty() && !std::thread::panicking() {
+            let methods: Vec<_> = interactions
+                .iter()
+                .map(|interaction| interaction.method.as_str())
+                .collect();
+            panic!(
+                "{} recorded interactions were not replayed: {methods:?}",
+                methods.len()
+            );
+        }
+    }
+}
+
+#[async_trait]
+impl ClientT for ReplayClient {
+    async fn notification<Params>(
+        &self,
+        method: &str,
+        params: Params,
+    ) -> Result<(), jsonrpsee::core::Error>
+    where
+        Params: ToRpcParams + Send,
+    {
+        let params = params_value(method, &params.to_rpc_params()?)?;
+        self.take(method, &params)?.result()
+    }
+
+    async fn request<R, Params>(
+        &self,
+        method: &str,
+        params: Params,
+    ) -> Result<R, jsonrpsee::core::Error>
+    where
+        R: DeserializeOwned,
+        Params: ToRpcParams + Send,
+    {
+        let params = params_value(method, &params.to_rpc_params()?)?;
+        self.take(method, &params)?.result()
+    }
+
+    async fn batch_request<'a, R>(
+        &self,
+        _batch: BatchRequestBuilder<'a>,
+    ) -> Result<BatchResponse<'a, R>, jsonrpsee::core::Error>
+    where
+        R: DeserializeOwned + fmt::Debug + 'a,
+    {
+        Err(batches_unsupported())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{ChainClient, WalletClient};
+    use crate::mock::{block_on, MockChain, MockClient};
+    use std::path::PathBuf;
+
+    fn cassette_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "bip300301-cassette-{}-{name}.jsonl",
+            std::process::id()
+        ))
+    }
+
+    fn node() -> MockClient {
+        let chain = MockChain::new();
+        chain.mine(3);
+        MockClient::new(move |method, params| match method {
+            "walletpassphrase" => Ok(serde_json::Value::Null),
+            _ => chain.handle(method, params).unwrap(),
+        })
+    }
+
+    /// Results of a session of typed calls, including a failing one.
+    async fn session<C>(client: &C) -> Vec<String>
+    where
+        C: ClientT + Sync,
+    {
+        let tip = client.getbestblockhash().await;
+        let header = match &tip {
+            Ok(tip) => format!("{:?}", client.getblockheader(tip).await),
+            Err(_) => String::new(),
+        };
+        vec![
+            format!("{:?}", client.getblockcount().await),
+            format!("{tip:?}"),
+            header,
+            format!("{:?}", client.getblockhash(1).await),
+            // Out of range
+            format!("{:?}", client.getblockhash(10).await),
+            format!("{:?}", client.walletpassphrase("hunter2", 60).await),
+        ]
+    }
+
+    #[test]
+    fn replays_a_recorded_session() {
+        let path = cassette_path("session");
+        let recording = RecordingClient::new(node(), &path).unwrap();
+        let recorded = block_on(session(&recording));
+        assert!(recorded[4].contains("Block height out of range"));
+        let cassette = std::fs::read_to_string(&path).unwrap();
+        assert!(!cassette.contains("hunter2"));
+        assert!(cassette.contains(REDACTED));
+
+        let replay = ReplayClient::from_cassette(&path).unwrap().strict_order();
+        assert_eq!(block_on(session(&replay)), recorded);
+        assert!(replay.remaining().is_empty());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn unrecorded_request_fails() {
+        let path = cassette_path("unrecorded");
+        let recording = RecordingClient::new(node(), &path).unwrap();
+        let recorded = block_on(recording.getblockhash(1)).unwrap();
+        assert_eq!(recording.inner().methods(), ["getblockhash"]);
+
+        let replay = ReplayClient::from_cassette(&path).unwrap();
+        let err = block_on(replay.getblockhash(2)).unwrap_err();
+        let message = err.to_string();
+        assert!(
+            message.contains("no recorded interaction matches `getblockhash`"),
+            "{message}"
+        );
+        assert!(message.contains("- params: [1]"), "{message}");
+        // The recorded interaction is still there
+        assert_eq!(replay.remaining().len(), 1);
+        assert_eq!(block_on(replay.getblockhash(1)).unwrap(), recorded);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
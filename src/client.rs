@@ -1,28 +1,125 @@
+use crate::enums::string_enum;
+use crate::introspection::rpc_traits;
+#[cfg(feature = "client")]
+use bitcoin::address::NetworkUnchecked;
 use bitcoin::amount::serde::SerdeAmount;
+use bitcoin::amount::{Denomination, ParseAmountError};
+use hashlink::LinkedHashMap;
+#[cfg(feature = "client")]
 use jsonrpsee::proc_macros::rpc;
-use std::ops::{Deref, DerefMut};
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::{Add, Deref, DerefMut, Sub};
+use std::str::FromStr;
 
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct WithdrawalStatus {
-    hash: bitcoin::Txid,
-    nblocksleft: usize,
-    nworkscore: usize,
+    pub hash: bitcoin::Txid,
+    pub nblocksleft: usize,
+    pub nworkscore: usize,
 }
 
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct SpentWithdrawal {
-    pub nsidechain: u8,
+    pub nsidechain: SidechainId,
     pub hash: bitcoin::Txid,
     pub hashblock: bitcoin::BlockHash,
 }
 
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+/// Critical transaction index pair: the escrow output currently holding the
+/// funds of a sidechain. Every deposit and withdrawal payout spends it and
+/// creates a new one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct Ctip {
+    pub txid: bitcoin::Txid,
+    pub n: u32,
+    pub amount: AmountBtc,
+}
+
+impl Ctip {
+    pub fn outpoint(&self) -> bitcoin::OutPoint {
+        bitcoin::OutPoint {
+            txid: self.txid,
+            vout: self.n,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct FailedWithdrawal {
-    pub nsidechain: u8,
+    pub nsidechain: SidechainId,
     pub hash: bitcoin::Txid,
 }
 
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+/// Sidechain slot number, sent to the node as a bare number.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[serde(transparent)]
+pub struct SidechainId(pub u8);
+
+impl SidechainId {
+    /// All 256 sidechain slots, in order.
+    pub const ALL: [SidechainId; 256] = {
+        let mut all = [SidechainId(0); 256];
+        let mut sidechain_number = 0;
+        while sidechain_number < all.len() {
+            all[sidechain_number] = SidechainId(sidechain_number as u8);
+            sidechain_number += 1;
+        }
+        all
+    };
+}
+
+impl From<u8> for SidechainId {
+    fn from(sidechain_number: u8) -> Self {
+        SidechainId(sidechain_number)
+    }
+}
+
+impl From<SidechainId> for u8 {
+    fn from(sidechain_id: SidechainId) -> Self {
+        sidechain_id.0
+    }
+}
+
+impl TryFrom<u32> for SidechainId {
+    type Error = std::num::TryFromIntError;
+
+    fn try_from(sidechain_number: u32) -> Result<Self, Self::Error> {
+        Ok(SidechainId(u8::try_from(sidechain_number)?))
+    }
+}
+
+impl fmt::Display for SidechainId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromStr for SidechainId {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(SidechainId(s.parse()?))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum Vote {
     Upvote,
@@ -30,8 +127,15 @@ pub enum Vote {
     Downvote,
 }
 
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+string_enum!(Vote {
+    Upvote => "upvote",
+    Abstain => "abstain",
+    Downvote => "downvote",
+});
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct Block {
     pub hash: bitcoin::BlockHash,
     pub confirmations: usize,
@@ -46,153 +150,3193 @@ pub struct Block {
     pub time: u32,
     pub mediantime: u32,
     pub nonce: u32,
-    pub bits: String,
-    pub difficulty: f64,
-    pub chainwork: String,
+    #[serde(with = "compact_target_hex")]
+    pub bits: bitcoin::CompactTarget,
+    pub difficulty: Difficulty,
+    pub chainwork: bitcoin::Work,
+    pub n_tx: usize,
+    pub previousblockhash: Option<bitcoin::BlockHash>,
+    pub nextblockhash: Option<bitcoin::BlockHash>,
+}
+
+/// Methods of [`Block`] and [`Header`], which share the header fields.
+macro_rules! header_methods {
+    ($ty:ident) => {
+        impl $ty {
+            pub fn target(&self) -> bitcoin::Target {
+                bitcoin::Target::from_compact(self.bits)
+            }
+
+            pub fn time_utc(&self) -> std::time::SystemTime {
+                unix_time(self.time)
+            }
+
+            /// Median time past of the last 11 blocks, including this one.
+            pub fn median_time_utc(&self) -> std::time::SystemTime {
+                unix_time(self.mediantime)
+            }
+
+            /// Time since the block timestamp, or zero if it is after `now`.
+            pub fn age(&self, now: std::time::SystemTime) -> std::time::Duration {
+                now.duration_since(self.time_utc()).unwrap_or_default()
+            }
+
+            /// Checks the consensus rules for the block timestamp: after
+            /// `median_time_past`, and at most `max_future_drift` (usually
+            /// [`MAX_FUTURE_BLOCK_TIME`]) after `now`.
+            ///
+            /// `median_time_past` must be that of the *previous* block, as
+            /// the `mediantime` of this block already includes its own
+            /// timestamp.
+            pub fn is_timestamp_valid(
+                &self,
+                median_time_past: std::time::SystemTime,
+                now: std::time::SystemTime,
+                max_future_drift: std::time::Duration,
+            ) -> bool {
+                is_timestamp_valid(self.time_utc(), median_time_past, now, max_future_drift)
+            }
+
+            pub fn work(&self) -> bitcoin::Work {
+                self.target().to_work()
+            }
+
+            /// Returns [`crate::Error::DifficultyMismatch`] if `difficulty`
+            /// does not agree with `bits`.
+            pub fn validate_difficulty(&self) -> Result<(), crate::Error> {
+                validate_difficulty(self.hash, self.bits, self.difficulty)
+            }
+        }
+    };
+}
+
+header_methods!(Block);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct BlockRef {
+    pub hash: bitcoin::BlockHash,
+    pub height: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct Header {
+    pub hash: bitcoin::BlockHash,
+    pub confirmations: i64,
+    pub height: usize,
+    pub version: i32,
+    pub version_hex: String,
+    pub merkleroot: bitcoin::hash_types::TxMerkleNode,
+    pub time: u32,
+    pub mediantime: u32,
+    pub nonce: u32,
+    #[serde(with = "compact_target_hex")]
+    pub bits: bitcoin::CompactTarget,
+    pub difficulty: Difficulty,
+    pub chainwork: bitcoin::Work,
+    pub n_tx: usize,
     pub previousblockhash: Option<bitcoin::BlockHash>,
     pub nextblockhash: Option<bitcoin::BlockHash>,
 }
 
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+header_methods!(Header);
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct MempoolEntryFees {
+    pub base: AmountBtc,
+    pub modified: AmountBtc,
+    pub ancestor: AmountBtc,
+    pub descendant: AmountBtc,
+}
+
+/// Entry of `getprioritisedtransactions`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct PrioritisedTransaction {
+    /// In sats.
+    pub fee_delta: i64,
+    pub in_mempool: bool,
+    /// Fee including `fee_delta`, in sats. Only set if in the mempool.
+    pub modified_fee: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct MempoolEntry {
+    pub vsize: u64,
+    /// Not reported by old nodes.
+    pub weight: Option<u64>,
+    pub time: u64,
+    pub height: usize,
+    pub descendantcount: u64,
+    pub descendantsize: u64,
+    pub ancestorcount: u64,
+    pub ancestorsize: u64,
+    pub wtxid: bitcoin::Wtxid,
+    pub fees: MempoolEntryFees,
+    pub depends: Vec<bitcoin::Txid>,
+    pub spentby: Vec<bitcoin::Txid>,
+    #[serde(rename = "bip125-replaceable", alias = "bip125_replaceable")]
+    pub bip125_replaceable: Option<bool>,
+    /// Not reported by old nodes.
+    pub unbroadcast: Option<bool>,
+}
+
+/// Response of `getrawmempool` with `verbose = true`.
+///
+/// Entries that fail to deserialize are collected into `skipped` together
+/// with the error, instead of failing the whole response.
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize)]
+pub struct RawMempoolVerbose {
+    #[serde(flatten)]
+    pub entries: HashMap<bitcoin::Txid, MempoolEntry>,
+    #[serde(skip)]
+    pub skipped: Vec<(bitcoin::Txid, String)>,
+}
+
+impl<'de> serde::Deserialize<'de> for RawMempoolVerbose {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let raw = HashMap::<bitcoin::Txid, serde_json::Value>::deserialize(deserializer)?;
+        let mut res = RawMempoolVerbose::default();
+        for (txid, value) in raw {
+            match serde_json::from_value(value) {
+                Ok(entry) => {
+                    res.entries.insert(txid, entry);
+                }
+                Err(err) => res.skipped.push((txid, err.to_string())),
+            }
+        }
+        Ok(res)
+    }
+}
+
+/// A deposit with its decoded transaction, checked against the block it is
+/// reported in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DepositVerbose {
+    pub deposit: Deposit,
+    pub transaction: bitcoin::Transaction,
+    /// Destination extracted from the `OP_RETURN` of `transaction`, without
+    /// the lossy conversion of [`Deposit::strdest`]. `None` if
+    /// [`crate::deposit::extract_dest_bytes`] finds no destination.
+    pub dest_bytes: Option<Vec<u8>>,
+}
+
+impl DepositVerbose {
+    /// Classifies `dest_bytes` with the default deposit address formats.
+    pub fn destination(&self) -> Option<crate::deposit::DepositDestination> {
+        let dest_bytes = self.dest_bytes.as_deref()?;
+        Some(crate::deposit::DepositDestination::parse(dest_bytes))
+    }
+
+    pub fn destination_with(
+        &self,
+        formats: &[&dyn crate::deposit::DepositAddressFormat],
+    ) -> Option<crate::deposit::DepositDestination> {
+        let dest_bytes = self.dest_bytes.as_deref()?;
+        Some(crate::deposit::DepositDestination::parse_with(
+            dest_bytes, formats,
+        ))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct Deposit {
     pub hashblock: bitcoin::BlockHash,
     pub nburnindex: usize,
     pub ntx: usize,
+    /// Destination as displayed by the node, which replaces bytes that are
+    /// not UTF-8. See [`DepositVerbose::dest_bytes`] for the exact bytes.
     pub strdest: String,
     pub txhex: String,
 }
 
-#[rpc(client)]
-pub trait Main {
-    #[method(name = "stop")]
-    async fn stop(&self) -> Result<String, jsonrpsee::core::Error>;
-    // FIXME: Define a "Deposit Address" type.
-    #[method(name = "listwithdrawalstatus")]
-    async fn listwithdrawalstatus(
-        &self,
-        nsidechain: u8,
-    ) -> Result<Vec<WithdrawalStatus>, jsonrpsee::core::Error>;
-    #[method(name = "listspentwithdrawals")]
-    async fn listspentwithdrawals(&self) -> Result<Vec<SpentWithdrawal>, jsonrpsee::core::Error>;
-    #[method(name = "listfailedwithdrawals")]
-    async fn listfailedwithdrawals(&self) -> Result<Vec<FailedWithdrawal>, jsonrpsee::core::Error>;
-    #[method(name = "getblockcount")]
-    async fn getblockcount(&self) -> Result<usize, jsonrpsee::core::Error>;
-    #[method(name = "getbestblockhash")]
-    async fn getbestblockhash(&self) -> Result<bitcoin::BlockHash, jsonrpsee::core::Error>;
-    #[method(name = "getblock")]
-    async fn getblock(
-        &self,
-        blockhash: &bitcoin::BlockHash,
-        verbosity: Option<usize>,
-    ) -> Result<Block, jsonrpsee::core::Error>;
-    #[method(name = "createbmmcriticaldatatx")]
-    async fn createbmmcriticaldatatx(
-        &self,
-        amount: AmountBtc,
-        height: u32,
-        criticalhash: &bitcoin::BlockHash,
-        nsidechain: u8,
-        prevbytes: &str,
-    ) -> Result<serde_json::Value, jsonrpsee::core::Error>;
-    #[method(name = "verifybmm")]
-    async fn verifybmm(
-        &self,
-        blockhash: &bitcoin::BlockHash,
-        criticalhash: &bitcoin::BlockHash,
-        nsidechain: u8,
-    ) -> Result<serde_json::Value, jsonrpsee::core::Error>;
+impl Deposit {
+    /// Decodes `txhex`, which some nodes return without witness data.
+    pub fn transaction(&self) -> Result<bitcoin::Transaction, crate::Error> {
+        use bitcoin::consensus::Decodable;
+        let bytes = hex::decode(&self.txhex)?;
+        let witness = match bitcoin::consensus::deserialize(&bytes) {
+            Ok(transaction) => return Ok(transaction),
+            Err(err) => err,
+        };
+        // A legacy transaction without inputs looks like a segwit marker to
+        // the regular decoder.
+        let decode_legacy = || {
+            let mut cursor = std::io::Cursor::new(&bytes);
+            let transaction = bitcoin::Transaction {
+                version: i32::consensus_decode(&mut cursor)?,
+                input: Vec::consensus_decode(&mut cursor)?,
+                output: Vec::consensus_decode(&mut cursor)?,
+                lock_time: bitcoin::absolute::LockTime::consensus_decode(&mut cursor)?,
+            };
+            if cursor.position() as usize != bytes.len() {
+                return Err(bitcoin::consensus::encode::Error::ParseFailed(
+                    "data not consumed entirely when explicitly deserializing",
+                ));
+            }
+            Ok(transaction)
+        };
+        decode_legacy().map_err(|legacy| crate::Error::DepositTransaction { witness, legacy })
+    }
 
-    #[method(name = "listsidechaindepositsbyblock")]
-    async fn listsidechaindepositsbyblock(
-        &self,
-        nsidechain: u8,
-        end_blockhash: Option<bitcoin::BlockHash>,
-        start_blockhash: Option<bitcoin::BlockHash>,
-    ) -> Result<Vec<Deposit>, jsonrpsee::core::Error>;
+    pub fn parsed_dest(&self) -> Result<crate::deposit::DepositAddress, crate::deposit::AddrError> {
+        crate::deposit::DepositAddress::parse_any(&self.strdest)
+    }
+}
 
-    #[method(name = "receivewithdrawalbundle")]
-    async fn receivewithdrawalbundle(
-        &self,
-        nsidechain: u8,
-        // Raw transaction hex.
-        rawtx: &str,
-    ) -> Result<serde_json::Value, jsonrpsee::core::Error>;
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct BlockFilter {
+    #[serde(with = "hex::serde")]
+    pub filter: Vec<u8>,
+    pub header: bitcoin::hash_types::FilterHeader,
+}
 
-    #[method(name = "generate")]
-    async fn generate(&self, num: u32) -> Result<serde_json::Value, jsonrpsee::core::Error>;
+/// A transaction to include in a block mined with `generateblock`.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(untagged)]
+pub enum GenerateBlockTx {
+    /// A transaction in the node's mempool.
+    Txid(bitcoin::Txid),
+    /// Raw transaction hex.
+    RawTx(String),
+}
 
-    #[method(name = "getnewaddress")]
-    async fn getnewaddress(
-        &self,
-        account: &str,
-        address_type: &str,
-    ) -> Result<bitcoin::Address<bitcoin::address::NetworkUnchecked>, jsonrpsee::core::Error>;
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct GenerateBlock {
+    pub hash: bitcoin::BlockHash,
+    /// Raw block hex, only returned if the block was not submitted.
+    pub hex: Option<String>,
+}
 
-    #[method(name = "createsidechaindeposit")]
-    async fn createsidechaindeposit(
-        &self,
-        nsidechain: u8,
-        depositaddress: &str,
-        amount: AmountBtc,
-        fee: AmountBtc,
-    ) -> Result<serde_json::Value, jsonrpsee::core::Error>;
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct BlockTemplateRequest {
+    pub rules: Vec<String>,
+    pub capabilities: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<TemplateRequestMode>,
+    /// Block to validate, only used in proposal mode.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<ConsensusEncoded<bitcoin::Block>>,
 }
 
-// Arguments:
-// 1. "amount"         (numeric or string, required) The amount in BTC to be spent.
-// 2. "height"         (numeric, required) The block height this transaction must be included in.
-// Note: If 0 is passed in for height, current block height will be used
-// 3. "criticalhash"   (string, required) h* you want added to a coinbase
-// 4. "nsidechain"     (numeric, required) Sidechain requesting BMM
-// 5. "prevbytes"      (string, required) a portion of the previous block hash
+impl Default for BlockTemplateRequest {
+    fn default() -> Self {
+        BlockTemplateRequest {
+            rules: vec!["segwit".to_owned()],
+            capabilities: Vec::new(),
+            mode: None,
+            data: None,
+        }
+    }
+}
 
-// FIXME: Make mainchain API machine friendly. Parsing human readable amounts
-// here is stupid -- just take and return values in satoshi.
-#[derive(Clone, Copy)]
-pub struct AmountBtc(pub bitcoin::Amount);
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TemplateRequestMode {
+    Template,
+    /// Validate a block without submitting it, see BIP23.
+    Proposal,
+}
 
-impl From<bitcoin::Amount> for AmountBtc {
-    fn from(other: bitcoin::Amount) -> AmountBtc {
-        AmountBtc(other)
+string_enum!(TemplateRequestMode {
+    Template => "template",
+    Proposal => "proposal",
+});
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct BlockTemplateTransaction {
+    #[serde(with = "hex::serde")]
+    pub data: Vec<u8>,
+    pub txid: bitcoin::Txid,
+    pub hash: bitcoin::Wtxid,
+    /// 1-based indexes of transactions in the template that this transaction
+    /// depends on.
+    pub depends: Vec<u32>,
+    /// Fee in sats, if known. Some patched nodes report it in BTC, which is
+    /// converted.
+    #[serde(default, with = "sats_or_btc_float")]
+    pub fee: Option<i64>,
+    pub sigops: Option<i64>,
+    pub weight: u64,
+}
+
+/// Response of `getblocktemplate`, see BIP22/BIP23.
+///
+/// Fields added by patched nodes are kept in `extra`, so unknown fields are
+/// accepted even with the `strict` feature.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct BlockTemplate {
+    pub capabilities: Vec<String>,
+    pub version: i32,
+    pub rules: Vec<crate::mining::TemplateRule>,
+    #[serde(rename = "vbavailable")]
+    pub version_bits_available: LinkedHashMap<String, u8>,
+    #[serde(rename = "vbrequired")]
+    pub version_bits_required: u32,
+    #[serde(rename = "previousblockhash")]
+    pub prev_blockhash: bitcoin::BlockHash,
+    pub transactions: Vec<BlockTemplateTransaction>,
+    #[serde(rename = "coinbaseaux", with = "hex_values")]
+    pub coinbase_aux: LinkedHashMap<String, Vec<u8>>,
+    #[serde(rename = "coinbasevalue", with = "bitcoin::amount::serde::as_sat")]
+    pub coinbase_value: bitcoin::Amount,
+    #[serde(rename = "longpollid")]
+    pub long_poll_id: Option<String>,
+    #[serde(with = "hex::serde")]
+    pub target: [u8; 32],
+    #[serde(rename = "mintime")]
+    pub min_time: u64,
+    pub mutable: Vec<crate::mining::TemplateMutability>,
+    #[serde(rename = "noncerange", with = "hex::serde")]
+    pub nonce_range: [u8; 8],
+    #[serde(rename = "sigoplimit")]
+    pub sigop_limit: u64,
+    #[serde(rename = "sizelimit")]
+    pub size_limit: u64,
+    #[serde(rename = "weightlimit")]
+    pub weight_limit: Option<u64>,
+    #[serde(rename = "curtime")]
+    pub current_time: u64,
+    pub bits: String,
+    pub height: u32,
+    pub signet_challenge: Option<bitcoin::ScriptBuf>,
+    pub default_witness_commitment: Option<bitcoin::ScriptBuf>,
+    /// Fields not defined by BIP22/BIP23 or Bitcoin Core, e.g.
+    /// `bmm_accepted_bids` or `coinbasedevreward`.
+    #[serde(flatten)]
+    pub extra: LinkedHashMap<String, serde_json::Value>,
+}
+
+impl BlockTemplate {
+    /// BMM requests included in the template by drivechain enabled nodes,
+    /// or `None` if the node did not report them.
+    pub fn bmm_accepted_bids(&self) -> Option<Result<Vec<BmmAcceptedBid>, serde_json::Error>> {
+        let bids = self.extra.get("bmm_accepted_bids")?;
+        Some(serde::Deserialize::deserialize(bids))
     }
 }
 
-impl From<AmountBtc> for bitcoin::Amount {
-    fn from(other: AmountBtc) -> bitcoin::Amount {
-        other.0
+/// BMM request of a sidechain accepted into a block template.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct BmmAcceptedBid {
+    pub nsidechain: SidechainId,
+    /// h*, the hash of the sidechain block.
+    pub criticalhash: bitcoin::BlockHash,
+    pub amount: AmountBtc,
+}
+
+/// (De)serializes a map of hex strings.
+mod hex_values {
+    use hashlink::LinkedHashMap;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S>(
+        map: &LinkedHashMap<String, Vec<u8>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let map: LinkedHashMap<&str, String> = map
+            .iter()
+            .map(|(key, value)| (key.as_str(), hex::encode(value)))
+            .collect();
+        map.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<LinkedHashMap<String, Vec<u8>>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        LinkedHashMap::<String, String>::deserialize(deserializer)?
+            .into_iter()
+            .map(|(key, value)| {
+                let value = hex::decode(value).map_err(serde::de::Error::custom)?;
+                Ok((key, value))
+            })
+            .collect()
     }
 }
 
-impl Deref for AmountBtc {
-    type Target = bitcoin::Amount;
+/// (De)serializes a fee rate as BTC/kvB.
+mod btc_per_kvb {
+    use super::AmountBtc;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
-    fn deref(&self) -> &Self::Target {
-        &self.0
+    pub fn serialize<S>(fee_rate: &bitcoin::FeeRate, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        AmountBtc::from_sat(fee_rate.to_sat_per_kwu() * 4).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<bitcoin::FeeRate, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let sat_per_kvb = AmountBtc::deserialize(deserializer)?.to_sat();
+        // Round up, so that the fee rate is never below the node's.
+        Ok(bitcoin::FeeRate::from_sat_per_kwu(sat_per_kvb.div_ceil(4)))
     }
 }
 
-impl DerefMut for AmountBtc {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+/// (De)serializes a PSBT as base64, the encoding the node uses.
+pub(crate) mod psbt_base64 {
+    use base64::Engine as _;
+    use bitcoin::psbt::PartiallySignedTransaction;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn encode(psbt: &PartiallySignedTransaction) -> String {
+        base64::engine::general_purpose::STANDARD.encode(psbt.serialize())
+    }
+
+    pub fn serialize<S>(psbt: &PartiallySignedTransaction, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&encode(psbt))
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<PartiallySignedTransaction, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(s)
+            .map_err(serde::de::Error::custom)?;
+        PartiallySignedTransaction::deserialize(&bytes).map_err(serde::de::Error::custom)
+    }
+
+    pub mod opt {
+        use bitcoin::psbt::PartiallySignedTransaction;
+        use serde::{Deserialize, Deserializer, Serializer};
+
+        pub fn serialize<S>(
+            psbt: &Option<PartiallySignedTransaction>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            match psbt {
+                Some(psbt) => super::serialize(psbt, serializer),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D>(
+            deserializer: D,
+        ) -> Result<Option<PartiallySignedTransaction>, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            #[derive(Deserialize)]
+            struct Wrapper(#[serde(with = "super")] PartiallySignedTransaction);
+            let psbt = Option::<Wrapper>::deserialize(deserializer)?;
+            Ok(psbt.map(|Wrapper(psbt)| psbt))
+        }
     }
 }
 
-impl<'de> serde::Deserialize<'de> for AmountBtc {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct RawMempoolWithSequence {
+    pub txids: Vec<bitcoin::Txid>,
+    /// Incremented on every transaction added to or removed from the
+    /// mempool. Starts over at 1 when the node restarts.
+    pub mempool_sequence: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct MempoolInfo {
+    pub loaded: bool,
+    pub size: u64,
+    pub bytes: u64,
+    pub usage: u64,
+    pub total_fee: AmountBtc,
+    pub maxmempool: u64,
+    #[serde(with = "btc_per_kvb")]
+    pub mempoolminfee: bitcoin::FeeRate,
+    #[serde(with = "btc_per_kvb")]
+    pub minrelaytxfee: bitcoin::FeeRate,
+    #[serde(with = "btc_per_kvb")]
+    pub incrementalrelayfee: bitcoin::FeeRate,
+    pub unbroadcastcount: u64,
+    /// Not reported by old nodes.
+    pub fullrbf: Option<bool>,
+}
+
+impl MempoolInfo {
+    /// Minimum fee rate for a transaction to be accepted into the mempool.
+    pub fn effective_min_feerate(&self) -> bitcoin::FeeRate {
+        std::cmp::max(self.mempoolminfee, self.minrelaytxfee)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Bip9Status {
+    Defined,
+    Started,
+    LockedIn,
+    Active,
+    Failed,
+}
+
+string_enum!(Bip9Status {
+    Defined => "defined",
+    Started => "started",
+    LockedIn => "locked_in",
+    Active => "active",
+    Failed => "failed",
+});
+
+/// Signalling statistics for the current period of a BIP9 deployment.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct Bip9Statistics {
+    pub period: u32,
+    /// Only reported while the deployment is `started`.
+    pub threshold: Option<u32>,
+    pub elapsed: u32,
+    pub count: u32,
+    /// Only reported while the deployment is `started`.
+    pub possible: Option<bool>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct Bip9Info {
+    pub bit: Option<u8>,
+    pub start_time: i64,
+    pub timeout: i64,
+    pub min_activation_height: u32,
+    pub status: Bip9Status,
+    pub since: u32,
+    pub status_next: Option<Bip9Status>,
+    pub statistics: Option<Bip9Statistics>,
+    pub signalling: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SoftFork {
+    /// Deployment with a hardcoded activation height.
+    Buried { active: bool, height: u32 },
+    Bip9 {
+        active: bool,
+        /// Height of the first block the rules are enforced in, if active.
+        height: Option<u32>,
+        bip9: Bip9Info,
+    },
+}
+
+impl SoftFork {
+    pub fn is_active(&self) -> bool {
+        match self {
+            SoftFork::Buried { active, .. } | SoftFork::Bip9 { active, .. } => *active,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct DeploymentInfo {
+    pub hash: bitcoin::BlockHash,
+    pub height: u32,
+    pub deployments: LinkedHashMap<String, SoftFork>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct SaveMempool {
+    /// Not reported by old nodes.
+    pub filename: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct LoadTxOutSet {
+    pub coins_loaded: u64,
+    pub tip_hash: bitcoin::BlockHash,
+    pub base_height: u32,
+    pub path: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct ActiveCommand {
+    pub method: String,
+    /// Running time in microseconds.
+    pub duration: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct RpcInfo {
+    /// Including the `getrpcinfo` call itself.
+    pub active_commands: Vec<ActiveCommand>,
+    /// Not reported by old nodes.
+    pub logpath: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MemoryInfoMode {
+    /// Usage of the pool of locked memory, which holds wallet keys.
+    Stats,
+    /// Report of the allocator, as XML. Only supported with glibc.
+    MallocInfo,
+}
+
+string_enum!(MemoryInfoMode {
+    Stats => "stats",
+    MallocInfo => "mallocinfo",
+});
+
+/// Mode of `getmemoryinfo`, always [`MemoryInfoMode::Stats`], so that it
+/// matches the response type of [`MemoryInfo`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryStatsMode;
+
+impl serde::Serialize for MemoryStatsMode {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
-        D: serde::Deserializer<'de>,
+        S: serde::Serializer,
     {
-        Ok(AmountBtc(bitcoin::Amount::des_btc(deserializer)?))
+        MemoryInfoMode::Stats.serialize(serializer)
     }
 }
 
-impl serde::Serialize for AmountBtc {
+/// Mode of `getmemoryinfomalloc`, always [`MemoryInfoMode::MallocInfo`], so
+/// that it matches the XML response.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MallocInfoMode;
+
+impl serde::Serialize for MallocInfoMode {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: serde::Serializer,
     {
-        self.0.ser_btc(serializer)
+        MemoryInfoMode::MallocInfo.serialize(serializer)
+    }
+}
+
+/// Usage of the pool of locked memory, in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct LockedMemory {
+    pub used: u64,
+    pub free: u64,
+    pub total: u64,
+    /// Memory the node could lock, less than `total` if the limit for
+    /// locked memory was reached.
+    pub locked: u64,
+    pub chunks_used: u64,
+    pub chunks_free: u64,
+}
+
+impl LockedMemory {
+    /// `used` as a fraction of `total`, 0 if the pool is empty.
+    pub fn utilization(&self) -> f32 {
+        if self.total == 0 {
+            return 0.0;
+        }
+        self.used as f32 / self.total as f32
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct MemoryInfo {
+    pub locked: LockedMemory,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AddNodeCommand {
+    /// Adds the node to the list of nodes to keep connected to.
+    Add,
+    Remove,
+    /// Tries to connect to the node once, without adding it.
+    Onetry,
+}
+
+string_enum!(AddNodeCommand {
+    Add => "add",
+    Remove => "remove",
+    Onetry => "onetry",
+});
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConnectionDirection {
+    Inbound,
+    Outbound,
+}
+
+string_enum!(ConnectionDirection {
+    Inbound => "inbound",
+    Outbound => "outbound",
+});
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct AddedNodeInfo {
+    pub addednode: String,
+    pub connected: bool,
+    /// Only set while connected.
+    #[serde(default)]
+    pub addresses: Vec<AddedNodeAddress>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct AddedNodeAddress {
+    pub address: String,
+    pub connected: ConnectionDirection,
+}
+
+/// A peer from `getpeerinfo`. Only fields reported by all supported node
+/// versions are kept, so unknown fields are accepted even with the
+/// `strict` feature.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct PeerInfo {
+    pub id: u64,
+    pub addr: String,
+    pub services: String,
+    pub lastsend: u64,
+    pub lastrecv: u64,
+    pub bytessent: u64,
+    pub bytesrecv: u64,
+    pub conntime: u64,
+    pub timeoffset: i64,
+    /// Not set until the first ping is answered.
+    pub pingtime: Option<f64>,
+    pub version: u32,
+    pub subver: String,
+    pub inbound: bool,
+}
+
+/// Unknown fields are accepted even with the `strict` feature, as the
+/// enforcer is expected to report more over time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct EnforcementStatus {
+    pub synced: bool,
+    pub enforcer_tip: bitcoin::BlockHash,
+    pub enforcer_height: u32,
+    pub mainchain_tip: bitcoin::BlockHash,
+    /// Number of blocks the enforcer is behind the node.
+    pub lag_blocks: u32,
+    /// Whether BIP300/301 rules are being enforced.
+    pub enforcing: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct NetworkReachability {
+    pub name: String,
+    pub limited: bool,
+    pub reachable: bool,
+    pub proxy: String,
+    pub proxy_randomize_credentials: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct LocalAddress {
+    pub address: String,
+    pub port: u16,
+    pub score: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct NetworkInfo {
+    /// E.g. `230100` for 23.1.0, or `160300` for 0.16.3.
+    pub version: u32,
+    pub subversion: String,
+    pub protocolversion: u32,
+    pub localservices: String,
+    /// Not reported by old nodes.
+    pub localservicesnames: Option<Vec<String>>,
+    pub localrelay: bool,
+    pub timeoffset: i64,
+    pub networkactive: bool,
+    pub connections: u32,
+    /// Not reported by old nodes.
+    pub connections_in: Option<u32>,
+    /// Not reported by old nodes.
+    pub connections_out: Option<u32>,
+    pub networks: Vec<NetworkReachability>,
+    pub relayfee: AmountBtc,
+    pub incrementalfee: AmountBtc,
+    pub localaddresses: Vec<LocalAddress>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct NetTotals {
+    pub totalbytesrecv: u64,
+    pub totalbytessent: u64,
+    /// Milliseconds since the UNIX epoch.
+    pub timemillis: u64,
+    pub uploadtarget: UploadTarget,
+}
+
+/// Limit set with `-maxuploadtarget`. All fields are zero or false if no
+/// limit is set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct UploadTarget {
+    /// Length of the measuring cycle, in seconds.
+    pub timeframe: u64,
+    /// Bytes that may be sent per cycle.
+    pub target: u64,
+    pub target_reached: bool,
+    pub serve_historical_blocks: bool,
+    pub bytes_left_in_cycle: u64,
+    /// Seconds until the current cycle ends.
+    pub time_left_in_cycle: u64,
+}
+
+/// Ratio of the difficulty 1 target to the target of a block, as reported
+/// by the node. The float is kept as reported for display, exact
+/// comparisons should use the target from `bits` where it is available.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, serde::Serialize, serde::Deserialize)]
+#[serde(transparent)]
+pub struct Difficulty(pub f64);
+
+impl Difficulty {
+    /// Maximum relative difference to the difficulty computed from `bits`
+    /// that is put down to float rounding.
+    const TOLERANCE: f64 = 1e-9;
+
+    /// The target closest to this difficulty that can be encoded in
+    /// compact form, as all consensus targets are. `None` if the difficulty
+    /// is not a positive number, or so low that the target would not fit
+    /// in 256 bits.
+    pub fn to_target(&self) -> Option<bitcoin::Target> {
+        if !self.0.is_finite() || self.0 <= 0.0 {
+            return None;
+        }
+        // The difficulty 1 target is 0xffff * 256^26, i.e. 0x1d00ffff
+        let mut mantissa = f64::from(0xffff) / self.0;
+        let mut exponent = 29;
+        while mantissa.round() > f64::from(0x7f_ffff) {
+            if exponent == 32 {
+                return None;
+            }
+            mantissa /= 256.0;
+            exponent += 1;
+        }
+        while mantissa.round() < f64::from(0x8000) && exponent > 3 {
+            mantissa *= 256.0;
+            exponent -= 1;
+        }
+        let compact = (exponent << 24) | mantissa.round() as u32;
+        Some(bitcoin::Target::from_compact(
+            bitcoin::CompactTarget::from_consensus(compact),
+        ))
+    }
+
+    /// `None` where [`Self::to_target`] is.
+    pub fn to_work(&self) -> Option<bitcoin::Work> {
+        self.to_target().map(|target| target.to_work())
+    }
+
+    /// Whether this difficulty agrees with `bits` within float tolerance.
+    pub fn matches(&self, bits: bitcoin::CompactTarget) -> bool {
+        let expected = bitcoin::Target::from_compact(bits).difficulty_float();
+        ((self.0 - expected) / expected).abs() <= Self::TOLERANCE
+    }
+}
+
+impl fmt::Display for Difficulty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Furthest a block timestamp may be ahead of the network-adjusted time.
+pub const MAX_FUTURE_BLOCK_TIME: std::time::Duration = std::time::Duration::from_secs(2 * 60 * 60);
+
+fn unix_time(secs: u32) -> std::time::SystemTime {
+    std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs.into())
+}
+
+fn is_timestamp_valid(
+    time: std::time::SystemTime,
+    median_time_past: std::time::SystemTime,
+    now: std::time::SystemTime,
+    max_future_drift: std::time::Duration,
+) -> bool {
+    time > median_time_past && time <= now + max_future_drift
+}
+
+fn validate_difficulty(
+    block_hash: bitcoin::BlockHash,
+    bits: bitcoin::CompactTarget,
+    difficulty: Difficulty,
+) -> Result<(), crate::Error> {
+    if difficulty.matches(bits) {
+        Ok(())
+    } else {
+        Err(crate::Error::DifficultyMismatch {
+            block_hash,
+            bits,
+            difficulty,
+        })
+    }
+}
+
+/// `bits` of blocks and headers, as hex of the consensus encoding.
+mod compact_target_hex {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S>(bits: &bitcoin::CompactTarget, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&format!("{:08x}", bits.to_consensus()))
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<bitcoin::CompactTarget, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        let bits = u32::from_str_radix(&s, 16).map_err(serde::de::Error::custom)?;
+        Ok(bitcoin::CompactTarget::from_consensus(bits))
+    }
+}
+
+/// (De)serializes an optional amount in sats, also accepting amounts in BTC
+/// as a float or string. Amounts in BTC are reported through the warning
+/// handler, see [`crate::warnings::set_warning_handler`], and must be a
+/// whole number of sats.
+mod sats_or_btc_float {
+    use bitcoin::{Denomination, SignedAmount};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S>(sats: &Option<i64>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        sats.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<i64>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Sats(i64),
+            Btc(f64),
+            BtcString(String),
+        }
+        let amount = match Option::<Repr>::deserialize(deserializer)? {
+            None => return Ok(None),
+            Some(Repr::Sats(sats)) => return Ok(Some(sats)),
+            Some(Repr::Btc(btc)) => {
+                SignedAmount::from_btc(btc).map_err(|err| (btc.to_string(), err))
+            }
+            Some(Repr::BtcString(btc)) => {
+                SignedAmount::from_str_in(&btc, Denomination::Bitcoin).map_err(|err| (btc, err))
+            }
+        };
+        match amount {
+            Ok(amount) => {
+                crate::warnings::warn(&format!(
+                    "amount {} was reported in BTC instead of sats",
+                    amount.display_in(Denomination::Bitcoin)
+                ));
+                Ok(Some(amount.to_sat()))
+            }
+            Err((btc, err)) => Err(serde::de::Error::custom(format!(
+                "{btc} BTC is not a valid amount: {err}"
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct BlockchainInfo {
+    pub chain: String,
+    pub blocks: usize,
+    pub headers: usize,
+    pub bestblockhash: bitcoin::BlockHash,
+    pub difficulty: Difficulty,
+    pub time: Option<u32>,
+    pub mediantime: u32,
+    pub verificationprogress: f64,
+    pub initialblockdownload: bool,
+    pub chainwork: bitcoin::Work,
+    pub size_on_disk: u64,
+    pub pruned: bool,
+    pub pruneheight: Option<usize>,
+    pub automatic_pruning: Option<bool>,
+    pub prune_target_size: Option<u64>,
+    /// Only reported on signet.
+    pub signet_challenge: Option<bitcoin::ScriptBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct ChainState {
+    pub blocks: usize,
+    pub bestblockhash: bitcoin::BlockHash,
+    pub difficulty: Difficulty,
+    pub verificationprogress: f64,
+    /// Set if the chainstate was loaded from an assumeutxo snapshot.
+    pub snapshot_blockhash: Option<bitcoin::BlockHash>,
+    pub coins_db_cache_bytes: u64,
+    pub coins_tip_cache_bytes: u64,
+    /// `false` while a snapshot chainstate is still being validated in the
+    /// background.
+    pub validated: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct ChainStates {
+    pub headers: usize,
+    /// The fully validated chainstate first, followed by the snapshot
+    /// chainstate if one is loaded.
+    pub chainstates: Vec<ChainState>,
+}
+
+impl ChainStates {
+    /// Height of the chainstate loaded from an assumeutxo snapshot, if any.
+    pub fn snapshot_height(&self) -> Option<usize> {
+        self.chainstates
+            .iter()
+            .find(|chainstate| chainstate.snapshot_blockhash.is_some())
+            .map(|chainstate| chainstate.blocks)
+    }
+
+    /// Height up to which the chain has been fully validated.
+    pub fn validated_height(&self) -> Option<usize> {
+        self.chainstates
+            .iter()
+            .filter(|chainstate| chainstate.validated)
+            .map(|chainstate| chainstate.blocks)
+            .max()
+    }
+
+    pub fn is_fully_validated(&self) -> bool {
+        self.chainstates
+            .iter()
+            .all(|chainstate| chainstate.validated)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct IndexInfo {
+    pub synced: bool,
+    pub best_block_height: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct AddressInfo {
+    pub address: bitcoin::Address<bitcoin::address::NetworkUnchecked>,
+    #[serde(rename = "scriptPubKey")]
+    pub script_pub_key: bitcoin::ScriptBuf,
+    pub ismine: bool,
+    pub iswatchonly: bool,
+    pub solvable: bool,
+    pub desc: Option<String>,
+    /// Descriptor this address was derived from, for descriptor wallets.
+    pub parent_desc: Option<String>,
+    pub isscript: bool,
+    pub ischange: bool,
+    pub iswitness: bool,
+    pub witness_version: Option<u8>,
+    pub witness_program: Option<String>,
+    pub pubkey: Option<String>,
+    pub iscompressed: Option<bool>,
+    pub timestamp: Option<u64>,
+    /// Not reported for non-HD wallets and imported addresses.
+    pub hdkeypath: Option<String>,
+    /// Not reported for descriptor wallets.
+    pub hdseedid: Option<String>,
+    pub hdmasterfingerprint: Option<String>,
+    #[serde(default)]
+    pub labels: Vec<String>,
+}
+
+impl AddressInfo {
+    pub fn script_pub_key_hex(&self) -> String {
+        self.script_pub_key.to_hex_string()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct BalanceDetails {
+    pub trusted: AmountBtc,
+    pub untrusted_pending: AmountBtc,
+    pub immature: AmountBtc,
+    /// Only reported if the wallet has `avoid_reuse` set.
+    pub used: Option<AmountBtc>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct Balances {
+    pub mine: BalanceDetails,
+    /// Only reported if the wallet has watch-only addresses.
+    pub watchonly: Option<BalanceDetails>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EstimateMode {
+    Unset,
+    Economical,
+    Conservative,
+}
+
+string_enum!(EstimateMode {
+    Unset => "unset",
+    Economical => "economical",
+    Conservative => "conservative",
+});
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct ScriptSig {
+    pub asm: String,
+    #[serde(with = "hex::serde")]
+    pub hex: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct RawTransactionInput {
+    /// Set instead of `txid` and `vout` for coinbase inputs.
+    pub coinbase: Option<String>,
+    pub txid: Option<bitcoin::Txid>,
+    pub vout: Option<u32>,
+    #[serde(rename = "scriptSig")]
+    pub script_sig: Option<ScriptSig>,
+    pub txinwitness: Option<Vec<String>>,
+    pub sequence: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct RawTransactionScriptPubKey {
+    pub asm: String,
+    /// Not reported by old nodes.
+    pub desc: Option<String>,
+    pub hex: bitcoin::ScriptBuf,
+    pub address: Option<bitcoin::Address<bitcoin::address::NetworkUnchecked>>,
+    #[serde(rename = "type")]
+    pub script_type: String,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct RawTransactionOutput {
+    pub value: AmountBtc,
+    pub n: u32,
+    #[serde(rename = "scriptPubKey")]
+    pub script_pub_key: RawTransactionScriptPubKey,
+}
+
+/// Transaction as decoded by the node.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct RawTransactionInfo {
+    pub txid: bitcoin::Txid,
+    pub hash: bitcoin::Wtxid,
+    pub version: i32,
+    pub size: u64,
+    pub vsize: u64,
+    pub weight: u64,
+    pub locktime: u32,
+    pub vin: Vec<RawTransactionInput>,
+    pub vout: Vec<RawTransactionOutput>,
+}
+
+/// Transaction as returned by `getrawtransaction` with `verbose` set.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct RawTransactionVerbose {
+    /// Only reported if a block hash was given.
+    pub in_active_chain: Option<bool>,
+    pub hex: String,
+    pub txid: bitcoin::Txid,
+    pub hash: bitcoin::Wtxid,
+    pub version: i32,
+    pub size: u64,
+    pub vsize: u64,
+    pub weight: u64,
+    pub locktime: u32,
+    pub vin: Vec<RawTransactionInput>,
+    pub vout: Vec<RawTransactionOutput>,
+    /// The following are only reported for confirmed transactions.
+    pub blockhash: Option<bitcoin::BlockHash>,
+    pub confirmations: Option<u32>,
+    pub time: Option<u64>,
+    pub blocktime: Option<u64>,
+}
+
+/// Script as decoded by `decodescript`. `segwit` holds the decoding of the
+/// script wrapped in a segwit output, which has the same shape.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct DecodedScript {
+    pub asm: String,
+    /// Only set on the nested `segwit` script.
+    pub hex: Option<bitcoin::ScriptBuf>,
+    /// Not reported by old nodes.
+    pub desc: Option<String>,
+    #[serde(rename = "type")]
+    pub script_type: String,
+    pub address: Option<bitcoin::Address<bitcoin::address::NetworkUnchecked>>,
+    /// Address of the script wrapped in P2SH, if it can be.
+    pub p2sh: Option<bitcoin::Address<bitcoin::address::NetworkUnchecked>>,
+    pub segwit: Option<Box<DecodedScript>>,
+    /// Address of the segwit script wrapped in P2SH.
+    #[serde(rename = "p2sh-segwit")]
+    pub p2sh_segwit: Option<bitcoin::Address<bitcoin::address::NetworkUnchecked>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WalletTxCategory {
+    Send,
+    Receive,
+    /// Mature coinbase output.
+    Generate,
+    Immature,
+    /// Coinbase output of a block that is not in the main chain.
+    Orphan,
+}
+
+string_enum!(WalletTxCategory {
+    Send => "send",
+    Receive => "receive",
+    Generate => "generate",
+    Immature => "immature",
+    Orphan => "orphan",
+});
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct ReceivedByAddress {
+    #[serde(rename = "involvesWatchonly")]
+    pub involves_watchonly: Option<bool>,
+    pub address: bitcoin::Address<bitcoin::address::NetworkUnchecked>,
+    /// Total received by the address.
+    pub amount: AmountBtc,
+    /// Confirmations of the most recent transaction.
+    pub confirmations: u32,
+    pub label: String,
+    pub txids: Vec<bitcoin::Txid>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct ReceivedByLabel {
+    #[serde(rename = "involvesWatchonly")]
+    pub involves_watchonly: Option<bool>,
+    /// Total received by the addresses with the label.
+    pub amount: AmountBtc,
+    /// Confirmations of the most recent transaction.
+    pub confirmations: u32,
+    pub label: String,
+}
+
+/// Wallet transaction entry, as listed by `listsinceblock`.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct WalletTx {
+    #[serde(rename = "involvesWatchonly")]
+    pub involves_watchonly: Option<bool>,
+    pub address: Option<bitcoin::Address<bitcoin::address::NetworkUnchecked>>,
+    pub category: WalletTxCategory,
+    /// Negative for sends.
+    #[serde(with = "bitcoin::amount::serde::as_btc")]
+    pub amount: bitcoin::SignedAmount,
+    pub label: Option<String>,
+    pub vout: u32,
+    /// Negative, only set for sends.
+    #[serde(default, with = "bitcoin::amount::serde::as_btc::opt")]
+    pub fee: Option<bitcoin::SignedAmount>,
+    /// Negative if the transaction conflicts with one in the main chain.
+    pub confirmations: i64,
+    pub generated: Option<bool>,
+    pub trusted: Option<bool>,
+    pub blockhash: Option<bitcoin::BlockHash>,
+    pub blockheight: Option<u32>,
+    pub blockindex: Option<u32>,
+    pub blocktime: Option<u64>,
+    pub txid: bitcoin::Txid,
+    /// Not reported by old nodes.
+    pub wtxid: Option<bitcoin::Wtxid>,
+    pub walletconflicts: Vec<bitcoin::Txid>,
+    /// Not reported by old nodes.
+    pub mempoolconflicts: Option<Vec<bitcoin::Txid>>,
+    pub replaced_by_txid: Option<bitcoin::Txid>,
+    pub replaces_txid: Option<bitcoin::Txid>,
+    pub comment: Option<String>,
+    pub to: Option<String>,
+    pub time: u64,
+    pub timereceived: u64,
+    #[serde(rename = "bip125-replaceable")]
+    pub bip125_replaceable: String,
+    pub parent_descs: Option<Vec<String>>,
+    /// Only set for sends.
+    pub abandoned: Option<bool>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct ListSinceBlock {
+    pub transactions: Vec<WalletTx>,
+    /// Transactions that were in blocks disconnected since the given block.
+    /// Only set if requested.
+    #[serde(default)]
+    pub removed: Vec<WalletTx>,
+    pub lastblock: bitcoin::BlockHash,
+}
+
+/// Output of a wallet transaction, as listed in [`WalletTransaction`].
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct WalletTransactionDetail {
+    #[serde(rename = "involvesWatchonly")]
+    pub involves_watchonly: Option<bool>,
+    pub address: Option<bitcoin::Address<bitcoin::address::NetworkUnchecked>>,
+    pub category: WalletTxCategory,
+    /// Negative for sends.
+    #[serde(with = "bitcoin::amount::serde::as_btc")]
+    pub amount: bitcoin::SignedAmount,
+    pub label: Option<String>,
+    pub vout: u32,
+    /// Negative, only set for sends.
+    #[serde(default, with = "bitcoin::amount::serde::as_btc::opt")]
+    pub fee: Option<bitcoin::SignedAmount>,
+    pub parent_descs: Option<Vec<String>>,
+    /// Only set for sends.
+    pub abandoned: Option<bool>,
+}
+
+/// Transaction as returned by `gettransaction`.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct WalletTransaction {
+    /// Net amount for the wallet, negative if it lost funds.
+    #[serde(with = "bitcoin::amount::serde::as_btc")]
+    pub amount: bitcoin::SignedAmount,
+    /// Negative, only set for sends.
+    #[serde(default, with = "bitcoin::amount::serde::as_btc::opt")]
+    pub fee: Option<bitcoin::SignedAmount>,
+    /// Negative if the transaction conflicts with one in the main chain.
+    pub confirmations: i64,
+    pub generated: Option<bool>,
+    pub trusted: Option<bool>,
+    pub blockhash: Option<bitcoin::BlockHash>,
+    pub blockheight: Option<u32>,
+    pub blockindex: Option<u32>,
+    pub blocktime: Option<u64>,
+    pub txid: bitcoin::Txid,
+    /// Not reported by old nodes.
+    pub wtxid: Option<bitcoin::Wtxid>,
+    /// Wallet transactions that spend an input of this one.
+    pub walletconflicts: Vec<bitcoin::Txid>,
+    /// Not reported by old nodes.
+    pub mempoolconflicts: Option<Vec<bitcoin::Txid>>,
+    pub replaced_by_txid: Option<bitcoin::Txid>,
+    pub replaces_txid: Option<bitcoin::Txid>,
+    pub comment: Option<String>,
+    pub to: Option<String>,
+    pub time: u64,
+    pub timereceived: u64,
+    /// `"yes"`, `"no"` or `"unknown"`.
+    #[serde(rename = "bip125-replaceable")]
+    pub bip125_replaceable: String,
+    pub parent_descs: Option<Vec<String>>,
+    pub details: Vec<WalletTransactionDetail>,
+    pub hex: String,
+    /// Only set if `verbose` was set.
+    pub decoded: Option<serde_json::Value>,
+    /// Not reported by old nodes.
+    pub lastprocessedblock: Option<BlockRef>,
+}
+
+impl WalletTransaction {
+    /// Whether the transaction can be replaced by fee, if the wallet knows.
+    pub fn is_replaceable(&self) -> Option<bool> {
+        match self.bip125_replaceable.as_str() {
+            "yes" => Some(true),
+            "no" => Some(false),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScanAction {
+    Start,
+    Abort,
+    Status,
+}
+
+string_enum!(ScanAction {
+    Start => "start",
+    Abort => "abort",
+    Status => "status",
+});
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(untagged)]
+pub enum ScanObject {
+    Descriptor(String),
+    /// A ranged descriptor, scanned over `range` (inclusive).
+    Ranged {
+        desc: String,
+        range: (u32, u32),
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct ScanUnspent {
+    pub txid: bitcoin::Txid,
+    pub vout: u32,
+    #[serde(rename = "scriptPubKey")]
+    pub script_pub_key: bitcoin::ScriptBuf,
+    pub desc: String,
+    pub amount: AmountBtc,
+    /// Not reported by old nodes.
+    pub coinbase: Option<bool>,
+    pub height: u32,
+    /// Not reported by old nodes.
+    pub blockhash: Option<bitcoin::BlockHash>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct ScanTxOutSetResult {
+    pub success: bool,
+    pub txouts: u64,
+    pub height: u32,
+    pub bestblock: bitcoin::BlockHash,
+    pub unspents: Vec<ScanUnspent>,
+    pub total_amount: AmountBtc,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct ScanStatus {
+    /// Percentage of the UTXO set scanned so far.
+    pub progress: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AddressType {
+    Legacy,
+    P2shSegwit,
+    Bech32,
+    Bech32m,
+}
+
+string_enum!(AddressType {
+    Legacy => "legacy",
+    P2shSegwit => "p2sh-segwit",
+    Bech32 => "bech32",
+    Bech32m => "bech32m",
+});
+
+/// Whether a labeled address is one of the wallet's, or one it sent to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LabelPurpose {
+    Send,
+    Receive,
+}
+
+string_enum!(LabelPurpose {
+    Send => "send",
+    Receive => "receive",
+});
+
+/// Entry of `getaddressesbylabel`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct LabelAddress {
+    pub purpose: LabelPurpose,
+}
+
+/// Optional named parameters of `sendtoaddress`. Fields that are `None` are
+/// left out of the request, so the node's defaults apply.
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize)]
+pub struct SendToAddressOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment_to: Option<String>,
+    #[serde(
+        rename = "subtractfeefromamount",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub subtract_fee_from_amount: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub replaceable: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub conf_target: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub estimate_mode: Option<EstimateMode>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub avoid_reuse: Option<bool>,
+    /// Fee rate in sat/vB.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fee_rate: Option<f64>,
+}
+
+/// Options of `bumpfee`. Fields that are `None` are left out, so the
+/// node's defaults apply. `conf_target` and `fee_rate` are exclusive.
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize)]
+pub struct BumpFeeOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub conf_target: Option<u32>,
+    /// Fee rate in sat/vB.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fee_rate: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub replaceable: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub estimate_mode: Option<EstimateMode>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct BumpFee {
+    /// The replacement transaction.
+    pub txid: bitcoin::Txid,
+    pub origfee: AmountBtc,
+    pub fee: AmountBtc,
+    #[serde(default)]
+    pub errors: Vec<String>,
+}
+
+/// Options of `fundrawtransaction` and `walletcreatefundedpsbt`. Fields
+/// that are `None` are left out, so the node's defaults apply.
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize)]
+pub struct FundRawTransactionOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub add_inputs: Option<bool>,
+    #[serde(rename = "changePosition", skip_serializing_if = "Option::is_none")]
+    pub change_position: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub replaceable: Option<bool>,
+    /// Fee rate in sat/vB.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fee_rate: Option<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct FundRawTransaction {
+    /// The funded, unsigned transaction.
+    pub hex: String,
+    pub fee: AmountBtc,
+    /// Position of the added change output, -1 if there is none.
+    pub changepos: i32,
+}
+
+/// Input of `walletcreatefundedpsbt`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub struct PsbtInput {
+    pub txid: bitcoin::Txid,
+    pub vout: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sequence: Option<u32>,
+}
+
+/// Output of `walletcreatefundedpsbt`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PsbtOutput {
+    Address {
+        address: bitcoin::Address<bitcoin::address::NetworkUnchecked>,
+        amount: AmountBtc,
+    },
+    /// An `OP_RETURN` output carrying `data`.
+    Data(Vec<u8>),
+}
+
+impl serde::Serialize for PsbtOutput {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        use serde::ser::SerializeMap;
+        let mut map = serializer.serialize_map(Some(1))?;
+        match self {
+            PsbtOutput::Address { address, amount } => map.serialize_entry(address, amount)?,
+            PsbtOutput::Data(data) => map.serialize_entry("data", &hex::encode(data))?,
+        }
+        map.end()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct WalletCreateFundedPsbt {
+    #[serde(with = "psbt_base64")]
+    pub psbt: bitcoin::psbt::PartiallySignedTransaction,
+    pub fee: AmountBtc,
+    /// Position of the added change output, -1 if there is none.
+    pub changepos: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct WalletProcessPsbt {
+    #[serde(with = "psbt_base64")]
+    pub psbt: bitcoin::psbt::PartiallySignedTransaction,
+    pub complete: bool,
+    /// The final transaction, if `complete`. Not reported by old nodes.
+    pub hex: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct FinalizePsbt {
+    /// Only set if the transaction was not extracted.
+    #[serde(default, with = "psbt_base64::opt")]
+    pub psbt: Option<bitcoin::psbt::PartiallySignedTransaction>,
+    /// The final transaction, if it was extracted.
+    pub hex: Option<String>,
+    pub complete: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct SignRawTransactionError {
+    pub txid: bitcoin::Txid,
+    pub vout: u32,
+    #[serde(rename = "scriptSig")]
+    pub script_sig: String,
+    pub sequence: u32,
+    pub witness: Option<Vec<String>>,
+    pub error: String,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct SignRawTransaction {
+    pub hex: String,
+    pub complete: bool,
+    #[serde(default)]
+    pub errors: Vec<SignRawTransactionError>,
+}
+
+rpc_traits! {
+    /// RPCs available on any Bitcoin Core compatible node.
+    #[cfg(feature = "client")]
+    #[rpc(client)]
+    pub trait Chain {
+        #[method(name = "stop")]
+        async fn stop(&self) -> Result<String, jsonrpsee::core::Error>;
+
+        #[method(name = "getblockcount")]
+        async fn getblockcount(&self) -> Result<usize, jsonrpsee::core::Error>;
+
+        #[method(name = "getbestblockhash")]
+        async fn getbestblockhash(&self) -> Result<bitcoin::BlockHash, jsonrpsee::core::Error>;
+
+        #[method(name = "getblock")]
+        async fn getblock(
+            &self,
+            blockhash: &bitcoin::BlockHash,
+            verbosity: Option<usize>,
+        ) -> Result<Block, jsonrpsee::core::Error>;
+
+        /// `verbosity` must be `0`, use `getblock` for the decoded block.
+        #[method(name = "getblock")]
+        async fn getblockhex(
+            &self,
+            blockhash: &bitcoin::BlockHash,
+            verbosity: usize,
+        ) -> Result<ConsensusEncodedLazy<bitcoin::Block>, jsonrpsee::core::Error>;
+
+        #[method(name = "getblockhash")]
+        async fn getblockhash(
+            &self,
+            height: usize,
+        ) -> Result<bitcoin::BlockHash, jsonrpsee::core::Error>;
+
+        #[method(name = "getblockheader")]
+        async fn getblockheader(
+            &self,
+            blockhash: &bitcoin::BlockHash,
+        ) -> Result<Header, jsonrpsee::core::Error>;
+
+        #[method(name = "getmempoolentry")]
+        async fn getmempoolentry(
+            &self,
+            txid: &bitcoin::Txid,
+        ) -> Result<MempoolEntry, jsonrpsee::core::Error>;
+
+        #[method(name = "getmempoolinfo")]
+        async fn getmempoolinfo(&self) -> Result<MempoolInfo, jsonrpsee::core::Error>;
+
+        #[method(name = "getrawmempool")]
+        async fn getrawmempool(&self) -> Result<Vec<bitcoin::Txid>, jsonrpsee::core::Error>;
+
+        /// `verbose` must be `false` and `mempool_sequence` must be `true`.
+        #[method(name = "getrawmempool")]
+        async fn getrawmempoolwithsequence(
+            &self,
+            verbose: bool,
+            mempool_sequence: bool,
+        ) -> Result<RawMempoolWithSequence, jsonrpsee::core::Error>;
+
+        /// `verbose` must be `true`, use `getrawmempool` for the list of txids.
+        #[method(name = "getrawmempool")]
+        async fn getrawmempoolverbose(
+            &self,
+            verbose: bool,
+        ) -> Result<RawMempoolVerbose, jsonrpsee::core::Error>;
+
+        /// Adds `fee_delta` sats to the fee the node uses to select the
+        /// transaction, whether or not it is in the mempool yet. Deltas of the
+        /// same transaction accumulate. `dummy` must be `None`.
+        #[method(name = "prioritisetransaction")]
+        async fn prioritisetransaction(
+            &self,
+            txid: &bitcoin::Txid,
+            dummy: Option<f64>,
+            fee_delta: i64,
+        ) -> Result<bool, jsonrpsee::core::Error>;
+
+        /// Fee deltas applied with `prioritisetransaction`, in the order they
+        /// were added. Not available before Bitcoin Core 26.
+        #[method(name = "getprioritisedtransactions")]
+        async fn getprioritisedtransactions(
+            &self,
+        ) -> Result<LinkedHashMap<bitcoin::Txid, PrioritisedTransaction>, jsonrpsee::core::Error>;
+
+        /// Returns the raw transaction hex. Requires `-txindex` unless the block
+        /// containing the transaction is given.
+        #[method(name = "getrawtransaction")]
+        async fn getrawtransaction(
+            &self,
+            txid: &bitcoin::Txid,
+            verbose: bool,
+            blockhash: Option<&bitcoin::BlockHash>,
+        ) -> Result<String, jsonrpsee::core::Error>;
+
+        /// `verbose` must be `true`, use `getrawtransaction` for the hex.
+        #[method(name = "getrawtransaction")]
+        async fn getrawtransactionverbose(
+            &self,
+            txid: &bitcoin::Txid,
+            verbose: bool,
+            blockhash: Option<&bitcoin::BlockHash>,
+        ) -> Result<RawTransactionVerbose, jsonrpsee::core::Error>;
+
+        /// A timeout of 0 waits forever.
+        #[method(name = "waitfornewblock")]
+        async fn waitfornewblock(&self, timeout: u64) -> Result<BlockRef, jsonrpsee::core::Error>;
+
+        /// A timeout of 0 waits forever.
+        #[method(name = "waitforblockheight")]
+        async fn waitforblockheight(
+            &self,
+            height: usize,
+            timeout: u64,
+        ) -> Result<BlockRef, jsonrpsee::core::Error>;
+
+        /// Deployment state at the given block, or at the tip if omitted.
+        #[method(name = "getdeploymentinfo")]
+        async fn getdeploymentinfo(
+            &self,
+            blockhash: Option<&bitcoin::BlockHash>,
+        ) -> Result<DeploymentInfo, jsonrpsee::core::Error>;
+
+        #[method(name = "getblocktemplate")]
+        async fn getblocktemplate(
+            &self,
+            template_request: &BlockTemplateRequest,
+        ) -> Result<BlockTemplate, jsonrpsee::core::Error>;
+
+        /// Returns `None` if the proposed block is valid, or the reason it was
+        /// rejected. The request must be in proposal mode.
+        #[method(name = "getblocktemplate")]
+        async fn getblocktemplateproposal(
+            &self,
+            template_request: &BlockTemplateRequest,
+        ) -> Result<Option<String>, jsonrpsee::core::Error>;
+
+        /// Returns `None` if the block was accepted, or the reason it was
+        /// rejected, e.g. `duplicate` or `high-hash`.
+        #[method(name = "submitblock")]
+        async fn submitblock(&self, hexdata: &str) -> Result<Option<String>, jsonrpsee::core::Error>;
+
+        /// Fails with `RPC_VERIFY_ERROR` if the header is invalid or its parent
+        /// is unknown.
+        #[method(name = "submitheader")]
+        async fn submitheader(&self, hexdata: &str) -> Result<(), jsonrpsee::core::Error>;
+
+        #[method(name = "decoderawtransaction")]
+        async fn decoderawtransaction(
+            &self,
+            hexstring: &str,
+            iswitness: Option<bool>,
+        ) -> Result<RawTransactionInfo, jsonrpsee::core::Error>;
+
+        #[method(name = "decodescript")]
+        async fn decodescript(&self, hexstring: &str) -> Result<DecodedScript, jsonrpsee::core::Error>;
+
+        #[method(name = "getblockfilter")]
+        async fn getblockfilter(
+            &self,
+            blockhash: &bitcoin::BlockHash,
+            filtertype: Option<&str>,
+        ) -> Result<BlockFilter, jsonrpsee::core::Error>;
+
+        #[method(name = "getblockchaininfo")]
+        async fn getblockchaininfo(
+            &self,
+        ) -> Result<crate::warnings::WithWarnings<BlockchainInfo>, jsonrpsee::core::Error>;
+
+        /// Returns the hex of a merkle proof that `txids` are in a block.
+        /// Requires `-txindex` unless the block is given.
+        #[method(name = "gettxoutproof")]
+        async fn gettxoutproof(
+            &self,
+            txids: &[bitcoin::Txid],
+            blockhash: Option<&bitcoin::BlockHash>,
+        ) -> Result<String, jsonrpsee::core::Error>;
+
+        #[method(name = "getnetworkinfo")]
+        async fn getnetworkinfo(
+            &self,
+        ) -> Result<crate::warnings::WithWarnings<NetworkInfo>, jsonrpsee::core::Error>;
+
+        #[method(name = "getnettotals")]
+        async fn getnettotals(&self) -> Result<NetTotals, jsonrpsee::core::Error>;
+
+        /// Only available on nodes with assumeutxo support.
+        #[method(name = "getchainstates")]
+        async fn getchainstates(&self) -> Result<ChainStates, jsonrpsee::core::Error>;
+
+        /// Status of the node's optional indexes, keyed by index name, e.g.
+        /// `txindex`. Indexes that are not enabled are omitted.
+        #[method(name = "getindexinfo")]
+        async fn getindexinfo(
+            &self,
+            index_name: Option<&str>,
+        ) -> Result<LinkedHashMap<String, IndexInfo>, jsonrpsee::core::Error>;
+
+        /// `action` must be `Start`. Returns once the scan is done, which can
+        /// take minutes.
+        #[method(name = "scantxoutset")]
+        async fn scantxoutsetstart(
+            &self,
+            action: ScanAction,
+            scanobjects: &[ScanObject],
+        ) -> Result<ScanTxOutSetResult, jsonrpsee::core::Error>;
+
+        /// `action` must be `Status`. Returns `None` if no scan is in progress.
+        #[method(name = "scantxoutset")]
+        async fn scantxoutsetstatus(
+            &self,
+            action: ScanAction,
+        ) -> Result<Option<ScanStatus>, jsonrpsee::core::Error>;
+
+        /// `action` must be `Abort`. Returns `false` if no scan was in progress.
+        #[method(name = "scantxoutset")]
+        async fn scantxoutsetabort(&self, action: ScanAction) -> Result<bool, jsonrpsee::core::Error>;
+
+        /// `signature` is base64 encoded, as returned by `signmessage`.
+        #[method(name = "verifymessage")]
+        async fn verifymessage(
+            &self,
+            address: &bitcoin::Address<bitcoin::address::NetworkUnchecked>,
+            signature: &str,
+            message: &str,
+        ) -> Result<bool, jsonrpsee::core::Error>;
+
+        #[method(name = "verifychain")]
+        async fn verifychain(
+            &self,
+            checklevel: Option<u8>,
+            nblocks: Option<u32>,
+        ) -> Result<bool, jsonrpsee::core::Error>;
+
+        #[method(name = "preciousblock")]
+        async fn preciousblock(
+            &self,
+            blockhash: &bitcoin::BlockHash,
+        ) -> Result<(), jsonrpsee::core::Error>;
+
+        #[method(name = "invalidateblock")]
+        async fn invalidateblock(
+            &self,
+            blockhash: &bitcoin::BlockHash,
+        ) -> Result<(), jsonrpsee::core::Error>;
+
+        #[method(name = "reconsiderblock")]
+        async fn reconsiderblock(
+            &self,
+            blockhash: &bitcoin::BlockHash,
+        ) -> Result<(), jsonrpsee::core::Error>;
+
+        #[method(name = "generatetoaddress")]
+        async fn generatetoaddress(
+            &self,
+            nblocks: u32,
+            address: &bitcoin::Address<bitcoin::address::NetworkUnchecked>,
+        ) -> Result<Vec<bitcoin::BlockHash>, jsonrpsee::core::Error>;
+
+        /// Mines to the address derived from `descriptor`, which must not be
+        /// ranged, e.g. `addr(...)` or `wpkh(<pubkey>)`.
+        #[method(name = "generatetodescriptor")]
+        async fn generatetodescriptor(
+            &self,
+            num_blocks: u32,
+            descriptor: &str,
+        ) -> Result<Vec<bitcoin::BlockHash>, jsonrpsee::core::Error>;
+
+        #[method(name = "sendrawtransaction")]
+        async fn sendrawtransaction(
+            &self,
+            hexstring: &str,
+        ) -> Result<bitcoin::Txid, jsonrpsee::core::Error>;
+
+        #[method(name = "generateblock")]
+        async fn generateblock(
+            &self,
+            // Address or descriptor to send the coinbase output to.
+            output: &str,
+            transactions: &[GenerateBlockTx],
+            submit: Option<bool>,
+        ) -> Result<GenerateBlock, jsonrpsee::core::Error>;
+
+        /// Only available on nodes with the deprecated wallet `generate` RPC,
+        /// prefer `generatetoaddress`.
+        #[method(name = "generate")]
+        async fn generate(&self, num: u32) -> Result<Vec<bitcoin::BlockHash>, jsonrpsee::core::Error>;
+    }
+
+    /// Wallet RPCs, only available if the node has a wallet loaded.
+    #[cfg(feature = "client")]
+    #[rpc(client)]
+    pub trait Wallet {
+        /// `None` is sent as `null`, [`crate::Drivechain::get_new_address`]
+        /// leaves out trailing `None`s instead.
+        #[method(name = "getnewaddress")]
+        async fn getnewaddress(
+            &self,
+            label: Option<&str>,
+            address_type: Option<AddressType>,
+        ) -> Result<bitcoin::Address<bitcoin::address::NetworkUnchecked>, jsonrpsee::core::Error>;
+
+        /// `None` is sent as `null`, [`crate::Drivechain::get_raw_change_address`]
+        /// leaves out trailing `None`s instead.
+        #[method(name = "getrawchangeaddress")]
+        async fn getrawchangeaddress(
+            &self,
+            address_type: Option<AddressType>,
+        ) -> Result<bitcoin::Address<bitcoin::address::NetworkUnchecked>, jsonrpsee::core::Error>;
+
+        #[method(name = "getbalance")]
+        async fn getbalance(
+            &self,
+            dummy: Option<&str>,
+            minconf: Option<u32>,
+            include_watchonly: Option<bool>,
+        ) -> Result<AmountBtc, jsonrpsee::core::Error>;
+
+        #[method(name = "listsinceblock")]
+        async fn listsinceblock(
+            &self,
+            blockhash: Option<&bitcoin::BlockHash>,
+            target_confirmations: Option<u32>,
+            include_watchonly: Option<bool>,
+            include_removed: Option<bool>,
+        ) -> Result<ListSinceBlock, jsonrpsee::core::Error>;
+
+        #[method(name = "gettransaction")]
+        async fn gettransaction(
+            &self,
+            txid: &bitcoin::Txid,
+            include_watchonly: bool,
+            verbose: bool,
+        ) -> Result<WalletTransaction, jsonrpsee::core::Error>;
+
+        #[method(name = "getbalances")]
+        async fn getbalances(&self) -> Result<Balances, jsonrpsee::core::Error>;
+
+        #[method(name = "walletpassphrase")]
+        async fn walletpassphrase(
+            &self,
+            passphrase: &str,
+            timeout: u64,
+        ) -> Result<(), jsonrpsee::core::Error>;
+
+        #[method(name = "walletlock")]
+        async fn walletlock(&self) -> Result<(), jsonrpsee::core::Error>;
+
+        #[method(name = "walletpassphrasechange")]
+        async fn walletpassphrasechange(
+            &self,
+            oldpassphrase: &str,
+            newpassphrase: &str,
+        ) -> Result<(), jsonrpsee::core::Error>;
+
+        /// Fills the keypool up to `newsize` keys, or the configured size. The
+        /// wallet must be unlocked if it is encrypted.
+        #[method(name = "keypoolrefill")]
+        async fn keypoolrefill(&self, newsize: Option<u32>) -> Result<(), jsonrpsee::core::Error>;
+
+        /// Signs with the key of `address`, which must be a legacy P2PKH
+        /// address. The signature is base64 encoded.
+        #[method(name = "signmessage")]
+        async fn signmessage(
+            &self,
+            address: &bitcoin::Address<bitcoin::address::NetworkUnchecked>,
+            message: &str,
+        ) -> Result<String, jsonrpsee::core::Error>;
+
+        #[method(name = "listreceivedbyaddress")]
+        async fn listreceivedbyaddress(
+            &self,
+            minconf: Option<u32>,
+            include_empty: Option<bool>,
+            include_watchonly: Option<bool>,
+            address_filter: Option<&bitcoin::Address<bitcoin::address::NetworkUnchecked>>,
+        ) -> Result<Vec<ReceivedByAddress>, jsonrpsee::core::Error>;
+
+        #[method(name = "listreceivedbylabel")]
+        async fn listreceivedbylabel(
+            &self,
+            minconf: Option<u32>,
+            include_empty: Option<bool>,
+            include_watchonly: Option<bool>,
+        ) -> Result<Vec<ReceivedByLabel>, jsonrpsee::core::Error>;
+
+        /// Sets the label of an address of the wallet, or of one it sent to.
+        #[method(name = "setlabel")]
+        async fn setlabel(
+            &self,
+            address: &bitcoin::Address<bitcoin::address::NetworkUnchecked>,
+            label: &str,
+        ) -> Result<(), jsonrpsee::core::Error>;
+
+        #[method(name = "listlabels")]
+        async fn listlabels(
+            &self,
+            purpose: Option<LabelPurpose>,
+        ) -> Result<Vec<String>, jsonrpsee::core::Error>;
+
+        /// Fails if no address has the label.
+        #[method(name = "getaddressesbylabel")]
+        async fn getaddressesbylabel(
+            &self,
+            label: &str,
+        ) -> Result<
+            LinkedHashMap<bitcoin::Address<NetworkUnchecked>, LabelAddress>,
+            jsonrpsee::core::Error,
+        >;
+
+        #[method(name = "getaddressinfo")]
+        async fn getaddressinfo(
+            &self,
+            address: &bitcoin::Address<bitcoin::address::NetworkUnchecked>,
+        ) -> Result<AddressInfo, jsonrpsee::core::Error>;
+
+        /// Adds a transaction to the wallet without rescanning, given a proof
+        /// from `gettxoutproof` that it is in a block.
+        #[method(name = "importprunedfunds")]
+        async fn importprunedfunds(
+            &self,
+            rawtransaction: &str,
+            txoutproof: &str,
+        ) -> Result<(), jsonrpsee::core::Error>;
+
+        #[method(name = "removeprunedfunds")]
+        async fn removeprunedfunds(&self, txid: &bitcoin::Txid) -> Result<(), jsonrpsee::core::Error>;
+
+        /// Marks an unconfirmed wallet transaction and its descendants as
+        /// abandoned, so that their inputs can be spent again.
+        #[method(name = "abandontransaction")]
+        async fn abandontransaction(&self, txid: &bitcoin::Txid) -> Result<(), jsonrpsee::core::Error>;
+
+        #[method(name = "bumpfee")]
+        async fn bumpfee(
+            &self,
+            txid: &bitcoin::Txid,
+            options: Option<&BumpFeeOptions>,
+        ) -> Result<BumpFee, jsonrpsee::core::Error>;
+
+        #[method(name = "fundrawtransaction")]
+        async fn fundrawtransaction(
+            &self,
+            hexstring: &str,
+            options: Option<&FundRawTransactionOptions>,
+        ) -> Result<FundRawTransaction, jsonrpsee::core::Error>;
+
+        #[method(name = "signrawtransactionwithwallet")]
+        async fn signrawtransactionwithwallet(
+            &self,
+            hexstring: &str,
+        ) -> Result<SignRawTransaction, jsonrpsee::core::Error>;
+
+        /// Sets the wallet fee rate in BTC/kvB, 0 to go back to estimating it.
+        #[method(name = "settxfee")]
+        async fn settxfee(&self, amount: AmountBtc) -> Result<bool, jsonrpsee::core::Error>;
+
+        #[method(name = "walletcreatefundedpsbt")]
+        async fn walletcreatefundedpsbt(
+            &self,
+            inputs: &[PsbtInput],
+            outputs: &[PsbtOutput],
+            locktime: Option<u32>,
+            options: Option<&FundRawTransactionOptions>,
+            bip32derivs: Option<bool>,
+        ) -> Result<WalletCreateFundedPsbt, jsonrpsee::core::Error>;
+
+        /// `psbt` is base64 encoded.
+        #[method(name = "walletprocesspsbt")]
+        async fn walletprocesspsbt(
+            &self,
+            psbt: &str,
+            sign: Option<bool>,
+            sighashtype: Option<&str>,
+            bip32derivs: Option<bool>,
+        ) -> Result<WalletProcessPsbt, jsonrpsee::core::Error>;
+
+        /// `psbt` is base64 encoded.
+        #[method(name = "finalizepsbt")]
+        async fn finalizepsbt(
+            &self,
+            psbt: &str,
+            extract: Option<bool>,
+        ) -> Result<FinalizePsbt, jsonrpsee::core::Error>;
+    }
+
+    /// Sidechain, BMM and withdrawal RPCs, only available on drivechain
+    /// enabled nodes.
+    #[cfg(feature = "client")]
+    #[rpc(client)]
+    pub trait Drivechain {
+        // FIXME: Define a "Deposit Address" type.
+        #[method(name = "listwithdrawalstatus")]
+        async fn listwithdrawalstatus(
+            &self,
+            nsidechain: SidechainId,
+        ) -> Result<Vec<WithdrawalStatus>, jsonrpsee::core::Error>;
+
+        #[method(name = "listspentwithdrawals")]
+        async fn listspentwithdrawals(&self) -> Result<Vec<SpentWithdrawal>, jsonrpsee::core::Error>;
+
+        #[method(name = "listfailedwithdrawals")]
+        async fn listfailedwithdrawals(&self) -> Result<Vec<FailedWithdrawal>, jsonrpsee::core::Error>;
+
+        #[method(name = "createbmmcriticaldatatx")]
+        async fn createbmmcriticaldatatx(
+            &self,
+            amount: AmountBtc,
+            height: u32,
+            criticalhash: &bitcoin::BlockHash,
+            nsidechain: SidechainId,
+            prevbytes: crate::bmm::PrevBytes,
+        ) -> Result<serde_json::Value, jsonrpsee::core::Error>;
+
+        #[method(name = "verifybmm")]
+        async fn verifybmm(
+            &self,
+            blockhash: &bitcoin::BlockHash,
+            criticalhash: &bitcoin::BlockHash,
+            nsidechain: SidechainId,
+        ) -> Result<serde_json::Value, jsonrpsee::core::Error>;
+
+        #[method(name = "listsidechaindepositsbyblock")]
+        async fn listsidechaindepositsbyblock(
+            &self,
+            nsidechain: SidechainId,
+            end_blockhash: Option<bitcoin::BlockHash>,
+            start_blockhash: Option<bitcoin::BlockHash>,
+        ) -> Result<Vec<Deposit>, jsonrpsee::core::Error>;
+
+        #[method(name = "receivewithdrawalbundle")]
+        async fn receivewithdrawalbundle(
+            &self,
+            nsidechain: SidechainId,
+            // Raw transaction hex.
+            rawtx: &str,
+        ) -> Result<serde_json::Value, jsonrpsee::core::Error>;
+
+        /// Returns the pending withdrawal bundle of a sidechain.
+        #[method(name = "getwithdrawalbundle")]
+        async fn getwithdrawalbundle(
+            &self,
+            nsidechain: SidechainId,
+        ) -> Result<Option<ConsensusEncoded<bitcoin::Transaction>>, jsonrpsee::core::Error>;
+
+        #[method(name = "createsidechaindeposit")]
+        async fn createsidechaindeposit(
+            &self,
+            nsidechain: SidechainId,
+            depositaddress: &str,
+            amount: AmountBtc,
+            fee: AmountBtc,
+        ) -> Result<serde_json::Value, jsonrpsee::core::Error>;
+
+        /// Fails if the sidechain has no CTIP yet, i.e. never received a
+        /// deposit.
+        #[method(name = "listsidechainctip")]
+        async fn listsidechainctip(
+            &self,
+            nsidechain: SidechainId,
+        ) -> Result<Ctip, jsonrpsee::core::Error>;
+    }
+
+    /// Node maintenance RPCs. These are not part of [`MainClient`] or the
+    /// prelude, as they affect the operation of the node rather than query or
+    /// extend the chain.
+    #[cfg(feature = "client")]
+    #[rpc(client)]
+    pub trait NodeAdmin {
+        /// Writes the mempool to disk, e.g. before a planned restart.
+        #[method(name = "savemempool")]
+        async fn savemempool(&self) -> Result<SaveMempool, jsonrpsee::core::Error>;
+
+        /// Loads an assumeutxo snapshot. This can take minutes, so the client
+        /// should be created with a matching request timeout, see
+        /// [`crate::new_client`].
+        #[method(name = "loadtxoutset")]
+        async fn loadtxoutset(&self, path: &str) -> Result<LoadTxOutSet, jsonrpsee::core::Error>;
+
+        #[method(name = "getrpcinfo")]
+        async fn getrpcinfo(&self) -> Result<RpcInfo, jsonrpsee::core::Error>;
+
+        #[method(name = "getmemoryinfo")]
+        async fn getmemoryinfo(
+            &self,
+            mode: MemoryStatsMode,
+        ) -> Result<MemoryInfo, jsonrpsee::core::Error>;
+
+        /// Returns the XML report unchanged.
+        #[method(name = "getmemoryinfo")]
+        async fn getmemoryinfomalloc(
+            &self,
+            mode: MallocInfoMode,
+        ) -> Result<String, jsonrpsee::core::Error>;
+
+        #[method(name = "getconnectioncount")]
+        async fn getconnectioncount(&self) -> Result<u64, jsonrpsee::core::Error>;
+
+        #[method(name = "getpeerinfo")]
+        async fn getpeerinfo(&self) -> Result<Vec<PeerInfo>, jsonrpsee::core::Error>;
+
+        #[method(name = "addnode")]
+        async fn addnode(
+            &self,
+            node: &str,
+            command: AddNodeCommand,
+        ) -> Result<(), jsonrpsee::core::Error>;
+
+        /// Disconnects the peer at `address`, or if it is empty, the peer with
+        /// id `nodeid`.
+        #[method(name = "disconnectnode")]
+        async fn disconnectnode(
+            &self,
+            address: &str,
+            nodeid: Option<u64>,
+        ) -> Result<(), jsonrpsee::core::Error>;
+
+        /// Only lists nodes added with `addnode`, all of them if `node` is not
+        /// set.
+        #[method(name = "getaddednodeinfo")]
+        async fn getaddednodeinfo(
+            &self,
+            node: Option<&str>,
+        ) -> Result<Vec<AddedNodeInfo>, jsonrpsee::core::Error>;
+
+        /// Enables or disables all P2P network activity. Returns the new state.
+        #[method(name = "setnetworkactive")]
+        async fn setnetworkactive(&self, state: bool) -> Result<bool, jsonrpsee::core::Error>;
+    }
+
+    /// RPCs of the BIP300/301 enforcer running alongside a node without
+    /// built-in drivechain support. These are not part of [`MainClient`].
+    #[cfg(feature = "client")]
+    #[rpc(client)]
+    pub trait Enforcer {
+        #[method(name = "getenforcementstatus")]
+        async fn getenforcementstatus(&self) -> Result<EnforcementStatus, jsonrpsee::core::Error>;
+    }
+}
+
+/// All RPCs of a drivechain enabled node with a wallet.
+///
+/// The methods are defined on [`ChainClient`], [`WalletClient`] and
+/// [`DrivechainClient`], which must be in scope to call them. Code that
+/// imported only `MainClient` should import the prelude instead:
+///
+/// ```no_run
+/// use bip300301::prelude::*;
+///
+/// # async fn example(client: bip300301::jsonrpsee::http_client::HttpClient) -> Result<(), Error> {
+/// let height = client.getblockcount().await?;
+/// let balances = client.getbalances().await?;
+/// # Ok(())
+/// # }
+/// ```
+#[cfg(feature = "client")]
+pub trait MainClient: ChainClient + WalletClient + DrivechainClient {}
+
+#[cfg(feature = "client")]
+impl<T> MainClient for T where T: ChainClient + WalletClient + DrivechainClient {}
+
+/// Value that is sent and received as hex of its consensus encoding.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConsensusEncoded<T>(pub T);
+
+impl<T> Deref for ConsensusEncoded<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<T> ConsensusEncoded<T>
+where
+    T: bitcoin::consensus::Encodable,
+{
+    pub fn encode_hex(&self) -> String {
+        bitcoin::consensus::encode::serialize_hex(&self.0)
+    }
+}
+
+impl<'de, T> serde::Deserialize<'de> for ConsensusEncoded<T>
+where
+    T: bitcoin::consensus::Decodable,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let value = deserializer.deserialize_str(HexVisitor(decode_hex::<T>))?;
+        Ok(ConsensusEncoded(value))
+    }
+}
+
+impl<T> serde::Serialize for ConsensusEncoded<T>
+where
+    T: bitcoin::consensus::Encodable,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.encode_hex())
+    }
+}
+
+/// Like [`ConsensusEncoded`], but only keeps the raw JSON string, as it was
+/// received, until [`ConsensusEncodedLazy::decode`] is called, e.g. to skip
+/// decoding blocks that turn out not to be needed.
+///
+/// Only `serde_json` can deserialize it.
+#[derive(Clone, Debug)]
+pub struct ConsensusEncodedLazy<T> {
+    /// A JSON string, including the quotes.
+    raw: Box<serde_json::value::RawValue>,
+    _marker: std::marker::PhantomData<fn() -> T>,
+}
+
+impl<T> ConsensusEncodedLazy<T>
+where
+    T: bitcoin::consensus::Decodable,
+{
+    pub fn decode(&self) -> Result<T, crate::Error> {
+        Ok(decode_hex(self.as_hex())?)
+    }
+}
+
+impl<T> ConsensusEncodedLazy<T> {
+    pub fn as_hex(&self) -> &str {
+        let raw = self.raw.get();
+        &raw[1..raw.len() - 1]
+    }
+}
+
+impl<T> PartialEq for ConsensusEncodedLazy<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.raw.get() == other.raw.get()
+    }
+}
+
+impl<T> Eq for ConsensusEncodedLazy<T> {}
+
+impl<'de, T> serde::Deserialize<'de> for ConsensusEncodedLazy<T> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let raw = Box::<serde_json::value::RawValue>::deserialize(deserializer)?;
+        if !raw.get().starts_with('"') {
+            return Err(serde::de::Error::invalid_type(
+                serde::de::Unexpected::Other("non-string JSON value"),
+                &"a hex string",
+            ));
+        }
+        Ok(ConsensusEncodedLazy {
+            raw,
+            _marker: std::marker::PhantomData,
+        })
+    }
+}
+
+impl<T> serde::Serialize for ConsensusEncodedLazy<T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(self.as_hex())
+    }
+}
+
+/// Decodes a hex string with the given function straight from the string,
+/// so that neither the hex nor the bytes are copied if the deserializer can
+/// lend the string.
+struct HexVisitor<T>(fn(&str) -> Result<T, bitcoin::consensus::encode::Error>);
+
+impl<'de, T> serde::de::Visitor<'de> for HexVisitor<T> {
+    type Value = T;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a hex string")
+    }
+
+    fn visit_str<E>(self, hex: &str) -> Result<T, E>
+    where
+        E: serde::de::Error,
+    {
+        (self.0)(hex).map_err(E::custom)
+    }
+}
+
+#[cfg(feature = "client")]
+/// The coinbase transaction of a block, deserialized from the hex of the
+/// whole block as returned by `getblock` with verbosity 0. The other
+/// transactions are not decoded.
+pub(crate) struct BlockCoinbase(pub(crate) bitcoin::Transaction);
+
+#[cfg(feature = "client")]
+impl<'de> serde::Deserialize<'de> for BlockCoinbase {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        fn decode_coinbase(
+            hex: &str,
+        ) -> Result<bitcoin::Transaction, bitcoin::consensus::encode::Error> {
+            use bitcoin::consensus::Decodable;
+            let mut reader = hex_reader(hex)?;
+            bitcoin::block::Header::consensus_decode(&mut reader)?;
+            let tx_count = bitcoin::VarInt::consensus_decode(&mut reader)?;
+            if tx_count.0 == 0 {
+                return Err(bitcoin::consensus::encode::Error::ParseFailed(
+                    "block has no transactions",
+                ));
+            }
+            bitcoin::Transaction::consensus_decode(&mut reader)
+        }
+
+        let coinbase = deserializer.deserialize_str(HexVisitor(decode_coinbase))?;
+        Ok(BlockCoinbase(coinbase))
+    }
+}
+
+/// Reads the bytes a hex string encodes, decoding as they are read.
+struct HexReader<'a> {
+    hex: &'a [u8],
+}
+
+impl std::io::Read for HexReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = std::cmp::min(buf.len(), self.hex.len() / 2);
+        let (hex, rest) = self.hex.split_at(len * 2);
+        hex::decode_to_slice(hex, &mut buf[..len])
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
+        self.hex = rest;
+        Ok(len)
+    }
+}
+
+fn hex_reader(hex: &str) -> Result<HexReader<'_>, bitcoin::consensus::encode::Error> {
+    if !hex.len().is_multiple_of(2) {
+        return Err(bitcoin::consensus::encode::Error::ParseFailed(
+            "odd length hex string",
+        ));
+    }
+    Ok(HexReader {
+        hex: hex.as_bytes(),
+    })
+}
+
+fn decode_hex<T>(hex: &str) -> Result<T, bitcoin::consensus::encode::Error>
+where
+    T: bitcoin::consensus::Decodable,
+{
+    let mut reader = hex_reader(hex)?;
+    let value = T::consensus_decode(&mut reader)?;
+    if !reader.hex.is_empty() {
+        return Err(bitcoin::consensus::encode::Error::ParseFailed(
+            "data not consumed entirely when explicitly deserializing",
+        ));
+    }
+    Ok(value)
+}
+
+// Arguments:
+// 1. "amount"         (numeric or string, required) The amount in BTC to be spent.
+// 2. "height"         (numeric, required) The block height this transaction must be included in.
+// Note: If 0 is passed in for height, current block height will be used
+// 3. "criticalhash"   (string, required) h* you want added to a coinbase
+// 4. "nsidechain"     (numeric, required) Sidechain requesting BMM
+// 5. "prevbytes"      (string, required) a portion of the previous block hash
+
+// FIXME: Make mainchain API machine friendly. Parsing human readable amounts
+// here is stupid -- just take and return values in satoshi.
+/// Amount that is sent to the node as a decimal BTC number, e.g.
+/// `0.00000001`, and accepted from the node as either a JSON number or a
+/// string.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct AmountBtc(pub bitcoin::Amount);
+
+impl AmountBtc {
+    pub const ZERO: AmountBtc = AmountBtc(bitcoin::Amount::ZERO);
+
+    pub fn from_sat(sat: u64) -> Self {
+        AmountBtc(bitcoin::Amount::from_sat(sat))
+    }
+
+    pub fn from_btc(btc: f64) -> Result<Self, ParseAmountError> {
+        Ok(AmountBtc(bitcoin::Amount::from_btc(btc)?))
+    }
+
+    pub fn checked_add(self, rhs: AmountBtc) -> Option<AmountBtc> {
+        self.0.checked_add(rhs.0).map(AmountBtc)
+    }
+
+    pub fn checked_sub(self, rhs: AmountBtc) -> Option<AmountBtc> {
+        self.0.checked_sub(rhs.0).map(AmountBtc)
+    }
+}
+
+impl Add for AmountBtc {
+    type Output = AmountBtc;
+
+    fn add(self, rhs: AmountBtc) -> AmountBtc {
+        AmountBtc(self.0 + rhs.0)
+    }
+}
+
+impl Sub for AmountBtc {
+    type Output = AmountBtc;
+
+    fn sub(self, rhs: AmountBtc) -> AmountBtc {
+        AmountBtc(self.0 - rhs.0)
+    }
+}
+
+impl fmt::Display for AmountBtc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0.to_string_in(Denomination::Bitcoin))
+    }
+}
+
+/// Parses either a plain BTC value such as `0.001`, or a value with an
+/// explicit denomination such as `100000 sats`.
+impl FromStr for AmountBtc {
+    type Err = ParseAmountError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let amount = if s.contains(char::is_whitespace) {
+            bitcoin::Amount::from_str_with_denomination(s)?
+        } else {
+            bitcoin::Amount::from_str_in(s, Denomination::Bitcoin)?
+        };
+        Ok(AmountBtc(amount))
+    }
+}
+
+impl From<bitcoin::Amount> for AmountBtc {
+    fn from(other: bitcoin::Amount) -> AmountBtc {
+        AmountBtc(other)
+    }
+}
+
+impl From<AmountBtc> for bitcoin::Amount {
+    fn from(other: AmountBtc) -> bitcoin::Amount {
+        other.0
+    }
+}
+
+impl Deref for AmountBtc {
+    type Target = bitcoin::Amount;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for AmountBtc {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for AmountBtc {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct Visitor;
+
+        impl<'de> serde::de::Visitor<'de> for Visitor {
+            type Value = AmountBtc;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("an amount in BTC, as a number or a string")
+            }
+
+            fn visit_f64<E: serde::de::Error>(self, v: f64) -> Result<AmountBtc, E> {
+                AmountBtc::from_btc(v).map_err(E::custom)
+            }
+
+            fn visit_u64<E: serde::de::Error>(self, v: u64) -> Result<AmountBtc, E> {
+                self.visit_f64(v as f64)
+            }
+
+            fn visit_i64<E: serde::de::Error>(self, v: i64) -> Result<AmountBtc, E> {
+                self.visit_f64(v as f64)
+            }
+
+            fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<AmountBtc, E> {
+                bitcoin::Amount::from_str_in(v, Denomination::Bitcoin)
+                    .map(AmountBtc)
+                    .map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+/// Serialized as a JSON number with exactly the decimal digits of the
+/// amount, rather than through a float, so that small amounts are never
+/// written in exponent notation (`1e-8`) or rounded. Serializers other than
+/// `serde_json` see an opaque raw JSON value.
+impl serde::Serialize for AmountBtc {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let number =
+            serde_json::value::RawValue::from_string(self.0.to_string_in(Denomination::Bitcoin))
+                .map_err(serde::ser::Error::custom)?;
+        number.serialize(serializer)
+    }
+}
+
+/// Amount that is (de)serialized as an integer number of satoshis.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct AmountSats(pub bitcoin::Amount);
+
+impl From<bitcoin::Amount> for AmountSats {
+    fn from(other: bitcoin::Amount) -> AmountSats {
+        AmountSats(other)
+    }
+}
+
+impl From<AmountSats> for bitcoin::Amount {
+    fn from(other: AmountSats) -> bitcoin::Amount {
+        other.0
+    }
+}
+
+impl From<AmountBtc> for AmountSats {
+    fn from(other: AmountBtc) -> AmountSats {
+        AmountSats(other.0)
+    }
+}
+
+impl From<AmountSats> for AmountBtc {
+    fn from(other: AmountSats) -> AmountBtc {
+        AmountBtc(other.0)
+    }
+}
+
+impl Deref for AmountSats {
+    type Target = bitcoin::Amount;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for AmountSats {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for AmountSats {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        Ok(AmountSats(bitcoin::Amount::des_sat(deserializer)?))
+    }
+}
+
+impl serde::Serialize for AmountSats {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        self.0.ser_sat(serializer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        AmountBtc, Block, BlockTemplate, BlockTemplateTransaction, BmmAcceptedBid,
+        ConsensusEncoded, ConsensusEncodedLazy, Difficulty, Header, SidechainId,
+        MAX_FUTURE_BLOCK_TIME,
+    };
+    use std::time::{Duration, UNIX_EPOCH};
+
+    /// `getblock` and `getblockheader` of the mainnet genesis block, as
+    /// returned by Bitcoin Core.
+    const GENESIS_BLOCK: &str = r#"{
+        "hash": "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f",
+        "confirmations": 1,
+        "height": 0,
+        "version": 1,
+        "versionHex": "00000001",
+        "merkleroot": "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b",
+        "time": 1231006505,
+        "mediantime": 1231006505,
+        "nonce": 2083236893,
+        "bits": "1d00ffff",
+        "difficulty": 1,
+        "chainwork": "0000000000000000000000000000000000000000000000000000000100010001",
+        "nTx": 1,
+        "nextblockhash": "00000000839a8e6886ab5951d76f411475428afc90947ee320161bbf18eb6048",
+        "strippedsize": 285,
+        "size": 285,
+        "weight": 1140,
+        "tx": ["4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b"]
+    }"#;
+    const GENESIS_HEADER: &str = r#"{
+        "hash": "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f",
+        "confirmations": 1,
+        "height": 0,
+        "version": 1,
+        "versionHex": "00000001",
+        "merkleroot": "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b",
+        "time": 1231006505,
+        "mediantime": 1231006505,
+        "nonce": 2083236893,
+        "bits": "1d00ffff",
+        "difficulty": 1,
+        "chainwork": "0000000000000000000000000000000000000000000000000000000100010001",
+        "nTx": 1,
+        "nextblockhash": "00000000839a8e6886ab5951d76f411475428afc90947ee320161bbf18eb6048"
+    }"#;
+
+    #[test]
+    fn genesis_block_deserializes() {
+        let block: Block = serde_json::from_str(GENESIS_BLOCK).unwrap();
+        assert_eq!(block.n_tx, 1);
+        assert_eq!(block.tx.len(), 1);
+        assert_eq!(block.previousblockhash, None);
+        block.validate_difficulty().unwrap();
+        let header: Header = serde_json::from_str(GENESIS_HEADER).unwrap();
+        assert_eq!(header.hash, block.hash);
+        assert_eq!(header.n_tx, block.n_tx);
+    }
+
+    #[test]
+    fn difficulty_to_target() {
+        let block: Block = serde_json::from_str(GENESIS_BLOCK).unwrap();
+        assert_eq!(block.difficulty.to_target(), Some(block.target()));
+        assert_eq!(block.difficulty.to_work(), Some(block.work()));
+        // Regtest
+        let bits = bitcoin::CompactTarget::from_consensus(0x207fffff);
+        let difficulty = Difficulty(bitcoin::Target::from_compact(bits).difficulty_float());
+        assert_eq!(
+            difficulty.to_target(),
+            Some(bitcoin::Target::from_compact(bits))
+        );
+        for difficulty in [
+            0.0,
+            -1.0,
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+            f64::NAN,
+            1e-300,
+        ] {
+            assert_eq!(Difficulty(difficulty).to_target(), None, "{difficulty}");
+            assert_eq!(Difficulty(difficulty).to_work(), None, "{difficulty}");
+        }
+    }
+
+    #[test]
+    fn timestamps() {
+        let header: Header = serde_json::from_str(GENESIS_HEADER).unwrap();
+        let time = UNIX_EPOCH + Duration::from_secs(1231006505);
+        assert_eq!(header.time_utc(), time);
+        assert_eq!(header.median_time_utc(), time);
+        assert_eq!(
+            header.age(time + Duration::from_secs(60)),
+            Duration::from_secs(60)
+        );
+        // Not negative for clocks that are behind
+        assert_eq!(header.age(time - Duration::from_secs(60)), Duration::ZERO);
+        let block: Block = serde_json::from_str(GENESIS_BLOCK).unwrap();
+        assert_eq!(block.time_utc(), time);
+    }
+
+    #[test]
+    fn timestamp_validity_boundaries() {
+        let header: Header = serde_json::from_str(GENESIS_HEADER).unwrap();
+        let time = header.time_utc();
+        let second = Duration::from_secs(1);
+        let valid = |median_time_past, now| {
+            header.is_timestamp_valid(median_time_past, now, MAX_FUTURE_BLOCK_TIME)
+        };
+        // Must be strictly after the median time past
+        assert!(!valid(time, time));
+        assert!(valid(time - second, time));
+        // At most two hours ahead of the adjusted time, inclusive
+        assert!(valid(time - second, time - MAX_FUTURE_BLOCK_TIME));
+        assert!(!valid(time - second, time - MAX_FUTURE_BLOCK_TIME - second));
+    }
+
+    #[test]
+    fn amount_btc_is_a_json_number() {
+        let cases = [
+            (AmountBtc::from_sat(1), "0.00000001"),
+            (AmountBtc::from_sat(2_100_000_000_000_000), "21000000"),
+        ];
+        for (amount, json) in cases {
+            assert_eq!(serde_json::to_string(&amount).unwrap(), json);
+            let value = serde_json::to_value(amount).unwrap();
+            assert!(value.is_number(), "{value}");
+            assert_eq!(serde_json::from_str::<AmountBtc>(json).unwrap(), amount);
+        }
+    }
+
+    #[test]
+    fn lazy_decode_matches_eager() {
+        let block = bitcoin::blockdata::constants::genesis_block(bitcoin::Network::Regtest);
+        let json = serde_json::to_string(&ConsensusEncoded(block.clone())).unwrap();
+        let eager: ConsensusEncoded<bitcoin::Block> = serde_json::from_str(&json).unwrap();
+        let lazy: ConsensusEncodedLazy<bitcoin::Block> = serde_json::from_str(&json).unwrap();
+        assert_eq!(lazy.decode().unwrap(), eager.0);
+        assert_eq!(eager.0, block);
+        assert_eq!(serde_json::to_string(&lazy).unwrap(), json);
+        assert!(serde_json::from_str::<ConsensusEncodedLazy<bitcoin::Block>>("1").is_err());
+    }
+
+    /// `getblocktemplate` of a regtest node, as returned by Bitcoin Core.
+    fn stock_template_json() -> serde_json::Value {
+        serde_json::json!({
+            "capabilities": ["proposal"],
+            "version": 536870912,
+            "rules": ["csv", "!segwit", "taproot"],
+            "vbavailable": {},
+            "vbrequired": 0,
+            "previousblockhash": "3cc8bab3fc4ed6b9b3d1b8d0a5ea6a0e9f5cbd3e6c6c34d0c8d1b7a5a9f4e6d2",
+            "transactions": [],
+            "coinbaseaux": {},
+            "coinbasevalue": 5000000000u64,
+            "longpollid": "3cc8bab3fc4ed6b9b3d1b8d0a5ea6a0e9f5cbd3e6c6c34d0c8d1b7a5a9f4e6d20",
+            "target": "7fffff0000000000000000000000000000000000000000000000000000000000",
+            "mintime": 1700000001,
+            "mutable": ["time", "transactions", "prevblock"],
+            "noncerange": "00000000ffffffff",
+            "sigoplimit": 80000,
+            "sizelimit": 4000000,
+            "weightlimit": 4000000,
+            "curtime": 1700000100,
+            "bits": "207fffff",
+            "height": 101,
+            "default_witness_commitment": "6a24aa21a9ede2f61c3f71d1defd3fa999dfa36953755c690689799962b48bebd836974e8cf9",
+        })
+    }
+
+    /// Parses `json` and checks that serializing the template gives back
+    /// the same template.
+    fn parse_template(json: serde_json::Value) -> BlockTemplate {
+        let template: BlockTemplate = serde_json::from_value(json).unwrap();
+        let serialized = serde_json::to_string(&template).unwrap();
+        assert_eq!(
+            serde_json::from_str::<BlockTemplate>(&serialized).unwrap(),
+            template
+        );
+        template
+    }
+
+    #[test]
+    fn stock_template() {
+        let template = parse_template(stock_template_json());
+        assert_eq!(template.height, 101);
+        assert_eq!(
+            template.coinbase_value,
+            bitcoin::Amount::from_btc(50.0).unwrap()
+        );
+        assert!(template.extra.is_empty());
+        assert!(template.bmm_accepted_bids().is_none());
+    }
+
+    #[test]
+    fn enforcer_template() {
+        let mut json = stock_template_json();
+        json["bmm_accepted_bids"] = serde_json::json!([{
+            "nsidechain": 1,
+            "criticalhash": "1111111111111111111111111111111111111111111111111111111111111111",
+            "amount": 0.0001,
+        }]);
+        let template = parse_template(json);
+        assert_eq!(template.extra.len(), 1);
+        let bids = template.bmm_accepted_bids().unwrap().unwrap();
+        assert_eq!(
+            bids,
+            [BmmAcceptedBid {
+                nsidechain: SidechainId(1),
+                criticalhash: "1111111111111111111111111111111111111111111111111111111111111111"
+                    .parse()
+                    .unwrap(),
+                amount: AmountBtc(bitcoin::Amount::from_sat(10_000)),
+            }]
+        );
+    }
+
+    #[test]
+    fn template_with_unknown_fields() {
+        let mut json = stock_template_json();
+        json["coinbasedevreward"] = serde_json::json!({
+            "scriptpubkey": "0014751e76e8199196d454941c45d1b3a323f1433bd6",
+            "value": 25000000,
+        });
+        json["masternode"] = serde_json::json!([{
+            "payee": "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080",
+            "amount": 100000000,
+        }]);
+        // A bid list this crate doesn't understand doesn't break the template
+        json["bmm_accepted_bids"] = serde_json::json!("none");
+        let template = parse_template(json.clone());
+        assert_eq!(template.height, 101);
+        let mut extra: Vec<_> = template.extra.keys().map(String::as_str).collect();
+        extra.sort();
+        assert_eq!(
+            extra,
+            ["bmm_accepted_bids", "coinbasedevreward", "masternode"]
+        );
+        assert_eq!(
+            template.extra["coinbasedevreward"],
+            json["coinbasedevreward"]
+        );
+        assert!(template.bmm_accepted_bids().unwrap().is_err());
+        let serialized = serde_json::to_value(&template).unwrap();
+        assert_eq!(serialized["masternode"], json["masternode"]);
+    }
+
+    /// Fee of a template transaction with `fee` in the response.
+    fn template_fee(fee: serde_json::Value) -> Result<Option<i64>, serde_json::Error> {
+        let transaction: BlockTemplateTransaction = serde_json::from_value(serde_json::json!({
+            "data": "",
+            "txid": "0000000000000000000000000000000000000000000000000000000000000000",
+            "hash": "0000000000000000000000000000000000000000000000000000000000000000",
+            "depends": [],
+            "fee": fee,
+            "sigops": 4,
+            "weight": 400,
+        }))?;
+        Ok(transaction.fee)
+    }
+
+    #[test]
+    fn fee_in_sats_or_btc() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        static WARNINGS: AtomicUsize = AtomicUsize::new(0);
+        let _lock = crate::warnings::TEST_HANDLER_LOCK.lock().unwrap();
+        crate::warnings::set_warning_handler(|warning| {
+            if warning.contains("reported in BTC") {
+                WARNINGS.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+        assert_eq!(template_fee(serde_json::json!(1250)).unwrap(), Some(1250));
+        assert_eq!(WARNINGS.load(Ordering::SeqCst), 0);
+        assert_eq!(
+            template_fee(serde_json::json!(0.0000125)).unwrap(),
+            Some(1250)
+        );
+        assert_eq!(
+            template_fee(serde_json::json!("0.0000125")).unwrap(),
+            Some(1250)
+        );
+        assert_eq!(WARNINGS.load(Ordering::SeqCst), 2);
+        assert_eq!(template_fee(serde_json::Value::Null).unwrap(), None);
+        // Less than a sat
+        assert!(template_fee(serde_json::json!(0.000000001)).is_err());
+        assert!(template_fee(serde_json::json!("0.000000001")).is_err());
+        assert!(template_fee(serde_json::json!("fee")).is_err());
+        crate::warnings::clear_warning_handler();
+        assert_eq!(WARNINGS.load(Ordering::SeqCst), 2);
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn label_rpcs() {
+        use super::{LabelAddress, LabelPurpose, WalletClient};
+        use crate::mock::{block_on, MockClient};
+        use serde_json::json;
+
+        const BECH32: &str = "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080";
+        const LEGACY: &str = "mipcBbFg9gMiCh81Kj8tqqdgoZub1ZJRfn";
+        let client = MockClient::new(|method, _| match method {
+            "setlabel" => Ok(serde_json::Value::Null),
+            "listlabels" => Ok(json!(["", "alice"])),
+            // Keys of both address types in one response, not in address
+            // order
+            "getaddressesbylabel" => Ok(json!({
+                BECH32: { "purpose": "receive" },
+                LEGACY: { "purpose": "send" },
+            })),
+            "listreceivedbylabel" => Ok(json!([{
+                "amount": 0.5,
+                "confirmations": 3,
+                "label": "alice",
+            }])),
+            _ => unreachable!("{method}"),
+        });
+        let bech32: bitcoin::Address<bitcoin::address::NetworkUnchecked> = BECH32.parse().unwrap();
+        block_on(client.setlabel(&bech32, "alice")).unwrap();
+        assert_eq!(
+            block_on(client.listlabels(Some(LabelPurpose::Receive))).unwrap(),
+            ["", "alice"]
+        );
+        let addresses = block_on(client.getaddressesbylabel("alice")).unwrap();
+        let addresses: Vec<_> = addresses
+            .into_iter()
+            .map(|(address, entry)| (address.assume_checked().to_string(), entry))
+            .collect();
+        assert_eq!(
+            addresses,
+            [
+                (
+                    BECH32.to_owned(),
+                    LabelAddress {
+                        purpose: LabelPurpose::Receive
+                    }
+                ),
+                (
+                    LEGACY.to_owned(),
+                    LabelAddress {
+                        purpose: LabelPurpose::Send
+                    }
+                ),
+            ]
+        );
+        let received = block_on(client.listreceivedbylabel(Some(1), None, None)).unwrap();
+        assert_eq!(received[0].label, "alice");
+        assert_eq!(
+            received[0].amount,
+            AmountBtc(bitcoin::Amount::from_sat(50_000_000))
+        );
+        let calls = client.calls();
+        assert_eq!(calls[0].1, [json!(BECH32), json!("alice")]);
+        assert_eq!(calls[1].1, [json!("receive")]);
+        assert_eq!(calls[3].1, [json!(1), json!(null), json!(null)]);
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn memory_info_modes() {
+        use super::{LockedMemory, MallocInfoMode, MemoryStatsMode, NodeAdminClient};
+        use crate::mock::{block_on, MockClient};
+        use serde_json::json;
+
+        const MALLOC_INFO: &str = "<malloc version=\"1\">\n<heap nr=\"0\">\n<sizes>\n</sizes>\n<total type=\"fast\" count=\"0\" size=\"0\"/>\n<system type=\"current\" size=\"135168\"/>\n</heap>\n</malloc>\n";
+        let client = MockClient::new(|method, params| match (method, params[0].as_str()) {
+            // As returned by Bitcoin Core 26
+            ("getmemoryinfo", Some("stats")) => Ok(json!({
+                "locked": {
+                    "used": 65_440,
+                    "free": 65_632,
+                    "total": 131_072,
+                    "locked": 131_072,
+                    "chunks_used": 2045,
+                    "chunks_free": 3,
+                }
+            })),
+            ("getmemoryinfo", Some("mallocinfo")) => Ok(json!(MALLOC_INFO)),
+            _ => unreachable!("{method}"),
+        });
+        let stats = block_on(client.getmemoryinfo(MemoryStatsMode)).unwrap();
+        assert_eq!(
+            stats.locked,
+            LockedMemory {
+                used: 65_440,
+                free: 65_632,
+                total: 131_072,
+                locked: 131_072,
+                chunks_used: 2045,
+                chunks_free: 3,
+            }
+        );
+        assert!((stats.locked.utilization() - 0.499_27).abs() < 1e-5);
+        assert_eq!(
+            block_on(client.getmemoryinfomalloc(MallocInfoMode)).unwrap(),
+            MALLOC_INFO
+        );
+        assert_eq!(
+            client.calls(),
+            [
+                ("getmemoryinfo".to_owned(), vec![json!("stats")]),
+                ("getmemoryinfo".to_owned(), vec![json!("mallocinfo")]),
+            ]
+        );
     }
 }
@@ -0,0 +1,281 @@
+//! Sharing responses between concurrent identical requests.
+use crate::cache::RawParams;
+use futures::future::{BoxFuture, FutureExt, Shared};
+use jsonrpsee::core::async_trait;
+use jsonrpsee::core::client::{BatchResponse, ClientT};
+use jsonrpsee::core::params::BatchRequestBuilder;
+use jsonrpsee::core::traits::ToRpcParams;
+use serde::de::DeserializeOwned;
+use serde_json::value::RawValue;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Methods that don't change the state of the node. Requests for anything
+/// not in this list are always sent as they are.
+pub const COALESCED_METHODS: &[&str] = &[
+    "getbestblockhash",
+    "getblock",
+    "getblockchaininfo",
+    "getblockcount",
+    "getblockfilter",
+    "getblockhash",
+    "getblockheader",
+    "getmempoolentry",
+    "getmempoolinfo",
+    "getnetworkinfo",
+    "getrawmempool",
+    "getrawtransaction",
+    "gettxoutproof",
+];
+
+/// Cheap queries about the chain tip, suitable for
+/// [`CoalescingClient::with_micro_cache`].
+pub const TIP_METHODS: &[&str] = &["getbestblockhash", "getblockcount"];
+
+type SharedResponse =
+    Shared<BoxFuture<'static, Result<Box<RawValue>, Arc<jsonrpsee::core::Error>>>>;
+
+struct Cached {
+    fetched: Instant,
+    response: Box<RawValue>,
+}
+
+/// Client wrapper that sends a single request for identical concurrent
+/// requests to [`COALESCED_METHODS`], keyed by method and parameters, and
+/// hands the response to every caller.
+///
+/// Clones share their in-flight requests and cache.
+pub struct CoalescingClient<C> {
+    inner: Arc<C>,
+    in_flight: Arc<Mutex<HashMap<String, SharedResponse>>>,
+    micro_cache: Arc<Mutex<HashMap<String, Cached>>>,
+    micro_cache_methods: &'static [&'static str],
+    micro_cache_ttl: Duration,
+}
+
+impl<C> Clone for CoalescingClient<C> {
+    fn clone(&self) -> Self {
+        CoalescingClient {
+            inner: self.inner.clone(),
+            in_flight: self.in_flight.clone(),
+            micro_cache: self.micro_cache.clone(),
+            micro_cache_methods: self.micro_cache_methods,
+            micro_cache_ttl: self.micro_cache_ttl,
+        }
+    }
+}
+
+impl<C> CoalescingClient<C> {
+    pub fn new(inner: C) -> Self {
+        CoalescingClient {
+            inner: Arc::new(inner),
+            in_flight: Arc::default(),
+            micro_cache: Arc::default(),
+            micro_cache_methods: &[],
+            micro_cache_ttl: Duration::ZERO,
+        }
+    }
+
+    /// Also reuses responses to `methods` for `ttl` after they arrive, e.g.
+    /// [`TIP_METHODS`] for 200ms to absorb bursts of polling. Methods that
+    /// are not in [`COALESCED_METHODS`] are never cached.
+    pub fn with_micro_cache(mut self, methods: &'static [&'static str], ttl: Duration) -> Self {
+        self.micro_cache_methods = methods;
+        self.micro_cache_ttl = ttl;
+        self
+    }
+
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+
+    fn cached(&self, key: &str) -> Option<Box<RawValue>> {
+        let mut micro_cache = self.micro_cache.lock().unwrap();
+        let cached = micro_cache.get(key)?;
+        if cached.fetched.elapsed() < self.micro_cache_ttl {
+            return Some(cached.response.clone());
+        }
+        micro_cache.remove(key);
+        None
+    }
+}
+
+impl<C> CoalescingClient<C>
+where
+    C: ClientT + Send + Sync + 'static,
+{
+    /// Joins the in-flight request for `key`, or sends it.
+    fn shared_request(
+        &self,
+        method: &str,
+        key: String,
+        params: Option<Box<RawValue>>,
+    ) -> SharedResponse {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        if let Some(response) = in_flight.get(&key) {
+            return response.clone();
+        }
+        let inner = self.inner.clone();
+        let method = method.to_owned();
+        let all_in_flight = self.in_flight.clone();
+        let micro_cache = self
+            .micro_cache_methods
+            .contains(&method.as_str())
+            .then(|| self.micro_cache.clone());
+        let request_key = key.clone();
+        let response = async move {
+            let response: Result<Box<RawValue>, _> =
+                inner.request(&method, RawParams(params)).await;
+            all_in_flight.lock().unwrap().remove(&request_key);
+            let response = response.map_err(Arc::new)?;
+            if let Some(micro_cache) = micro_cache {
+                let cached = Cached {
+                    fetched: Instant::now(),
+                    response: response.clone(),
+                };
+                micro_cache.lock().unwrap().insert(request_key, cached);
+            }
+            Ok(response)
+        }
+        .boxed()
+        .shared();
+        in_flight.insert(key, response.clone());
+        response
+    }
+}
+
+/// Errors are shared between callers, but only some can be cloned.
+fn clone_error(err: &jsonrpsee::core::Error) -> jsonrpsee::core::Error {
+    match err {
+        jsonrpsee::core::Error::Call(err) => jsonrpsee::core::Error::Call(err.clone()),
+        jsonrpsee::core::Error::RequestTimeout => jsonrpsee::core::Error::RequestTimeout,
+        err => jsonrpsee::core::Error::Custom(err.to_string()),
+    }
+}
+
+#[async_trait]
+impl<C> ClientT for CoalescingClient<C>
+where
+    C: ClientT + Send + Sync + 'static,
+{
+    async fn notification<Params>(
+        &self,
+        method: &str,
+        params: Params,
+    ) -> Result<(), jsonrpsee::core::Error>
+    where
+        Params: ToRpcParams + Send,
+    {
+        self.inner.notification(method, params).await
+    }
+
+    async fn request<R, Params>(
+        &self,
+        method: &str,
+        params: Params,
+    ) -> Result<R, jsonrpsee::core::Error>
+    where
+        R: DeserializeOwned,
+        Params: ToRpcParams + Send,
+    {
+        if !COALESCED_METHODS.contains(&method) {
+            return self.inner.request(method, params).await;
+        }
+        let params = params.to_rpc_params()?;
+        let key = match &params {
+            Some(params) => format!("{method}{}", params.get()),
+            None => method.to_owned(),
+        };
+        let response = match self.cached(&key) {
+            Some(response) => response,
+            None => self
+                .shared_request(method, key, params)
+                .await
+                .map_err(|err| clone_error(&err))?,
+        };
+        Ok(serde_json::from_str(response.get())?)
+    }
+
+    async fn batch_request<'a, R>(
+        &self,
+        batch: BatchRequestBuilder<'a>,
+    ) -> Result<BatchResponse<'a, R>, jsonrpsee::core::Error>
+    where
+        R: DeserializeOwned + fmt::Debug + 'a,
+    {
+        self.inner.batch_request(batch).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::ChainClient;
+    use crate::mock::{block_on, MockChain, MockClient};
+    use futures::future::join_all;
+
+    fn node(chain: &MockChain) -> MockClient {
+        let chain = chain.clone();
+        MockClient::new(move |method, params| match method {
+            "sendrawtransaction" => Ok(serde_json::json!(
+                "0000000000000000000000000000000000000000000000000000000000000001"
+            )),
+            _ => chain.handle(method, params).unwrap(),
+        })
+        .with_delay(Duration::from_millis(10))
+    }
+
+    fn count(client: &CoalescingClient<MockClient>, method: &str) -> usize {
+        client
+            .inner()
+            .methods()
+            .iter()
+            .filter(|called| *called == method)
+            .count()
+    }
+
+    #[test]
+    fn concurrent_identical_reads_are_sent_once() {
+        let chain = MockChain::new();
+        let hashes = chain.mine(2);
+        let client = CoalescingClient::new(node(&chain));
+        block_on(async {
+            let counts = join_all((0..100).map(|_| client.getblockcount()));
+            let first = join_all((0..100).map(|_| client.getblockhash(1)));
+            let second = join_all((0..50).map(|_| client.getblockhash(2)));
+            let (counts, first, second) = futures::join!(counts, first, second);
+            assert!(counts.into_iter().all(|count| count.unwrap() == 2));
+            assert!(first.into_iter().all(|hash| hash.unwrap() == hashes[0]));
+            assert!(second.into_iter().all(|hash| hash.unwrap() == hashes[1]));
+        });
+        assert_eq!(count(&client, "getblockcount"), 1);
+        // Different params are different requests
+        assert_eq!(count(&client, "getblockhash"), 2);
+        // Once it is answered, the next request is sent again
+        chain.mine(1);
+        assert_eq!(block_on(client.getblockcount()).unwrap(), 3);
+        assert_eq!(count(&client, "getblockcount"), 2);
+    }
+
+    #[test]
+    fn non_idempotent_calls_are_not_coalesced() {
+        let chain = MockChain::new();
+        let client = CoalescingClient::new(node(&chain));
+        let address: bitcoin::Address<bitcoin::address::NetworkUnchecked> =
+            "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080"
+                .parse()
+                .unwrap();
+        block_on(async {
+            let sends = join_all((0..20).map(|_| client.sendrawtransaction("00")));
+            let mined = join_all((0..5).map(|_| client.generatetoaddress(1, &address)));
+            let (sends, mined) = futures::join!(sends, mined);
+            assert!(sends.into_iter().all(|txid| txid.is_ok()));
+            assert!(mined.into_iter().all(|hashes| hashes.unwrap().len() == 1));
+        });
+        assert_eq!(count(&client, "sendrawtransaction"), 20);
+        assert_eq!(count(&client, "generatetoaddress"), 5);
+        assert_eq!(chain.height(), 5);
+    }
+}
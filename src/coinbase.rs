@@ -0,0 +1,200 @@
+//! Coinbase transactions of mainchain blocks.
+#[cfg(feature = "client")]
+use crate::client::{BlockCoinbase, ChainClient};
+#[cfg(feature = "client")]
+use crate::Error;
+use bitcoin::blockdata::opcodes::all::{OP_PUSHNUM_1, OP_PUSHNUM_16};
+use bitcoin::blockdata::script::Instruction;
+#[cfg(feature = "client")]
+use jsonrpsee::core::client::ClientT;
+
+/// Height from which BIP34 requires the coinbase to commit to the block
+/// height, with the default parameters of Bitcoin Core. Coinbases of
+/// earlier blocks, including the genesis block, may start with any push.
+pub fn bip34_height(network: bitcoin::Network) -> usize {
+    match network {
+        bitcoin::Network::Bitcoin => 227_931,
+        bitcoin::Network::Testnet => 21_111,
+        _ => 1,
+    }
+}
+
+/// A decoded coinbase transaction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Coinbase(pub bitcoin::Transaction);
+
+impl Coinbase {
+    /// Destination script and amount of every output, in order.
+    pub fn payout_scripts(&self) -> impl Iterator<Item = (&bitcoin::Script, bitcoin::Amount)> {
+        self.0.output.iter().map(|output| {
+            (
+                output.script_pubkey.as_script(),
+                bitcoin::Amount::from_sat(output.value),
+            )
+        })
+    }
+
+    /// Block height committed to by the first push of the scriptSig, as
+    /// required by BIP34. Heights up to 16 are pushed as small integers,
+    /// e.g. `OP_1` for height 1. Below [`bip34_height`], the push is not
+    /// a height.
+    pub fn height(&self) -> Option<u32> {
+        let input = self.0.input.first()?;
+        match input.script_sig.instructions_minimal().next()?.ok()? {
+            Instruction::PushBytes(bytes) => {
+                let height = bitcoin::script::read_scriptint(bytes.as_bytes()).ok()?;
+                u32::try_from(height).ok()
+            }
+            Instruction::Op(op)
+                if (OP_PUSHNUM_1.to_u8()..=OP_PUSHNUM_16.to_u8()).contains(&op.to_u8()) =>
+            {
+                Some(u32::from(op.to_u8() - OP_PUSHNUM_1.to_u8()) + 1)
+            }
+            Instruction::Op(_) => None,
+        }
+    }
+}
+
+#[cfg(feature = "client")]
+/// Fetches the coinbase transaction of `block_hash`, which works without
+/// `-txindex` as the block is fetched rather than the transaction.
+///
+/// If the coinbase commits to a height, it is checked against the height
+/// the node reports for the block. Blocks below [`bip34_height`] are not
+/// checked, the network is only fetched to tell if a mismatching block is.
+pub async fn get_coinbase<C>(client: &C, block_hash: &bitcoin::BlockHash) -> Result<Coinbase, Error>
+where
+    C: ClientT + Sync,
+{
+    let BlockCoinbase(transaction) = client
+        .request("getblock", jsonrpsee::rpc_params![block_hash, 0])
+        .await?;
+    let coinbase = Coinbase(transaction);
+    let Some(height) = coinbase.height() else {
+        return Ok(coinbase);
+    };
+    let expected = client.getblockheader(block_hash).await?.height;
+    if height as usize == expected || expected == 0 {
+        return Ok(coinbase);
+    }
+    let chain = client.getblockchaininfo().await?.value.chain;
+    let network =
+        bitcoin::Network::from_core_arg(&chain).map_err(|_| Error::UnknownChain { chain })?;
+    if expected < bip34_height(network) {
+        return Ok(coinbase);
+    }
+    Err(Error::CoinbaseHeightMismatch {
+        block_hash: *block_hash,
+        expected,
+        actual: height,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::blockdata::constants::genesis_block;
+    use bitcoin::script::Builder;
+
+    /// Coinbase with the given scriptSig and a single output.
+    fn coinbase(script_sig: bitcoin::ScriptBuf) -> bitcoin::Transaction {
+        let mut transaction = genesis_block(bitcoin::Network::Regtest).txdata[0].clone();
+        transaction.input[0].script_sig = script_sig;
+        transaction
+    }
+
+    fn with_height(height: i64) -> Coinbase {
+        Coinbase(coinbase(
+            Builder::new()
+                .push_int(height)
+                .push_slice(b"pool")
+                .into_script(),
+        ))
+    }
+
+    #[test]
+    fn heights() {
+        for height in [1, 2, 16, 17, 127, 128, 255, 256, 32_767, 32_768, 840_000] {
+            assert_eq!(with_height(height).height(), Some(height as u32));
+        }
+        assert_eq!(with_height(-1).height(), None);
+        let op_return = Builder::new()
+            .push_opcode(bitcoin::opcodes::all::OP_RETURN)
+            .into_script();
+        assert_eq!(Coinbase(coinbase(op_return)).height(), None);
+        assert_eq!(Coinbase(coinbase(bitcoin::ScriptBuf::new())).height(), None);
+    }
+
+    #[cfg(feature = "client")]
+    mod get_coinbase {
+        use super::*;
+        use crate::mock::{block_on, blockchain_info, header, MockClient};
+        use bitcoin::consensus::encode::serialize_hex;
+        use bitcoin::hashes::Hash as _;
+        use serde_json::json;
+
+        /// Node on `chain` that reports `height` for a block with `coinbase`.
+        fn client(
+            chain: &'static str,
+            height: usize,
+            coinbase: bitcoin::Transaction,
+        ) -> MockClient {
+            let mut block = genesis_block(bitcoin::Network::Regtest);
+            block.txdata = vec![coinbase];
+            let block_hex = serialize_hex(&block);
+            MockClient::new(move |method, params| match method {
+                "getblock" => Ok(json!(block_hex)),
+                "getblockheader" => Ok(header(
+                    serde_json::from_value(params[0].clone()).unwrap(),
+                    height,
+                    None,
+                )),
+                "getblockchaininfo" => Ok(blockchain_info(chain, height)),
+                _ => panic!("unexpected call to {method}"),
+            })
+        }
+
+        fn get(client: &MockClient) -> Result<Coinbase, Error> {
+            block_on(get_coinbase(client, &bitcoin::BlockHash::all_zeros()))
+        }
+
+        #[test]
+        fn genesis_is_not_checked() {
+            // The genesis coinbase starts with a push of 486604799
+            let genesis = genesis_block(bitcoin::Network::Regtest).txdata[0].clone();
+            let client = client("regtest", 0, genesis.clone());
+            assert_eq!(get(&client).unwrap(), Coinbase(genesis));
+            assert_eq!(client.methods(), ["getblock", "getblockheader"]);
+        }
+
+        #[test]
+        fn matching_height() {
+            let client = client("regtest", 17, with_height(17).0);
+            assert_eq!(get(&client).unwrap().height(), Some(17));
+            assert_eq!(client.methods(), ["getblock", "getblockheader"]);
+        }
+
+        #[test]
+        fn mismatch_after_activation() {
+            let client = client("regtest", 5, with_height(6).0);
+            assert!(matches!(
+                get(&client),
+                Err(Error::CoinbaseHeightMismatch {
+                    expected: 5,
+                    actual: 6,
+                    ..
+                })
+            ));
+        }
+
+        #[test]
+        fn mismatch_before_activation() {
+            let client = client("main", 1000, with_height(4).0);
+            assert!(get(&client).is_ok());
+            assert_eq!(
+                client.methods(),
+                ["getblock", "getblockheader", "getblockchaininfo"]
+            );
+        }
+    }
+}
@@ -0,0 +1,289 @@
+//! Structural diffs of large node responses, for tests that compare two
+//! nodes, or a node and a local computation, where the `Debug` output of a
+//! failing `assert_eq!` is too long to read.
+use crate::client::{BlockTemplate, RawMempoolVerbose};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+/// Fields of `getblocktemplate` that change between calls on the same node.
+pub const VOLATILE_TEMPLATE_FIELDS: &[&str] = &["curtime", "longpollid"];
+
+/// Fields of `getrawmempool` entries that differ between nodes with the
+/// same mempool, as they depend on when each node received a transaction.
+pub const VOLATILE_MEMPOOL_FIELDS: &[&str] = &["time", "height", "unbroadcast"];
+
+/// Relative difference up to which floats, such as difficulties reported in
+/// extra template fields, are equal.
+const FLOAT_TOLERANCE: f64 = 1e-9;
+
+/// A field with different values, as they are sent by the node.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldDiff {
+    /// Path of the field in the JSON of the response, e.g. `fees.base` or
+    /// `depends[0]`.
+    pub path: String,
+    /// `Null` if the field is missing.
+    pub a: Value,
+    pub b: Value,
+}
+
+impl fmt::Display for FieldDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} != {}", self.path, self.a, self.b)
+    }
+}
+
+fn floats_equal(a: &serde_json::Number, b: &serde_json::Number) -> bool {
+    if !(a.is_f64() || b.is_f64()) {
+        return a == b;
+    }
+    match (a.as_f64(), b.as_f64()) {
+        (Some(a), Some(b)) => a == b || (a - b).abs() <= FLOAT_TOLERANCE * a.abs().max(b.abs()),
+        _ => false,
+    }
+}
+
+fn diff_values(path: &str, a: &Value, b: &Value, ignore: &[&str], diffs: &mut Vec<FieldDiff>) {
+    if ignore.contains(&path) {
+        return;
+    }
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) if floats_equal(a, b) => (),
+        (Value::Object(a), Value::Object(b)) => {
+            let keys: BTreeSet<_> = a.keys().chain(b.keys()).collect();
+            for key in keys {
+                let path = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{path}.{key}")
+                };
+                let a = a.get(key).unwrap_or(&Value::Null);
+                let b = b.get(key).unwrap_or(&Value::Null);
+                diff_values(&path, a, b, ignore, diffs);
+            }
+        }
+        (Value::Array(a), Value::Array(b)) if a.len() == b.len() => {
+            for (index, (a, b)) in a.iter().zip(b).enumerate() {
+                diff_values(&format!("{path}[{index}]"), a, b, ignore, diffs);
+            }
+        }
+        (a, b) if a == b => (),
+        (a, b) => diffs.push(FieldDiff {
+            path: path.to_owned(),
+            a: a.clone(),
+            b: b.clone(),
+        }),
+    }
+}
+
+fn to_value<T>(value: &T) -> Value
+where
+    T: serde::Serialize,
+{
+    serde_json::to_value(value).expect("node responses serialize to JSON")
+}
+
+/// Change of a transaction between two templates, or of an entry between
+/// two mempools.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TxChange {
+    /// Only in `b`.
+    Added(bitcoin::Txid),
+    /// Only in `a`.
+    Removed(bitcoin::Txid),
+    /// In both, with different fields, e.g. `fee`.
+    Changed {
+        txid: bitcoin::Txid,
+        fields: Vec<FieldDiff>,
+    },
+}
+
+impl fmt::Display for TxChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TxChange::Added(txid) => write!(f, "+ {txid}"),
+            TxChange::Removed(txid) => write!(f, "- {txid}"),
+            TxChange::Changed { txid, fields } => {
+                write!(f, "~ {txid}")?;
+                for field in fields {
+                    write!(f, "\n    {field}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Compares transactions keyed by txid, in the order of `a` and then of
+/// the ones only in `b`.
+fn diff_transactions(
+    a: Vec<(bitcoin::Txid, Value)>,
+    b: Vec<(bitcoin::Txid, Value)>,
+    ignore: &[&str],
+) -> Vec<TxChange> {
+    let mut b_by_txid: BTreeMap<_, _> = b.iter().map(|(txid, tx)| (*txid, tx)).collect();
+    let mut changes = vec![];
+    for (txid, a_tx) in &a {
+        let Some(b_tx) = b_by_txid.remove(txid) else {
+            changes.push(TxChange::Removed(*txid));
+            continue;
+        };
+        let mut fields = vec![];
+        diff_values("", a_tx, b_tx, ignore, &mut fields);
+        if !fields.is_empty() {
+            changes.push(TxChange::Changed {
+                txid: *txid,
+                fields,
+            });
+        }
+    }
+    changes.extend(
+        b.iter()
+            .filter(|(txid, _)| b_by_txid.contains_key(txid))
+            .map(|(txid, _)| TxChange::Added(*txid)),
+    );
+    changes
+}
+
+/// Differences between two block templates, see [`diff_block_templates`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TemplateDiff {
+    /// Differences outside of `transactions`, including extra fields.
+    pub fields: Vec<FieldDiff>,
+    pub transactions: Vec<TxChange>,
+}
+
+impl TemplateDiff {
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty() && self.transactions.is_empty()
+    }
+}
+
+impl fmt::Display for TemplateDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for field in &self.fields {
+            writeln!(f, "{field}")?;
+        }
+        for change in &self.transactions {
+            writeln!(f, "transaction {change}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Compares `a` and `b` field by field, and their transactions by txid.
+///
+/// Fields are named as in the JSON of `getblocktemplate`, and are skipped
+/// if their path is in `ignore`, e.g. [`VOLATILE_TEMPLATE_FIELDS`].
+/// Transaction fields are named relative to the transaction, e.g. `fee`.
+pub fn diff_block_templates(a: &BlockTemplate, b: &BlockTemplate, ignore: &[&str]) -> TemplateDiff {
+    let transactions = |template: &BlockTemplate| {
+        template
+            .transactions
+            .iter()
+            .map(|tx| (tx.txid, to_value(tx)))
+            .collect()
+    };
+    let mut fields = vec![];
+    let (mut a_value, mut b_value) = (to_value(a), to_value(b));
+    for value in [&mut a_value, &mut b_value] {
+        if let Value::Object(object) = value {
+            object.remove("transactions");
+        }
+    }
+    diff_values("", &a_value, &b_value, ignore, &mut fields);
+    TemplateDiff {
+        fields,
+        transactions: diff_transactions(transactions(a), transactions(b), ignore),
+    }
+}
+
+/// Differences between two verbose mempools, see [`diff_mempool`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MempoolDiff {
+    pub entries: Vec<TxChange>,
+}
+
+impl MempoolDiff {
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl fmt::Display for MempoolDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for change in &self.entries {
+            writeln!(f, "{change}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Compares the entries of `a` and `b` by txid, in txid order. Entry fields
+/// are named as in the JSON of `getrawmempool`, e.g. `fees.base`, and are
+/// skipped if their path is in `ignore`, e.g. [`VOLATILE_MEMPOOL_FIELDS`].
+///
+/// Entries that failed to deserialize are not compared.
+pub fn diff_mempool(a: &RawMempoolVerbose, b: &RawMempoolVerbose, ignore: &[&str]) -> MempoolDiff {
+    let entries = |mempool: &RawMempoolVerbose| {
+        let entries: BTreeMap<_, _> = mempool.entries.iter().collect();
+        entries
+            .into_iter()
+            .map(|(txid, entry)| (*txid, to_value(entry)))
+            .collect()
+    };
+    MempoolDiff {
+        entries: diff_transactions(entries(a), entries(b), ignore),
+    }
+}
+
+/// Asserts that two [`BlockTemplate`]s are equal, apart from the fields in
+/// [`VOLATILE_TEMPLATE_FIELDS`] or the given ignore-set, and panics with a
+/// [`TemplateDiff`] of them if not.
+///
+/// ```
+/// # let a: bip300301::BlockTemplate = serde_json::from_value(serde_json::json!({
+/// #     "capabilities": ["proposal"],
+/// #     "version": 536870912,
+/// #     "rules": ["csv", "!segwit", "taproot"],
+/// #     "vbavailable": {},
+/// #     "vbrequired": 0,
+/// #     "previousblockhash": "3cc8bab3fc4ed6b9b3d1b8d0a5ea6a0e9f5cbd3e6c6c34d0c8d1b7a5a9f4e6d2",
+/// #     "transactions": [],
+/// #     "coinbaseaux": {},
+/// #     "coinbasevalue": 5000000000u64,
+/// #     "longpollid": "3cc8bab3fc4ed6b9b3d1b8d0a5ea6a0e9f5cbd3e6c6c34d0c8d1b7a5a9f4e6d20",
+/// #     "target": "7fffff0000000000000000000000000000000000000000000000000000000000",
+/// #     "mintime": 1700000001,
+/// #     "mutable": ["time", "transactions", "prevblock"],
+/// #     "noncerange": "00000000ffffffff",
+/// #     "sigoplimit": 80000,
+/// #     "sizelimit": 4000000,
+/// #     "weightlimit": 4000000,
+/// #     "curtime": 1700000100,
+/// #     "bits": "207fffff",
+/// #     "height": 101,
+/// # }))
+/// # .unwrap();
+/// // The same template, fetched later
+/// let mut b = a.clone();
+/// b.current_time += 30;
+/// b.long_poll_id = Some("3cc8bab3fc4ed6b9b3d1b8d0a5ea6a0e9f5cbd3e6c6c34d0c8d1b7a5a9f4e6d21".to_owned());
+/// bip300301::assert_templates_equivalent!(a, b);
+/// b.coinbase_value += bitcoin::Amount::from_sat(1000);
+/// // Also ignore the fees, which affect the coinbase value
+/// bip300301::assert_templates_equivalent!(a, b, &["curtime", "longpollid", "coinbasevalue", "fee"]);
+/// ```
+#[macro_export]
+macro_rules! assert_templates_equivalent {
+    ($a:expr, $b:expr $(,)?) => {
+        $crate::assert_templates_equivalent!($a, $b, $crate::compare::VOLATILE_TEMPLATE_FIELDS)
+    };
+    ($a:expr, $b:expr, $ignore:expr $(,)?) => {{
+        let diff = $crate::compare::diff_block_templates(&$a, &$b, $ignore);
+        if !diff.is_empty() {
+            panic!("block templates are not equivalent:\n{diff}");
+        }
+    }};
+}
@@ -0,0 +1,392 @@
+//! Waiting for transactions to be buried under enough blocks, following
+//! them through reorgs.
+use crate::cancel::Limits;
+use crate::client::ChainClient;
+use crate::Error;
+use jsonrpsee::core::client::ClientT;
+use std::time::Duration;
+
+/// Code of the error for transactions the node can't find.
+const RPC_INVALID_ADDRESS_OR_KEY: i32 = -5;
+
+/// Start of the error for transactions the node can't find, if it has a
+/// synced `-txindex`. Without it, or while it is syncing, the message
+/// differs.
+const NOT_IN_TXINDEX_MESSAGE: &str =
+    "No such mempool or blockchain transaction. Use gettransaction";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfirmationOutcome {
+    Confirmed {
+        block_hash: bitcoin::BlockHash,
+        height: usize,
+        confirmations: u32,
+    },
+    /// The block the transaction was in was disconnected, and the
+    /// transaction was not confirmed again before the timeout.
+    Reorged {
+        previously_in: bitcoin::BlockHash,
+    },
+    /// The transaction is neither in the main chain nor in the mempool.
+    Evicted,
+    /// The transaction is not in the mempool or in the blocks searched, and
+    /// the node has no `-txindex` to look for it in older blocks. It may be
+    /// confirmed in a block further back, or gone.
+    Unknown,
+    TimedOut {
+        best_seen_confirmations: u32,
+    },
+}
+
+/// Where the transaction was last seen.
+enum Seen {
+    /// In the mempool, while the tip was at this height.
+    Mempool {
+        tip_height: usize,
+    },
+    Block(bitcoin::BlockHash),
+}
+
+/// Result of looking for a transaction.
+enum Lookup {
+    Found(Seen),
+    /// Neither in the main chain nor in the mempool.
+    Gone,
+    /// Not found, but it may be in a block that was not searched.
+    Unknown,
+}
+
+/// Polls every `poll_interval` until `txid` has `confirmations`
+/// confirmations, giving up after `timeout`.
+///
+/// The transaction is looked up through the block it was last seen in, so
+/// `-txindex` is not needed as long as the transaction is in the mempool or
+/// in one of the last `confirmations` blocks when this is called. Otherwise
+/// the outcome is [`ConfirmationOutcome::Unknown`] rather than
+/// [`ConfirmationOutcome::Evicted`].
+pub async fn wait_for_confirmations<C>(
+    client: &C,
+    txid: &bitcoin::Txid,
+    confirmations: u32,
+    poll_interval: Duration,
+    timeout: Duration,
+) -> Result<ConfirmationOutcome, Error>
+where
+    C: ClientT + Sync,
+{
+    let limits = Limits::new().with_timeout(timeout);
+    wait_for_confirmations_with_limits(client, txid, confirmations, poll_interval, &limits).await
+}
+
+/// Like [`wait_for_confirmations`], but gives up once `limits` are reached.
+/// The deadline of `limits` has the same outcome as the timeout of
+/// [`wait_for_confirmations`], and cancelling returns [`Error::Cancelled`].
+pub async fn wait_for_confirmations_with_limits<C>(
+    client: &C,
+    txid: &bitcoin::Txid,
+    confirmations: u32,
+    poll_interval: Duration,
+    limits: &Limits,
+) -> Result<ConfirmationOutcome, Error>
+where
+    C: ClientT + Sync,
+{
+    let mut seen = None;
+    let mut previously_in = None;
+    let mut best_seen_confirmations = 0;
+    let poll = async {
+        loop {
+            if let Some(Seen::Block(block_hash)) = seen {
+                let header = client.getblockheader(&block_hash).await?;
+                // Blocks that are not in the main chain have -1 confirmations
+                if header.confirmations > 0 {
+                    let block_confirmations = header.confirmations as u32;
+                    best_seen_confirmations = best_seen_confirmations.max(block_confirmations);
+                    if block_confirmations >= confirmations {
+                        return Ok(ConfirmationOutcome::Confirmed {
+                            block_hash,
+                            height: header.height,
+                            confirmations: block_confirmations,
+                        });
+                    }
+                    tokio::time::sleep(poll_interval).await;
+                    continue;
+                }
+                previously_in = Some(block_hash);
+            }
+            seen = match locate(client, txid, confirmations, seen.as_ref()).await? {
+                Lookup::Found(found) => Some(found),
+                Lookup::Gone => return Ok(ConfirmationOutcome::Evicted),
+                Lookup::Unknown => return Ok(ConfirmationOutcome::Unknown),
+            };
+            match seen {
+                Some(Seen::Block(block_hash)) if previously_in == Some(block_hash) => {
+                    previously_in = None
+                }
+                Some(Seen::Block(_)) | None => (),
+                Some(Seen::Mempool { .. }) => tokio::time::sleep(poll_interval).await,
+            }
+        }
+    };
+    match limits.start().race(poll, 0, None).await {
+        Err(Error::DeadlineExceeded { .. }) => Ok(match (previously_in, &seen) {
+            (Some(previously_in), Some(Seen::Mempool { .. })) => {
+                ConfirmationOutcome::Reorged { previously_in }
+            }
+            _ => ConfirmationOutcome::TimedOut {
+                best_seen_confirmations,
+            },
+        }),
+        outcome => outcome,
+    }
+}
+
+/// Finds the transaction in the mempool, through `-txindex` if the node has
+/// it, or else in the blocks mined since it was last seen in the mempool.
+async fn locate<C>(
+    client: &C,
+    txid: &bitcoin::Txid,
+    confirmations: u32,
+    last_seen: Option<&Seen>,
+) -> Result<Lookup, Error>
+where
+    C: ClientT + Sync,
+{
+    let tip_height = client.getblockcount().await?;
+    match client.getrawtransactionverbose(txid, true, None).await {
+        Ok(tx) => {
+            return Ok(Lookup::Found(match tx.blockhash {
+                Some(block_hash) => Seen::Block(block_hash),
+                None => Seen::Mempool { tip_height },
+            }))
+        }
+        Err(jsonrpsee::core::Error::Call(err))
+            if err.code() == RPC_INVALID_ADDRESS_OR_KEY
+                && err.message().starts_with(NOT_IN_TXINDEX_MESSAGE) =>
+        {
+            return Ok(Lookup::Gone)
+        }
+        Err(jsonrpsee::core::Error::Call(err)) if err.code() == RPC_INVALID_ADDRESS_OR_KEY => (),
+        Err(err) => return Err(err.into()),
+    }
+    let lowest_height = match last_seen {
+        Some(Seen::Mempool { tip_height }) => tip_height + 1,
+        _ => (tip_height + 1).saturating_sub(confirmations as usize),
+    };
+    for height in (lowest_height..=tip_height).rev() {
+        let block_hash = client.getblockhash(height).await?;
+        if client.getblock(&block_hash, None).await?.tx.contains(txid) {
+            return Ok(Lookup::Found(Seen::Block(block_hash)));
+        }
+    }
+    // All blocks since the transaction was in the mempool were searched
+    Ok(match last_seen {
+        Some(Seen::Mempool { .. }) => Lookup::Gone,
+        _ => Lookup::Unknown,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{block_on, rpc_error, MockChain, MockClient};
+    use bitcoin::hashes::Hash as _;
+    use serde_json::{json, Value};
+    use std::sync::{Arc, Mutex};
+
+    const POLL_INTERVAL: Duration = Duration::from_millis(1);
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    fn txid() -> bitcoin::Txid {
+        bitcoin::Txid::from_byte_array([1; 32])
+    }
+
+    /// Node with `txid` in its mempool. `steps[n]` is applied to the chain
+    /// and mempool before answering the `n`th `getblockcount` or
+    /// `getblockheader` call, i.e. once per poll.
+    fn client(
+        txindex: bool,
+        chain: MockChain,
+        steps: Vec<fn(&MockChain, &mut bool)>,
+    ) -> MockClient {
+        let in_mempool = Arc::new(Mutex::new(true));
+        let step = Mutex::new(0);
+        MockClient::new(move |method, params| {
+            let mut in_mempool = in_mempool.lock().unwrap();
+            if matches!(method, "getblockcount" | "getblockheader") {
+                let mut step = step.lock().unwrap();
+                if let Some(apply) = steps.get(*step) {
+                    apply(&chain, &mut in_mempool);
+                }
+                *step += 1;
+            }
+            if method == "getrawtransaction" {
+                let block_hash = if *in_mempool {
+                    None
+                } else if let Some(block_hash) = chain.block_of(&txid()).filter(|_| txindex) {
+                    Some(block_hash)
+                } else if txindex {
+                    return Err(rpc_error(
+                        -5,
+                        "No such mempool or blockchain transaction. Use gettransaction for wallet transactions.",
+                    ));
+                } else {
+                    return Err(rpc_error(
+                        -5,
+                        "No such mempool transaction. Use -txindex or provide a block hash to enable blockchain transaction queries. Use gettransaction for wallet transactions.",
+                    ));
+                };
+                return Ok(raw_transaction(block_hash));
+            }
+            chain.handle(method, params).unwrap()
+        })
+    }
+
+    fn raw_transaction(block_hash: Option<bitcoin::BlockHash>) -> Value {
+        let mut tx = json!({
+            "hex": "",
+            "txid": txid(),
+            "hash": txid(),
+            "version": 2,
+            "size": 100,
+            "vsize": 100,
+            "weight": 400,
+            "locktime": 0,
+            "vin": [],
+            "vout": [],
+        });
+        if let Some(block_hash) = block_hash {
+            tx["blockhash"] = json!(block_hash);
+        }
+        tx
+    }
+
+    fn confirm(chain: &MockChain, in_mempool: &mut bool) {
+        chain.mine_with(vec![txid()]);
+        *in_mempool = false;
+    }
+
+    fn wait(client: &MockClient, confirmations: u32, timeout: Duration) -> ConfirmationOutcome {
+        block_on(wait_for_confirmations(
+            client,
+            &txid(),
+            confirmations,
+            POLL_INTERVAL,
+            timeout,
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn confirm_reorg_reconfirm() {
+        let chain = MockChain::new();
+        chain.mine(5);
+        let steps: Vec<fn(&MockChain, &mut bool)> = vec![
+            // In the mempool
+            |_, _| (),
+            // Mined at height 6
+            confirm,
+            // 1 confirmation
+            |_, _| (),
+            // Reorged out, back in the mempool
+            |chain, in_mempool| {
+                chain.disconnect(1);
+                *in_mempool = true;
+            },
+            |_, _| (),
+            // Mined again at height 6, and another block on top
+            |chain, in_mempool| {
+                confirm(chain, in_mempool);
+                chain.mine(1);
+            },
+        ];
+        let client = client(false, chain.clone(), steps);
+        let outcome = wait(&client, 2, TIMEOUT);
+        assert_eq!(
+            outcome,
+            ConfirmationOutcome::Confirmed {
+                block_hash: chain.block_of(&txid()).unwrap(),
+                height: 6,
+                confirmations: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn reorged_until_timeout() {
+        let chain = MockChain::new();
+        let steps: Vec<fn(&MockChain, &mut bool)> =
+            vec![|_, _| (), confirm, |chain, in_mempool| {
+                chain.disconnect(1);
+                *in_mempool = true;
+            }];
+        let client = client(false, chain.clone(), steps);
+        let outcome = wait(&client, 2, Duration::from_millis(50));
+        assert!(matches!(outcome, ConfirmationOutcome::Reorged { .. }));
+    }
+
+    #[test]
+    fn cancelled_while_in_the_mempool() {
+        let client = client(false, MockChain::new(), vec![]);
+        let cancel = crate::cancel::CancellationToken::new();
+        let limits = Limits::new()
+            .with_cancellation(cancel.clone())
+            .with_timeout(TIMEOUT);
+        let result = block_on(async {
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                cancel.cancel();
+            });
+            wait_for_confirmations_with_limits(&client, &txid(), 1, POLL_INTERVAL, &limits).await
+        });
+        assert!(matches!(result, Err(Error::Cancelled)));
+    }
+
+    #[test]
+    fn evicted_from_mempool() {
+        let chain = MockChain::new();
+        let steps: Vec<fn(&MockChain, &mut bool)> = vec![|_, _| (), |chain, in_mempool| {
+            chain.mine(1);
+            *in_mempool = false;
+        }];
+        let client = client(false, chain, steps);
+        assert_eq!(wait(&client, 1, TIMEOUT), ConfirmationOutcome::Evicted);
+    }
+
+    #[test]
+    fn deep_confirmation_without_txindex_is_unknown() {
+        let chain = MockChain::new();
+        let steps: Vec<fn(&MockChain, &mut bool)> = vec![|chain, in_mempool| {
+            confirm(chain, in_mempool);
+            chain.mine(10);
+        }];
+        let client = client(false, chain, steps);
+        assert_eq!(wait(&client, 3, TIMEOUT), ConfirmationOutcome::Unknown);
+    }
+
+    #[test]
+    fn deep_confirmation_with_txindex() {
+        let chain = MockChain::new();
+        let steps: Vec<fn(&MockChain, &mut bool)> = vec![|chain, in_mempool| {
+            confirm(chain, in_mempool);
+            chain.mine(10);
+        }];
+        let client = client(true, chain, steps);
+        assert!(matches!(
+            wait(&client, 3, TIMEOUT),
+            ConfirmationOutcome::Confirmed {
+                height: 1,
+                confirmations: 11,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn gone_with_txindex_is_evicted() {
+        let chain = MockChain::new();
+        let steps: Vec<fn(&MockChain, &mut bool)> = vec![|_, in_mempool| *in_mempool = false];
+        let client = client(true, chain, steps);
+        assert_eq!(wait(&client, 1, TIMEOUT), ConfirmationOutcome::Evicted);
+    }
+}
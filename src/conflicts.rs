@@ -0,0 +1,590 @@
+//! Noticing when unconfirmed transactions, such as deposits, are replaced
+//! or double-spent before they confirm.
+use crate::cancel::{Limits, Running};
+use crate::client::{ChainClient, WalletClient, WalletTransaction};
+use crate::Error;
+use futures::Stream;
+use jsonrpsee::core::client::ClientT;
+use jsonrpsee::http_client::HttpClient;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::Duration;
+
+/// Code of the error for transactions the node or wallet can't find.
+const RPC_INVALID_ADDRESS_OR_KEY: i32 = -5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum ConflictStatus {
+    /// The transaction confirmed. It is no longer watched.
+    Confirmed { block_hash: bitcoin::BlockHash },
+    /// A conflicting transaction confirmed, so the transaction can never
+    /// confirm unless the block is disconnected. It is no longer watched.
+    DoubleSpent { block_hash: bitcoin::BlockHash },
+    /// A conflicting transaction is in the mempool instead of the
+    /// transaction, e.g. after it was bumped with RBF.
+    Replaced,
+    /// The wallet knows of a conflicting transaction, but neither is
+    /// confirmed and the conflicting one is not in the mempool.
+    Conflicted,
+    /// The transaction left the mempool without confirming, and no
+    /// conflicting transaction is known.
+    Evicted,
+}
+
+impl ConflictStatus {
+    /// Whether the transaction is no longer watched after this status.
+    pub fn is_final(&self) -> bool {
+        matches!(
+            self,
+            ConflictStatus::Confirmed { .. } | ConflictStatus::DoubleSpent { .. }
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ConflictEvent {
+    /// The watched transaction.
+    pub txid: bitcoin::Txid,
+    /// Set for [`ConflictStatus::DoubleSpent`], [`ConflictStatus::Replaced`]
+    /// and [`ConflictStatus::Conflicted`].
+    pub conflicting_txid: Option<bitcoin::Txid>,
+    pub status: ConflictStatus,
+}
+
+/// Returns `None` for transactions that are not in the wallet.
+async fn get_wallet_transaction<C>(
+    client: &C,
+    txid: &bitcoin::Txid,
+) -> Result<Option<WalletTransaction>, Error>
+where
+    C: ClientT + Sync,
+{
+    match client.gettransaction(txid, true, false).await {
+        Ok(tx) => Ok(Some(tx)),
+        Err(jsonrpsee::core::Error::Call(err)) if err.code() == RPC_INVALID_ADDRESS_OR_KEY => {
+            Ok(None)
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+async fn in_mempool<C>(client: &C, txid: &bitcoin::Txid) -> Result<bool, Error>
+where
+    C: ClientT + Sync,
+{
+    match client.getmempoolentry(txid).await {
+        Ok(_) => Ok(true),
+        Err(jsonrpsee::core::Error::Call(err)) if err.code() == RPC_INVALID_ADDRESS_OR_KEY => {
+            Ok(false)
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Block in the main chain that `txid` is in, looked up in the wallet and
+/// then through `-txindex`. Without `-txindex`, transactions that are not
+/// in the wallet are never found.
+async fn confirmed_in<C>(
+    client: &C,
+    txid: &bitcoin::Txid,
+) -> Result<Option<bitcoin::BlockHash>, Error>
+where
+    C: ClientT + Sync,
+{
+    if let Some(tx) = get_wallet_transaction(client, txid).await? {
+        if tx.confirmations > 0 {
+            return Ok(tx.blockhash);
+        }
+    }
+    match client.getrawtransactionverbose(txid, true, None).await {
+        Ok(tx) => Ok(tx.blockhash.filter(|_| tx.confirmations > Some(0))),
+        Err(jsonrpsee::core::Error::Call(err)) if err.code() == RPC_INVALID_ADDRESS_OR_KEY => {
+            Ok(None)
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Polls watched transactions for conflicts, see [`watch_for_conflicts`].
+pub struct ConflictWatcher<C = HttpClient> {
+    client: C,
+    poll_interval: Duration,
+    polled: bool,
+    /// Last status reported for every watched transaction, `None` while it
+    /// is in the mempool without conflicts.
+    watched: HashMap<bitcoin::Txid, Option<ConflictEvent>>,
+    events: VecDeque<ConflictEvent>,
+    limits: Limits,
+    /// Started on the first poll.
+    running: Option<Running>,
+    polls: usize,
+}
+
+impl<C> ConflictWatcher<C>
+where
+    C: ClientT + Sync,
+{
+    pub fn new(
+        client: C,
+        txids: impl IntoIterator<Item = bitcoin::Txid>,
+        poll_interval: Duration,
+    ) -> Self {
+        ConflictWatcher {
+            client,
+            poll_interval,
+            polled: false,
+            watched: txids.into_iter().map(|txid| (txid, None)).collect(),
+            events: VecDeque::new(),
+            limits: Limits::default(),
+            running: None,
+            polls: 0,
+        }
+    }
+
+    /// Stops polling with [`Error::Cancelled`] or [`Error::DeadlineExceeded`]
+    /// once `limits` are reached, counting completed polls. [`Self::run`]
+    /// ends after yielding the error.
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn watch(&mut self, txid: bitcoin::Txid) {
+        self.watched.entry(txid).or_default();
+    }
+
+    pub fn unwatch(&mut self, txid: &bitcoin::Txid) {
+        self.watched.remove(txid);
+    }
+
+    pub fn watched(&self) -> impl Iterator<Item = &bitcoin::Txid> {
+        self.watched.keys()
+    }
+
+    /// Checks every watched transaction once, and returns an event for
+    /// every one whose status changed since the last check.
+    pub async fn poll(&mut self) -> Result<Vec<ConflictEvent>, Error> {
+        let running = self
+            .running
+            .get_or_insert_with(|| self.limits.start())
+            .clone();
+        let mut events = vec![];
+        let txids: Vec<_> = self.watched.keys().copied().collect();
+        for txid in txids {
+            let event = running.race(self.check(txid), self.polls, None).await?;
+            if event.is_some_and(|event| event.status.is_final()) {
+                self.watched.remove(&txid);
+            } else {
+                let last = self.watched.insert(txid, event);
+                if last.flatten() == event {
+                    continue;
+                }
+            }
+            events.extend(event);
+        }
+        self.polls += 1;
+        Ok(events)
+    }
+
+    /// Yields events until no transactions are watched. Transactions are
+    /// checked every `poll_interval`.
+    pub fn run(self) -> impl Stream<Item = Result<ConflictEvent, Error>> {
+        futures::stream::unfold(self, |mut watcher| async move {
+            let event = watcher.next_event().await?;
+            Some((event, watcher))
+        })
+    }
+
+    async fn next_event(&mut self) -> Option<Result<ConflictEvent, Error>> {
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Some(Ok(event));
+            }
+            if self.watched.is_empty() {
+                return None;
+            }
+            if self.polled {
+                let sleep = async {
+                    tokio::time::sleep(self.poll_interval).await;
+                    Ok(())
+                };
+                let running = self.running.get_or_insert_with(|| self.limits.start());
+                if let Err(err) = running.race(sleep, self.polls, None).await {
+                    self.watched.clear();
+                    return Some(Err(err));
+                }
+            }
+            self.polled = true;
+            match self.poll().await {
+                Ok(events) => self.events.extend(events),
+                Err(err @ (Error::Cancelled | Error::DeadlineExceeded { .. })) => {
+                    self.watched.clear();
+                    return Some(Err(err));
+                }
+                Err(err) => return Some(Err(err)),
+            }
+        }
+    }
+
+    /// Current status of `txid`, or `None` if it is in the mempool without
+    /// conflicts.
+    async fn check(&self, txid: bitcoin::Txid) -> Result<Option<ConflictEvent>, Error> {
+        let event = |conflicting_txid, status| {
+            Ok(Some(ConflictEvent {
+                txid,
+                conflicting_txid,
+                status,
+            }))
+        };
+        let wallet_tx = get_wallet_transaction(&self.client, &txid).await?;
+        let mut conflicts = vec![];
+        if let Some(wallet_tx) = wallet_tx {
+            if let (true, Some(block_hash)) = (wallet_tx.confirmations > 0, wallet_tx.blockhash) {
+                return event(None, ConflictStatus::Confirmed { block_hash });
+            }
+            // The replacement is listed first, as it is the most likely to
+            // be mined
+            conflicts.extend(wallet_tx.replaced_by_txid);
+            conflicts.extend(wallet_tx.walletconflicts);
+            let mut seen = HashSet::new();
+            conflicts.retain(|conflict| seen.insert(*conflict));
+        }
+        // A conflict may have confirmed since the wallet transaction was
+        // fetched, so this is checked even if it had no negative
+        // confirmations
+        for conflict in &conflicts {
+            if let Some(block_hash) = confirmed_in(&self.client, conflict).await? {
+                return event(Some(*conflict), ConflictStatus::DoubleSpent { block_hash });
+            }
+        }
+        let in_mempool = in_mempool(&self.client, &txid).await?;
+        if let Some(first_conflict) = conflicts.first() {
+            // Checked after the transaction itself, so a replacement in
+            // between is seen as one
+            for conflict in &conflicts {
+                if self::in_mempool(&self.client, conflict).await? {
+                    return event(Some(*conflict), ConflictStatus::Replaced);
+                }
+            }
+            return event(Some(*first_conflict), ConflictStatus::Conflicted);
+        }
+        if in_mempool {
+            return Ok(None);
+        }
+        // The transaction may have confirmed after it was looked up
+        if let Some(block_hash) = confirmed_in(&self.client, &txid).await? {
+            return event(None, ConflictStatus::Confirmed { block_hash });
+        }
+        // Or been replaced by a transaction the wallet only now knows of
+        if let Some(wallet_tx) = get_wallet_transaction(&self.client, &txid).await? {
+            if let Some(conflict) = wallet_tx
+                .replaced_by_txid
+                .or_else(|| wallet_tx.walletconflicts.first().copied())
+            {
+                // Classified on the next poll
+                return event(Some(conflict), ConflictStatus::Conflicted);
+            }
+        }
+        event(None, ConflictStatus::Evicted)
+    }
+}
+
+/// Yields an event whenever one of `txids` gains a wallet conflict, leaves
+/// the mempool without confirming, or is resolved by it or a conflicting
+/// transaction confirming. Every status is reported once per transaction,
+/// until it changes.
+///
+/// Conflicts are only known for transactions in the wallet, including
+/// watch-only ones. Whether a conflicting transaction that is not in the
+/// wallet confirmed can only be found with `-txindex`.
+pub fn watch_for_conflicts<C>(
+    client: C,
+    txids: impl IntoIterator<Item = bitcoin::Txid>,
+    poll_interval: Duration,
+) -> impl Stream<Item = Result<ConflictEvent, Error>>
+where
+    C: ClientT + Sync,
+{
+    ConflictWatcher::new(client, txids, poll_interval).run()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{block_on, mempool_entry, rpc_error, MockClient};
+    use bitcoin::hashes::Hash as _;
+    use serde_json::{json, Value};
+    use std::sync::{Arc, Mutex};
+
+    fn watched() -> bitcoin::Txid {
+        bitcoin::Txid::from_byte_array([1; 32])
+    }
+
+    fn replacement() -> bitcoin::Txid {
+        bitcoin::Txid::from_byte_array([2; 32])
+    }
+
+    fn block_hash() -> bitcoin::BlockHash {
+        bitcoin::BlockHash::from_byte_array([9; 32])
+    }
+
+    #[derive(Default)]
+    struct State {
+        mempool: HashSet<bitcoin::Txid>,
+        confirmed: HashSet<bitcoin::Txid>,
+        /// Wallet transactions and their wallet conflicts.
+        wallet: HashMap<bitcoin::Txid, Vec<bitcoin::Txid>>,
+    }
+
+    impl State {
+        /// The watched transaction in the wallet and mempool.
+        fn new() -> Self {
+            let mut state = State::default();
+            state.mempool.insert(watched());
+            state.wallet.insert(watched(), vec![]);
+            state
+        }
+
+        /// Replaces the watched transaction with one from the wallet.
+        fn replace(&mut self) {
+            self.mempool.remove(&watched());
+            self.mempool.insert(replacement());
+            self.wallet.insert(watched(), vec![replacement()]);
+            self.wallet.insert(replacement(), vec![watched()]);
+        }
+
+        fn confirm(&mut self, txid: bitcoin::Txid) {
+            self.mempool.remove(&txid);
+            self.confirmed.insert(txid);
+        }
+
+        fn confirmations(&self, txid: &bitcoin::Txid) -> i64 {
+            if self.confirmed.contains(txid) {
+                1
+            } else if self.wallet[txid]
+                .iter()
+                .any(|conflict| self.confirmed.contains(conflict))
+            {
+                -1
+            } else {
+                0
+            }
+        }
+
+        fn wallet_transaction(&self, txid: &bitcoin::Txid) -> Value {
+            let mut tx = json!({
+                "amount": 0.001,
+                "confirmations": self.confirmations(txid),
+                "txid": txid,
+                "walletconflicts": self.wallet[txid],
+                "time": 1_700_000_000,
+                "timereceived": 1_700_000_000,
+                "bip125-replaceable": "yes",
+                "details": [],
+                "hex": "",
+            });
+            if self.confirmed.contains(txid) {
+                tx["blockhash"] = json!(block_hash());
+            }
+            tx
+        }
+
+        fn raw_transaction(&self, txid: &bitcoin::Txid) -> Value {
+            let mut tx = json!({
+                "hex": "",
+                "txid": txid,
+                "hash": txid,
+                "version": 2,
+                "size": 100,
+                "vsize": 100,
+                "weight": 400,
+                "locktime": 0,
+                "vin": [],
+                "vout": [],
+            });
+            if self.confirmed.contains(txid) {
+                tx["blockhash"] = json!(block_hash());
+                tx["confirmations"] = json!(1);
+            }
+            tx
+        }
+    }
+
+    /// Change of the node state before a call.
+    type Step = (&'static str, usize, fn(&mut State));
+
+    /// Node with `-txindex` in `state`. A step `(method, n, apply)` is
+    /// applied before the `n`th call of `method`, counting from 0.
+    fn client(state: &Arc<Mutex<State>>, steps: Vec<Step>) -> MockClient {
+        let state = state.clone();
+        let calls = Mutex::new(HashMap::<String, usize>::new());
+        MockClient::new(move |method, params| {
+            let mut state = state.lock().unwrap();
+            let mut calls = calls.lock().unwrap();
+            let call = calls.entry(method.to_owned()).or_default();
+            for (step_method, n, apply) in &steps {
+                if *step_method == method && n == call {
+                    apply(&mut state);
+                }
+            }
+            *call += 1;
+            let txid: bitcoin::Txid = serde_json::from_value(params[0].clone()).unwrap();
+            let not_found = || {
+                Err(rpc_error(
+                    RPC_INVALID_ADDRESS_OR_KEY,
+                    "No such mempool or blockchain transaction",
+                ))
+            };
+            match method {
+                "gettransaction" if state.wallet.contains_key(&txid) => {
+                    Ok(state.wallet_transaction(&txid))
+                }
+                "getmempoolentry" if state.mempool.contains(&txid) => Ok(mempool_entry(txid)),
+                "getrawtransaction"
+                    if state.mempool.contains(&txid) || state.confirmed.contains(&txid) =>
+                {
+                    Ok(state.raw_transaction(&txid))
+                }
+                "gettransaction" | "getmempoolentry" | "getrawtransaction" => not_found(),
+                method => panic!("unexpected {method}"),
+            }
+        })
+    }
+
+    fn event(conflicting_txid: Option<bitcoin::Txid>, status: ConflictStatus) -> ConflictEvent {
+        ConflictEvent {
+            txid: watched(),
+            conflicting_txid,
+            status,
+        }
+    }
+
+    fn watcher(client: MockClient) -> ConflictWatcher<MockClient> {
+        ConflictWatcher::new(client, [watched()], Duration::from_millis(1))
+    }
+
+    #[test]
+    fn confirms_without_conflicts() {
+        let state = Arc::new(Mutex::new(State::new()));
+        let mut watcher = watcher(client(&state, vec![]));
+        block_on(async {
+            assert_eq!(watcher.poll().await.unwrap(), []);
+            state.lock().unwrap().confirm(watched());
+            let confirmed = ConflictStatus::Confirmed {
+                block_hash: block_hash(),
+            };
+            assert_eq!(watcher.poll().await.unwrap(), [event(None, confirmed)]);
+        });
+        assert_eq!(watcher.watched().count(), 0);
+    }
+
+    #[test]
+    fn replaced_then_double_spent() {
+        let state = Arc::new(Mutex::new(State::new()));
+        let mut watcher = watcher(client(&state, vec![]));
+        block_on(async {
+            state.lock().unwrap().replace();
+            let replaced = event(Some(replacement()), ConflictStatus::Replaced);
+            assert_eq!(watcher.poll().await.unwrap(), [replaced]);
+            // Reported once
+            assert_eq!(watcher.poll().await.unwrap(), []);
+            state.lock().unwrap().confirm(replacement());
+            let double_spent = ConflictStatus::DoubleSpent {
+                block_hash: block_hash(),
+            };
+            assert_eq!(
+                watcher.poll().await.unwrap(),
+                [event(Some(replacement()), double_spent)]
+            );
+        });
+        assert_eq!(watcher.watched().count(), 0);
+    }
+
+    #[test]
+    fn evicted_and_back() {
+        let state = Arc::new(Mutex::new(State::new()));
+        let mut watcher = watcher(client(&state, vec![]));
+        block_on(async {
+            state.lock().unwrap().mempool.clear();
+            let evicted = event(None, ConflictStatus::Evicted);
+            assert_eq!(watcher.poll().await.unwrap(), [evicted]);
+            state.lock().unwrap().mempool.insert(watched());
+            assert_eq!(watcher.poll().await.unwrap(), []);
+            state.lock().unwrap().mempool.clear();
+            assert_eq!(watcher.poll().await.unwrap(), [evicted]);
+        });
+    }
+
+    #[test]
+    fn conflict_also_unconfirmed() {
+        let state = Arc::new(Mutex::new(State::new()));
+        let mut watcher = watcher(client(&state, vec![]));
+        {
+            let mut node = state.lock().unwrap();
+            node.replace();
+            // The replacement was evicted as well
+            node.mempool.clear();
+        }
+        block_on(async {
+            let conflicted = event(Some(replacement()), ConflictStatus::Conflicted);
+            assert_eq!(watcher.poll().await.unwrap(), [conflicted]);
+        });
+    }
+
+    #[test]
+    fn confirmed_between_wallet_and_mempool_lookup() {
+        let state = Arc::new(Mutex::new(State::new()));
+        let steps: Vec<Step> = vec![("getmempoolentry", 0, |state| state.confirm(watched()))];
+        let mut watcher = watcher(client(&state, steps));
+        let events = block_on(watcher.poll()).unwrap();
+        let confirmed = ConflictStatus::Confirmed {
+            block_hash: block_hash(),
+        };
+        assert_eq!(events, [event(None, confirmed)]);
+    }
+
+    #[test]
+    fn conflict_confirmed_after_wallet_lookup() {
+        let state = Arc::new(Mutex::new(State::new()));
+        state.lock().unwrap().replace();
+        // The wallet still reports the replacement as unconfirmed
+        let steps: Vec<Step> = vec![("getrawtransaction", 0, |state| state.confirm(replacement()))];
+        let mut watcher = watcher(client(&state, steps));
+        let events = block_on(watcher.poll()).unwrap();
+        let double_spent = ConflictStatus::DoubleSpent {
+            block_hash: block_hash(),
+        };
+        assert_eq!(events, [event(Some(replacement()), double_spent)]);
+    }
+
+    #[test]
+    fn replaced_after_wallet_lookup() {
+        let state = Arc::new(Mutex::new(State::new()));
+        let steps: Vec<Step> = vec![("getmempoolentry", 0, State::replace)];
+        let mut watcher = watcher(client(&state, steps));
+        block_on(async {
+            // Seen as a conflict first, and classified on the next poll
+            let conflicted = event(Some(replacement()), ConflictStatus::Conflicted);
+            assert_eq!(watcher.poll().await.unwrap(), [conflicted]);
+            let replaced = event(Some(replacement()), ConflictStatus::Replaced);
+            assert_eq!(watcher.poll().await.unwrap(), [replaced]);
+        });
+    }
+
+    #[test]
+    fn run_ends_once_resolved() {
+        use futures::StreamExt as _;
+
+        let state = Arc::new(Mutex::new(State::new()));
+        state.lock().unwrap().confirm(watched());
+        let events: Vec<_> = block_on(
+            watch_for_conflicts(
+                client(&state, vec![]),
+                [watched()],
+                Duration::from_millis(1),
+            )
+            .collect(),
+        );
+        assert_eq!(events.len(), 1);
+        assert!(events[0].as_ref().unwrap().status.is_final());
+    }
+}
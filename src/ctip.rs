@@ -0,0 +1,275 @@
+//! Following the CTIP of a sidechain as deposits and withdrawal payouts move
+//! it.
+use crate::client::{ChainClient, Ctip, DrivechainClient, SidechainId};
+use crate::Error;
+use jsonrpsee::core::client::ClientT;
+use jsonrpsee::http_client::HttpClient;
+use std::collections::VecDeque;
+
+/// Number of blocks a [`CtipTracker`] remembers, and so the deepest reorg
+/// it can revert events for.
+pub const MAX_REORG_DEPTH: usize = 100;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CtipEvent {
+    /// The CTIP amount increased by `delta`.
+    Deposit {
+        new_ctip: Ctip,
+        delta: bitcoin::Amount,
+    },
+    /// The CTIP amount decreased by `delta`.
+    WithdrawalPayout {
+        new_ctip: Ctip,
+        delta: bitcoin::Amount,
+    },
+    /// `event`, previously yielded at `block_hash`, was undone because the
+    /// block was disconnected.
+    Revert {
+        block_hash: bitcoin::BlockHash,
+        event: Box<CtipEvent>,
+    },
+}
+
+/// Error code of `listsidechainctip` if the sidechain has no CTIP or is not
+/// active, `RPC_MISC_ERROR`.
+const RPC_NO_CTIP: i32 = -1;
+
+/// Returns `true` if `err` is the error of `listsidechainctip` for a
+/// sidechain without CTIP.
+pub(crate) fn is_no_ctip_error(err: &jsonrpsee::types::ErrorObject<'_>) -> bool {
+    err.code() == RPC_NO_CTIP
+}
+
+/// The current CTIP of `sidechain`, or `None` if it has none yet.
+pub async fn get_sidechain_ctip<C>(
+    client: &C,
+    sidechain: SidechainId,
+) -> Result<Option<Ctip>, Error>
+where
+    C: ClientT + Sync,
+{
+    match client.listsidechainctip(sidechain).await {
+        Ok(ctip) => Ok(Some(ctip)),
+        Err(jsonrpsee::core::Error::Call(err)) if is_no_ctip_error(&err) => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+struct Seen {
+    block_hash: bitcoin::BlockHash,
+    height: usize,
+    ctip: Option<Ctip>,
+    event: Option<CtipEvent>,
+}
+
+/// Polls the CTIP of a single sidechain and turns changes into events.
+///
+/// The node only reports the current CTIP, so several deposits or payouts
+/// between two polls are reported as a single event with their net delta.
+pub struct CtipTracker<C = HttpClient> {
+    pub sidechain: SidechainId,
+    pub client: C,
+    seen: VecDeque<Seen>,
+}
+
+impl<C> CtipTracker<C>
+where
+    C: ClientT + Sync,
+{
+    pub fn new(client: C, sidechain: SidechainId) -> Self {
+        CtipTracker {
+            sidechain,
+            client,
+            seen: VecDeque::new(),
+        }
+    }
+
+    /// Returns the events since the last poll: reverts of events in blocks
+    /// that were disconnected first, then the change in the current tip, if
+    /// any. The first poll only records the current CTIP.
+    pub async fn poll(&mut self) -> Result<Vec<CtipEvent>, Error> {
+        let tip = self.client.getbestblockhash().await?;
+        if self.seen.back().is_some_and(|seen| seen.block_hash == tip) {
+            return Ok(vec![]);
+        }
+        let height = self.client.getblockheader(&tip).await?.height;
+        let mut events = vec![];
+        while let Some(seen) = self.seen.back() {
+            if seen.height <= height
+                && self.client.getblockhash(seen.height).await? == seen.block_hash
+            {
+                break;
+            }
+            let seen = self.seen.pop_back().unwrap();
+            if let Some(event) = seen.event {
+                events.push(CtipEvent::Revert {
+                    block_hash: seen.block_hash,
+                    event: Box::new(event),
+                });
+            }
+        }
+        let ctip = get_sidechain_ctip(&self.client, self.sidechain).await?;
+        let event = match self.seen.back() {
+            Some(previous) => change(previous.ctip, ctip),
+            None => None,
+        };
+        events.extend(event.clone());
+        self.seen.push_back(Seen {
+            block_hash: tip,
+            height,
+            ctip,
+            event,
+        });
+        if self.seen.len() > MAX_REORG_DEPTH {
+            self.seen.pop_front();
+        }
+        Ok(events)
+    }
+}
+
+fn change(previous: Option<Ctip>, current: Option<Ctip>) -> Option<CtipEvent> {
+    let new_ctip = current?;
+    if previous == Some(new_ctip) {
+        return None;
+    }
+    let previous_amount = previous.map_or(bitcoin::Amount::ZERO, |ctip| ctip.amount.0);
+    if new_ctip.amount.0 >= previous_amount {
+        Some(CtipEvent::Deposit {
+            new_ctip,
+            delta: new_ctip.amount.0 - previous_amount,
+        })
+    } else {
+        Some(CtipEvent::WithdrawalPayout {
+            new_ctip,
+            delta: previous_amount - new_ctip.amount.0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::AmountBtc;
+    use crate::mock::{block_on, rpc_error, MockChain, MockClient};
+    use bitcoin::hashes::Hash as _;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    /// Chain with the CTIP of sidechain 0 after each block.
+    #[derive(Clone)]
+    struct Node {
+        chain: MockChain,
+        ctips: Arc<Mutex<HashMap<bitcoin::BlockHash, Ctip>>>,
+    }
+
+    impl Node {
+        fn new() -> Self {
+            Node {
+                chain: MockChain::new(),
+                ctips: Arc::default(),
+            }
+        }
+
+        /// Mines a block after which the CTIP is `ctip`.
+        fn mine(&self, ctip: Option<Ctip>) -> bitcoin::BlockHash {
+            let block_hash = self.chain.mine(1)[0];
+            if let Some(ctip) = ctip {
+                self.ctips.lock().unwrap().insert(block_hash, ctip);
+            }
+            block_hash
+        }
+
+        fn tracker(&self) -> CtipTracker<MockClient> {
+            let node = self.clone();
+            let client = MockClient::new(move |method, params| match method {
+                "listsidechainctip" => {
+                    assert_eq!(params[0], 0);
+                    match node.ctips.lock().unwrap().get(&node.chain.tip()) {
+                        Some(ctip) => Ok(serde_json::to_value(ctip).unwrap()),
+                        None => Err(rpc_error(RPC_NO_CTIP, "No CTIP found for sidechain")),
+                    }
+                }
+                _ => node.chain.handle(method, params).unwrap(),
+            });
+            CtipTracker::new(client, SidechainId(0))
+        }
+    }
+
+    fn ctip(txid: u8, btc: f64) -> Ctip {
+        Ctip {
+            txid: bitcoin::Txid::from_byte_array([txid; 32]),
+            n: 0,
+            amount: AmountBtc(bitcoin::Amount::from_btc(btc).unwrap()),
+        }
+    }
+
+    fn btc(btc: f64) -> bitcoin::Amount {
+        bitcoin::Amount::from_btc(btc).unwrap()
+    }
+
+    #[test]
+    fn deposit_and_withdrawal_payout() {
+        let node = Node::new();
+        let mut tracker = node.tracker();
+        assert_eq!(block_on(tracker.poll()).unwrap(), []);
+        node.mine(Some(ctip(1, 1.0)));
+        assert_eq!(
+            block_on(tracker.poll()).unwrap(),
+            [CtipEvent::Deposit {
+                new_ctip: ctip(1, 1.0),
+                delta: btc(1.0),
+            }]
+        );
+        // Nothing changed
+        assert_eq!(block_on(tracker.poll()).unwrap(), []);
+        node.mine(Some(ctip(2, 0.6)));
+        assert_eq!(
+            block_on(tracker.poll()).unwrap(),
+            [CtipEvent::WithdrawalPayout {
+                new_ctip: ctip(2, 0.6),
+                delta: btc(0.4),
+            }]
+        );
+        // A block that doesn't move the CTIP
+        node.mine(Some(ctip(2, 0.6)));
+        assert_eq!(block_on(tracker.poll()).unwrap(), []);
+    }
+
+    #[test]
+    fn reorg_reverts_the_deposit() {
+        let node = Node::new();
+        let mut tracker = node.tracker();
+        node.mine(Some(ctip(1, 1.0)));
+        block_on(tracker.poll()).unwrap();
+        let deposit_block = node.mine(Some(ctip(2, 1.5)));
+        let deposit = CtipEvent::Deposit {
+            new_ctip: ctip(2, 1.5),
+            delta: btc(0.5),
+        };
+        assert_eq!(
+            block_on(tracker.poll()).unwrap(),
+            std::slice::from_ref(&deposit)
+        );
+
+        // The deposit block is replaced by two blocks without it
+        node.chain.disconnect(1);
+        node.mine(Some(ctip(1, 1.0)));
+        node.mine(Some(ctip(1, 1.0)));
+        assert_eq!(
+            block_on(tracker.poll()).unwrap(),
+            [CtipEvent::Revert {
+                block_hash: deposit_block,
+                event: Box::new(deposit),
+            }]
+        );
+        // The deposit is mined again
+        node.mine(Some(ctip(2, 1.5)));
+        assert_eq!(
+            block_on(tracker.poll()).unwrap(),
+            [CtipEvent::Deposit {
+                new_ctip: ctip(2, 1.5),
+                delta: btc(0.5),
+            }]
+        );
+    }
+}
@@ -0,0 +1,903 @@
+//! Deposit address formats used by sidechains, and attributing deposits to
+//! the mainchain addresses they were received on.
+#[cfg(feature = "client")]
+use crate::cancel::{Limits, Partial};
+use crate::client::SidechainId;
+#[cfg(feature = "client")]
+use crate::client::{DrivechainClient, WalletClient};
+#[cfg(feature = "client")]
+use crate::Error;
+use bitcoin::address::NetworkUnchecked;
+use bitcoin::hashes::{sha256, Hash as _};
+#[cfg(feature = "client")]
+use jsonrpsee::core::client::ClientT;
+#[cfg(feature = "client")]
+use std::collections::HashMap;
+use std::fmt;
+
+/// Number of hex characters of the checksum in the legacy format.
+const LEGACY_CHECKSUM_LEN: usize = 6;
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum AddrError {
+    #[error("deposit address does not match any known format")]
+    UnknownFormat,
+    #[error("deposit address is malformed: {reason}")]
+    Malformed { reason: &'static str },
+    #[error("deposit address checksum mismatch, expected {expected}")]
+    Checksum { expected: String },
+}
+
+pub trait DepositAddressFormat: Send + Sync {
+    /// Short name identifying the format.
+    fn name(&self) -> &'static str;
+
+    fn validate(&self, address: &str) -> Result<(), AddrError>;
+
+    /// The sidechain a valid `address` is for, if the format encodes it.
+    fn sidechain_id_hint(&self, address: &str) -> Option<SidechainId>;
+
+    /// Whether a deposit with destination `dest_bytes` asks the sidechain to
+    /// refund it. There is no common refund destination, so by default none
+    /// is recognized.
+    fn is_refund(&self, _dest_bytes: &[u8]) -> bool {
+        false
+    }
+}
+
+/// `s<sidechain number>_<destination>_<checksum>`, where the checksum is the
+/// first 6 hex characters of the SHA256 of everything before it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LegacyFormat;
+
+impl LegacyFormat {
+    /// Formats `destination` as a deposit address for `sidechain_number`.
+    pub fn format(sidechain_number: SidechainId, destination: &str) -> String {
+        let prefix = format!("s{sidechain_number}_{destination}_");
+        let checksum = Self::checksum(&prefix);
+        format!("{prefix}{checksum}")
+    }
+
+    fn checksum(prefix: &str) -> String {
+        let hash = sha256::Hash::hash(prefix.as_bytes()).to_string();
+        hash[..LEGACY_CHECKSUM_LEN].to_owned()
+    }
+
+    /// Splits an address into sidechain number, destination and checksum.
+    fn split(address: &str) -> Result<(SidechainId, &str, &str), AddrError> {
+        let malformed = |reason| AddrError::Malformed { reason };
+        let rest = address
+            .strip_prefix('s')
+            .ok_or(malformed("missing `s` prefix"))?;
+        let (sidechain_number, rest) = rest
+            .split_once('_')
+            .ok_or(malformed("missing sidechain number"))?;
+        let sidechain_number = sidechain_number
+            .parse()
+            .map_err(|_| malformed("invalid sidechain number"))?;
+        let (destination, checksum) = rest.rsplit_once('_').ok_or(malformed("missing checksum"))?;
+        if destination.is_empty() {
+            return Err(malformed("empty destination"));
+        }
+        Ok((sidechain_number, destination, checksum))
+    }
+}
+
+impl DepositAddressFormat for LegacyFormat {
+    fn name(&self) -> &'static str {
+        "legacy"
+    }
+
+    fn validate(&self, address: &str) -> Result<(), AddrError> {
+        let (_, _, checksum) = Self::split(address)?;
+        let prefix = &address[..address.len() - checksum.len()];
+        let expected = Self::checksum(prefix);
+        if checksum != expected {
+            return Err(AddrError::Checksum { expected });
+        }
+        Ok(())
+    }
+
+    fn sidechain_id_hint(&self, address: &str) -> Option<SidechainId> {
+        Self::split(address)
+            .ok()
+            .map(|(sidechain_number, _, _)| sidechain_number)
+    }
+}
+
+/// Formats tried by [`DepositAddress::parse_any`], in order.
+pub static DEFAULT_FORMATS: &[&dyn DepositAddressFormat] = &[&LegacyFormat];
+
+/// A deposit address that was validated against one of the known formats.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DepositAddress {
+    address: String,
+    format: &'static str,
+}
+
+impl DepositAddress {
+    /// Parses `address` with the first of [`DEFAULT_FORMATS`] that accepts it.
+    pub fn parse_any(address: &str) -> Result<Self, AddrError> {
+        Self::parse_with(address, DEFAULT_FORMATS)
+    }
+
+    /// Parses `address` with the first of `formats` that accepts it. If no
+    /// format accepts it, the error from the first format that recognized
+    /// the overall shape is returned.
+    pub fn parse_with(
+        address: &str,
+        formats: &[&dyn DepositAddressFormat],
+    ) -> Result<Self, AddrError> {
+        let mut error = AddrError::UnknownFormat;
+        for format in formats {
+            match format.validate(address) {
+                Ok(()) => {
+                    return Ok(DepositAddress {
+                        address: address.to_owned(),
+                        format: format.name(),
+                    })
+                }
+                Err(err @ AddrError::Checksum { .. }) if error == AddrError::UnknownFormat => {
+                    error = err
+                }
+                Err(_) => (),
+            }
+        }
+        Err(error)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.address
+    }
+
+    /// Name of the format the address matched.
+    pub fn format(&self) -> &'static str {
+        self.format
+    }
+}
+
+impl fmt::Display for DepositAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.address)
+    }
+}
+
+/// Destination of a deposit, from the bytes of its `OP_RETURN`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DepositDestination {
+    /// Valid UTF-8 that one of the formats accepts.
+    SidechainAddress(String),
+    /// A refund destination of one of the formats, see
+    /// [`DepositAddressFormat::is_refund`].
+    Refund,
+    /// Anything else, e.g. text the depositor wrote or binary data.
+    Unknown(Vec<u8>),
+}
+
+impl DepositDestination {
+    /// Classifies `dest_bytes` with [`DEFAULT_FORMATS`].
+    pub fn parse(dest_bytes: &[u8]) -> Self {
+        Self::parse_with(dest_bytes, DEFAULT_FORMATS)
+    }
+
+    /// Classifies `dest_bytes` as a refund if one of `formats` recognizes
+    /// them as one, as a sidechain address if they are UTF-8 that one of
+    /// `formats` accepts, and as unknown otherwise.
+    pub fn parse_with(dest_bytes: &[u8], formats: &[&dyn DepositAddressFormat]) -> Self {
+        if formats.iter().any(|format| format.is_refund(dest_bytes)) {
+            return Self::Refund;
+        }
+        match std::str::from_utf8(dest_bytes) {
+            Ok(address) if DepositAddress::parse_with(address, formats).is_ok() => {
+                Self::SidechainAddress(address.to_owned())
+            }
+            _ => Self::Unknown(dest_bytes.to_owned()),
+        }
+    }
+}
+
+/// Extracts the destination from the first `OP_RETURN` output of a deposit
+/// transaction, concatenating the data of its pushes. `None` if there is no
+/// `OP_RETURN` output, or if it has anything but pushes after the
+/// `OP_RETURN`.
+pub fn extract_dest_bytes(transaction: &bitcoin::Transaction) -> Option<Vec<u8>> {
+    let script = &transaction
+        .output
+        .iter()
+        .find(|output| output.script_pubkey.is_op_return())?
+        .script_pubkey;
+    let mut dest_bytes = vec![];
+    for instruction in script.instructions().skip(1) {
+        match instruction.ok()? {
+            bitcoin::script::Instruction::PushBytes(push) => {
+                dest_bytes.extend_from_slice(push.as_bytes())
+            }
+            bitcoin::script::Instruction::Op(_) => return None,
+        }
+    }
+    Some(dest_bytes)
+}
+
+/// Sidechain deposits received on a single mainchain address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddressDeposits {
+    pub address: bitcoin::Address<NetworkUnchecked>,
+    /// Deposits to the sidechain that paid the address.
+    pub deposits: Vec<bitcoin::Txid>,
+    /// Sum of the outputs of `deposits` that paid the address.
+    pub total: bitcoin::Amount,
+    /// Wallet transactions that paid the address, but are not deposits to
+    /// the sidechain, e.g. because they lack the deposit `OP_RETURN`.
+    pub not_deposited: Vec<bitcoin::Txid>,
+}
+
+#[cfg(feature = "client")]
+/// Attributes the deposits to `sidechain` to the wallet `addresses` they
+/// paid, using `listreceivedbyaddress` for every address. Watch-only and
+/// unconfirmed transactions are included.
+pub async fn index_deposits_by_address<C>(
+    client: &C,
+    sidechain: SidechainId,
+    addresses: &[bitcoin::Address<NetworkUnchecked>],
+) -> Result<Vec<AddressDeposits>, Error>
+where
+    C: ClientT + Sync,
+{
+    index_deposits_by_address_with_limits(client, sidechain, addresses, &Limits::default())
+        .await?
+        .into_result()
+}
+
+#[cfg(feature = "client")]
+/// Like [`index_deposits_by_address`], but stops once `limits` are reached,
+/// with the addresses indexed so far, in order.
+///
+/// Progress in [`Error::DeadlineExceeded`] is counted in addresses.
+pub async fn index_deposits_by_address_with_limits<C>(
+    client: &C,
+    sidechain: SidechainId,
+    addresses: &[bitcoin::Address<NetworkUnchecked>],
+    limits: &Limits,
+) -> Result<Partial<Vec<AddressDeposits>>, Error>
+where
+    C: ClientT + Sync,
+{
+    let mut index = Vec::with_capacity(addresses.len());
+    let result = index_addresses(client, sidechain, addresses, limits, &mut index).await;
+    Partial::new(index, result)
+}
+
+#[cfg(feature = "client")]
+async fn index_addresses<C>(
+    client: &C,
+    sidechain: SidechainId,
+    addresses: &[bitcoin::Address<NetworkUnchecked>],
+    limits: &Limits,
+    index: &mut Vec<AddressDeposits>,
+) -> Result<(), Error>
+where
+    C: ClientT + Sync,
+{
+    let running = limits.start();
+    let mut deposits = HashMap::new();
+    let sidechain_deposits = running
+        .race(
+            async {
+                Ok(client
+                    .listsidechaindepositsbyblock(sidechain, None, None)
+                    .await?)
+            },
+            0,
+            Some(addresses.len()),
+        )
+        .await?;
+    for deposit in sidechain_deposits {
+        let transaction = deposit.transaction()?;
+        deposits.insert(transaction.txid(), transaction);
+    }
+    for address in addresses {
+        let received = running
+            .race(
+                async {
+                    Ok(client
+                        .listreceivedbyaddress(Some(0), Some(true), Some(true), Some(address))
+                        .await?)
+                },
+                index.len(),
+                Some(addresses.len() - index.len()),
+            )
+            .await?;
+        let txids = received.into_iter().flat_map(|received| received.txids);
+        index.push(attribute_deposits(address.clone(), txids, &deposits));
+    }
+    Ok(())
+}
+
+/// Splits the wallet transactions `txids` that paid `address` into
+/// `deposits` and the rest.
+#[cfg(feature = "client")]
+fn attribute_deposits(
+    address: bitcoin::Address<NetworkUnchecked>,
+    txids: impl IntoIterator<Item = bitcoin::Txid>,
+    deposits: &HashMap<bitcoin::Txid, bitcoin::Transaction>,
+) -> AddressDeposits {
+    let script_pubkey = address.payload.script_pubkey();
+    let mut address_deposits = AddressDeposits {
+        address,
+        deposits: vec![],
+        total: bitcoin::Amount::ZERO,
+        not_deposited: vec![],
+    };
+    for txid in txids {
+        let Some(transaction) = deposits.get(&txid) else {
+            address_deposits.not_deposited.push(txid);
+            continue;
+        };
+        address_deposits.total += transaction
+            .output
+            .iter()
+            .filter(|output| output.script_pubkey == script_pubkey)
+            .map(|output| bitcoin::Amount::from_sat(output.value))
+            .sum();
+        address_deposits.deposits.push(txid);
+    }
+    address_deposits
+}
+
+/// Sidechain deposits received on the wallet addresses with a label.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LabelDeposits {
+    pub label: String,
+    /// Only the addresses with the label that received deposits.
+    pub addresses: Vec<AddressDeposits>,
+    /// Sum of [`AddressDeposits::total`] of `addresses`.
+    pub total: bitcoin::Amount,
+}
+
+/// Deposits to a sidechain grouped by wallet label, see
+/// [`deposits_by_label`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DepositsByLabel {
+    /// In label order.
+    pub labels: Vec<LabelDeposits>,
+    /// Deposits received on wallet addresses without a label, which can't
+    /// be attributed to a user.
+    pub unlabeled: Vec<AddressDeposits>,
+}
+
+#[cfg(feature = "client")]
+/// Attributes the deposits to `sidechain` to the labels of the wallet
+/// addresses they paid, using a single `listreceivedbyaddress`. Watch-only
+/// and unconfirmed transactions are included.
+///
+/// A deposit that paid addresses with different labels is attributed to
+/// each of them, with the amounts paid to their addresses.
+pub async fn deposits_by_label<C>(
+    client: &C,
+    sidechain: SidechainId,
+) -> Result<DepositsByLabel, Error>
+where
+    C: ClientT + Sync,
+{
+    let mut deposits = HashMap::new();
+    for deposit in client
+        .listsidechaindepositsbyblock(sidechain, None, None)
+        .await?
+    {
+        let transaction = deposit.transaction()?;
+        deposits.insert(transaction.txid(), transaction);
+    }
+    let received = client
+        .listreceivedbyaddress(Some(0), Some(false), Some(true), None)
+        .await?;
+    let mut labels = std::collections::BTreeMap::<String, LabelDeposits>::new();
+    let mut unlabeled = vec![];
+    for received in received {
+        let address_deposits = attribute_deposits(received.address, received.txids, &deposits);
+        if address_deposits.deposits.is_empty() {
+            continue;
+        }
+        if received.label.is_empty() {
+            unlabeled.push(address_deposits);
+            continue;
+        }
+        let label_deposits =
+            labels
+                .entry(received.label)
+                .or_insert_with_key(|label| LabelDeposits {
+                    label: label.clone(),
+                    addresses: vec![],
+                    total: bitcoin::Amount::ZERO,
+                });
+        label_deposits.total += address_deposits.total;
+        label_deposits.addresses.push(address_deposits);
+    }
+    Ok(DepositsByLabel {
+        labels: labels.into_values().collect(),
+        unlabeled,
+    })
+}
+
+#[cfg(feature = "client")]
+/// Wallet sends since `block_hash` that pay to `escrow_script`, e.g. that of
+/// [`crate::escrow::script_for_sidechain`]. The escrow script has no
+/// address, so each send is checked against the transaction stored by the
+/// wallet.
+///
+/// Abandoned sends and sends that conflict with the main chain are skipped,
+/// as they can never become deposits.
+pub async fn deposits_since<C>(
+    client: &C,
+    escrow_script: &bitcoin::Script,
+    block_hash: Option<&bitcoin::BlockHash>,
+) -> Result<Vec<crate::client::WalletTx>, Error>
+where
+    C: ClientT + Sync,
+{
+    let list = client.listsinceblock(block_hash, None, None, None).await?;
+    let mut transactions = HashMap::new();
+    let mut deposits = Vec::new();
+    for wallet_tx in list.transactions {
+        if wallet_tx.category != crate::client::WalletTxCategory::Send
+            || wallet_tx.abandoned == Some(true)
+            || wallet_tx.confirmations < 0
+        {
+            continue;
+        }
+        let transaction = match transactions.entry(wallet_tx.txid) {
+            std::collections::hash_map::Entry::Occupied(entry) => entry.into_mut(),
+            std::collections::hash_map::Entry::Vacant(entry) => {
+                let tx_hex = client
+                    .gettransaction(&wallet_tx.txid, true, false)
+                    .await?
+                    .hex;
+                let transaction: bitcoin::Transaction =
+                    bitcoin::consensus::deserialize(&hex::decode(tx_hex)?)?;
+                entry.insert(transaction)
+            }
+        };
+        let pays_escrow = transaction
+            .output
+            .get(wallet_tx.vout as usize)
+            .is_some_and(|output| output.script_pubkey.as_script() == escrow_script);
+        if pays_escrow {
+            deposits.push(wallet_tx);
+        }
+    }
+    Ok(deposits)
+}
+
+#[cfg(all(test, feature = "client"))]
+mod tests {
+    use super::*;
+    use crate::mock::{block_on, MockClient};
+    use serde_json::{json, Value};
+
+    fn txid(n: u8) -> bitcoin::Txid {
+        bitcoin::Txid::from_byte_array([n; 32])
+    }
+
+    /// `listsinceblock` entry of a send of output `vout` of `txid`.
+    fn send(txid: bitcoin::Txid, vout: u32, confirmations: i64, abandoned: bool) -> Value {
+        json!({
+            "category": "send",
+            "amount": -1.0,
+            "fee": -0.0001,
+            "vout": vout,
+            "confirmations": confirmations,
+            "txid": txid,
+            "walletconflicts": [],
+            "time": 1_700_000_000,
+            "timereceived": 1_700_000_000,
+            "bip125-replaceable": "no",
+            "abandoned": abandoned,
+        })
+    }
+
+    /// `gettransaction` response for a transaction paying the escrow of
+    /// sidechain 1 in output 0 and another script in output 1.
+    fn wallet_transaction(txid: bitcoin::Txid) -> Value {
+        let escrow = crate::escrow::script_for_sidechain(SidechainId(1));
+        let transaction = bitcoin::Transaction {
+            version: 2,
+            lock_time: bitcoin::absolute::LockTime::ZERO,
+            input: vec![bitcoin::TxIn::default()],
+            output: vec![
+                bitcoin::TxOut {
+                    value: 100_000_000,
+                    script_pubkey: escrow,
+                },
+                bitcoin::TxOut {
+                    value: 1_000,
+                    script_pubkey: bitcoin::ScriptBuf::new_op_return(&[]),
+                },
+            ],
+        };
+        json!({
+            "amount": -1.0,
+            "confirmations": 1,
+            "txid": txid,
+            "walletconflicts": [],
+            "time": 1_700_000_000,
+            "timereceived": 1_700_000_000,
+            "bip125-replaceable": "no",
+            "details": [],
+            "hex": bitcoin::consensus::encode::serialize_hex(&transaction),
+        })
+    }
+
+    #[test]
+    fn deposits_since_skips_abandoned_and_conflicted_sends() {
+        let client = MockClient::new(|method, params| match method {
+            "listsinceblock" => Ok(json!({
+                "transactions": [
+                    send(txid(1), 0, 1, false),
+                    send(txid(1), 1, 1, false),
+                    send(txid(2), 0, 0, true),
+                    send(txid(3), 0, -1, false),
+                ],
+                "lastblock": "0000000000000000000000000000000000000000000000000000000000000001",
+            })),
+            "gettransaction" => Ok(wallet_transaction(
+                serde_json::from_value(params[0].clone()).unwrap(),
+            )),
+            _ => unreachable!("{method}"),
+        });
+        let escrow = crate::escrow::script_for_sidechain(SidechainId(1));
+        let deposits = block_on(deposits_since(&client, &escrow, None)).unwrap();
+        assert_eq!(deposits.len(), 1);
+        assert_eq!((deposits[0].txid, deposits[0].vout), (txid(1), 0));
+        assert_eq!(client.methods(), ["listsinceblock", "gettransaction"]);
+    }
+
+    fn address(n: u8) -> bitcoin::Address<NetworkUnchecked> {
+        let script =
+            bitcoin::ScriptBuf::new_v0_p2wpkh(&bitcoin::WPubkeyHash::from_byte_array([n; 20]));
+        bitcoin::Address::from_script(&script, bitcoin::Network::Regtest)
+            .unwrap()
+            .to_string()
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn flags_wallet_transactions_that_are_not_deposits() {
+        let (paid, unused) = (address(1), address(2));
+        let deposit = bitcoin::Transaction {
+            version: 2,
+            lock_time: bitcoin::absolute::LockTime::ZERO,
+            input: vec![bitcoin::TxIn::default()],
+            output: vec![
+                bitcoin::TxOut {
+                    value: 100_000_000,
+                    script_pubkey: crate::escrow::script_for_sidechain(SidechainId(1)),
+                },
+                bitcoin::TxOut {
+                    value: 50_000,
+                    script_pubkey: paid.payload.script_pubkey(),
+                },
+            ],
+        };
+        let deposit_txid = deposit.txid();
+        let client = MockClient::new({
+            let paid = paid.clone();
+            move |method, params| match method {
+                "listsidechaindepositsbyblock" => Ok(json!([{
+                    "hashblock": bitcoin::BlockHash::all_zeros(),
+                    "nburnindex": 0,
+                    "ntx": 1,
+                    "strdest": "sidechain address",
+                    "txhex": bitcoin::consensus::encode::serialize_hex(&deposit),
+                }])),
+                "listreceivedbyaddress" if params[3] == json!(paid) => Ok(json!([{
+                    "address": paid,
+                    "amount": 0.001,
+                    "confirmations": 1,
+                    "label": "",
+                    "txids": [deposit_txid, txid(9)],
+                }])),
+                "listreceivedbyaddress" => Ok(json!([])),
+                _ => unreachable!("{method}"),
+            }
+        });
+        let index = block_on(index_deposits_by_address(
+            &client,
+            SidechainId(1),
+            &[paid.clone(), unused.clone()],
+        ))
+        .unwrap();
+        assert_eq!(
+            index,
+            [
+                AddressDeposits {
+                    address: paid,
+                    deposits: vec![deposit_txid],
+                    total: bitcoin::Amount::from_sat(50_000),
+                    not_deposited: vec![txid(9)],
+                },
+                AddressDeposits {
+                    address: unused,
+                    deposits: vec![],
+                    total: bitcoin::Amount::ZERO,
+                    not_deposited: vec![],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn groups_deposits_by_label() {
+        let (alice_1, alice_2, bob, unlabeled) = (address(1), address(2), address(3), address(4));
+        let deposit = |outputs: &[(&bitcoin::Address<NetworkUnchecked>, u64)]| {
+            let mut output = vec![bitcoin::TxOut {
+                value: 100_000_000,
+                script_pubkey: crate::escrow::script_for_sidechain(SidechainId(1)),
+            }];
+            output.extend(outputs.iter().map(|(address, value)| bitcoin::TxOut {
+                value: *value,
+                script_pubkey: address.payload.script_pubkey(),
+            }));
+            bitcoin::Transaction {
+                version: 2,
+                lock_time: bitcoin::absolute::LockTime::ZERO,
+                input: vec![bitcoin::TxIn::default()],
+                output,
+            }
+        };
+        // The second deposit pays both of alice's addresses and bob's
+        let deposits = [
+            deposit(&[(&alice_1, 1_000)]),
+            deposit(&[(&alice_2, 2_000), (&bob, 4_000), (&alice_1, 8_000)]),
+            deposit(&[(&unlabeled, 16_000)]),
+        ];
+        let txids: Vec<_> = deposits.iter().map(bitcoin::Transaction::txid).collect();
+        let received = json!([
+            {
+                "address": bob,
+                "amount": 0.00004,
+                "confirmations": 1,
+                "label": "bob",
+                "txids": [txids[1]],
+            },
+            {
+                "address": alice_1,
+                "amount": 0.00009,
+                "confirmations": 1,
+                "label": "alice",
+                "txids": [txids[0], txids[1]],
+            },
+            {
+                "address": unlabeled,
+                "amount": 0.00016,
+                "confirmations": 0,
+                "label": "",
+                "txids": [txids[2]],
+            },
+            {
+                "address": alice_2,
+                "amount": 0.00003,
+                "confirmations": 1,
+                "label": "alice",
+                "txids": [txids[1], txid(9)],
+            },
+            // Labeled, but without deposits
+            {
+                "address": address(5),
+                "amount": 0.1,
+                "confirmations": 1,
+                "label": "carol",
+                "txids": [txid(9)],
+            },
+        ]);
+        let client = MockClient::new(move |method, params| match method {
+            "listsidechaindepositsbyblock" => Ok(Value::Array(
+                deposits
+                    .iter()
+                    .map(|deposit| {
+                        json!({
+                            "hashblock": bitcoin::BlockHash::all_zeros(),
+                            "nburnindex": 0,
+                            "ntx": 1,
+                            "strdest": "sidechain address",
+                            "txhex": bitcoin::consensus::encode::serialize_hex(deposit),
+                        })
+                    })
+                    .collect(),
+            )),
+            "listreceivedbyaddress" => {
+                assert_eq!(params, [json!(0), json!(false), json!(true), json!(null)]);
+                Ok(received.clone())
+            }
+            _ => unreachable!("{method}"),
+        });
+        let by_label = block_on(deposits_by_label(&client, SidechainId(1))).unwrap();
+        assert_eq!(
+            by_label,
+            DepositsByLabel {
+                labels: vec![
+                    LabelDeposits {
+                        label: "alice".to_owned(),
+                        addresses: vec![
+                            AddressDeposits {
+                                address: alice_1,
+                                deposits: vec![txids[0], txids[1]],
+                                total: bitcoin::Amount::from_sat(9_000),
+                                not_deposited: vec![],
+                            },
+                            AddressDeposits {
+                                address: alice_2,
+                                deposits: vec![txids[1]],
+                                total: bitcoin::Amount::from_sat(2_000),
+                                not_deposited: vec![txid(9)],
+                            },
+                        ],
+                        total: bitcoin::Amount::from_sat(11_000),
+                    },
+                    LabelDeposits {
+                        label: "bob".to_owned(),
+                        addresses: vec![AddressDeposits {
+                            address: bob,
+                            deposits: vec![txids[1]],
+                            total: bitcoin::Amount::from_sat(4_000),
+                            not_deposited: vec![],
+                        }],
+                        total: bitcoin::Amount::from_sat(4_000),
+                    },
+                ],
+                unlabeled: vec![AddressDeposits {
+                    address: unlabeled,
+                    deposits: vec![txids[2]],
+                    total: bitcoin::Amount::from_sat(16_000),
+                    not_deposited: vec![],
+                }],
+            }
+        );
+        assert_eq!(
+            client.methods(),
+            ["listsidechaindepositsbyblock", "listreceivedbyaddress"]
+        );
+    }
+
+    #[test]
+    fn stops_with_the_addresses_indexed_so_far() {
+        let cancel = crate::cancel::CancellationToken::new();
+        let client = MockClient::new({
+            let cancel = cancel.clone();
+            let received = std::sync::atomic::AtomicUsize::new(0);
+            move |method, _| match method {
+                "listsidechaindepositsbyblock" => Ok(json!([])),
+                "listreceivedbyaddress" => {
+                    if received.fetch_add(1, std::sync::atomic::Ordering::SeqCst) == 1 {
+                        cancel.cancel();
+                    }
+                    Ok(json!([]))
+                }
+                _ => unreachable!("{method}"),
+            }
+        });
+        let addresses = [address(1), address(2), address(3)];
+        let limits = Limits::new().with_cancellation(cancel);
+        let index = block_on(index_deposits_by_address_with_limits(
+            &client,
+            SidechainId(1),
+            &addresses,
+            &limits,
+        ))
+        .unwrap();
+        assert!(matches!(index.stopped, Some(Error::Cancelled)));
+        let indexed: Vec<_> = index
+            .value
+            .iter()
+            .map(|deposits| &deposits.address)
+            .collect();
+        assert_eq!(indexed, [&addresses[0], &addresses[1]]);
+        assert_eq!(
+            client.methods(),
+            [
+                "listsidechaindepositsbyblock",
+                "listreceivedbyaddress",
+                "listreceivedbyaddress"
+            ]
+        );
+    }
+
+    #[test]
+    fn deadline_before_the_first_request() {
+        let client = MockClient::new(|method, _| unreachable!("{method}"));
+        let limits = Limits::new().with_timeout(std::time::Duration::ZERO);
+        let index = block_on(index_deposits_by_address_with_limits(
+            &client,
+            SidechainId(1),
+            &[address(1), address(2)],
+            &limits,
+        ))
+        .unwrap();
+        assert!(index.value.is_empty());
+        assert!(matches!(
+            index.stopped,
+            Some(Error::DeadlineExceeded {
+                completed: 0,
+                remaining_estimate: Some(2),
+            })
+        ));
+        assert!(client.calls().is_empty());
+    }
+
+    fn deposit_with_op_return(script_pubkey: bitcoin::ScriptBuf) -> bitcoin::Transaction {
+        bitcoin::Transaction {
+            version: 2,
+            lock_time: bitcoin::absolute::LockTime::ZERO,
+            input: vec![bitcoin::TxIn::default()],
+            output: vec![
+                bitcoin::TxOut {
+                    value: 100_000_000,
+                    script_pubkey: crate::escrow::script_for_sidechain(SidechainId(1)),
+                },
+                bitcoin::TxOut {
+                    value: 0,
+                    script_pubkey,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn destinations_keep_the_exact_bytes() {
+        let address = LegacyFormat::format(SidechainId(1), "destination");
+        let binary = [0xff, 0x00, 0xfe];
+        for (dest_bytes, expected) in [
+            (
+                address.as_bytes(),
+                DepositDestination::SidechainAddress(address.clone()),
+            ),
+            (&binary[..], DepositDestination::Unknown(binary.to_vec())),
+            (
+                b"any text",
+                DepositDestination::Unknown(b"any text".to_vec()),
+            ),
+        ] {
+            let push = <&bitcoin::script::PushBytes>::try_from(dest_bytes).unwrap();
+            let script = bitcoin::script::Builder::new()
+                .push_opcode(bitcoin::opcodes::all::OP_RETURN)
+                .push_slice(push)
+                .into_script();
+            let extracted = extract_dest_bytes(&deposit_with_op_return(script)).unwrap();
+            assert_eq!(extracted, dest_bytes);
+            assert_eq!(DepositDestination::parse(&extracted), expected);
+        }
+        let without_op_return = deposit_with_op_return(bitcoin::ScriptBuf::new());
+        assert_eq!(extract_dest_bytes(&without_op_return), None);
+    }
+
+    #[test]
+    fn refunds_are_recognized_by_the_formats() {
+        struct WithRefund;
+
+        impl DepositAddressFormat for WithRefund {
+            fn name(&self) -> &'static str {
+                "with refund"
+            }
+
+            fn validate(&self, _: &str) -> Result<(), AddrError> {
+                Err(AddrError::UnknownFormat)
+            }
+
+            fn sidechain_id_hint(&self, _: &str) -> Option<SidechainId> {
+                None
+            }
+
+            fn is_refund(&self, dest_bytes: &[u8]) -> bool {
+                dest_bytes == b"refund"
+            }
+        }
+
+        assert_eq!(
+            DepositDestination::parse(b"refund"),
+            DepositDestination::Unknown(b"refund".to_vec())
+        );
+        assert_eq!(
+            DepositDestination::parse_with(b"refund", &[&LegacyFormat, &WithRefund]),
+            DepositDestination::Refund
+        );
+    }
+}
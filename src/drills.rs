@@ -0,0 +1,398 @@
+//! Disaster recovery drills on regtest: the mainchain "loses" its last
+//! blocks, and the sidechain has to recover its deposits and withdrawals.
+//!
+//! [`rollback`] records what was in the blocks it disconnects, so the
+//! recovery of the sidechain can be checked against it, and [`replay`]
+//! connects them again.
+use crate::client::{ChainClient, Deposit, DrivechainClient};
+use crate::coinbase::get_coinbase;
+use crate::snapshot::{
+    snapshot_sidechain_state, SidechainStateSnapshot, DEFAULT_SNAPSHOT_ATTEMPTS,
+};
+use crate::Error;
+use jsonrpsee::core::client::ClientT;
+use std::collections::HashSet;
+
+/// A block disconnected by [`rollback`].
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct RolledBackBlock {
+    pub block_hash: bitcoin::BlockHash,
+    pub height: usize,
+    /// `OP_RETURN` outputs of the coinbase, which carry the witness, BMM
+    /// and withdrawal bundle commitments. They are recorded as they are,
+    /// without decoding them.
+    pub commitments: Vec<bitcoin::ScriptBuf>,
+}
+
+/// What [`rollback`] disconnected, to store as an artifact of the drill and
+/// to pass to [`replay`].
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct RollbackReport {
+    /// The tip after the rollback.
+    pub fork_point: bitcoin::BlockHash,
+    /// In height order.
+    pub blocks: Vec<RolledBackBlock>,
+    /// Deposits to sidechains with a CTIP that were in the disconnected
+    /// blocks.
+    pub deposits: Vec<Deposit>,
+    /// Drivechain state before the rollback, at the last disconnected block.
+    pub snapshot: SidechainStateSnapshot,
+}
+
+impl RollbackReport {
+    /// Every commitment output in the disconnected blocks, in block order.
+    pub fn commitments(&self) -> impl Iterator<Item = &bitcoin::ScriptBuf> {
+        self.blocks.iter().flat_map(|block| &block.commitments)
+    }
+}
+
+async fn require_regtest<C>(client: &C) -> Result<(), Error>
+where
+    C: ClientT + Sync,
+{
+    let chain = client.getblockchaininfo().await?.value.chain;
+    let network =
+        bitcoin::Network::from_core_arg(&chain).map_err(|_| Error::UnknownChain { chain })?;
+    if network != bitcoin::Network::Regtest {
+        return Err(Error::RollbackNotAllowed { network });
+    }
+    Ok(())
+}
+
+/// Invalidates the top `n_blocks` blocks, after recording the drivechain
+/// state and the deposits and commitments in those blocks.
+///
+/// Returns [`Error::RollbackNotAllowed`] unless the node is on regtest.
+pub async fn rollback<C>(client: &C, n_blocks: usize) -> Result<RollbackReport, Error>
+where
+    C: ClientT + Sync,
+{
+    require_regtest(client).await?;
+    let snapshot = snapshot_sidechain_state(client, DEFAULT_SNAPSHOT_ATTEMPTS).await?;
+    // Walked back from the snapshot rather than looked up by height, so the
+    // blocks match the snapshot even if the tip moved since
+    let mut blocks = Vec::with_capacity(n_blocks);
+    let mut cursor = snapshot.at_block;
+    let fork_point = loop {
+        let header = client.getblockheader(&cursor).await?;
+        if blocks.len() == n_blocks {
+            break cursor;
+        }
+        let Some(prev) = header.previousblockhash else {
+            return Err(Error::InvalidParam {
+                name: "n_blocks",
+                reason: format!("only {} blocks can be rolled back", header.height),
+            });
+        };
+        let commitments = get_coinbase(client, &cursor)
+            .await?
+            .payout_scripts()
+            .map(|(script, _)| script)
+            .filter(|script| script.is_op_return())
+            .map(bitcoin::Script::to_owned)
+            .collect();
+        blocks.push(RolledBackBlock {
+            block_hash: cursor,
+            height: header.height,
+            commitments,
+        });
+        cursor = prev;
+    };
+    blocks.reverse();
+    let block_hashes: HashSet<_> = blocks.iter().map(|block| block.block_hash).collect();
+    let mut deposits = vec![];
+    for sidechain in snapshot.ctips.keys() {
+        deposits.extend(
+            client
+                .listsidechaindepositsbyblock(*sidechain, None, None)
+                .await?
+                .into_iter()
+                .filter(|deposit| block_hashes.contains(&deposit.hashblock)),
+        );
+    }
+    if let Some(first) = blocks.first() {
+        client.invalidateblock(&first.block_hash).await?;
+    }
+    let tip = client.getbestblockhash().await?;
+    if tip != fork_point {
+        return Err(Error::UnexpectedTip {
+            expected: fork_point,
+            actual: tip,
+        });
+    }
+    Ok(RollbackReport {
+        fork_point,
+        blocks,
+        deposits,
+        snapshot,
+    })
+}
+
+/// Reconsiders the blocks disconnected by [`rollback`], and checks that the
+/// drivechain state is the same as before the rollback.
+///
+/// Returns [`Error::ReplayMismatch`] with the differences if it is not.
+pub async fn replay<C>(client: &C, report: &RollbackReport) -> Result<(), Error>
+where
+    C: ClientT + Sync,
+{
+    require_regtest(client).await?;
+    let Some(first) = report.blocks.first() else {
+        return Ok(());
+    };
+    client.reconsiderblock(&first.block_hash).await?;
+    let tip = client.getbestblockhash().await?;
+    if tip != report.snapshot.at_block {
+        return Err(Error::UnexpectedTip {
+            expected: report.snapshot.at_block,
+            actual: tip,
+        });
+    }
+    let snapshot = snapshot_sidechain_state(client, DEFAULT_SNAPSHOT_ATTEMPTS).await?;
+    let diff = report.snapshot.diff(&snapshot);
+    if !diff.is_empty() {
+        return Err(Error::ReplayMismatch { diff });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{Ctip, SidechainId};
+    use crate::mock::{block_on, blockchain_info, rpc_error, MockChain, MockClient};
+    use bitcoin::hashes::Hash as _;
+    use serde_json::{json, Value};
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    /// Chain with deposits to sidechain 0, and its CTIP after each block.
+    #[derive(Clone)]
+    struct Node {
+        network: &'static str,
+        chain: MockChain,
+        ctips: Arc<Mutex<HashMap<bitcoin::BlockHash, Value>>>,
+        deposits: Arc<Mutex<Vec<(bitcoin::Txid, Value)>>>,
+    }
+
+    impl Node {
+        fn new(network: &'static str) -> Self {
+            let node = Node {
+                network,
+                chain: MockChain::new(),
+                ctips: Arc::default(),
+                deposits: Arc::default(),
+            };
+            node.chain.mine(2);
+            node
+        }
+
+        /// Mines a block with a deposit that brings the CTIP to `sats`.
+        fn mine_deposit(&self, sats: u64) {
+            let transaction = bitcoin::Transaction {
+                version: 2,
+                lock_time: bitcoin::absolute::LockTime::ZERO,
+                input: vec![bitcoin::TxIn::default()],
+                output: vec![bitcoin::TxOut {
+                    value: sats,
+                    script_pubkey: crate::escrow::script_for_sidechain(SidechainId(0)),
+                }],
+            };
+            let txid = transaction.txid();
+            let block_hash = self.chain.mine_with(vec![txid]);
+            self.ctips.lock().unwrap().insert(
+                block_hash,
+                json!(Ctip {
+                    txid,
+                    n: 0,
+                    amount: crate::AmountBtc::from_sat(sats),
+                }),
+            );
+            self.deposits.lock().unwrap().push((
+                txid,
+                json!({
+                    "hashblock": block_hash,
+                    "nburnindex": 0,
+                    "ntx": 1,
+                    "strdest": "alice",
+                    "txhex": bitcoin::consensus::encode::serialize_hex(&transaction),
+                }),
+            ));
+        }
+
+        fn client(&self) -> MockClient {
+            let node = self.clone();
+            MockClient::new(move |method, params| node.handle(method, params))
+        }
+
+        fn handle(&self, method: &str, params: &[Value]) -> Result<Value, jsonrpsee::core::Error> {
+            Ok(match method {
+                "getblockchaininfo" => blockchain_info(self.network, self.chain.height()),
+                "listspentwithdrawals" | "listfailedwithdrawals" => json!([]),
+                "listwithdrawalstatus" => json!([]),
+                "listsidechainctip" => match (
+                    &params[0],
+                    self.ctips.lock().unwrap().get(&self.chain.tip()),
+                ) {
+                    (sidechain, Some(ctip)) if *sidechain == 0 => ctip.clone(),
+                    _ => return Err(rpc_error(-1, "No CTIP found for sidechain")),
+                },
+                "listsidechaindepositsbyblock" => {
+                    let deposits = self.deposits.lock().unwrap();
+                    let in_main_chain = deposits
+                        .iter()
+                        .filter(|(txid, _)| self.chain.block_of(txid).is_some())
+                        .map(|(_, deposit)| deposit.clone());
+                    json!(in_main_chain.collect::<Vec<_>>())
+                }
+                // Coinbase with a commitment to the block hash
+                "getblock" if params[1] == 0 => {
+                    let block_hash: bitcoin::BlockHash =
+                        serde_json::from_value(params[0].clone()).unwrap();
+                    json!(bitcoin::consensus::encode::serialize_hex(&block(
+                        block_hash
+                    )))
+                }
+                _ => return self.chain.handle(method, params).unwrap(),
+            })
+        }
+    }
+
+    fn commitment(block_hash: bitcoin::BlockHash) -> bitcoin::ScriptBuf {
+        bitcoin::ScriptBuf::new_op_return(&block_hash.to_byte_array())
+    }
+
+    fn block(block_hash: bitcoin::BlockHash) -> bitcoin::Block {
+        let genesis = bitcoin::constants::genesis_block(bitcoin::Network::Regtest);
+        let coinbase = bitcoin::Transaction {
+            version: 2,
+            lock_time: bitcoin::absolute::LockTime::ZERO,
+            input: vec![bitcoin::TxIn::default()],
+            output: vec![
+                bitcoin::TxOut {
+                    value: 50 * 100_000_000,
+                    script_pubkey: bitcoin::ScriptBuf::from(vec![0x51]),
+                },
+                bitcoin::TxOut {
+                    value: 0,
+                    script_pubkey: commitment(block_hash),
+                },
+            ],
+        };
+        bitcoin::Block {
+            header: genesis.header,
+            txdata: vec![coinbase],
+        }
+    }
+
+    #[test]
+    fn rolls_back_and_replays_three_blocks() {
+        let node = Node::new("regtest");
+        let fork_point = node.chain.tip();
+        for sats in [1_000, 3_000, 6_000] {
+            node.mine_deposit(sats);
+        }
+        let tip = node.chain.tip();
+        let client = node.client();
+
+        let report = block_on(rollback(&client, 3)).unwrap();
+        assert_eq!(report.fork_point, fork_point);
+        assert_eq!(node.chain.tip(), fork_point);
+        assert_eq!(
+            report
+                .blocks
+                .iter()
+                .map(|block| block.height)
+                .collect::<Vec<_>>(),
+            [3, 4, 5]
+        );
+        assert_eq!(report.blocks[2].block_hash, tip);
+        assert_eq!(
+            report.commitments().cloned().collect::<Vec<_>>(),
+            report
+                .blocks
+                .iter()
+                .map(|block| commitment(block.block_hash))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(report.deposits.len(), 3);
+        assert_eq!(report.snapshot.at_block, tip);
+        assert_eq!(
+            report.snapshot.ctips[&SidechainId(0)].amount,
+            crate::AmountBtc::from_sat(6_000)
+        );
+        // The report is stored as an artifact of the drill
+        let json = serde_json::to_string(&report).unwrap();
+        assert_eq!(
+            serde_json::from_str::<RollbackReport>(&json).unwrap(),
+            report
+        );
+
+        block_on(replay(&client, &report)).unwrap();
+        assert_eq!(node.chain.tip(), tip);
+    }
+
+    #[test]
+    fn replay_detects_a_changed_state() {
+        let node = Node::new("regtest");
+        for sats in [1_000, 3_000, 6_000] {
+            node.mine_deposit(sats);
+        }
+        let tip = node.chain.tip();
+        let client = node.client();
+        let report = block_on(rollback(&client, 3)).unwrap();
+        // The last deposit pays less the second time
+        node.ctips.lock().unwrap().get_mut(&tip).unwrap()["amount"] = json!(0.00005);
+        let Err(Error::ReplayMismatch { diff }) = block_on(replay(&client, &report)) else {
+            panic!("replay should fail");
+        };
+        assert_eq!(diff.changes.len(), 1, "{diff}");
+        assert!(matches!(
+            &diff.changes[0],
+            crate::snapshot::SidechainStateChange::Ctip {
+                sidechain: SidechainId(0),
+                ours: Some(ours),
+                theirs: Some(theirs),
+            } if ours.amount.0.to_sat() == 6_000 && theirs.amount.0.to_sat() == 5_000
+        ));
+    }
+
+    #[test]
+    fn refuses_networks_other_than_regtest() {
+        for (chain, network) in [
+            ("main", bitcoin::Network::Bitcoin),
+            ("signet", bitcoin::Network::Signet),
+        ] {
+            let node = Node::new(chain);
+            node.mine_deposit(1_000);
+            let tip = node.chain.tip();
+            let client = node.client();
+            assert!(matches!(
+                block_on(rollback(&client, 1)),
+                Err(Error::RollbackNotAllowed { network: refused }) if refused == network
+            ));
+            let report = RollbackReport {
+                fork_point: tip,
+                blocks: vec![RolledBackBlock {
+                    block_hash: tip,
+                    height: node.chain.height(),
+                    commitments: vec![],
+                }],
+                deposits: vec![],
+                snapshot: crate::snapshot::SidechainStateSnapshot {
+                    at_block: tip,
+                    ctips: Default::default(),
+                    withdrawal_statuses: Default::default(),
+                    spent_withdrawals: vec![],
+                    failed_withdrawals: vec![],
+                },
+            };
+            assert!(matches!(
+                block_on(replay(&client, &report)),
+                Err(Error::RollbackNotAllowed { network: refused }) if refused == network
+            ));
+            assert_eq!(node.chain.tip(), tip);
+            assert_eq!(client.methods(), ["getblockchaininfo", "getblockchaininfo"]);
+        }
+    }
+}
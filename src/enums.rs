@@ -0,0 +1,118 @@
+//! `Display` and `FromStr` for enums whose values are used as plain strings,
+//! e.g. in CLI arguments and config files.
+
+/// Error parsing one of the crate's enums from a string.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("invalid {type_name} `{value}`, expected one of {expected:?}")]
+pub struct ParseEnumError {
+    pub type_name: &'static str,
+    pub value: String,
+    pub expected: &'static [&'static str],
+}
+
+/// Implements `Display`, `FromStr` and a `VARIANTS` constant for an enum of
+/// unit variants. Strings are parsed case-insensitively and displayed as
+/// given, which should be the wire format where there is one.
+macro_rules! string_enum {
+    ($ty:ident { $($variant:ident => $s:literal),* $(,)? }) => {
+        impl $ty {
+            /// The string of every variant, as displayed.
+            pub const VARIANTS: &'static [&'static str] = &[$($s),*];
+
+            pub fn as_str(&self) -> &'static str {
+                match self {
+                    $($ty::$variant => $s),*
+                }
+            }
+        }
+
+        impl std::fmt::Display for $ty {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str(self.as_str())
+            }
+        }
+
+        impl std::str::FromStr for $ty {
+            type Err = $crate::enums::ParseEnumError;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                $(
+                    if s.eq_ignore_ascii_case($s) {
+                        return Ok($ty::$variant);
+                    }
+                )*
+                Err($crate::enums::ParseEnumError {
+                    type_name: stringify!($ty),
+                    value: s.to_owned(),
+                    expected: Self::VARIANTS,
+                })
+            }
+        }
+    };
+}
+
+pub(crate) use string_enum;
+
+#[cfg(test)]
+mod tests {
+    use crate::client::{
+        AddNodeCommand, AddressType, Bip9Status, ConnectionDirection, EstimateMode, LabelPurpose,
+        MemoryInfoMode, ScanAction, TemplateRequestMode, Vote, WalletTxCategory,
+    };
+    use crate::WithdrawalBundleStatus;
+    use std::collections::HashSet;
+
+    /// Every string in `VARIANTS` parses, in any case, to a distinct variant
+    /// that is displayed as the same string.
+    macro_rules! assert_round_trips {
+        ($($ty:ident),* $(,)?) => {
+            $(
+                let mut parsed = HashSet::new();
+                for s in $ty::VARIANTS {
+                    let variant: $ty = s.parse().unwrap();
+                    assert_eq!(variant.to_string(), *s);
+                    assert_eq!(variant.as_str(), *s);
+                    assert_eq!(s.to_uppercase().parse::<$ty>().unwrap(), variant);
+                    assert!(parsed.insert(variant.as_str()), "{s} parsed twice");
+                }
+                let err = "unknown".parse::<$ty>().unwrap_err();
+                assert_eq!(err.type_name, stringify!($ty));
+                assert_eq!(err.expected, $ty::VARIANTS);
+            )*
+        };
+    }
+
+    #[test]
+    fn display_and_from_str_round_trip() {
+        assert_round_trips!(
+            AddNodeCommand,
+            AddressType,
+            Bip9Status,
+            ConnectionDirection,
+            EstimateMode,
+            LabelPurpose,
+            MemoryInfoMode,
+            ScanAction,
+            TemplateRequestMode,
+            Vote,
+            WalletTxCategory,
+            WithdrawalBundleStatus,
+        );
+    }
+
+    #[test]
+    fn vote_wire_format() {
+        for (vote, s) in [
+            (Vote::Upvote, "upvote"),
+            (Vote::Abstain, "abstain"),
+            (Vote::Downvote, "downvote"),
+        ] {
+            assert_eq!(serde_json::to_value(vote).unwrap(), serde_json::json!(s));
+            assert_eq!(serde_json::from_value::<Vote>(s.into()).unwrap(), vote);
+            assert_eq!(vote.to_string(), s);
+        }
+        assert_eq!(Vote::VARIANTS, ["upvote", "abstain", "downvote"]);
+        assert!(serde_json::from_value::<Vote>("Upvote".into()).is_err());
+        assert!(serde_json::from_value::<Vote>("yes".into()).is_err());
+    }
+}
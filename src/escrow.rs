@@ -0,0 +1,138 @@
+//! Escrow scripts of sidechains, and descriptors to watch them with wallet
+//! software.
+use crate::client::SidechainId;
+use bitcoin::opcodes::all::{OP_NOP5, OP_PUSHBYTES_1, OP_PUSHNUM_1};
+use bitcoin::{Script, ScriptBuf};
+
+/// `OP_DRIVECHAIN` from BIP300, which redefines `OP_NOP5`.
+const OP_DRIVECHAIN: u8 = OP_NOP5.to_u8();
+
+/// Characters allowed in descriptors, in the order used by the checksum.
+const INPUT_CHARSET: &str =
+    "0123456789()[],'/*abcdefgh@:$%{}IJKLMNOPQRSTUVWXYZ&+-.;<=>?!^_|~ijklmnopqrstuvwxyzABCDEFGH`#\"\\ ";
+const CHECKSUM_CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+/// The escrow script of `sidechain`:
+/// `OP_DRIVECHAIN <sidechain number> OP_TRUE`.
+pub fn script_for_sidechain(sidechain: SidechainId) -> ScriptBuf {
+    ScriptBuf::from(vec![
+        OP_DRIVECHAIN,
+        OP_PUSHBYTES_1.to_u8(),
+        sidechain.0,
+        OP_PUSHNUM_1.to_u8(),
+    ])
+}
+
+/// The sidechain `script` is the escrow script of, if any.
+pub fn sidechain_for_script(script: &Script) -> Option<SidechainId> {
+    match *script.as_bytes() {
+        [OP_DRIVECHAIN, push, sidechain, op_true]
+            if push == OP_PUSHBYTES_1.to_u8() && op_true == OP_PUSHNUM_1.to_u8() =>
+        {
+            Some(SidechainId(sidechain))
+        }
+        _ => None,
+    }
+}
+
+/// `raw(...)` descriptor with checksum for the escrow script of
+/// `sidechain`. The script has no address, so wallets can only watch it
+/// through this descriptor.
+pub fn descriptor_for_sidechain(sidechain: SidechainId) -> String {
+    let descriptor = format!("raw({:x})", script_for_sidechain(sidechain));
+    let checksum = descriptor_checksum(&descriptor).unwrap();
+    format!("{descriptor}#{checksum}")
+}
+
+fn polymod(c: u64, value: u64) -> u64 {
+    const GENERATORS: [u64; 5] = [
+        0xf5dee51989,
+        0xa9fdca3312,
+        0x1bab10e32d,
+        0x3706b1677a,
+        0x644d626ffd,
+    ];
+    let c0 = c >> 35;
+    let mut c = ((c & 0x7ffffffff) << 5) ^ value;
+    for (i, generator) in GENERATORS.iter().enumerate() {
+        if c0 & (1 << i) != 0 {
+            c ^= generator;
+        }
+    }
+    c
+}
+
+/// Checksum of `descriptor` as specified by BIP380, without the leading
+/// `#`, or `None` if the descriptor contains characters that are not
+/// allowed.
+pub fn descriptor_checksum(descriptor: &str) -> Option<String> {
+    let mut c = 1;
+    let mut class = 0;
+    let mut class_count = 0;
+    for ch in descriptor.chars() {
+        let position = INPUT_CHARSET.find(ch)? as u64;
+        c = polymod(c, position & 31);
+        // Groups of three characters are also checksummed by their class
+        class = class * 3 + (position >> 5);
+        class_count += 1;
+        if class_count == 3 {
+            c = polymod(c, class);
+            class = 0;
+            class_count = 0;
+        }
+    }
+    if class_count > 0 {
+        c = polymod(c, class);
+    }
+    for _ in 0..8 {
+        c = polymod(c, 0);
+    }
+    c ^= 1;
+    let checksum = (0..8)
+        .map(|i| CHECKSUM_CHARSET[((c >> (5 * (7 - i))) & 31) as usize] as char)
+        .collect();
+    Some(checksum)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Test vectors of BIP380.
+    #[test]
+    fn checksum() {
+        assert_eq!(
+            descriptor_checksum("raw(deadbeef)").as_deref(),
+            Some("89f8spxm")
+        );
+        assert_eq!(descriptor_checksum("raw(Ü)"), None);
+    }
+
+    #[test]
+    fn escrow_script() {
+        let script = script_for_sidechain(SidechainId(7));
+        assert_eq!(format!("{script:x}"), "b4010751");
+        for sidechain in SidechainId::ALL {
+            assert_eq!(
+                sidechain_for_script(&script_for_sidechain(sidechain)),
+                Some(sidechain)
+            );
+        }
+        assert_eq!(
+            sidechain_for_script(&ScriptBuf::from(vec![0xb4, 0x01, 0x07])),
+            None
+        );
+        assert_eq!(
+            sidechain_for_script(&ScriptBuf::from(vec![0xb4, 0x01, 0x07, 0x52])),
+            None
+        );
+    }
+
+    #[test]
+    fn descriptor() {
+        let descriptor = descriptor_for_sidechain(SidechainId(7));
+        let (body, checksum) = descriptor.split_once('#').unwrap();
+        assert_eq!(body, "raw(b4010751)");
+        assert_eq!(descriptor_checksum(body).as_deref(), Some(checksum));
+    }
+}
@@ -0,0 +1,84 @@
+use crate::{client, Error};
+use bitcoin::bip158;
+use bitcoin::hash_types::FilterHeader;
+
+/// BIP158 filter for a single block, used to check whether a block is worth
+/// fetching before downloading it.
+#[derive(Debug, Clone)]
+pub struct BlockFilter {
+    pub filter: bip158::BlockFilter,
+    pub header: FilterHeader,
+}
+
+impl BlockFilter {
+    /// Returns `true` if any of `scripts` may appear in the block.
+    /// False positives are possible, false negatives are not.
+    pub fn matches_scripts(
+        &self,
+        block_hash: &bitcoin::BlockHash,
+        scripts: &[bitcoin::ScriptBuf],
+    ) -> Result<bool, Error> {
+        // `match_any` matches everything for an empty query
+        if scripts.is_empty() {
+            return Ok(false);
+        }
+        let query = scripts.iter().map(|script| script.as_bytes());
+        Ok(self.filter.match_any(block_hash, query)?)
+    }
+}
+
+impl From<client::BlockFilter> for BlockFilter {
+    fn from(other: client::BlockFilter) -> BlockFilter {
+        BlockFilter {
+            filter: bip158::BlockFilter::new(&other.filter),
+            header: other.header,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hashes::Hash as _;
+
+    /// `getblockfilter` response for the testnet genesis block, the first
+    /// of the BIP158 test vectors.
+    fn genesis_filter() -> BlockFilter {
+        let filter: client::BlockFilter = serde_json::from_value(serde_json::json!({
+            "filter": "019dfca8",
+            "header": "21584579b7eb08997773e5aeff3a7f932700042d0ed2a6129012b7d7ae81b750",
+        }))
+        .unwrap();
+        filter.into()
+    }
+
+    #[test]
+    fn matches_scripts_of_the_block() {
+        let block = bitcoin::blockdata::constants::genesis_block(bitcoin::Network::Testnet);
+        let block_hash = block.block_hash();
+        let filter = genesis_filter();
+        assert_eq!(
+            filter.filter.filter_header(&FilterHeader::all_zeros()),
+            filter.header
+        );
+        let coinbase_script = block.txdata[0].output[0].script_pubkey.clone();
+        let unrelated = bitcoin::ScriptBuf::new_v0_p2wsh(&bitcoin::ScriptBuf::new().wscript_hash());
+        assert!(filter
+            .matches_scripts(&block_hash, std::slice::from_ref(&coinbase_script))
+            .unwrap());
+        assert!(filter
+            .matches_scripts(&block_hash, &[unrelated.clone(), coinbase_script])
+            .unwrap());
+        assert!(!filter
+            .matches_scripts(&block_hash, std::slice::from_ref(&unrelated))
+            .unwrap());
+        assert!(!filter.matches_scripts(&block_hash, &[]).unwrap());
+        // The filter is keyed by the block hash
+        assert!(!filter
+            .matches_scripts(
+                &bitcoin::BlockHash::all_zeros(),
+                std::slice::from_ref(&block.txdata[0].output[0].script_pubkey)
+            )
+            .unwrap());
+    }
+}
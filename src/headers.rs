@@ -0,0 +1,455 @@
+#[cfg(feature = "client")]
+use crate::batch::{Batch, BatchItemResult};
+#[cfg(feature = "client")]
+use crate::cancel::{Limits, Partial};
+#[cfg(feature = "client")]
+use crate::client::ChainClient;
+use crate::client::Header;
+use crate::Error;
+#[cfg(feature = "client")]
+use jsonrpsee::core::client::ClientT;
+use std::collections::HashMap;
+#[cfg(feature = "client")]
+use std::time::{Duration, SystemTime};
+
+#[cfg(feature = "client")]
+/// The current time, adjusted by the offset of the node's clock from its
+/// peers, as used by the node to check block timestamps.
+pub async fn adjusted_now<C>(client: &C) -> Result<SystemTime, Error>
+where
+    C: ClientT + Sync,
+{
+    let offset = client.getnetworkinfo().await?.timeoffset;
+    let now = SystemTime::now();
+    let magnitude = Duration::from_secs(offset.unsigned_abs());
+    Ok(if offset >= 0 {
+        now + magnitude
+    } else {
+        now - magnitude
+    })
+}
+
+/// Headers indexed by hash, plus the height index of the active chain.
+#[derive(Debug, Clone, Default)]
+pub struct HeaderCache {
+    headers: HashMap<bitcoin::BlockHash, Header>,
+    active: HashMap<usize, bitcoin::BlockHash>,
+    tip: Option<bitcoin::BlockHash>,
+}
+
+impl HeaderCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[cfg(feature = "client")]
+    /// Fetches missing headers through `client` instead of giving up.
+    pub fn with_client<C>(self, client: C) -> LazyHeaderCache<C> {
+        LazyHeaderCache {
+            cache: self,
+            client,
+        }
+    }
+
+    pub fn tip(&self) -> Option<&Header> {
+        self.headers.get(self.tip.as_ref()?)
+    }
+
+    pub fn header(&self, block_hash: &bitcoin::BlockHash) -> Option<&Header> {
+        self.headers.get(block_hash)
+    }
+
+    /// Header at `height` in the active chain.
+    pub fn header_at_height(&self, height: usize) -> Option<&Header> {
+        self.headers.get(self.active.get(&height)?)
+    }
+
+    /// Adds a header without changing the active chain.
+    pub fn insert(&mut self, header: Header) {
+        self.headers.insert(header.hash, header);
+    }
+
+    /// Makes `header` the tip of the active chain. If it doesn't extend the
+    /// current tip, the active chain is walked back to the fork point, and
+    /// the headers that were disconnected are returned, highest first.
+    ///
+    /// The headers between the fork point and `header` must already be in
+    /// the cache.
+    pub fn connect(&mut self, header: Header) -> Result<Vec<Header>, Error> {
+        let block_hash = header.hash;
+        let extends_tip = self.tip.is_none() || header.previousblockhash == self.tip;
+        self.insert(header);
+        if extends_tip {
+            self.set_tip(block_hash);
+            return Ok(Vec::new());
+        }
+        // Walk back the new branch until it meets the active chain.
+        let mut branch = Vec::new();
+        let mut cursor = block_hash;
+        let fork_height = loop {
+            let header = self
+                .headers
+                .get(&cursor)
+                .ok_or(Error::UnknownBlock { block_hash: cursor })?;
+            if self.active.get(&header.height) == Some(&cursor) {
+                break header.height;
+            }
+            branch.push((header.height, cursor));
+            cursor = header
+                .previousblockhash
+                .ok_or(Error::UnknownBlock { block_hash: cursor })?;
+        };
+        let tip_height = self.tip().map_or(fork_height, |tip| tip.height);
+        let mut disconnected = Vec::new();
+        for height in (fork_height + 1..=tip_height).rev() {
+            if let Some(block_hash) = self.active.remove(&height) {
+                disconnected.extend(self.headers.get(&block_hash).cloned());
+            }
+        }
+        self.active.extend(branch);
+        self.tip = Some(block_hash);
+        Ok(disconnected)
+    }
+
+    fn set_tip(&mut self, block_hash: bitcoin::BlockHash) {
+        let height = self.headers[&block_hash].height;
+        self.active.insert(height, block_hash);
+        self.tip = Some(block_hash);
+    }
+
+    /// Ancestor of `block_hash` at `height`, if the headers in between are
+    /// known.
+    pub fn ancestor(
+        &self,
+        block_hash: &bitcoin::BlockHash,
+        height: usize,
+    ) -> Option<bitcoin::BlockHash> {
+        let mut header = self.headers.get(block_hash)?;
+        if header.height < height {
+            return None;
+        }
+        while header.height > height {
+            // Shortcut through the active chain.
+            if self.active.get(&header.height) == Some(&header.hash) {
+                return self.active.get(&height).copied();
+            }
+            header = self.headers.get(header.previousblockhash.as_ref()?)?;
+        }
+        Some(header.hash)
+    }
+
+    /// Whether `ancestor` is `descendant` or one of its ancestors. `None` if
+    /// that can't be determined from the cached headers.
+    pub fn is_ancestor(
+        &self,
+        ancestor: &bitcoin::BlockHash,
+        descendant: &bitcoin::BlockHash,
+    ) -> Option<bool> {
+        let height = self.headers.get(ancestor)?.height;
+        if height > self.headers.get(descendant)?.height {
+            return Some(false);
+        }
+        Some(self.ancestor(descendant, height)? == *ancestor)
+    }
+
+    /// Last block that both `a` and `b` build on.
+    pub fn common_ancestor(
+        &self,
+        a: &bitcoin::BlockHash,
+        b: &bitcoin::BlockHash,
+    ) -> Option<bitcoin::BlockHash> {
+        let height = std::cmp::min(self.headers.get(a)?.height, self.headers.get(b)?.height);
+        let mut a = self.ancestor(a, height)?;
+        let mut b = self.ancestor(b, height)?;
+        while a != b {
+            a = self.headers.get(&a)?.previousblockhash?;
+            b = self.headers.get(&b)?.previousblockhash?;
+        }
+        Some(a)
+    }
+
+    /// Header of the block where the chains ending in `tip_a` and `tip_b`
+    /// diverge.
+    pub fn fork_point(
+        &self,
+        tip_a: &bitcoin::BlockHash,
+        tip_b: &bitcoin::BlockHash,
+    ) -> Option<&Header> {
+        self.headers.get(&self.common_ancestor(tip_a, tip_b)?)
+    }
+}
+
+#[cfg(feature = "client")]
+/// [`HeaderCache`] that fetches missing headers from the node.
+pub struct LazyHeaderCache<C> {
+    pub cache: HeaderCache,
+    client: C,
+}
+
+#[cfg(feature = "client")]
+impl<C> LazyHeaderCache<C>
+where
+    C: ClientT + Sync,
+{
+    /// Connects the headers of the node's active chain above the cached
+    /// tip, fetching `batch_size` of them per round trip, and returns the
+    /// headers that were disconnected by reorgs, highest first.
+    pub async fn sync(&mut self, batch_size: usize) -> Result<Vec<Header>, Error> {
+        self.sync_with_limits(batch_size, &Limits::default())
+            .await?
+            .into_result()
+    }
+
+    /// Like [`Self::sync`], but stops once `limits` are reached. Headers
+    /// connected before that stay in the cache, and the sync can be resumed
+    /// by calling this again.
+    ///
+    /// Progress in [`Error::DeadlineExceeded`] is counted in headers.
+    pub async fn sync_with_limits(
+        &mut self,
+        batch_size: usize,
+        limits: &Limits,
+    ) -> Result<Partial<Vec<Header>>, Error> {
+        let mut disconnected = vec![];
+        let result = self
+            .sync_batches(batch_size, limits, &mut disconnected)
+            .await;
+        Partial::new(disconnected, result)
+    }
+
+    async fn sync_batches(
+        &mut self,
+        batch_size: usize,
+        limits: &Limits,
+        disconnected: &mut Vec<Header>,
+    ) -> Result<(), Error> {
+        let running = limits.start();
+        let start = self.cache.tip().map_or(0, |tip| tip.height + 1);
+        let tip_height = running
+            .race(async { Ok(self.client.getblockcount().await?) }, 0, None)
+            .await?;
+        let mut completed = 0;
+        let heights: Vec<_> = (start..=tip_height).collect();
+        for heights in heights.chunks(batch_size.max(1)) {
+            let remaining = Some(tip_height + 1 - start - completed);
+            let block_hashes = running
+                .race(
+                    heights
+                        .iter()
+                        .fold(Batch::new(), |batch, height| batch.get_block_hash(*height))
+                        .send(&self.client),
+                    completed,
+                    remaining,
+                )
+                .await?;
+            let mut batch = Batch::new();
+            for result in block_hashes {
+                match result? {
+                    BatchItemResult::BlockHash(block_hash) => {
+                        batch = batch.get_block_header(block_hash)
+                    }
+                    _ => {
+                        return Err(Error::UnexpectedBatchResult {
+                            method: "getblockhash",
+                        })
+                    }
+                }
+            }
+            let headers = running
+                .race(batch.send(&self.client), completed, remaining)
+                .await?;
+            for result in headers {
+                let BatchItemResult::BlockHeader(header) = result? else {
+                    return Err(Error::UnexpectedBatchResult {
+                        method: "getblockheader",
+                    });
+                };
+                let mut reorged = self.connect(header).await?;
+                reorged.append(disconnected);
+                *disconnected = reorged;
+                completed += 1;
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn header(&mut self, block_hash: &bitcoin::BlockHash) -> Result<&Header, Error> {
+        if !self.cache.headers.contains_key(block_hash) {
+            let header = self.client.getblockheader(block_hash).await?;
+            self.cache.insert(header);
+        }
+        Ok(&self.cache.headers[block_hash])
+    }
+
+    /// Header at `height` in the cached active chain, or in the node's
+    /// active chain if the height is not cached.
+    pub async fn header_at_height(&mut self, height: usize) -> Result<&Header, Error> {
+        let block_hash = match self.cache.active.get(&height) {
+            Some(block_hash) => *block_hash,
+            None => self.client.getblockhash(height).await?,
+        };
+        self.header(&block_hash).await
+    }
+
+    /// Like [`HeaderCache::connect`], fetching the headers of the new
+    /// branch as needed.
+    pub async fn connect(&mut self, header: Header) -> Result<Vec<Header>, Error> {
+        let mut cursor = header.previousblockhash;
+        while let Some(block_hash) = cursor {
+            let known = self.cache.headers.get(&block_hash);
+            if known.is_some_and(|known| self.cache.active.get(&known.height) == Some(&block_hash))
+            {
+                break;
+            }
+            cursor = self.header(&block_hash).await?.previousblockhash;
+        }
+        self.cache.connect(header)
+    }
+
+    async fn ancestor(
+        &mut self,
+        block_hash: &bitcoin::BlockHash,
+        height: usize,
+    ) -> Result<Option<bitcoin::BlockHash>, Error> {
+        let mut cursor = *block_hash;
+        loop {
+            if let Some(ancestor) = self.cache.ancestor(&cursor, height) {
+                return Ok(Some(ancestor));
+            }
+            let header = self.header(&cursor).await?;
+            if header.height <= height {
+                return Ok((header.height == height).then_some(cursor));
+            }
+            match header.previousblockhash {
+                Some(prev) => cursor = prev,
+                None => return Ok(None),
+            }
+        }
+    }
+
+    pub async fn is_ancestor(
+        &mut self,
+        ancestor: &bitcoin::BlockHash,
+        descendant: &bitcoin::BlockHash,
+    ) -> Result<bool, Error> {
+        let height = self.header(ancestor).await?.height;
+        if height > self.header(descendant).await?.height {
+            return Ok(false);
+        }
+        Ok(self.ancestor(descendant, height).await? == Some(*ancestor))
+    }
+
+    pub async fn common_ancestor(
+        &mut self,
+        a: &bitcoin::BlockHash,
+        b: &bitcoin::BlockHash,
+    ) -> Result<Option<bitcoin::BlockHash>, Error> {
+        let height = std::cmp::min(self.header(a).await?.height, self.header(b).await?.height);
+        let (Some(mut a), Some(mut b)) = (
+            self.ancestor(a, height).await?,
+            self.ancestor(b, height).await?,
+        ) else {
+            return Ok(None);
+        };
+        while a != b {
+            let (Some(prev_a), Some(prev_b)) = (
+                self.header(&a).await?.previousblockhash,
+                self.header(&b).await?.previousblockhash,
+            ) else {
+                return Ok(None);
+            };
+            a = prev_a;
+            b = prev_b;
+        }
+        Ok(Some(a))
+    }
+}
+
+#[cfg(all(test, feature = "client"))]
+mod tests {
+    use super::*;
+    use crate::cancel::CancellationToken;
+    use crate::mock::{block_on, network_info, MockChain, MockClient};
+    use serde_json::json;
+
+    #[test]
+    fn adjusted_now_applies_the_offset() {
+        for offset in [0, 30, -30] {
+            let client = MockClient::new(move |_, _| {
+                let mut info = network_info(230000);
+                info["timeoffset"] = json!(offset);
+                Ok(info)
+            });
+            let before = SystemTime::now();
+            let adjusted = block_on(adjusted_now(&client)).unwrap();
+            let after = SystemTime::now();
+            let magnitude = Duration::from_secs(30);
+            let (earliest, latest) = match offset {
+                0 => (before, after),
+                30 => (before + magnitude, after + magnitude),
+                _ => (before - magnitude, after - magnitude),
+            };
+            assert!(
+                earliest <= adjusted && adjusted <= latest,
+                "offset {offset}"
+            );
+        }
+    }
+
+    #[test]
+    fn cancelled_sync_keeps_the_connected_batches() {
+        let chain = MockChain::new();
+        chain.mine(19);
+        let cancel = CancellationToken::new();
+        let client = MockClient::new({
+            let chain = chain.clone();
+            let cancel = cancel.clone();
+            move |method, params| {
+                // First request of the 3rd of 10 batches
+                if method == "getblockhash" && params[0] == 4 {
+                    cancel.cancel();
+                }
+                chain.handle(method, params).unwrap()
+            }
+        });
+        let mut headers = HeaderCache::new().with_client(client);
+        let limits = Limits::new().with_cancellation(cancel);
+        let partial = block_on(headers.sync_with_limits(2, &limits)).unwrap();
+        assert!(matches!(partial.stopped, Some(Error::Cancelled)));
+        assert!(partial.value.is_empty());
+        assert_eq!(headers.cache.tip().unwrap().height, 3);
+        let methods = headers.client.methods();
+        assert_eq!(methods.len(), 1 + 2 * 4 + 2);
+        assert_eq!(methods.last().unwrap(), "getblockhash");
+
+        let disconnected = block_on(headers.sync(2)).unwrap();
+        assert!(disconnected.is_empty());
+        assert_eq!(headers.cache.tip().unwrap().hash, chain.tip());
+        // Resumed above the headers that were connected
+        let resumed = &headers.client.calls()[methods.len()..];
+        assert_eq!(resumed[1].0, "getblockhash");
+        assert_eq!(resumed[1].1[0], 4);
+    }
+
+    #[test]
+    fn deadline_counts_headers() {
+        let chain = MockChain::new();
+        chain.mine(9);
+        let client = MockClient::new({
+            let chain = chain.clone();
+            move |method, params| chain.handle(method, params).unwrap()
+        });
+        let mut headers = HeaderCache::new().with_client(client);
+        let limits = Limits::new().with_timeout(std::time::Duration::ZERO);
+        let partial = block_on(headers.sync_with_limits(2, &limits)).unwrap();
+        assert!(matches!(
+            partial.stopped,
+            Some(Error::DeadlineExceeded {
+                completed: 0,
+                remaining_estimate: None,
+            })
+        ));
+        assert!(headers.cache.tip().is_none());
+        assert!(headers.client.calls().is_empty());
+    }
+}
@@ -0,0 +1,426 @@
+use crate::client::{
+    ChainClient, EnforcementStatus, EnforcerClient, MemoryStatsMode, NodeAdminClient,
+};
+use crate::Error;
+use jsonrpsee::core::client::ClientT;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+/// Error code returned by the node while it is still starting up.
+const RPC_IN_WARMUP: i32 = -28;
+/// Error code returned by the node for unknown methods.
+const RPC_METHOD_NOT_FOUND: i32 = -32601;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NodeStatus {
+    /// The node is reachable but still loading, e.g. verifying blocks.
+    Starting,
+    Healthy {
+        height: usize,
+        best_hash: bitcoin::BlockHash,
+        /// Utilization of the node's locked memory, see
+        /// [`crate::LockedMemory::utilization`]. `None` if it is not
+        /// polled, see [`HealthConfig::report_memory_pressure`], or the
+        /// node does not report it.
+        memory_pressure: Option<f32>,
+    },
+    Degraded {
+        reason: DegradedReason,
+        /// 0 if `reason` is [`DegradedReason::MemoryPressure`].
+        consecutive_failures: u32,
+        memory_pressure: Option<f32>,
+    },
+    /// The node is responding, but the enforcer is more than
+    /// [`HealthConfig::max_enforcer_lag`] blocks behind it.
+    EnforcerLagging {
+        blocks: u32,
+    },
+    Down,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DegradedReason {
+    /// [`HealthConfig::degraded_after`] polls failed in a row.
+    FailedPolls,
+    /// The memory pressure exceeds [`HealthConfig::max_memory_pressure`].
+    MemoryPressure,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct HealthConfig {
+    pub interval: Duration,
+    /// Polling interval is doubled while the node is down, up to this value.
+    pub max_interval: Duration,
+    /// Number of consecutive failed polls before the node is `Degraded`.
+    pub degraded_after: u32,
+    /// Number of consecutive failed polls before the node is `Down`.
+    pub down_after: u32,
+    /// If set, the status of the enforcer is polled as well, and the node
+    /// is reported as [`NodeStatus::EnforcerLagging`] while the enforcer is
+    /// more than this many blocks behind. Nodes without an enforcer are not
+    /// affected.
+    pub max_enforcer_lag: Option<u32>,
+    /// Whether `getmemoryinfo` is polled to report the memory pressure of
+    /// [`NodeStatus::Healthy`]. It is also polled if
+    /// [`Self::max_memory_pressure`] is set. Failures of this request don't
+    /// count as failed polls, the memory pressure is `None` then.
+    pub report_memory_pressure: bool,
+    /// If set, the node is reported as [`NodeStatus::Degraded`] while its
+    /// memory pressure is above this value, between 0 and 1. Swapping nodes
+    /// get slow long before requests fail.
+    pub max_memory_pressure: Option<f32>,
+}
+
+impl HealthConfig {
+    pub fn new(interval: Duration) -> Self {
+        HealthConfig {
+            interval,
+            max_interval: interval * 16,
+            degraded_after: 1,
+            down_after: 3,
+            max_enforcer_lag: None,
+            report_memory_pressure: false,
+            max_memory_pressure: None,
+        }
+    }
+}
+
+/// Periodically polls the mainchain node and publishes status transitions.
+/// The polling task is stopped when the monitor is dropped.
+pub struct HealthMonitor {
+    status: watch::Receiver<NodeStatus>,
+    task: JoinHandle<()>,
+}
+
+impl HealthMonitor {
+    pub async fn spawn<C>(client: C, interval: Duration) -> Self
+    where
+        C: ClientT + Send + Sync + 'static,
+    {
+        Self::spawn_with_config(client, HealthConfig::new(interval)).await
+    }
+
+    pub async fn spawn_with_config<C>(client: C, config: HealthConfig) -> Self
+    where
+        C: ClientT + Send + Sync + 'static,
+    {
+        let mut poller = Poller {
+            client,
+            config,
+            consecutive_failures: 0,
+            last_ok: None,
+        };
+        let initial = poller.poll().await;
+        let (sender, status) = watch::channel(initial);
+        let task = tokio::spawn(async move {
+            let mut interval = config.interval;
+            loop {
+                tokio::time::sleep(interval).await;
+                let status = poller.poll().await;
+                interval = match status {
+                    NodeStatus::Down => std::cmp::min(interval * 2, config.max_interval),
+                    _ => config.interval,
+                };
+                sender.send_if_modified(|current| {
+                    if *current == status {
+                        return false;
+                    }
+                    *current = status;
+                    true
+                });
+                if sender.is_closed() {
+                    return;
+                }
+            }
+        });
+        HealthMonitor { status, task }
+    }
+
+    pub fn status(&self) -> NodeStatus {
+        *self.status.borrow()
+    }
+
+    /// Returns a receiver that is notified on every status transition.
+    pub fn subscribe(&self) -> watch::Receiver<NodeStatus> {
+        self.status.clone()
+    }
+}
+
+impl Drop for HealthMonitor {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Status of the enforcer, or `None` if the node has no enforcer.
+pub async fn get_enforcement_status<C>(client: &C) -> Result<Option<EnforcementStatus>, Error>
+where
+    C: ClientT + Sync,
+{
+    match client.getenforcementstatus().await {
+        Ok(status) => Ok(Some(status)),
+        Err(jsonrpsee::core::Error::Call(err)) if err.code() == RPC_METHOD_NOT_FOUND => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Summary of whether the node's view of the chain can be relied on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct TrustReport {
+    /// Result of `verifychain`.
+    pub chain_verified: bool,
+    pub initial_block_download: bool,
+    /// `false` while an assumeutxo snapshot is still being validated in the
+    /// background.
+    pub fully_validated: bool,
+}
+
+impl TrustReport {
+    /// Runs `verifychain`, which can take minutes, so the client should be
+    /// created with a matching request timeout.
+    pub async fn fetch<C>(
+        client: &C,
+        check_level: Option<u8>,
+        nblocks: Option<u32>,
+    ) -> Result<Self, Error>
+    where
+        C: ClientT + Sync,
+    {
+        let chain_verified = client.verifychain(check_level, nblocks).await?;
+        let initial_block_download = client.getblockchaininfo().await?.initialblockdownload;
+        let fully_validated = match client.getchainstates().await {
+            Ok(chain_states) => chain_states.is_fully_validated(),
+            // Nodes without `getchainstates` can't load snapshots.
+            Err(jsonrpsee::core::Error::Call(err)) if err.code() == RPC_METHOD_NOT_FOUND => true,
+            Err(err) => return Err(err.into()),
+        };
+        Ok(TrustReport {
+            chain_verified,
+            initial_block_download,
+            fully_validated,
+        })
+    }
+
+    pub fn is_trusted(&self) -> bool {
+        self.chain_verified && !self.initial_block_download && self.fully_validated
+    }
+}
+
+struct Poller<C> {
+    client: C,
+    config: HealthConfig,
+    consecutive_failures: u32,
+    /// Last status reported while the node was responding.
+    last_ok: Option<NodeStatus>,
+}
+
+impl<C> Poller<C>
+where
+    C: ClientT + Send + Sync,
+{
+    async fn poll(&mut self) -> NodeStatus {
+        let result = async {
+            let height = self.client.getblockcount().await?;
+            let best_hash = self.client.getbestblockhash().await?;
+            if let Some(max_enforcer_lag) = self.config.max_enforcer_lag {
+                match self.client.getenforcementstatus().await {
+                    Ok(status) if status.lag_blocks > max_enforcer_lag => {
+                        return Ok(NodeStatus::EnforcerLagging {
+                            blocks: status.lag_blocks,
+                        });
+                    }
+                    Ok(_) => (),
+                    Err(jsonrpsee::core::Error::Call(err))
+                        if err.code() == RPC_METHOD_NOT_FOUND => {}
+                    Err(err) => return Err(err),
+                }
+            }
+            let memory_pressure = if self.config.report_memory_pressure
+                || self.config.max_memory_pressure.is_some()
+            {
+                // Nodes may not have the method, or not allow it
+                self.client
+                    .getmemoryinfo(MemoryStatsMode)
+                    .await
+                    .ok()
+                    .map(|memory_info| memory_info.locked.utilization())
+            } else {
+                None
+            };
+            if let (Some(memory_pressure), Some(max_memory_pressure)) =
+                (memory_pressure, self.config.max_memory_pressure)
+            {
+                if memory_pressure > max_memory_pressure {
+                    return Ok(NodeStatus::Degraded {
+                        reason: DegradedReason::MemoryPressure,
+                        consecutive_failures: 0,
+                        memory_pressure: Some(memory_pressure),
+                    });
+                }
+            }
+            Ok::<_, jsonrpsee::core::Error>(NodeStatus::Healthy {
+                height,
+                best_hash,
+                memory_pressure,
+            })
+        }
+        .await;
+        let status = match result {
+            Ok(status) => status,
+            Err(jsonrpsee::core::Error::Call(err)) if err.code() == RPC_IN_WARMUP => {
+                NodeStatus::Starting
+            }
+            Err(_) => {
+                self.consecutive_failures += 1;
+                let consecutive_failures = self.consecutive_failures;
+                return if consecutive_failures >= self.config.down_after {
+                    NodeStatus::Down
+                } else if consecutive_failures >= self.config.degraded_after {
+                    NodeStatus::Degraded {
+                        reason: DegradedReason::FailedPolls,
+                        consecutive_failures,
+                        memory_pressure: None,
+                    }
+                } else {
+                    // Not enough failures yet to report anything but the
+                    // previous status.
+                    self.last_ok.unwrap_or(NodeStatus::Degraded {
+                        reason: DegradedReason::FailedPolls,
+                        consecutive_failures,
+                        memory_pressure: None,
+                    })
+                };
+            }
+        };
+        self.consecutive_failures = 0;
+        self.last_ok = Some(status);
+        status
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{block_on, rpc_error, MockClient};
+    use bitcoin::hashes::Hash as _;
+    use serde_json::json;
+
+    /// `getmemoryinfo` response with `used` of 1000 bytes of locked memory
+    /// in use.
+    fn memory_info(used: u64) -> serde_json::Value {
+        json!({
+            "locked": {
+                "used": used,
+                "free": 1000 - used,
+                "total": 1000,
+                "locked": 1000,
+                "chunks_used": 4,
+                "chunks_free": 1,
+            }
+        })
+    }
+
+    fn poller(client: MockClient, config: HealthConfig) -> Poller<MockClient> {
+        Poller {
+            client,
+            config,
+            consecutive_failures: 0,
+            last_ok: None,
+        }
+    }
+
+    fn node(memory_info: fn() -> Result<serde_json::Value, jsonrpsee::core::Error>) -> MockClient {
+        MockClient::new(move |method, _| match method {
+            "getblockcount" => Ok(json!(100)),
+            "getbestblockhash" => Ok(json!(bitcoin::BlockHash::all_zeros())),
+            "getmemoryinfo" => memory_info(),
+            _ => unreachable!("{method}"),
+        })
+    }
+
+    fn healthy(memory_pressure: Option<f32>) -> NodeStatus {
+        NodeStatus::Healthy {
+            height: 100,
+            best_hash: bitcoin::BlockHash::all_zeros(),
+            memory_pressure,
+        }
+    }
+
+    #[test]
+    fn memory_info_is_not_polled_by_default() {
+        let mut poller = poller(
+            node(|| Ok(memory_info(900))),
+            HealthConfig::new(Duration::ZERO),
+        );
+        assert_eq!(block_on(poller.poll()), healthy(None));
+        assert_eq!(
+            poller.client.methods(),
+            ["getblockcount", "getbestblockhash"]
+        );
+    }
+
+    #[test]
+    fn memory_pressure_alone_degrades() {
+        let mut config = HealthConfig::new(Duration::ZERO);
+        config.report_memory_pressure = true;
+        let mut reporting = poller(node(|| Ok(memory_info(900))), config);
+        assert_eq!(block_on(reporting.poll()), healthy(Some(0.9)));
+
+        config.max_memory_pressure = Some(0.8);
+        let mut degrading = poller(node(|| Ok(memory_info(900))), config);
+        assert_eq!(
+            block_on(degrading.poll()),
+            NodeStatus::Degraded {
+                reason: DegradedReason::MemoryPressure,
+                consecutive_failures: 0,
+                memory_pressure: Some(0.9),
+            }
+        );
+
+        // The threshold alone polls the memory pressure too
+        config.report_memory_pressure = false;
+        config.max_memory_pressure = Some(0.95);
+        let mut below = poller(node(|| Ok(memory_info(900))), config);
+        assert_eq!(block_on(below.poll()), healthy(Some(0.9)));
+    }
+
+    #[test]
+    fn memory_info_failures_are_not_failed_polls() {
+        let mut config = HealthConfig::new(Duration::ZERO);
+        config.max_memory_pressure = Some(0.8);
+        // Not in the `rpcwhitelist` of the node
+        let mut forbidden = poller(
+            node(|| {
+                Err(jsonrpsee::core::Error::Transport(
+                    jsonrpsee::http_client::transport::Error::RequestFailure { status_code: 403 }
+                        .into(),
+                ))
+            }),
+            config,
+        );
+        let mut missing = poller(node(|| Err(rpc_error(-32601, "Method not found"))), config);
+        for poller in [&mut forbidden, &mut missing] {
+            for _ in 0..config.down_after {
+                assert_eq!(block_on(poller.poll()), healthy(None));
+            }
+            assert_eq!(poller.consecutive_failures, 0);
+        }
+    }
+
+    #[test]
+    fn failed_polls_degrade() {
+        let client = MockClient::new(|_, _| Err(rpc_error(-1, "internal error")));
+        let mut poller = poller(client, HealthConfig::new(Duration::ZERO));
+        assert_eq!(
+            block_on(poller.poll()),
+            NodeStatus::Degraded {
+                reason: DegradedReason::FailedPolls,
+                consecutive_failures: 1,
+                memory_pressure: None,
+            }
+        );
+        block_on(poller.poll());
+        assert_eq!(block_on(poller.poll()), NodeStatus::Down);
+    }
+}
@@ -0,0 +1,224 @@
+//! Table of the RPC methods the client traits cover, with their Rust
+//! signatures, and comparison with the methods a node offers.
+//!
+//! The table is generated by [`rpc_traits`] along with the client traits.
+//! Types are given as `stringify!` prints them.
+#[cfg(feature = "client")]
+use crate::Error;
+#[cfg(feature = "client")]
+use jsonrpsee::core::client::ClientT;
+use std::collections::BTreeSet;
+use std::fmt::Write as _;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub struct ParamInfo {
+    pub name: &'static str,
+    pub ty: &'static str,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub struct MethodInfo {
+    /// Name of the method on the node. Several Rust methods can share it,
+    /// e.g. for different verbosities.
+    pub rpc_name: &'static str,
+    pub rust_name: &'static str,
+    /// Trait the method is declared on, e.g. `Chain` for
+    /// [`crate::ChainClient`].
+    pub trait_name: &'static str,
+    pub params: &'static [ParamInfo],
+    pub response: &'static str,
+    /// Doc comment of the method, on a single line.
+    pub doc: &'static str,
+}
+
+impl MethodInfo {
+    /// Rust signature, e.g. `getblockhash(height: usize) -> bitcoin::BlockHash`.
+    pub fn signature(&self) -> String {
+        let params: Vec<_> = self
+            .params
+            .iter()
+            .map(|param| format!("{}: {}", param.name, param.ty))
+            .collect();
+        format!(
+            "{}({}) -> {}",
+            self.rust_name,
+            params.join(", "),
+            self.response
+        )
+    }
+}
+
+/// Declares the `#[rpc]` client traits, and a `METHODS` table of their
+/// methods so that it can't go out of sync with them. Methods must have the
+/// form
+///
+/// ```ignore
+/// /// Doc comment.
+/// #[method(name = "rpcname")]
+/// async fn rust_name(&self, param: Type, ...) -> Result<Response, jsonrpsee::core::Error>;
+/// ```
+///
+/// Anything else fails to compile. `stringify!` wraps types that don't fit
+/// on a line, shorten those with imports.
+macro_rules! rpc_traits {
+    ($(
+        $(#[$trait_attr:meta])*
+        pub trait $trait_name:ident {
+            $(
+                $(#[doc = $doc:literal])*
+                #[method(name = $rpc_name:literal)]
+                async fn $rust_name:ident(&self $(, $param:ident: $ty:ty)* $(,)?)
+                    -> Result<$response:ty, jsonrpsee::core::Error $(,)?>;
+            )*
+        }
+    )*) => {
+        $(
+            $(#[$trait_attr])*
+            pub trait $trait_name {
+                $(
+                    $(#[doc = $doc])*
+                    #[method(name = $rpc_name)]
+                    async fn $rust_name(&self $(, $param: $ty)*)
+                        -> Result<$response, jsonrpsee::core::Error>;
+                )*
+            }
+        )*
+
+        pub(crate) static METHODS: &[$crate::introspection::MethodInfo] = &[$($(
+            $crate::introspection::MethodInfo {
+                rpc_name: $rpc_name,
+                rust_name: stringify!($rust_name),
+                trait_name: stringify!($trait_name),
+                params: &[$($crate::introspection::ParamInfo {
+                    name: stringify!($param),
+                    ty: stringify!($ty),
+                }),*],
+                response: stringify!($response),
+                doc: concat!($($doc),*).trim_ascii(),
+            },
+        )*)*];
+    };
+}
+pub(crate) use rpc_traits;
+
+/// Every RPC method of the client traits, in the order they are declared.
+pub fn methods() -> &'static [MethodInfo] {
+    crate::client::METHODS
+}
+
+/// Formats `methods` as a table with a row per method.
+pub fn format_table(methods: &[MethodInfo]) -> String {
+    let rpc_width = methods
+        .iter()
+        .map(|method| method.rpc_name.len())
+        .max()
+        .unwrap_or(0)
+        .max("RPC".len());
+    let trait_width = methods
+        .iter()
+        .map(|method| method.trait_name.len())
+        .max()
+        .unwrap_or(0)
+        .max("TRAIT".len());
+    let mut table = format!(
+        "{:rpc_width$}  {:trait_width$}  SIGNATURE\n",
+        "RPC", "TRAIT"
+    );
+    for method in methods {
+        let _ = writeln!(
+            table,
+            "{:rpc_width$}  {:trait_width$}  {}",
+            method.rpc_name,
+            method.trait_name,
+            method.signature()
+        );
+    }
+    table
+}
+
+/// Method names listed in the output of the `help` RPC, which has a line
+/// per method that starts with its name, and section headings such as
+/// `== Blockchain ==`.
+pub fn parse_help(help: &str) -> BTreeSet<String> {
+    help.lines()
+        .filter(|line| !line.starts_with("==") && !line.trim().is_empty())
+        .filter_map(|line| line.split_whitespace().next())
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Difference between the methods a node offers and the methods covered
+/// by the client traits.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize)]
+pub struct MethodCoverage {
+    /// Offered by the node, but not covered.
+    pub missing_in_crate: BTreeSet<String>,
+    /// Covered, but not offered by the node, e.g. because it is an older
+    /// version or a wallet method on a node without wallet support.
+    pub missing_in_node: BTreeSet<&'static str>,
+}
+
+impl MethodCoverage {
+    /// Compares the methods listed by `help` with [`methods`].
+    pub fn from_help(help: &str) -> Self {
+        let offered = parse_help(help);
+        let covered: BTreeSet<_> = methods().iter().map(|method| method.rpc_name).collect();
+        MethodCoverage {
+            missing_in_crate: offered
+                .iter()
+                .filter(|method| !covered.contains(method.as_str()))
+                .cloned()
+                .collect(),
+            missing_in_node: covered
+                .into_iter()
+                .filter(|method| !offered.contains(*method))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(feature = "client")]
+/// Calls `help` on the node and compares the methods it offers with
+/// [`methods`].
+pub async fn method_coverage<C>(client: &C) -> Result<MethodCoverage, Error>
+where
+    C: ClientT + Sync,
+{
+    let help: String = client.request("help", jsonrpsee::rpc_params![]).await?;
+    Ok(MethodCoverage::from_help(&help))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn table_matches_the_traits() {
+        let method = methods()
+            .iter()
+            .find(|method| method.rust_name == "getblockhex")
+            .unwrap();
+        assert_eq!(method.rpc_name, "getblock");
+        assert_eq!(method.trait_name, "Chain");
+        assert_eq!(
+            method.signature(),
+            "getblockhex(blockhash: &bitcoin::BlockHash, verbosity: usize) -> ConsensusEncodedLazy<bitcoin::Block>"
+        );
+        assert_eq!(
+            method.doc,
+            "`verbosity` must be `0`, use `getblock` for the decoded block."
+        );
+        let traits: BTreeSet<_> = methods().iter().map(|method| method.trait_name).collect();
+        assert_eq!(
+            traits,
+            BTreeSet::from(["Chain", "Drivechain", "Enforcer", "NodeAdmin", "Wallet"])
+        );
+    }
+
+    #[test]
+    fn signatures_are_single_lines() {
+        for method in methods() {
+            assert!(!method.signature().contains('\n'), "{}", method.signature());
+        }
+    }
+}
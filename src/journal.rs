@@ -0,0 +1,1027 @@
+//! Append-only journal of the mainchain events relevant to a sidechain,
+//! that can be resumed after a crash.
+//!
+//! [`Journal`] follows the main chain block by block and appends the events
+//! of every block to a [`JournalStore`]. Entries have consecutive ids, and
+//! are ordered by block and by [`JournalEntry::seq_within_block`] within a
+//! block. When blocks are disconnected, their events are undone newest
+//! first with [`JournalEvent::Reverted`] entries, before the events of the
+//! blocks that replace them.
+//!
+//! The store is the only state: on restart, the journal continues from the
+//! entries in it without duplicating or skipping any. The events of a block
+//! are derived from the chain and from the entries before the block, so a
+//! block that was only partly appended is completed with the same events.
+//! The exception are [`JournalEvent::BundleProposed`],
+//! [`JournalEvent::BundleVoted`] and [`JournalEvent::BundleFailed`], which
+//! the node only reports for its current tip, and which are therefore only
+//! derived for the tip. If the
+//! tip moved before a block was completed, they are derived for the new tip
+//! instead.
+use crate::cancel::{Limits, Running};
+use crate::client::{ChainClient, Deposit, DrivechainClient, SidechainId, WithdrawalStatus};
+use crate::Error;
+use futures::Stream;
+use jsonrpsee::core::client::ClientT;
+use jsonrpsee::http_client::HttpClient;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::fs::OpenOptions;
+use std::io::Write as _;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Confirmations after which [`JournalEvent::DepositConfirmed`] is
+/// appended by default.
+pub const DEFAULT_DEPOSIT_CONFIRMATIONS: u32 = 6;
+
+/// Blocks the cursor keeps the events of, to revert them on reorgs. Deeper
+/// reorgs fail with [`Error::JournalReorgTooDeep`].
+pub const REORG_WINDOW: usize = 100;
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum JournalEvent {
+    /// First event of every block, so that blocks without other events are
+    /// journaled too.
+    BlockConnected,
+    DepositSeen {
+        txid: bitcoin::Txid,
+        /// Destination in the `OP_RETURN` of the deposit, `None` if
+        /// [`crate::deposit::extract_dest_bytes`] finds none.
+        #[serde(with = "hex_opt")]
+        destination: Option<Vec<u8>>,
+    },
+    /// A deposit seen by the journal reached the configured number of
+    /// confirmations in this block.
+    DepositConfirmed {
+        txid: bitcoin::Txid,
+        confirmations: u32,
+    },
+    /// The bundle is new in `listwithdrawalstatus`, as reported while the
+    /// block was the tip.
+    BundleProposed {
+        bundle_txid: bitcoin::Txid,
+    },
+    /// The work score of the bundle changed, as reported while the block was
+    /// the tip.
+    BundleVoted {
+        bundle_txid: bitcoin::Txid,
+        work_score: usize,
+        blocks_left: usize,
+    },
+    BundleSpent {
+        bundle_txid: bitcoin::Txid,
+    },
+    /// The bundle failed, as reported while the block was the tip.
+    BundleFailed {
+        bundle_txid: bitcoin::Txid,
+    },
+    /// The block of the event with id `id` was disconnected.
+    Reverted {
+        id: u64,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct JournalEntry {
+    /// Consecutive, starting at 0.
+    pub id: u64,
+    pub mainchain_height: usize,
+    pub block_hash: bitcoin::BlockHash,
+    /// Consecutive within the block, starting at 0 with
+    /// [`JournalEvent::BlockConnected`]. Reverted entries continue after
+    /// the events of the block.
+    pub seq_within_block: u32,
+    pub event: JournalEvent,
+}
+
+/// (De)serializes optional bytes as a hex string or `null`.
+mod hex_opt {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S>(bytes: &Option<Vec<u8>>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match bytes {
+            Some(bytes) => serializer.serialize_some(&hex::encode(bytes)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<Vec<u8>>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Option::<String>::deserialize(deserializer)?
+            .map(|hex| hex::decode(hex).map_err(serde::de::Error::custom))
+            .transpose()
+    }
+}
+
+/// What the events so far imply for the events of later blocks.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+struct JournalState {
+    /// Deposits seen but not confirmed yet, with the height they were seen
+    /// at.
+    pending_deposits: BTreeMap<bitcoin::Txid, usize>,
+    /// Last work score of every bundle that was voted on, until it is spent
+    /// or failed.
+    work_scores: BTreeMap<bitcoin::Txid, usize>,
+    /// Bundles that [`JournalEvent::BundleProposed`] was appended for.
+    proposed: BTreeSet<bitcoin::Txid>,
+    failed: BTreeSet<bitcoin::Txid>,
+}
+
+impl JournalState {
+    /// Applies `event` of the block at `height`, or undoes it if `revert`.
+    fn apply(&mut self, height: usize, event: &JournalEvent, revert: bool) {
+        match (event, revert) {
+            (JournalEvent::DepositSeen { txid, .. }, false) => {
+                self.pending_deposits.insert(*txid, height);
+            }
+            (JournalEvent::DepositSeen { txid, .. }, true)
+            | (JournalEvent::DepositConfirmed { txid, .. }, false) => {
+                self.pending_deposits.remove(txid);
+            }
+            (
+                JournalEvent::DepositConfirmed {
+                    txid,
+                    confirmations,
+                },
+                true,
+            ) => {
+                let seen_height = (height + 1).saturating_sub(*confirmations as usize);
+                self.pending_deposits.insert(*txid, seen_height);
+            }
+            (JournalEvent::BundleProposed { bundle_txid }, false) => {
+                self.proposed.insert(*bundle_txid);
+            }
+            (JournalEvent::BundleProposed { bundle_txid }, true) => {
+                self.proposed.remove(bundle_txid);
+            }
+            (
+                JournalEvent::BundleVoted {
+                    bundle_txid,
+                    work_score,
+                    ..
+                },
+                false,
+            ) => {
+                self.work_scores.insert(*bundle_txid, *work_score);
+            }
+            // The current score is appended again at the next tip
+            (JournalEvent::BundleVoted { bundle_txid, .. }, true)
+            | (JournalEvent::BundleSpent { bundle_txid }, false) => {
+                self.work_scores.remove(bundle_txid);
+            }
+            (JournalEvent::BundleFailed { bundle_txid }, false) => {
+                self.work_scores.remove(bundle_txid);
+                self.failed.insert(*bundle_txid);
+            }
+            (JournalEvent::BundleFailed { bundle_txid }, true) => {
+                self.failed.remove(bundle_txid);
+            }
+            _ => (),
+        }
+    }
+}
+
+/// A block in the cursor, with its events that were not reverted.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+struct JournalBlock {
+    height: usize,
+    hash: bitcoin::BlockHash,
+    next_seq: u32,
+    events: Vec<(u64, JournalEvent)>,
+}
+
+impl JournalBlock {
+    fn is_reverting(&self) -> bool {
+        self.events.len() < self.next_seq as usize
+    }
+}
+
+/// Where a journal continues from: the result of applying every entry in a
+/// store, in order, with [`JournalCursor::apply`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct JournalCursor {
+    next_id: u64,
+    /// The last [`REORG_WINDOW`] blocks that were not reverted, oldest
+    /// first.
+    blocks: VecDeque<JournalBlock>,
+    state: JournalState,
+    /// `state` before the events of the last block, to derive them again if
+    /// the block was only partly appended. `None` if the last block became
+    /// the last by reverting the one after it, as it is then complete.
+    state_before_last_block: Option<JournalState>,
+}
+
+impl JournalCursor {
+    /// Id of the next entry.
+    pub fn next_id(&self) -> u64 {
+        self.next_id
+    }
+
+    /// Height and hash of the last block that was connected and not
+    /// reverted.
+    pub fn tip(&self) -> Option<(usize, bitcoin::BlockHash)> {
+        self.blocks.back().map(|block| (block.height, block.hash))
+    }
+
+    fn block_at(&self, height: usize) -> Option<&JournalBlock> {
+        let first = self.blocks.front()?.height;
+        self.blocks.get(height.checked_sub(first)?)
+    }
+
+    /// Advances the cursor past `entry`, which must be the next entry.
+    pub fn apply(&mut self, entry: &JournalEntry) -> Result<(), Error> {
+        let invalid = |reason| Error::InvalidJournalEntry {
+            id: entry.id,
+            reason,
+        };
+        if entry.id != self.next_id {
+            return Err(invalid("id is not the next one"));
+        }
+        if let JournalEvent::BlockConnected = entry.event {
+            if entry.seq_within_block != 0 {
+                return Err(invalid("block does not start at sequence number 0"));
+            }
+            if self
+                .tip()
+                .is_some_and(|(height, _)| entry.mainchain_height != height + 1)
+            {
+                return Err(invalid("block does not extend the last block"));
+            }
+            self.state_before_last_block = Some(self.state.clone());
+            self.blocks.push_back(JournalBlock {
+                height: entry.mainchain_height,
+                hash: entry.block_hash,
+                next_seq: 1,
+                events: vec![(entry.id, JournalEvent::BlockConnected)],
+            });
+            if self.blocks.len() > REORG_WINDOW {
+                self.blocks.pop_front();
+            }
+            self.next_id += 1;
+            return Ok(());
+        }
+        let block = self
+            .blocks
+            .back_mut()
+            .filter(|block| {
+                block.hash == entry.block_hash && block.height == entry.mainchain_height
+            })
+            .ok_or(invalid("not in the last block"))?;
+        if entry.seq_within_block != block.next_seq {
+            return Err(invalid("sequence number is not the next one"));
+        }
+        block.next_seq += 1;
+        let height = block.height;
+        match &entry.event {
+            JournalEvent::Reverted { id } => {
+                let index = block
+                    .events
+                    .iter()
+                    .position(|(event_id, _)| event_id == id)
+                    .ok_or(invalid("reverted event is not in the last block"))?;
+                if index == 0 && block.events.len() > 1 {
+                    return Err(invalid("block reverted before its events"));
+                }
+                let (_, event) = block.events.remove(index);
+                if block.events.is_empty() {
+                    self.blocks.pop_back();
+                    self.state_before_last_block = None;
+                }
+                self.state.apply(height, &event, true);
+            }
+            event => {
+                block.events.push((entry.id, event.clone()));
+                self.state.apply(height, event, false);
+            }
+        }
+        self.next_id += 1;
+        Ok(())
+    }
+}
+
+/// Where a [`Journal`] appends its entries.
+pub trait JournalStore {
+    /// The cursor after every entry appended so far, `None` if there are
+    /// none.
+    fn load_cursor(&mut self) -> Result<Option<JournalCursor>, Error>;
+
+    /// Appends `entries`, the events of a single block. If this fails, the
+    /// entries that were stored must be a prefix of `entries`, and be
+    /// reflected by the next [`Self::load_cursor`].
+    fn append(&mut self, entries: &[JournalEntry]) -> Result<(), Error>;
+}
+
+/// Stores entries as JSON, one per line.
+#[derive(Debug, Clone)]
+pub struct FileJournalStore {
+    pub path: PathBuf,
+}
+
+impl FileJournalStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        FileJournalStore { path: path.into() }
+    }
+}
+
+impl JournalStore for FileJournalStore {
+    /// Reads every entry. An incomplete last line, left by a crash while
+    /// appending, is removed from the file.
+    fn load_cursor(&mut self) -> Result<Option<JournalCursor>, Error> {
+        let contents = match std::fs::read(&self.path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        let complete_len = contents
+            .iter()
+            .rposition(|byte| *byte == b'\n')
+            .map_or(0, |index| index + 1);
+        if complete_len < contents.len() {
+            let file = OpenOptions::new().write(true).open(&self.path)?;
+            file.set_len(complete_len as u64)?;
+            file.sync_data()?;
+        }
+        let mut cursor = None;
+        for (index, line) in contents[..complete_len]
+            .split(|byte| *byte == b'\n')
+            .enumerate()
+        {
+            if line.is_empty() {
+                continue;
+            }
+            let entry: JournalEntry =
+                serde_json::from_slice(line).map_err(|err| Error::InvalidJournal {
+                    line: index + 1,
+                    reason: err.to_string(),
+                })?;
+            cursor
+                .get_or_insert_with(JournalCursor::default)
+                .apply(&entry)?;
+        }
+        Ok(cursor)
+    }
+
+    fn append(&mut self, entries: &[JournalEntry]) -> Result<(), Error> {
+        let mut lines = vec![];
+        for entry in entries {
+            serde_json::to_writer(&mut lines, entry)?;
+            lines.push(b'\n');
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        file.write_all(&lines)?;
+        file.sync_data()?;
+        Ok(())
+    }
+}
+
+/// What the node reported while `hash` was its tip.
+struct TipReport {
+    hash: bitcoin::BlockHash,
+    statuses: Vec<WithdrawalStatus>,
+    failed: Vec<bitcoin::Txid>,
+}
+
+/// Node data the events of blocks are derived from, fetched once per poll.
+struct PollData {
+    deposits: HashMap<bitcoin::BlockHash, Vec<Deposit>>,
+    spent: HashMap<bitcoin::BlockHash, Vec<bitcoin::Txid>>,
+    /// `None` if the tip changed while fetching it.
+    tip: Option<TipReport>,
+}
+
+/// Journals the events of a single sidechain, see the [module
+/// documentation](self).
+pub struct Journal<C = HttpClient, S = FileJournalStore> {
+    client: C,
+    sidechain: SidechainId,
+    store: S,
+    confirmations: u32,
+    start_height: Option<usize>,
+    /// Loaded from the store on the first poll, and again after an error.
+    cursor: Option<JournalCursor>,
+    /// Whether the last block of the cursor may have been partly appended.
+    resumed: bool,
+    polled: bool,
+    entries: VecDeque<JournalEntry>,
+    limits: Limits,
+    /// Started on the first poll of [`Self::run`].
+    running: Option<Running>,
+    polls: usize,
+    /// Whether [`Self::run`] was stopped by its limits.
+    stopped: bool,
+}
+
+impl<C, S> Journal<C, S>
+where
+    C: ClientT + Sync,
+    S: JournalStore,
+{
+    pub fn new(client: C, sidechain: SidechainId, store: S) -> Self {
+        Journal {
+            client,
+            sidechain,
+            store,
+            confirmations: DEFAULT_DEPOSIT_CONFIRMATIONS,
+            start_height: None,
+            cursor: None,
+            resumed: false,
+            polled: false,
+            entries: VecDeque::new(),
+            limits: Limits::default(),
+            running: None,
+            polls: 0,
+            stopped: false,
+        }
+    }
+
+    /// Confirmations after which deposits are confirmed, at least 1.
+    pub fn with_confirmations(mut self, confirmations: u32) -> Self {
+        self.confirmations = confirmations.max(1);
+        self
+    }
+
+    /// Height of the first block of an empty journal, the tip by default.
+    pub fn with_start_height(mut self, start_height: usize) -> Self {
+        self.start_height = Some(start_height);
+        self
+    }
+
+    /// Stops [`Self::run`] with [`Error::Cancelled`] or
+    /// [`Error::DeadlineExceeded`] once `limits` are reached, counting
+    /// completed polls. The stream ends after yielding the error, and the
+    /// entries appended so far stay in the store.
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    /// Appends the events of the blocks connected since the last poll, and
+    /// returns the appended entries.
+    ///
+    /// After an error, the next poll continues from the entries in the
+    /// store.
+    pub async fn poll(&mut self) -> Result<Vec<JournalEntry>, Error> {
+        let mut cursor = match self.cursor.take() {
+            Some(cursor) => cursor,
+            None => {
+                self.resumed = true;
+                self.store.load_cursor()?.unwrap_or_default()
+            }
+        };
+        let mut appended = vec![];
+        self.poll_with(&mut cursor, &mut appended).await?;
+        self.cursor = Some(cursor);
+        Ok(appended)
+    }
+
+    /// Yields entries as they are appended, polling every `poll_interval`.
+    pub fn run(self, poll_interval: Duration) -> impl Stream<Item = Result<JournalEntry, Error>> {
+        futures::stream::unfold(self, move |mut journal| async move {
+            let entry = journal.next_entry(poll_interval).await?;
+            Some((entry, journal))
+        })
+    }
+
+    async fn next_entry(&mut self, poll_interval: Duration) -> Option<Result<JournalEntry, Error>> {
+        loop {
+            if let Some(entry) = self.entries.pop_front() {
+                return Some(Ok(entry));
+            }
+            if self.stopped {
+                return None;
+            }
+            let running = self
+                .running
+                .get_or_insert_with(|| self.limits.start())
+                .clone();
+            let polls = self.polls;
+            if self.polled {
+                let sleep = async {
+                    tokio::time::sleep(poll_interval).await;
+                    Ok(())
+                };
+                if let Err(err) = running.race(sleep, polls, None).await {
+                    self.stopped = true;
+                    return Some(Err(err));
+                }
+            }
+            self.polled = true;
+            match running.race(self.poll(), polls, None).await {
+                Ok(entries) => {
+                    self.polls += 1;
+                    self.entries.extend(entries);
+                }
+                Err(err @ (Error::Cancelled | Error::DeadlineExceeded { .. })) => {
+                    self.stopped = true;
+                    return Some(Err(err));
+                }
+                Err(err) => return Some(Err(err)),
+            }
+        }
+    }
+
+    async fn append(
+        &mut self,
+        cursor: &mut JournalCursor,
+        height: usize,
+        block_hash: bitcoin::BlockHash,
+        events: impl IntoIterator<Item = (u32, JournalEvent)>,
+        appended: &mut Vec<JournalEntry>,
+    ) -> Result<(), Error> {
+        let entries: Vec<_> = events
+            .into_iter()
+            .zip(cursor.next_id..)
+            .map(|((seq_within_block, event), id)| JournalEntry {
+                id,
+                mainchain_height: height,
+                block_hash,
+                seq_within_block,
+                event,
+            })
+            .collect();
+        if entries.is_empty() {
+            return Ok(());
+        }
+        self.store.append(&entries)?;
+        for entry in &entries {
+            cursor.apply(entry)?;
+        }
+        appended.extend(entries);
+        Ok(())
+    }
+
+    async fn poll_with(
+        &mut self,
+        cursor: &mut JournalCursor,
+        appended: &mut Vec<JournalEntry>,
+    ) -> Result<(), Error> {
+        let tip_hash = self.client.getbestblockhash().await?;
+        let tip = self.client.getblockheader(&tip_hash).await?;
+        // Blocks to connect, newest first, walked back from the tip to the
+        // last block of the cursor that is still in the main chain
+        let mut connect = vec![];
+        let (mut height, mut hash, mut prev) = (tip.height, tip_hash, tip.previousblockhash);
+        let fork_height = loop {
+            match cursor.block_at(height) {
+                // A block that was partly reverted is reverted completely,
+                // even if it is back in the main chain
+                Some(block) if block.hash == hash && !block.is_reverting() => break Some(height),
+                None if cursor
+                    .blocks
+                    .front()
+                    .is_some_and(|first| height < first.height) =>
+                {
+                    return Err(Error::JournalReorgTooDeep {
+                        window: REORG_WINDOW,
+                    })
+                }
+                _ => (),
+            }
+            if cursor.blocks.is_empty() && height < self.start_height.unwrap_or(tip.height) {
+                break None;
+            }
+            connect.push((height, hash));
+            let Some(prev_hash) = prev else {
+                break None;
+            };
+            let header = self.client.getblockheader(&prev_hash).await?;
+            (height, hash, prev) = (header.height, prev_hash, header.previousblockhash);
+        };
+        let data = self.fetch_data(tip_hash).await?;
+        while let Some((top_height, top_hash)) = cursor.tip() {
+            if fork_height.is_some_and(|fork_height| top_height <= fork_height) {
+                break;
+            }
+            let block = cursor.blocks.back().expect("cursor has a tip");
+            let reverted: Vec<_> = block
+                .events
+                .iter()
+                .rev()
+                .zip(block.next_seq..)
+                .map(|((id, _), seq)| (seq, JournalEvent::Reverted { id: *id }))
+                .collect();
+            self.append(cursor, top_height, top_hash, reverted, appended)
+                .await?;
+        }
+        if std::mem::take(&mut self.resumed) {
+            if let (Some(block), Some(state)) =
+                (cursor.blocks.back(), &cursor.state_before_last_block)
+            {
+                let (height, hash, next_seq) = (block.height, block.hash, block.next_seq);
+                let events = self.derive(height, hash, state.clone(), &data).await?;
+                let missing = events.into_iter().skip(next_seq as usize);
+                self.append(cursor, height, hash, missing, appended).await?;
+            }
+        }
+        for (height, hash) in connect.into_iter().rev() {
+            let events = self
+                .derive(height, hash, cursor.state.clone(), &data)
+                .await?;
+            self.append(cursor, height, hash, events, appended).await?;
+        }
+        Ok(())
+    }
+
+    async fn fetch_data(&self, tip_hash: bitcoin::BlockHash) -> Result<PollData, Error> {
+        let mut deposits = HashMap::<_, Vec<_>>::new();
+        for deposit in self
+            .client
+            .listsidechaindepositsbyblock(self.sidechain, None, None)
+            .await?
+        {
+            deposits.entry(deposit.hashblock).or_default().push(deposit);
+        }
+        let mut spent = HashMap::<_, Vec<_>>::new();
+        for withdrawal in self.client.listspentwithdrawals().await? {
+            if withdrawal.nsidechain == self.sidechain {
+                spent
+                    .entry(withdrawal.hashblock)
+                    .or_default()
+                    .push(withdrawal.hash);
+            }
+        }
+        let statuses = self.client.listwithdrawalstatus(self.sidechain).await?;
+        let failed = self
+            .client
+            .listfailedwithdrawals()
+            .await?
+            .into_iter()
+            .filter(|failed| failed.nsidechain == self.sidechain)
+            .map(|failed| failed.hash)
+            .collect();
+        let tip = (self.client.getbestblockhash().await? == tip_hash).then_some(TipReport {
+            hash: tip_hash,
+            statuses,
+            failed,
+        });
+        Ok(PollData {
+            deposits,
+            spent,
+            tip,
+        })
+    }
+
+    /// Every event of the block, numbered, given the state before it.
+    async fn derive(
+        &self,
+        height: usize,
+        hash: bitcoin::BlockHash,
+        mut state: JournalState,
+        data: &PollData,
+    ) -> Result<Vec<(u32, JournalEvent)>, Error> {
+        let mut events = vec![JournalEvent::BlockConnected];
+        let mut push = |state: &mut JournalState, event| {
+            state.apply(height, &event, false);
+            events.push(event);
+        };
+        let mut deposits: Vec<_> = data.deposits.get(&hash).into_iter().flatten().collect();
+        deposits.sort_by_key(|deposit| deposit.ntx);
+        for deposit in deposits {
+            let transaction = deposit.transaction()?;
+            let destination = crate::deposit::extract_dest_bytes(&transaction);
+            push(
+                &mut state,
+                JournalEvent::DepositSeen {
+                    txid: transaction.txid(),
+                    destination,
+                },
+            );
+        }
+        let mut confirmed: Vec<_> = state
+            .pending_deposits
+            .iter()
+            .filter(|(_, seen_height)| height + 1 >= *seen_height + self.confirmations as usize)
+            .map(|(txid, seen_height)| (*seen_height, *txid))
+            .collect();
+        confirmed.sort();
+        for (_, txid) in confirmed {
+            push(
+                &mut state,
+                JournalEvent::DepositConfirmed {
+                    txid,
+                    confirmations: self.confirmations,
+                },
+            );
+        }
+        let mut spent: Vec<_> = data.spent.get(&hash).into_iter().flatten().collect();
+        spent.sort();
+        for bundle_txid in spent {
+            push(
+                &mut state,
+                JournalEvent::BundleSpent {
+                    bundle_txid: *bundle_txid,
+                },
+            );
+        }
+        if let Some(tip) = data.tip.as_ref().filter(|tip| tip.hash == hash) {
+            let mut statuses: Vec<_> = tip
+                .statuses
+                .iter()
+                .filter(|status| !tip.failed.contains(&status.hash))
+                .collect();
+            statuses.sort_by_key(|status| status.hash);
+            for status in statuses {
+                if !state.proposed.contains(&status.hash) {
+                    push(
+                        &mut state,
+                        JournalEvent::BundleProposed {
+                            bundle_txid: status.hash,
+                        },
+                    );
+                }
+                if state.work_scores.get(&status.hash) != Some(&status.nworkscore) {
+                    push(
+                        &mut state,
+                        JournalEvent::BundleVoted {
+                            bundle_txid: status.hash,
+                            work_score: status.nworkscore,
+                            blocks_left: status.nblocksleft,
+                        },
+                    );
+                }
+            }
+            let mut failed: Vec<_> = tip.failed.iter().collect();
+            failed.sort();
+            for bundle_txid in failed {
+                if !state.failed.contains(bundle_txid) {
+                    push(
+                        &mut state,
+                        JournalEvent::BundleFailed {
+                            bundle_txid: *bundle_txid,
+                        },
+                    );
+                }
+            }
+        }
+        Ok((0..).zip(events).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{block_on, MockChain, MockClient};
+    use serde_json::{json, Value};
+    use std::sync::{Arc, Mutex};
+
+    /// What the node reports besides the chain.
+    #[derive(Default)]
+    struct Sidechain {
+        deposits: Vec<Value>,
+        spent: Vec<Value>,
+        statuses: Vec<Value>,
+        failed: Vec<Value>,
+    }
+
+    fn client(chain: &MockChain, sidechain: &Arc<Mutex<Sidechain>>) -> MockClient {
+        let (chain, sidechain) = (chain.clone(), sidechain.clone());
+        MockClient::new(move |method, params| {
+            if let Some(result) = chain.handle(method, params) {
+                return result;
+            }
+            let sidechain = sidechain.lock().unwrap();
+            match method {
+                "listsidechaindepositsbyblock" => Ok(json!(sidechain.deposits)),
+                "listspentwithdrawals" => Ok(json!(sidechain.spent)),
+                "listwithdrawalstatus" => Ok(json!(sidechain.statuses)),
+                "listfailedwithdrawals" => Ok(json!(sidechain.failed)),
+                _ => unreachable!("{method}"),
+            }
+        })
+    }
+
+    fn txid(n: u8) -> bitcoin::Txid {
+        use bitcoin::hashes::Hash as _;
+        bitcoin::Txid::from_byte_array([n; 32])
+    }
+
+    /// Mines a block with a deposit of `value` sats to `destination`.
+    fn mine_deposit(
+        chain: &MockChain,
+        sidechain: &Mutex<Sidechain>,
+        value: u64,
+        destination: &[u8; 5],
+    ) {
+        let transaction = bitcoin::Transaction {
+            version: 2,
+            lock_time: bitcoin::absolute::LockTime::ZERO,
+            input: vec![bitcoin::TxIn::default()],
+            output: vec![
+                bitcoin::TxOut {
+                    value,
+                    script_pubkey: crate::escrow::script_for_sidechain(SidechainId(1)),
+                },
+                bitcoin::TxOut {
+                    value: 0,
+                    script_pubkey: bitcoin::ScriptBuf::new_op_return(destination),
+                },
+            ],
+        };
+        let hashblock = chain.mine_with(vec![transaction.txid()]);
+        sidechain.lock().unwrap().deposits.push(json!({
+            "hashblock": hashblock,
+            "nburnindex": 0,
+            "ntx": 1,
+            "strdest": String::from_utf8_lossy(destination),
+            "txhex": bitcoin::consensus::encode::serialize_hex(&transaction),
+        }));
+    }
+
+    /// Changes the chain and the node for each of `STEPS` polls: deposits,
+    /// a bundle that is voted on, a reorg that drops a deposit and
+    /// confirms another, and bundles that are spent and fail.
+    const STEPS: usize = 5;
+
+    fn step(step: usize, chain: &MockChain, sidechain: &Mutex<Sidechain>) {
+        match step {
+            0 => {
+                chain.mine(2);
+            }
+            1 => {
+                mine_deposit(chain, sidechain, 1_000, b"alice");
+                sidechain.lock().unwrap().statuses = vec![json!({
+                    "hash": txid(1),
+                    "nblocksleft": 10,
+                    "nworkscore": 1,
+                })];
+            }
+            2 => {
+                mine_deposit(chain, sidechain, 2_000, b"bob__");
+                chain.mine(1);
+                sidechain.lock().unwrap().statuses[0]["nworkscore"] = json!(2);
+            }
+            3 => {
+                chain.disconnect(2);
+                chain.mine(1);
+                mine_deposit(chain, sidechain, 3_000, b"carol");
+                let tip = chain.mine(1)[0];
+                let mut sidechain = sidechain.lock().unwrap();
+                sidechain.spent = vec![json!({
+                    "nsidechain": 1,
+                    "hash": txid(1),
+                    "hashblock": tip,
+                })];
+                sidechain.statuses = vec![json!({
+                    "hash": txid(2),
+                    "nblocksleft": 20,
+                    "nworkscore": 1,
+                })];
+            }
+            4 => {
+                chain.mine(1);
+                let mut sidechain = sidechain.lock().unwrap();
+                sidechain.statuses.clear();
+                sidechain.failed = vec![json!({ "nsidechain": 1, "hash": txid(2) })];
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// Keeps entries in memory, and fails once `capacity` entries were
+    /// stored, after storing as many of the block's entries as fit.
+    #[derive(Clone, Default)]
+    struct MemoryStore {
+        entries: Arc<Mutex<Vec<JournalEntry>>>,
+        capacity: Option<usize>,
+    }
+
+    impl JournalStore for MemoryStore {
+        fn load_cursor(&mut self) -> Result<Option<JournalCursor>, Error> {
+            let entries = self.entries.lock().unwrap();
+            let mut cursor = None;
+            for entry in entries.iter() {
+                cursor
+                    .get_or_insert_with(JournalCursor::default)
+                    .apply(entry)?;
+            }
+            Ok(cursor)
+        }
+
+        fn append(&mut self, new_entries: &[JournalEntry]) -> Result<(), Error> {
+            let mut entries = self.entries.lock().unwrap();
+            for entry in new_entries {
+                if self
+                    .capacity
+                    .is_some_and(|capacity| entries.len() >= capacity)
+                {
+                    return Err(std::io::Error::other("store killed").into());
+                }
+                entries.push(entry.clone());
+            }
+            Ok(())
+        }
+    }
+
+    /// Runs the steps with a store that is killed after `capacity` entries,
+    /// each time followed by a new journal on the entries stored so far.
+    fn run(capacity: Option<usize>) -> Vec<JournalEntry> {
+        let chain = MockChain::new();
+        let sidechain = Arc::default();
+        let store = MemoryStore {
+            entries: Arc::default(),
+            capacity,
+        };
+        let journal = |store| {
+            Journal::new(client(&chain, &sidechain), SidechainId(1), store)
+                .with_confirmations(2)
+                .with_start_height(1)
+        };
+        let mut killed = journal(store.clone());
+        let mut resumed = None;
+        for n in 0..STEPS {
+            step(n, &chain, &sidechain);
+            let resumed = match resumed.as_mut() {
+                Some(resumed) => resumed,
+                None => match block_on(killed.poll()) {
+                    Ok(_) => continue,
+                    Err(err) => {
+                        assert!(matches!(err, Error::Io(_)), "{err}");
+                        resumed.insert(journal(MemoryStore {
+                            entries: store.entries.clone(),
+                            capacity: None,
+                        }))
+                    }
+                },
+            };
+            block_on(resumed.poll()).unwrap();
+        }
+        let entries = store.entries.lock().unwrap().clone();
+        entries
+    }
+
+    #[test]
+    fn resumes_after_every_entry() {
+        let expected = run(None);
+        let events: Vec<_> = expected.iter().map(|entry| &entry.event).collect();
+        let reverted = events
+            .iter()
+            .filter(|event| matches!(event, JournalEvent::Reverted { .. }))
+            .count();
+        assert_eq!(reverted, 6);
+        for event in [
+            JournalEvent::BundleProposed {
+                bundle_txid: txid(1),
+            },
+            JournalEvent::BundleSpent {
+                bundle_txid: txid(1),
+            },
+            JournalEvent::BundleFailed {
+                bundle_txid: txid(2),
+            },
+        ] {
+            assert!(events.contains(&&event), "{event:?}");
+        }
+        let destinations: Vec<_> = events
+            .iter()
+            .filter_map(|event| match event {
+                JournalEvent::DepositSeen { destination, .. } => destination.as_deref(),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(destinations, [b"alice", b"bob__", b"carol"]);
+        for (id, entry) in expected.iter().enumerate() {
+            assert_eq!(entry.id, id as u64);
+        }
+        for capacity in 0..expected.len() {
+            assert_eq!(run(Some(capacity)), expected, "killed after {capacity}");
+        }
+    }
+
+    #[test]
+    fn file_store_drops_an_incomplete_line() {
+        let path = std::env::temp_dir().join(format!(
+            "bip300301-journal-{}-incomplete",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let mut store = FileJournalStore::new(&path);
+        assert!(store.load_cursor().unwrap().is_none());
+        let entries = run(None);
+        let block_end = entries
+            .iter()
+            .position(|entry| entry.mainchain_height == 2)
+            .unwrap();
+        store.append(&entries[..block_end]).unwrap();
+        let complete = std::fs::read(&path).unwrap();
+        let mut torn = complete.clone();
+        torn.extend_from_slice(&serde_json::to_vec(&entries[block_end]).unwrap()[..20]);
+        std::fs::write(&path, torn).unwrap();
+        let cursor = store.load_cursor().unwrap().unwrap();
+        assert_eq!(cursor.next_id(), block_end as u64);
+        assert_eq!(std::fs::read(&path).unwrap(), complete);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
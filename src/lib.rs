@@ -1,39 +1,175 @@
+#[cfg(feature = "client")]
+pub mod batch;
+pub mod block_txs;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+#[cfg(feature = "client")]
+pub mod bmm;
+#[cfg(feature = "client")]
+pub mod cache;
+#[cfg(feature = "client")]
+pub mod cancel;
+#[cfg(feature = "record_replay")]
+pub mod cassette;
 mod client;
+#[cfg(feature = "client")]
+pub mod coalesce;
+pub mod coinbase;
+#[cfg(feature = "test_util")]
+pub mod compare;
+#[cfg(feature = "client")]
+pub mod confirmations;
+#[cfg(feature = "client")]
+pub mod conflicts;
+#[cfg(feature = "client")]
+pub mod ctip;
+pub mod deposit;
+#[cfg(feature = "client")]
+pub mod drills;
+mod enums;
+pub mod escrow;
+pub mod filters;
+pub mod headers;
+#[cfg(feature = "client")]
+pub mod health;
+pub mod introspection;
+#[cfg(feature = "client")]
+pub mod journal;
+#[cfg(feature = "client")]
+pub mod mempool;
+pub mod message;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod mining;
+#[cfg(all(test, feature = "client"))]
+mod mock;
+#[cfg(feature = "client")]
+pub mod multi;
+#[cfg(feature = "client")]
+pub mod peers;
+pub mod prelude;
+#[cfg(feature = "client")]
+pub mod priority;
+#[cfg(feature = "client")]
+pub mod pruned;
+#[cfg(feature = "client")]
+pub mod reconcile;
+#[cfg(feature = "client")]
+pub mod reconnect;
+#[cfg(feature = "client")]
+pub mod regtest;
+#[cfg(feature = "client")]
+pub mod rescue;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod snapshot;
+#[cfg(feature = "client")]
+pub mod throttle;
+pub mod tx_summaries;
+#[cfg(feature = "client")]
+pub mod unlock;
+#[cfg(feature = "client")]
+mod validation;
+pub mod version;
+pub mod warnings;
+#[cfg(feature = "webhook")]
+pub mod webhook;
+#[cfg(feature = "client")]
+pub mod withdrawals;
+#[cfg(feature = "client")]
 use base64::Engine as _;
-use bitcoin::consensus::{Decodable, Encodable};
+#[cfg(feature = "client")]
+use bitcoin::consensus::Encodable;
+#[cfg(feature = "client")]
+use jsonrpsee::core::client::ClientT;
+#[cfg(feature = "client")]
+use jsonrpsee::core::params::ObjectParams;
+#[cfg(feature = "client")]
+use jsonrpsee::core::traits::ToRpcParams;
+#[cfg(feature = "client")]
 use jsonrpsee::http_client::{HeaderMap, HttpClient, HttpClientBuilder};
+#[cfg(feature = "client")]
+use serde_json::value::RawValue;
 use std::collections::HashMap;
+#[cfg(feature = "client")]
 use std::net::SocketAddr;
+#[cfg(feature = "client")]
+use std::time::Duration;
 
 pub use bitcoin;
-pub use client::MainClient;
+pub use client::{
+    ActiveCommand, AddNodeCommand, AddedNodeAddress, AddedNodeInfo, AddressInfo, AddressType,
+    AmountBtc, AmountSats, BalanceDetails, Balances, Bip9Info, Bip9Statistics, Bip9Status, Block,
+    BlockRef, BlockTemplate, BlockTemplateRequest, BlockTemplateTransaction, BlockchainInfo,
+    BmmAcceptedBid, BumpFee, BumpFeeOptions, ChainState, ChainStates, ConnectionDirection,
+    ConsensusEncoded, ConsensusEncodedLazy, Ctip, DecodedScript, DeploymentInfo, Deposit,
+    DepositVerbose, Difficulty, EnforcementStatus, EstimateMode, FinalizePsbt, FundRawTransaction,
+    FundRawTransactionOptions, GenerateBlock, GenerateBlockTx, Header, IndexInfo, LabelAddress,
+    LabelPurpose, ListSinceBlock, LoadTxOutSet, LocalAddress, LockedMemory, MallocInfoMode,
+    MemoryInfo, MemoryInfoMode, MemoryStatsMode, MempoolEntry, MempoolEntryFees, MempoolInfo,
+    NetTotals, NetworkInfo, NetworkReachability, PeerInfo, PrioritisedTransaction, PsbtInput,
+    PsbtOutput, RawMempoolVerbose, RawMempoolWithSequence, RawTransactionInfo, RawTransactionInput,
+    RawTransactionOutput, RawTransactionScriptPubKey, RawTransactionVerbose, ReceivedByAddress,
+    ReceivedByLabel, RpcInfo, SaveMempool, ScanAction, ScanObject, ScanStatus, ScanTxOutSetResult,
+    ScanUnspent, ScriptSig, SendToAddressOptions, SidechainId, SignRawTransaction,
+    SignRawTransactionError, SoftFork, TemplateRequestMode, UploadTarget, Vote,
+    WalletCreateFundedPsbt, WalletProcessPsbt, WalletTransaction, WalletTransactionDetail,
+    WalletTx, WalletTxCategory, WithdrawalStatus, MAX_FUTURE_BLOCK_TIME,
+};
+#[cfg(feature = "client")]
+pub use client::{
+    ChainClient, DrivechainClient, EnforcerClient, MainClient, NodeAdminClient, WalletClient,
+};
+pub use enums::ParseEnumError;
+pub use introspection::methods;
+#[cfg(feature = "client")]
 pub use jsonrpsee;
 
-#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub enum WithdrawalBundleStatus {
     Failed,
     Confirmed,
 }
 
-#[derive(Default, Debug, Clone, serde::Serialize, serde::Deserialize)]
+enums::string_enum!(WithdrawalBundleStatus {
+    Failed => "failed",
+    Confirmed => "confirmed",
+});
+
+#[derive(Default, Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct TwoWayPegData {
     pub deposits: HashMap<bitcoin::OutPoint, Output>,
     pub deposit_block_hash: Option<bitcoin::BlockHash>,
     pub bundle_statuses: HashMap<bitcoin::Txid, WithdrawalBundleStatus>,
 }
 
+#[cfg(feature = "client")]
 #[derive(Clone)]
 pub struct Drivechain {
-    pub sidechain_number: u8,
+    pub sidechain_number: SidechainId,
     pub client: HttpClient,
+    /// Network of the mainchain node. Addresses are only checked against it
+    /// if it is set.
+    pub network: Option<bitcoin::Network>,
+    /// Pass parameters through to the node without checking them first.
+    pub skip_validation: bool,
+    /// Version of the mainchain node, detected by [`Self::connect`]. If it
+    /// is set, methods it is too old for are refused without calling the
+    /// node, and responses of older versions are adapted to the current
+    /// shape, see [`version::adapt_response`].
+    pub server_version: Option<version::ServerVersion>,
+    /// Network reported by the mainchain node, fetched the first time it is
+    /// needed if `network` is not set. Shared by clones.
+    detected_network: std::sync::Arc<std::sync::OnceLock<bitcoin::Network>>,
 }
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub struct Output {
     pub address: String,
     pub value: u64,
 }
 
+#[cfg(feature = "client")]
 impl Drivechain {
     pub async fn verify_bmm(
         &self,
@@ -86,6 +222,832 @@ impl Drivechain {
         Ok(())
     }
 
+    pub async fn create_bmm_critical_data_tx(
+        &self,
+        amount: bitcoin::Amount,
+        height: u32,
+        critical_hash: &bitcoin::BlockHash,
+        prev_bytes: bmm::PrevBytes,
+    ) -> Result<serde_json::Value, Error> {
+        let value = self
+            .client
+            .createbmmcriticaldatatx(
+                amount.into(),
+                height,
+                critical_hash,
+                self.sidechain_number,
+                prev_bytes,
+            )
+            .await?;
+        Ok(value)
+    }
+
+    /// Bids for BMM of this sidechain in every mainchain block, see
+    /// [`bmm::AutoBidder`].
+    pub fn auto_bidder(&self, config: bmm::AutoBidderConfig) -> bmm::AutoBidder {
+        bmm::AutoBidder::new(self.client.clone(), self.sidechain_number, config)
+    }
+
+    /// Drivechain state of the node, see
+    /// [`snapshot::snapshot_sidechain_state`].
+    pub async fn snapshot_sidechain_state(
+        &self,
+    ) -> Result<snapshot::SidechainStateSnapshot, Error> {
+        snapshot::snapshot_sidechain_state(&self.client, snapshot::DEFAULT_SNAPSHOT_ATTEMPTS).await
+    }
+
+    pub async fn generate_to_address(
+        &self,
+        nblocks: u32,
+        address: &bitcoin::Address<bitcoin::address::NetworkUnchecked>,
+    ) -> Result<Vec<bitcoin::BlockHash>, Error> {
+        if let (false, Some(network)) = (self.skip_validation, self.network) {
+            validation::address_network(address, network)?;
+        }
+        Ok(self.client.generatetoaddress(nblocks, address).await?)
+    }
+
+    /// Returns [`Error::WrongNetwork`] without calling the node if `address`
+    /// is not for the node's network.
+    pub async fn generate_to_address_checked(
+        &self,
+        nblocks: u32,
+        address: &bitcoin::Address,
+    ) -> Result<Vec<bitcoin::BlockHash>, Error> {
+        validation::checked_address_network(address, self.node_network().await?)?;
+        Ok(self
+            .client
+            .generatetoaddress(nblocks, &unchecked(address))
+            .await?)
+    }
+
+    pub async fn generate_to_descriptor(
+        &self,
+        n_blocks: u32,
+        descriptor: &str,
+    ) -> Result<Vec<bitcoin::BlockHash>, Error> {
+        self.require_version("generatetodescriptor")?;
+        Ok(self
+            .client
+            .generatetodescriptor(n_blocks, descriptor)
+            .await?)
+    }
+
+    /// Mines a block with exactly `transactions`, paying the coinbase to
+    /// `output`, an address or descriptor.
+    pub async fn generate_block(
+        &self,
+        output: &str,
+        transactions: &[GenerateBlockTx],
+        submit: Option<bool>,
+    ) -> Result<GenerateBlock, Error> {
+        self.require_version("generateblock")?;
+        Ok(self
+            .client
+            .generateblock(output, transactions, submit)
+            .await?)
+    }
+
+    pub async fn get_block(&self, block_hash: &bitcoin::BlockHash) -> Result<Block, Error> {
+        Ok(self.client.getblock(block_hash, Some(1)).await?)
+    }
+
+    pub async fn get_block_raw(
+        &self,
+        block_hash: &bitcoin::BlockHash,
+    ) -> Result<bitcoin::Block, Error> {
+        let block: ConsensusEncoded<bitcoin::Block> = self
+            .request("getblock", jsonrpsee::rpc_params![block_hash, 0])
+            .await?;
+        Ok(block.0)
+    }
+
+    pub async fn get_raw_mempool(&self) -> Result<Vec<bitcoin::Txid>, Error> {
+        Ok(self.client.getrawmempool().await?)
+    }
+
+    pub async fn get_mempool_entry(&self, txid: &bitcoin::Txid) -> Result<MempoolEntry, Error> {
+        self.request("getmempoolentry", jsonrpsee::rpc_params![txid])
+            .await
+    }
+
+    pub async fn get_index_info(
+        &self,
+        index_name: Option<&str>,
+    ) -> Result<hashlink::LinkedHashMap<String, IndexInfo>, Error> {
+        self.require_version("getindexinfo")?;
+        Ok(self.client.getindexinfo(index_name).await?)
+    }
+
+    /// Fails unless the node has a fully synced `-txindex`, which is needed
+    /// to look up transactions without knowing their block.
+    pub async fn require_txindex(&self) -> Result<(), Error> {
+        let index_info = self.get_index_info(Some("txindex")).await?;
+        let Some(txindex) = index_info.get("txindex") else {
+            return Err(Error::TxIndexDisabled);
+        };
+        if !txindex.synced {
+            let tip_height = self.client.getblockcount().await?;
+            return Err(Error::TxIndexSyncing {
+                index_height: txindex.best_block_height,
+                tip_height,
+            });
+        }
+        Ok(())
+    }
+
+    pub async fn get_mempool_info(&self) -> Result<MempoolInfo, Error> {
+        Ok(self.client.getmempoolinfo().await?)
+    }
+
+    /// Fee deltas applied with `prioritisetransaction`, see [`priority`].
+    pub async fn get_prioritised_transactions(
+        &self,
+    ) -> Result<hashlink::LinkedHashMap<bitcoin::Txid, PrioritisedTransaction>, Error> {
+        self.require_version("getprioritisedtransactions")?;
+        Ok(self.client.getprioritisedtransactions().await?)
+    }
+
+    /// Verifies the last `nblocks` blocks of the chain database. This can
+    /// take minutes, so the client should be created with a matching
+    /// request timeout, see [`new_client`].
+    pub async fn verify_chain(
+        &self,
+        check_level: Option<u8>,
+        nblocks: Option<u32>,
+    ) -> Result<bool, Error> {
+        Ok(self.client.verifychain(check_level, nblocks).await?)
+    }
+
+    pub async fn get_chain_states(&self) -> Result<ChainStates, Error> {
+        self.require_version("getchainstates")?;
+        Ok(self.client.getchainstates().await?)
+    }
+
+    pub async fn get_deployment_info(
+        &self,
+        block_hash: Option<&bitcoin::BlockHash>,
+    ) -> Result<DeploymentInfo, Error> {
+        self.require_version("getdeploymentinfo")?;
+        Ok(self.client.getdeploymentinfo(block_hash).await?)
+    }
+
+    /// Scans the UTXO set for outputs matching `scan_objects`. This can take
+    /// minutes, so the client should be created with a matching request
+    /// timeout, see [`new_client`].
+    pub async fn scan_tx_out_set(
+        &self,
+        scan_objects: &[ScanObject],
+    ) -> Result<ScanTxOutSetResult, Error> {
+        self.require_version("scantxoutset")?;
+        Ok(self
+            .client
+            .scantxoutsetstart(ScanAction::Start, scan_objects)
+            .await?)
+    }
+
+    /// Like [`Self::scan_tx_out_set`], calling `progress` with the
+    /// percentage scanned every `poll_interval` while the scan runs.
+    pub async fn scan_with_progress<F>(
+        &self,
+        scan_objects: &[ScanObject],
+        poll_interval: Duration,
+        mut progress: F,
+    ) -> Result<ScanTxOutSetResult, Error>
+    where
+        F: FnMut(f64),
+    {
+        let mut scan = std::pin::pin!(self.scan_tx_out_set(scan_objects));
+        loop {
+            let sleep = std::pin::pin!(tokio::time::sleep(poll_interval));
+            match futures::future::select(scan.as_mut(), sleep).await {
+                futures::future::Either::Left((result, _)) => return result,
+                futures::future::Either::Right(((), _)) => {
+                    if let Some(status) = self.client.scantxoutsetstatus(ScanAction::Status).await?
+                    {
+                        progress(status.progress);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Aborts a running UTXO set scan. Returns `false` if none was running.
+    pub async fn abort_scan_tx_out_set(&self) -> Result<bool, Error> {
+        Ok(self.client.scantxoutsetabort(ScanAction::Abort).await?)
+    }
+
+    pub async fn decode_raw_transaction(
+        &self,
+        tx_hex: &str,
+        is_witness: Option<bool>,
+    ) -> Result<RawTransactionInfo, Error> {
+        Ok(self.client.decoderawtransaction(tx_hex, is_witness).await?)
+    }
+
+    pub async fn decode_script(&self, script_hex: &str) -> Result<DecodedScript, Error> {
+        Ok(self.client.decodescript(script_hex).await?)
+    }
+
+    /// Has the node decode `transaction` and returns a human readable
+    /// summary, e.g. to compare against a local parse.
+    pub async fn explain_tx(&self, transaction: &bitcoin::Transaction) -> Result<String, Error> {
+        use std::fmt::Write as _;
+        let tx_hex = bitcoin::consensus::encode::serialize_hex(transaction);
+        let decoded = self.decode_raw_transaction(&tx_hex, None).await?;
+        let mut summary = format!(
+            "{} (version {}, {} vB, locktime {})\n",
+            decoded.txid, decoded.version, decoded.vsize, decoded.locktime
+        );
+        for (index, input) in decoded.vin.iter().enumerate() {
+            let _ = match (&input.coinbase, input.txid, input.vout) {
+                (Some(coinbase), _, _) => writeln!(summary, "  in  {index}: coinbase {coinbase}"),
+                (None, Some(txid), Some(vout)) => writeln!(summary, "  in  {index}: {txid}:{vout}"),
+                _ => writeln!(summary, "  in  {index}: ?"),
+            };
+        }
+        for output in &decoded.vout {
+            let script_pub_key = &output.script_pub_key;
+            let destination = match &script_pub_key.address {
+                Some(address) => address.clone().assume_checked().to_string(),
+                None => script_pub_key.asm.clone(),
+            };
+            let _ = writeln!(
+                summary,
+                "  out {}: {} {} {}",
+                output.n, output.value, script_pub_key.script_type, destination
+            );
+        }
+        Ok(summary)
+    }
+
+    pub async fn import_pruned_funds(
+        &self,
+        raw_tx_hex: &str,
+        txout_proof_hex: &str,
+    ) -> Result<(), Error> {
+        Ok(self
+            .client
+            .importprunedfunds(raw_tx_hex, txout_proof_hex)
+            .await?)
+    }
+
+    pub async fn remove_pruned_funds(&self, txid: &bitcoin::Txid) -> Result<(), Error> {
+        Ok(self.client.removeprunedfunds(txid).await?)
+    }
+
+    /// Unlocks the wallet for `timeout`, e.g. before creating BMM requests
+    /// with an encrypted wallet.
+    pub async fn wallet_passphrase(
+        &self,
+        passphrase: &str,
+        timeout: Duration,
+    ) -> Result<(), Error> {
+        unlock::wallet_passphrase(&self.client, passphrase, timeout).await
+    }
+
+    pub async fn wallet_lock(&self) -> Result<(), Error> {
+        unlock::wallet_lock(&self.client).await
+    }
+
+    pub async fn wallet_passphrase_change(
+        &self,
+        old_passphrase: &str,
+        new_passphrase: &str,
+    ) -> Result<(), Error> {
+        unlock::wallet_passphrase_change(&self.client, old_passphrase, new_passphrase).await
+    }
+
+    /// Unlocks the wallet until the returned guard is closed or dropped.
+    pub async fn unlock_for(
+        &self,
+        passphrase: unlock::Zeroizing<String>,
+        duration: Duration,
+    ) -> Result<unlock::UnlockedWalletGuard<HttpClient>, Error> {
+        unlock::unlock_for(self.client.clone(), passphrase, duration).await
+    }
+
+    pub async fn keypool_refill(&self, new_size: Option<u32>) -> Result<(), Error> {
+        Ok(self.client.keypoolrefill(new_size).await?)
+    }
+
+    pub async fn abandon_transaction(&self, txid: &bitcoin::Txid) -> Result<(), Error> {
+        Ok(self.client.abandontransaction(txid).await?)
+    }
+
+    /// Returns [`Error::BumpFeeRefused`] if the transaction can't be
+    /// replaced, in which case [`rescue::rescue_stuck_tx`] may still get it
+    /// confirmed by a child paying for it.
+    pub async fn bump_fee(
+        &self,
+        txid: &bitcoin::Txid,
+        options: &BumpFeeOptions,
+    ) -> Result<BumpFee, Error> {
+        rescue::bump_fee(&self.client, txid, Some(options)).await
+    }
+
+    pub async fn set_tx_fee(&self, fee_rate: bitcoin::FeeRate) -> Result<bool, Error> {
+        let btc_per_kvb = AmountBtc::from_sat(fee_rate.to_sat_per_kwu() * 4);
+        Ok(self.client.settxfee(btc_per_kvb).await?)
+    }
+
+    pub async fn sign_raw_transaction_with_wallet(
+        &self,
+        tx_hex: &str,
+    ) -> Result<SignRawTransaction, Error> {
+        self.require_version("signrawtransactionwithwallet")?;
+        Ok(self.client.signrawtransactionwithwallet(tx_hex).await?)
+    }
+
+    /// Creates a PSBT funded from the wallet, to be signed elsewhere, e.g.
+    /// by a hardware wallet.
+    pub async fn wallet_create_funded_psbt(
+        &self,
+        inputs: &[PsbtInput],
+        outputs: &[PsbtOutput],
+        locktime: Option<u32>,
+        options: &FundRawTransactionOptions,
+        bip32derivs: Option<bool>,
+    ) -> Result<WalletCreateFundedPsbt, Error> {
+        self.require_version("walletcreatefundedpsbt")?;
+        Ok(self
+            .client
+            .walletcreatefundedpsbt(inputs, outputs, locktime, Some(options), bip32derivs)
+            .await?)
+    }
+
+    pub async fn wallet_process_psbt(
+        &self,
+        psbt: &bitcoin::psbt::PartiallySignedTransaction,
+        sign: Option<bool>,
+        sighashtype: Option<&str>,
+        bip32derivs: Option<bool>,
+    ) -> Result<WalletProcessPsbt, Error> {
+        self.require_version("walletprocesspsbt")?;
+        let psbt = client::psbt_base64::encode(psbt);
+        Ok(self
+            .client
+            .walletprocesspsbt(&psbt, sign, sighashtype, bip32derivs)
+            .await?)
+    }
+
+    pub async fn finalize_psbt(
+        &self,
+        psbt: &bitcoin::psbt::PartiallySignedTransaction,
+        extract: Option<bool>,
+    ) -> Result<FinalizePsbt, Error> {
+        self.require_version("finalizepsbt")?;
+        let psbt = client::psbt_base64::encode(psbt);
+        Ok(self.client.finalizepsbt(&psbt, extract).await?)
+    }
+
+    /// The current CTIP of the sidechain, or `None` if it has none yet.
+    pub async fn get_sidechain_ctip(&self) -> Result<Option<Ctip>, Error> {
+        ctip::get_sidechain_ctip(&self.client, self.sidechain_number).await
+    }
+
+    pub async fn reconcile_balance(
+        &self,
+        from_block: Option<bitcoin::BlockHash>,
+    ) -> Result<reconcile::ReconciliationReport, Error> {
+        reconcile::reconcile_sidechain_balance(&self.client, self.sidechain_number, from_block)
+            .await
+    }
+
+    pub async fn get_coinbase(
+        &self,
+        block_hash: &bitcoin::BlockHash,
+    ) -> Result<coinbase::Coinbase, Error> {
+        coinbase::get_coinbase(&self.client, block_hash).await
+    }
+
+    pub async fn wait_for_confirmations(
+        &self,
+        txid: &bitcoin::Txid,
+        confirmations: u32,
+        poll_interval: Duration,
+        timeout: Duration,
+    ) -> Result<confirmations::ConfirmationOutcome, Error> {
+        confirmations::wait_for_confirmations(
+            &self.client,
+            txid,
+            confirmations,
+            poll_interval,
+            timeout,
+        )
+        .await
+    }
+
+    pub async fn submit_header(&self, header_hex: String) -> Result<(), Error> {
+        self.require_version("submitheader")?;
+        mining::submit_header(&self.client, &header_hex).await
+    }
+
+    pub async fn submit_header_typed(&self, header: &bitcoin::block::Header) -> Result<(), Error> {
+        self.require_version("submitheader")?;
+        let header_hex = bitcoin::consensus::encode::serialize_hex(header);
+        mining::submit_header(&self.client, &header_hex).await
+    }
+
+    /// Status of the enforcer, or `None` if the node has no enforcer.
+    pub async fn get_enforcement_status(&self) -> Result<Option<EnforcementStatus>, Error> {
+        health::get_enforcement_status(&self.client).await
+    }
+
+    /// The current time, adjusted by the node's clock offset from its peers.
+    pub async fn adjusted_now(&self) -> Result<std::time::SystemTime, Error> {
+        headers::adjusted_now(&self.client).await
+    }
+
+    /// Bytes the node has sent and received over the network.
+    pub async fn get_net_totals(&self) -> Result<NetTotals, Error> {
+        Ok(self.client.getnettotals().await?)
+    }
+
+    pub async fn get_connection_count(&self) -> Result<u64, Error> {
+        Ok(self.client.getconnectioncount().await?)
+    }
+
+    pub async fn add_node(&self, node: &str, command: AddNodeCommand) -> Result<(), Error> {
+        Ok(self.client.addnode(node, command).await?)
+    }
+
+    pub async fn disconnect_node(&self, peer: &peers::PeerSelector) -> Result<(), Error> {
+        peers::disconnect_node(&self.client, peer).await
+    }
+
+    pub async fn get_added_node_info(
+        &self,
+        node: Option<&str>,
+    ) -> Result<Vec<AddedNodeInfo>, Error> {
+        Ok(self.client.getaddednodeinfo(node).await?)
+    }
+
+    /// Returns the file the mempool was written to, if the node reports it.
+    pub async fn save_mempool(&self) -> Result<Option<String>, Error> {
+        Ok(self.client.savemempool().await?.filename)
+    }
+
+    /// Loads an assumeutxo snapshot. This can take minutes, so the client
+    /// should be created with a matching request timeout, see
+    /// [`new_client`].
+    pub async fn load_tx_out_set(&self, path: &str) -> Result<LoadTxOutSet, Error> {
+        self.require_version("loadtxoutset")?;
+        Ok(self.client.loadtxoutset(path).await?)
+    }
+
+    pub async fn get_rpc_info(&self) -> Result<RpcInfo, Error> {
+        self.require_version("getrpcinfo")?;
+        Ok(self.client.getrpcinfo().await?)
+    }
+
+    /// Makes the node prefer `block_hash` over other tips with the same work.
+    pub async fn precious_block(&self, block_hash: &bitcoin::BlockHash) -> Result<(), Error> {
+        Ok(self.client.preciousblock(block_hash).await?)
+    }
+
+    pub async fn send_raw_transaction(&self, tx_hex: &str) -> Result<bitcoin::Txid, Error> {
+        if !self.skip_validation {
+            validation::raw_transaction(tx_hex)?;
+        }
+        Ok(self.client.sendrawtransaction(tx_hex).await?)
+    }
+
+    /// Requires the mainchain node to run with `-blockfilterindex`.
+    /// `filter_type` defaults to `basic`, the only type Bitcoin Core
+    /// supports.
+    pub async fn get_block_filter(
+        &self,
+        block_hash: &bitcoin::BlockHash,
+        filter_type: Option<&str>,
+    ) -> Result<filters::BlockFilter, Error> {
+        self.require_version("getblockfilter")?;
+        let filter = self
+            .client
+            .getblockfilter(block_hash, filter_type)
+            .await
+            .map_err(|err| match err {
+                jsonrpsee::core::Error::Call(err)
+                    if err.message().starts_with("Index is not enabled") =>
+                {
+                    Error::FilterIndexDisabled
+                }
+                err => err.into(),
+            })?;
+        Ok(filter.into())
+    }
+
+    /// Waits for a new block, or returns the current tip once `timeout`
+    /// elapses. Waits forever if `timeout` is `None`.
+    pub async fn wait_for_new_block(&self, timeout: Option<Duration>) -> Result<BlockRef, Error> {
+        Ok(self.client.waitfornewblock(timeout_millis(timeout)).await?)
+    }
+
+    /// Waits until the chain reaches `height`, or returns the current tip
+    /// once `timeout` elapses. Returns immediately if the height was already
+    /// reached. Waits forever if `timeout` is `None`.
+    pub async fn wait_for_block_height(
+        &self,
+        height: usize,
+        timeout: Option<Duration>,
+    ) -> Result<BlockRef, Error> {
+        Ok(self
+            .client
+            .waitforblockheight(height, timeout_millis(timeout))
+            .await?)
+    }
+
+    pub async fn get_new_address(
+        &self,
+        label: Option<&str>,
+        address_type: Option<AddressType>,
+    ) -> Result<bitcoin::Address<bitcoin::address::NetworkUnchecked>, Error> {
+        let params = PositionalParams(vec![
+            serde_json::to_value(label)?,
+            serde_json::to_value(address_type)?,
+        ]);
+        Ok(self.client.request("getnewaddress", params).await?)
+    }
+
+    /// Returns [`Error::WrongNetwork`] if the node returns an address that
+    /// is not for its network.
+    pub async fn get_new_address_checked(
+        &self,
+        label: Option<&str>,
+        address_type: Option<AddressType>,
+    ) -> Result<bitcoin::Address, Error> {
+        let address = self.get_new_address(label, address_type).await?;
+        let network = self.node_network().await?;
+        let got = address.network;
+        address
+            .require_network(network)
+            .map_err(|_| Error::WrongNetwork {
+                expected: network,
+                got,
+            })
+    }
+
+    pub async fn get_address_info(
+        &self,
+        address: &bitcoin::Address<bitcoin::address::NetworkUnchecked>,
+    ) -> Result<AddressInfo, Error> {
+        if let (false, Some(network)) = (self.skip_validation, self.network) {
+            validation::address_network(address, network)?;
+        }
+        Ok(self.client.getaddressinfo(address).await?)
+    }
+
+    /// Returns [`Error::WrongNetwork`] without calling the node if `address`
+    /// is not for the node's network.
+    pub async fn get_address_info_checked(
+        &self,
+        address: &bitcoin::Address,
+    ) -> Result<AddressInfo, Error> {
+        validation::checked_address_network(address, self.node_network().await?)?;
+        Ok(self.client.getaddressinfo(&unchecked(address)).await?)
+    }
+
+    /// Signs `message` with the key of `address`, e.g. for attestations that
+    /// can be checked with [`message::verify_message_offline`].
+    pub async fn sign_message(
+        &self,
+        address: &bitcoin::Address<bitcoin::address::NetworkUnchecked>,
+        message: &str,
+    ) -> Result<String, Error> {
+        if let (false, Some(network)) = (self.skip_validation, self.network) {
+            validation::address_network(address, network)?;
+        }
+        Ok(self.client.signmessage(address, message).await?)
+    }
+
+    pub async fn verify_message(
+        &self,
+        address: &bitcoin::Address<bitcoin::address::NetworkUnchecked>,
+        signature: &str,
+        message: &str,
+    ) -> Result<bool, Error> {
+        if let (false, Some(network)) = (self.skip_validation, self.network) {
+            validation::address_network(address, network)?;
+        }
+        Ok(self
+            .client
+            .verifymessage(address, signature, message)
+            .await?)
+    }
+
+    pub async fn get_raw_change_address(
+        &self,
+        address_type: Option<AddressType>,
+    ) -> Result<bitcoin::Address<bitcoin::address::NetworkUnchecked>, Error> {
+        let params = PositionalParams(vec![serde_json::to_value(address_type)?]);
+        Ok(self.client.request("getrawchangeaddress", params).await?)
+    }
+
+    pub async fn create_sidechain_deposit(
+        &self,
+        address: &deposit::DepositAddress,
+        amount: AmountBtc,
+        fee: AmountBtc,
+    ) -> Result<serde_json::Value, Error> {
+        Ok(self
+            .client
+            .createsidechaindeposit(self.sidechain_number, address.as_str(), amount, fee)
+            .await?)
+    }
+
+    /// Lists deposits like `listsidechaindepositsbyblock`, with each
+    /// transaction decoded and checked to be at `ntx` in its block and to
+    /// have an output at `nburnindex`. Deposits without a destination are
+    /// listed with [`DepositVerbose::dest_bytes`] set to `None`.
+    pub async fn list_deposits_verbose(
+        &self,
+        end: Option<bitcoin::BlockHash>,
+        start: Option<bitcoin::BlockHash>,
+    ) -> Result<Vec<DepositVerbose>, Error> {
+        let deposits = self
+            .client
+            .listsidechaindepositsbyblock(self.sidechain_number, end, start)
+            .await?;
+        let mut block_txids = HashMap::new();
+        let mut verbose = Vec::with_capacity(deposits.len());
+        for deposit in deposits {
+            let transaction = deposit.transaction()?;
+            let txid = transaction.txid();
+            let invalid = |reason| Error::InvalidDeposit {
+                txid,
+                block_hash: deposit.hashblock,
+                reason,
+            };
+            if deposit.nburnindex >= transaction.output.len() {
+                return Err(invalid("burn index out of range"));
+            }
+            let dest_bytes = deposit::extract_dest_bytes(&transaction);
+            let txids = match block_txids.entry(deposit.hashblock) {
+                std::collections::hash_map::Entry::Occupied(entry) => entry.into_mut(),
+                std::collections::hash_map::Entry::Vacant(entry) => {
+                    entry.insert(self.get_block(&deposit.hashblock).await?.tx)
+                }
+            };
+            if txids.get(deposit.ntx) != Some(&txid) {
+                return Err(invalid("transaction is not at the reported index"));
+            }
+            verbose.push(DepositVerbose {
+                deposit,
+                transaction,
+                dest_bytes,
+            });
+        }
+        Ok(verbose)
+    }
+
+    pub async fn get_wallet_transaction(
+        &self,
+        txid: &bitcoin::Txid,
+        include_watchonly: bool,
+        verbose: bool,
+    ) -> Result<WalletTransaction, Error> {
+        Ok(self
+            .client
+            .gettransaction(txid, include_watchonly, verbose)
+            .await?)
+    }
+
+    /// Watches `txids`, e.g. deposits, for replacements and double-spends.
+    /// See [`conflicts::watch_for_conflicts`].
+    pub fn conflict_watcher(
+        &self,
+        txids: impl IntoIterator<Item = bitcoin::Txid>,
+        poll_interval: Duration,
+    ) -> conflicts::ConflictWatcher {
+        conflicts::ConflictWatcher::new(self.client.clone(), txids, poll_interval)
+    }
+
+    pub async fn list_received_by_address(
+        &self,
+        minconf: Option<u32>,
+        include_empty: Option<bool>,
+        include_watchonly: Option<bool>,
+        address_filter: Option<&bitcoin::Address<bitcoin::address::NetworkUnchecked>>,
+    ) -> Result<Vec<ReceivedByAddress>, Error> {
+        if let (false, Some(network), Some(address)) =
+            (self.skip_validation, self.network, address_filter)
+        {
+            validation::address_network(address, network)?;
+        }
+        Ok(self
+            .client
+            .listreceivedbyaddress(minconf, include_empty, include_watchonly, address_filter)
+            .await?)
+    }
+
+    /// Deposits to this sidechain received on each of `addresses`. See
+    /// [`deposit::index_deposits_by_address`].
+    pub async fn index_deposits_by_address(
+        &self,
+        addresses: &[bitcoin::Address<bitcoin::address::NetworkUnchecked>],
+    ) -> Result<Vec<deposit::AddressDeposits>, Error> {
+        deposit::index_deposits_by_address(&self.client, self.sidechain_number, addresses).await
+    }
+
+    /// See [`deposit::index_deposits_by_address_with_limits`].
+    pub async fn index_deposits_by_address_with_limits(
+        &self,
+        addresses: &[bitcoin::Address<bitcoin::address::NetworkUnchecked>],
+        limits: &cancel::Limits,
+    ) -> Result<cancel::Partial<Vec<deposit::AddressDeposits>>, Error> {
+        deposit::index_deposits_by_address_with_limits(
+            &self.client,
+            self.sidechain_number,
+            addresses,
+            limits,
+        )
+        .await
+    }
+
+    /// Deposits to this sidechain grouped by wallet label. See
+    /// [`deposit::deposits_by_label`].
+    pub async fn deposits_by_label(&self) -> Result<deposit::DepositsByLabel, Error> {
+        deposit::deposits_by_label(&self.client, self.sidechain_number).await
+    }
+
+    pub async fn list_since_block(
+        &self,
+        block_hash: Option<&bitcoin::BlockHash>,
+        target_confirmations: Option<u32>,
+        include_watchonly: Option<bool>,
+        include_removed: Option<bool>,
+    ) -> Result<ListSinceBlock, Error> {
+        Ok(self
+            .client
+            .listsinceblock(
+                block_hash,
+                target_confirmations,
+                include_watchonly,
+                include_removed,
+            )
+            .await?)
+    }
+
+    /// Wallet sends since `block_hash` that pay to `escrow_script`. See
+    /// [`deposit::deposits_since`].
+    pub async fn deposits_since(
+        &self,
+        escrow_script: &bitcoin::Script,
+        block_hash: Option<&bitcoin::BlockHash>,
+    ) -> Result<Vec<WalletTx>, Error> {
+        deposit::deposits_since(&self.client, escrow_script, block_hash).await
+    }
+
+    pub async fn send_to_address(
+        &self,
+        address: &bitcoin::Address<bitcoin::address::NetworkUnchecked>,
+        amount: AmountBtc,
+        options: &SendToAddressOptions,
+    ) -> Result<bitcoin::Txid, Error> {
+        if let (false, Some(network)) = (self.skip_validation, self.network) {
+            validation::address_network(address, network)?;
+        }
+        let mut params = ObjectParams::new();
+        params.insert("address", address)?;
+        params.insert("amount", amount)?;
+        if let serde_json::Value::Object(options) = serde_json::to_value(options)? {
+            for (name, value) in options {
+                params.insert(&name, value)?;
+            }
+        }
+        self.request("sendtoaddress", params).await
+    }
+
+    /// Returns [`Error::WrongNetwork`] without calling the node if `address`
+    /// is not for the node's network.
+    pub async fn send_to_address_checked(
+        &self,
+        address: &bitcoin::Address,
+        amount: AmountBtc,
+        options: &SendToAddressOptions,
+    ) -> Result<bitcoin::Txid, Error> {
+        validation::checked_address_network(address, self.node_network().await?)?;
+        self.send_to_address(&unchecked(address), amount, options)
+            .await
+    }
+
+    /// Sends an arbitrary request to the mainchain node. If the response
+    /// does not deserialize, the error includes the path of the offending
+    /// field and the start of the raw response.
+    pub async fn request<T, Params>(&self, method: &str, params: Params) -> Result<T, Error>
+    where
+        T: serde::de::DeserializeOwned,
+        Params: ToRpcParams + Send,
+    {
+        let mut response: Box<RawValue> = self.client.request(method, params).await?;
+        if let Some(server_version) = &self.server_version {
+            let mut value = serde_json::from_str(response.get())?;
+            version::adapt_response(method, server_version.version, &mut value);
+            response = serde_json::value::to_raw_value(&value)?;
+        }
+        decode_response(method, &response)
+    }
+
     async fn get_deposit_outputs(
         &self,
         end: bitcoin::BlockHash,
@@ -105,9 +1067,7 @@ impl Drivechain {
         let mut last_total = 0;
         let mut outputs = HashMap::new();
         for deposit in &deposits {
-            let transaction = hex::decode(&deposit.txhex)?;
-            let transaction =
-                bitcoin::Transaction::consensus_decode(&mut std::io::Cursor::new(transaction))?;
+            let transaction = deposit.transaction()?;
             if let Some(start) = start {
                 if deposit.hashblock == start {
                     last_total = transaction.output[deposit.nburnindex].value;
@@ -151,33 +1111,232 @@ impl Drivechain {
     }
 
     pub fn new(
-        sidechain_number: u8,
+        sidechain_number: SidechainId,
         main_addr: SocketAddr,
         user: &str,
         password: &str,
     ) -> Result<Self, Error> {
-        let mut headers = HeaderMap::new();
-        let auth = format!("{user}:{password}");
-        let header_value = format!(
-            "Basic {}",
-            base64::engine::general_purpose::STANDARD_NO_PAD.encode(auth)
-        )
-        .parse()?;
-        headers.insert("authorization", header_value);
-        let client = HttpClientBuilder::default()
-            .set_headers(headers.clone())
-            .build(format!("http://{main_addr}"))?;
+        let client = new_client(main_addr, user, password, None)?;
         Ok(Drivechain {
             sidechain_number,
             client,
+            network: None,
+            skip_validation: false,
+            server_version: None,
+            detected_network: std::sync::Arc::default(),
+        })
+    }
+
+    /// Like [`Self::new`], detecting the version of the mainchain node.
+    pub async fn connect(
+        sidechain_number: SidechainId,
+        main_addr: SocketAddr,
+        user: &str,
+        password: &str,
+    ) -> Result<Self, Error> {
+        Self::new(sidechain_number, main_addr, user, password)?
+            .detect_server_version()
+            .await
+    }
+
+    pub fn with_network(mut self, network: bitcoin::Network) -> Self {
+        self.network = Some(network);
+        self
+    }
+
+    /// Sets `server_version` to the version of the mainchain node.
+    pub async fn detect_server_version(mut self) -> Result<Self, Error> {
+        self.server_version = Some(version::ServerVersion::detect(&self.client).await?);
+        Ok(self)
+    }
+
+    fn require_version(&self, method: &'static str) -> Result<(), Error> {
+        match &self.server_version {
+            Some(server_version) => server_version.require(method),
+            None => Ok(()),
+        }
+    }
+
+    /// Sets `network` to the network the mainchain node is on.
+    pub async fn detect_network(mut self) -> Result<Self, Error> {
+        self.network = Some(self.fetch_network().await?);
+        Ok(self)
+    }
+
+    /// `network` if it is set, otherwise the network the mainchain node
+    /// reports, which is only fetched once.
+    async fn node_network(&self) -> Result<bitcoin::Network, Error> {
+        if let Some(network) = self.network.or(self.detected_network.get().copied()) {
+            return Ok(network);
+        }
+        let network = self.fetch_network().await?;
+        Ok(*self.detected_network.get_or_init(|| network))
+    }
+
+    async fn fetch_network(&self) -> Result<bitcoin::Network, Error> {
+        let chain = self.client.getblockchaininfo().await?.value.chain;
+        bitcoin::Network::from_core_arg(&chain).map_err(|_| Error::UnknownChain { chain })
+    }
+
+    /// Disables client side parameter validation, e.g. for deliberately
+    /// sending malformed requests to the node in tests.
+    pub fn danger_skip_validation(mut self) -> Self {
+        self.skip_validation = true;
+        self
+    }
+}
+
+#[cfg(feature = "client")]
+/// Maximum number of characters of a raw response included in errors.
+const RESPONSE_SNIPPET_LEN: usize = 256;
+
+#[cfg(feature = "client")]
+fn decode_response<T>(method: &str, response: &RawValue) -> Result<T, Error>
+where
+    T: serde::de::DeserializeOwned,
+{
+    let mut deserializer = serde_json::Deserializer::from_str(response.get());
+    serde_path_to_error::deserialize(&mut deserializer).map_err(|err| {
+        let snippet = response.get().chars().take(RESPONSE_SNIPPET_LEN).collect();
+        Error::Deserialize {
+            method: method.to_owned(),
+            path: err.path().to_string(),
+            source: err.into_inner(),
+            snippet,
+        }
+    })
+}
+
+#[cfg(feature = "client")]
+/// The node interprets a timeout of 0 as "wait forever".
+fn timeout_millis(timeout: Option<Duration>) -> u64 {
+    match timeout {
+        Some(timeout) => u64::try_from(timeout.as_millis())
+            .unwrap_or(u64::MAX)
+            .max(1),
+        None => 0,
+    }
+}
+
+/// Positional params with trailing `null`s left out, so that the node
+/// applies its defaults rather than being sent `[null, null]`.
+#[cfg(feature = "client")]
+struct PositionalParams(Vec<serde_json::Value>);
+
+#[cfg(feature = "client")]
+impl ToRpcParams for PositionalParams {
+    fn to_rpc_params(mut self) -> Result<Option<Box<RawValue>>, jsonrpsee::core::Error> {
+        while self.0.last().is_some_and(serde_json::Value::is_null) {
+            self.0.pop();
+        }
+        Ok(Some(serde_json::value::to_raw_value(&self.0)?))
+    }
+}
+
+#[cfg(feature = "client")]
+fn unchecked(address: &bitcoin::Address) -> bitcoin::Address<bitcoin::address::NetworkUnchecked> {
+    bitcoin::Address::new(address.network, address.payload.clone())
+}
+
+#[cfg(feature = "client")]
+fn auth_headers(user: &str, password: &str) -> Result<HeaderMap, Error> {
+    let mut headers = HeaderMap::new();
+    let auth = format!("{user}:{password}");
+    let header_value = format!(
+        "Basic {}",
+        base64::engine::general_purpose::STANDARD_NO_PAD.encode(auth)
+    )
+    .parse()?;
+    headers.insert("authorization", header_value);
+    Ok(headers)
+}
+
+#[cfg(feature = "client")]
+/// Builds an HTTP client for the mainchain node using basic auth.
+///
+/// `request_timeout` defaults to 60 seconds. RPCs that block on the node,
+/// such as `waitfornewblock`, need a client with a timeout longer than the
+/// time they are expected to wait.
+pub fn new_client(
+    main_addr: SocketAddr,
+    user: &str,
+    password: &str,
+    request_timeout: Option<Duration>,
+) -> Result<HttpClient, Error> {
+    http_client(
+        format!("http://{main_addr}"),
+        user,
+        password,
+        request_timeout,
+    )
+}
+
+#[cfg(feature = "client")]
+/// Builds an HTTP client for the wallet `wallet_name` of the mainchain node,
+/// for nodes with more than one wallet loaded.
+pub fn new_wallet_client(
+    main_addr: SocketAddr,
+    wallet_name: &str,
+    user: &str,
+    password: &str,
+    request_timeout: Option<Duration>,
+) -> Result<HttpClient, Error> {
+    let wallet_name: String = wallet_name
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                char::from(byte).to_string()
+            }
+            _ => format!("%{byte:02X}"),
         })
+        .collect();
+    http_client(
+        format!("http://{main_addr}/wallet/{wallet_name}"),
+        user,
+        password,
+        request_timeout,
+    )
+}
+
+#[cfg(feature = "client")]
+fn http_client(
+    url: String,
+    user: &str,
+    password: &str,
+    request_timeout: Option<Duration>,
+) -> Result<HttpClient, Error> {
+    let headers = auth_headers(user, password)?;
+    let mut builder = HttpClientBuilder::default().set_headers(headers);
+    if let Some(request_timeout) = request_timeout {
+        builder = builder.request_timeout(request_timeout);
     }
+    Ok(builder.build(url)?)
+}
+
+/// Creates a WebSocket client for `url`, e.g. `ws://127.0.0.1:18443`.
+/// Bitcoin Core only serves RPC over HTTP, so this requires a WebSocket
+/// capable proxy in front of the node.
+#[cfg(feature = "ws")]
+pub async fn new_ws_client(
+    url: &str,
+    user: &str,
+    password: &str,
+    request_timeout: Option<Duration>,
+) -> Result<jsonrpsee::ws_client::WsClient, Error> {
+    let headers = auth_headers(user, password)?;
+    let mut builder = jsonrpsee::ws_client::WsClientBuilder::default().set_headers(headers);
+    if let Some(request_timeout) = request_timeout {
+        builder = builder.request_timeout(request_timeout);
+    }
+    Ok(builder.build(url).await?)
 }
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
+    #[cfg(feature = "client")]
     #[error("jsonrpsee error")]
-    Jsonrpsee(#[from] jsonrpsee::core::Error),
+    Jsonrpsee(#[source] jsonrpsee::core::Error),
+    #[cfg(feature = "client")]
     #[error("header error")]
     InvalidHeaderValue(#[from] http::header::InvalidHeaderValue),
     #[error("bitcoin consensus encode error")]
@@ -190,4 +1349,258 @@ pub enum Error {
     NoNextBlock { prev_main_hash: bitcoin::BlockHash },
     #[error("io error")]
     Io(#[from] std::io::Error),
+    #[error("json error")]
+    Json(#[from] serde_json::Error),
+    #[error(
+        "failed to deserialize `{method}` response at `{path}`: {source} (response: {snippet})"
+    )]
+    Deserialize {
+        method: String,
+        path: String,
+        source: serde_json::Error,
+        snippet: String,
+    },
+    #[error("bip158 error")]
+    Bip158(#[from] bitcoin::bip158::Error),
+    #[error("block filter index is not enabled on the mainchain node")]
+    FilterIndexDisabled,
+    #[error("coinbase flags are {len} bytes, exceeding the coinbase scriptSig limit")]
+    CoinbaseFlagsTooLong { len: usize },
+    #[error("deployment `{name}` is not available for signalling")]
+    UnknownDeployment { name: String },
+    #[error("invalid parameter `{name}`: {reason}")]
+    InvalidParam { name: &'static str, reason: String },
+    #[error("timed out waiting for withdrawal bundle {bundle_txid} to resolve")]
+    BundleResolutionTimeout { bundle_txid: bitcoin::Txid },
+    #[error("submitted withdrawal bundle {expected}, but the node received {actual}")]
+    BundleTxidMismatch {
+        expected: bitcoin::Txid,
+        actual: bitcoin::Txid,
+    },
+    #[error("transaction index is not enabled on the mainchain node, restart it with -txindex")]
+    TxIndexDisabled,
+    #[error("transaction index is still syncing, at height {index_height} of {tip_height}")]
+    TxIndexSyncing {
+        index_height: u32,
+        tip_height: usize,
+    },
+    #[error("failed to decode deposit transaction with witness ({witness}) or without ({legacy})")]
+    DepositTransaction {
+        witness: bitcoin::consensus::encode::Error,
+        legacy: bitcoin::consensus::encode::Error,
+    },
+    #[error("deposit {txid} does not match block {block_hash}: {reason}")]
+    InvalidDeposit {
+        txid: bitcoin::Txid,
+        block_hash: bitcoin::BlockHash,
+        reason: &'static str,
+    },
+    #[error("deposit address error")]
+    DepositAddress(#[from] deposit::AddrError),
+    #[error("block {block_hash} does not extend {expected_parent}, another process is mining")]
+    ConcurrentMining {
+        block_hash: bitcoin::BlockHash,
+        expected_parent: bitcoin::BlockHash,
+    },
+    #[error("condition still not met after mining {max_blocks} blocks")]
+    MaxBlocksMined { max_blocks: u32 },
+    #[error("header of block {block_hash} is not known")]
+    UnknownBlock { block_hash: bitcoin::BlockHash },
+    #[error("block {block_hash} is not in the main chain")]
+    NotInMainChain { block_hash: bitcoin::BlockHash },
+    #[error("expected tip {expected} after reorg, found {actual}")]
+    UnexpectedTip {
+        expected: bitcoin::BlockHash,
+        actual: bitcoin::BlockHash,
+    },
+    #[cfg(feature = "client")]
+    #[error("can't bump the fee of {txid}, {reason}")]
+    BumpFeeRefused {
+        txid: bitcoin::Txid,
+        reason: rescue::BumpFeeRefusal,
+    },
+    #[error("transaction {txid} has no output belonging to the wallet")]
+    NoWalletOutput { txid: bitcoin::Txid },
+    #[error("wallet could not sign all inputs: {errors:?}")]
+    IncompleteSignature { errors: Vec<String> },
+    #[error(
+        "coinbase of block {block_hash} commits to height {actual}, but the block is at {expected}"
+    )]
+    CoinbaseHeightMismatch {
+        block_hash: bitcoin::BlockHash,
+        expected: usize,
+        actual: u32,
+    },
+    #[error("`{method}` needs mainchain node version {needs}, but it is {has}")]
+    UnsupportedByServer {
+        method: &'static str,
+        needs: version::CoreVersion,
+        has: version::CoreVersion,
+    },
+    #[error("block {block_hash} was pruned")]
+    BlockPruned { block_hash: bitcoin::BlockHash },
+    #[error("transaction {txid} is not in block {block_hash}")]
+    TxNotInBlock {
+        txid: bitcoin::Txid,
+        block_hash: bitcoin::BlockHash,
+    },
+    #[error("wallet does not watch any script of transaction {txid}")]
+    NotWatched { txid: bitcoin::Txid },
+    #[error("coinbase has no witness commitment")]
+    MissingWitnessCommitment,
+    #[error("witness commitment error")]
+    WitnessCommitment(#[from] mining::WitnessCommitmentError),
+    #[error("template signet challenge {template:?} does not match the node's {node:?}")]
+    SignetChallengeMismatch {
+        template: Option<bitcoin::ScriptBuf>,
+        node: Option<bitcoin::ScriptBuf>,
+    },
+    #[error("refusing to mine on {network}, only regtest and signet are allowed")]
+    MiningNotAllowed { network: bitcoin::Network },
+    #[error("the mainchain node does not know the parent header {prev_block_hash}")]
+    MissingParentHeader { prev_block_hash: bitcoin::BlockHash },
+    #[error("header rejected: {reason}")]
+    HeaderRejected { reason: String },
+    #[error("block {block_hash} rejected: {reason}")]
+    BlockRejected {
+        block_hash: bitcoin::BlockHash,
+        reason: String,
+    },
+    #[error("difficulty {difficulty} of block {block_hash} does not match its bits {bits:?}")]
+    DifficultyMismatch {
+        block_hash: bitcoin::BlockHash,
+        bits: bitcoin::CompactTarget,
+        difficulty: Difficulty,
+    },
+    #[error("invalid cassette at line {line}: {reason}")]
+    InvalidCassette { line: usize, reason: String },
+    #[error("invalid journal entry {id}: {reason}")]
+    InvalidJournalEntry { id: u64, reason: &'static str },
+    #[error("invalid journal at line {line}: {reason}")]
+    InvalidJournal { line: usize, reason: String },
+    #[error("unknown chain `{chain}` reported by the mainchain node")]
+    UnknownChain { chain: String },
+    #[error("address is for {got}, but the mainchain node is on {expected}")]
+    WrongNetwork {
+        expected: bitcoin::Network,
+        got: bitcoin::Network,
+    },
+    #[error("mainchain tip changed during all {attempts} attempts to take a snapshot")]
+    InconsistentSnapshot { attempts: u32 },
+    #[error("batch of {expected} requests was answered with {actual} responses")]
+    BatchResponseLength { expected: usize, actual: usize },
+    #[error("batch response is not the result of `{method}`")]
+    UnexpectedBatchResult { method: &'static str },
+    #[error("refusing to roll back blocks on {network}, only regtest is allowed")]
+    RollbackNotAllowed { network: bitcoin::Network },
+    #[error("drivechain state after replaying the rolled back blocks differs:\n{diff}")]
+    ReplayMismatch { diff: snapshot::SidechainStateDiff },
+    #[error("wallet passphrase is incorrect")]
+    WrongPassphrase,
+    #[error("wallet is not encrypted")]
+    WalletNotEncrypted,
+    #[error("messages can only be signed by legacy P2PKH addresses, not {address_type:?}")]
+    UnsupportedMessageAddress {
+        address_type: Option<bitcoin::AddressType>,
+    },
+    /// The node's RPC work queue is full, see [`throttle`].
+    #[cfg(feature = "client")]
+    #[error("mainchain node is busy: RPC work queue depth exceeded")]
+    ServerBusy,
+    /// See [`cancel`].
+    #[cfg(feature = "client")]
+    #[error("cancelled")]
+    Cancelled,
+    /// See [`cancel`]. Progress is counted in units of the helper, e.g.
+    /// headers.
+    #[cfg(feature = "client")]
+    #[error("deadline exceeded with {completed} completed")]
+    DeadlineExceeded {
+        completed: usize,
+        remaining_estimate: Option<usize>,
+    },
+    /// See [`blocking`].
+    #[cfg(feature = "blocking")]
+    #[error("blocking client called from within a tokio runtime, use the async API instead")]
+    BlockingInRuntime,
+    /// See [`journal`].
+    #[cfg(feature = "client")]
+    #[error("reorg deeper than the {window} blocks the journal can revert")]
+    JournalReorgTooDeep { window: usize },
+}
+
+#[cfg(feature = "client")]
+impl From<jsonrpsee::core::Error> for Error {
+    fn from(err: jsonrpsee::core::Error) -> Self {
+        if throttle::is_server_busy(&err) {
+            Error::ServerBusy
+        } else {
+            Error::Jsonrpsee(err)
+        }
+    }
+}
+
+#[cfg(all(test, feature = "client"))]
+mod tests {
+    use super::{Drivechain, Error, PositionalParams};
+    use crate::client::SidechainId;
+    use crate::mock::{block_on, blockchain_info, serve, MockClient};
+    use jsonrpsee::core::traits::ToRpcParams;
+    use serde_json::json;
+
+    #[test]
+    fn trailing_null_params_are_omitted() {
+        let cases = [
+            (vec![json!(null), json!(null)], "[]"),
+            (vec![json!(null), json!("bech32")], r#"[null,"bech32"]"#),
+            (vec![json!("label"), json!(null)], r#"["label"]"#),
+        ];
+        for (params, expected) in cases {
+            let params = PositionalParams(params).to_rpc_params().unwrap().unwrap();
+            assert_eq!(params.get(), expected);
+        }
+    }
+
+    #[test]
+    fn new_address_for_another_network_is_refused() {
+        let script = bitcoin::ScriptBuf::new();
+        let mainnet = bitcoin::Address::p2wsh(&script, bitcoin::Network::Bitcoin);
+        let regtest = bitcoin::Address::p2wsh(&script, bitcoin::Network::Regtest);
+        let (mainnet_json, regtest_json) = (json!(mainnet.to_string()), json!(regtest.to_string()));
+        let (addr, node) = serve(MockClient::new(move |method, params| {
+            Ok(match method {
+                "getblockchaininfo" => blockchain_info("regtest", 101),
+                "getnewaddress" if params.is_empty() => mainnet_json.clone(),
+                "getnewaddress" => regtest_json.clone(),
+                _ => unreachable!("{method}"),
+            })
+        }));
+        block_on(async {
+            let drivechain = Drivechain::new(SidechainId(0), addr, "user", "password").unwrap();
+            let err = drivechain
+                .get_new_address_checked(None, None)
+                .await
+                .unwrap_err();
+            assert!(
+                matches!(
+                    err,
+                    Error::WrongNetwork {
+                        expected: bitcoin::Network::Regtest,
+                        got: bitcoin::Network::Bitcoin,
+                    }
+                ),
+                "{err:?}"
+            );
+            let address = drivechain
+                .get_new_address_checked(Some("label"), None)
+                .await
+                .unwrap();
+            assert_eq!(address, regtest);
+        });
+        // The network is fetched once
+        assert_eq!(
+            node.methods(),
+            ["getnewaddress", "getblockchaininfo", "getnewaddress"]
+        );
+    }
 }
@@ -0,0 +1,166 @@
+use crate::cancel::{Limits, Running};
+use crate::client::ChainClient;
+use crate::Error;
+use futures::Stream;
+use jsonrpsee::core::client::ClientT;
+use std::collections::HashSet;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MempoolDelta {
+    Update {
+        added: Vec<bitcoin::Txid>,
+        removed: Vec<bitcoin::Txid>,
+        sequence: u64,
+    },
+    /// The full mempool. Sent first, and whenever the deltas can't be trusted
+    /// anymore, e.g. after the node restarted.
+    Resync {
+        full_set: HashSet<bitcoin::Txid>,
+        sequence: u64,
+    },
+}
+
+struct State<C> {
+    client: C,
+    poll_interval: Duration,
+    txids: HashSet<bitcoin::Txid>,
+    /// `None` until the first snapshot was taken.
+    sequence: Option<u64>,
+    polled: bool,
+    running: Running,
+    polls: usize,
+}
+
+impl<C> State<C>
+where
+    C: ClientT + Sync,
+{
+    async fn next(&mut self) -> Result<MempoolDelta, Error> {
+        loop {
+            if self.polled {
+                let sleep = async {
+                    tokio::time::sleep(self.poll_interval).await;
+                    Ok(())
+                };
+                self.running.race(sleep, self.polls, None).await?;
+            }
+            self.polled = true;
+            let mempool = self
+                .running
+                .race(
+                    async { Ok(self.client.getrawmempoolwithsequence(false, true).await?) },
+                    self.polls,
+                    None,
+                )
+                .await?;
+            self.polls += 1;
+            let txids: HashSet<_> = mempool.txids.into_iter().collect();
+            let sequence = mempool.mempool_sequence;
+            let Some(last_sequence) = self.sequence else {
+                return Ok(self.resync(txids, sequence));
+            };
+            if sequence == last_sequence {
+                continue;
+            }
+            let added: Vec<_> = txids.difference(&self.txids).copied().collect();
+            let removed: Vec<_> = self.txids.difference(&txids).copied().collect();
+            // Every addition and removal bumps the sequence, so it can't have
+            // moved less than the snapshots differ.
+            let consistent = sequence
+                .checked_sub(last_sequence)
+                .is_some_and(|elapsed| elapsed >= (added.len() + removed.len()) as u64);
+            if !consistent {
+                return Ok(self.resync(txids, sequence));
+            }
+            self.txids = txids;
+            self.sequence = Some(sequence);
+            return Ok(MempoolDelta::Update {
+                added,
+                removed,
+                sequence,
+            });
+        }
+    }
+
+    fn resync(&mut self, txids: HashSet<bitcoin::Txid>, sequence: u64) -> MempoolDelta {
+        self.txids = txids.clone();
+        self.sequence = Some(sequence);
+        MempoolDelta::Resync {
+            full_set: txids,
+            sequence,
+        }
+    }
+}
+
+/// Polls the mempool every `poll_interval` and yields the changes, starting
+/// with a [`MempoolDelta::Resync`] of the current mempool. Polls that see no
+/// change are skipped. Errors are yielded without ending the stream.
+pub fn watch<C>(
+    client: C,
+    poll_interval: Duration,
+) -> impl Stream<Item = Result<MempoolDelta, Error>>
+where
+    C: ClientT + Sync,
+{
+    watch_with_limits(client, poll_interval, Limits::default())
+}
+
+/// Like [`watch`], but stops with [`Error::Cancelled`] or
+/// [`Error::DeadlineExceeded`] once `limits` are reached, counting completed
+/// polls. The stream ends after yielding the error.
+pub fn watch_with_limits<C>(
+    client: C,
+    poll_interval: Duration,
+    limits: Limits,
+) -> impl Stream<Item = Result<MempoolDelta, Error>>
+where
+    C: ClientT + Sync,
+{
+    let state = State {
+        client,
+        poll_interval,
+        txids: HashSet::new(),
+        sequence: None,
+        polled: false,
+        running: limits.start(),
+        polls: 0,
+    };
+    futures::stream::unfold(Some(state), |state| async move {
+        let mut state = state?;
+        match state.next().await {
+            Err(err @ (Error::Cancelled | Error::DeadlineExceeded { .. })) => {
+                Some((Err(err), None))
+            }
+            delta => Some((delta, Some(state))),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cancel::CancellationToken;
+    use crate::mock::{block_on, MockClient};
+    use futures::StreamExt as _;
+
+    #[test]
+    fn ends_after_cancellation() {
+        let client =
+            MockClient::new(|_, _| Ok(serde_json::json!({"txids": [], "mempool_sequence": 1})));
+        let cancel = CancellationToken::new();
+        let limits = Limits::new().with_cancellation(cancel.clone());
+        block_on(async {
+            let deltas = watch_with_limits(client, Duration::from_secs(60), limits);
+            futures::pin_mut!(deltas);
+            assert!(matches!(
+                deltas.next().await,
+                Some(Ok(MempoolDelta::Resync { sequence: 1, .. }))
+            ));
+            // Interrupts the wait for the next poll
+            cancel.cancel();
+            assert!(matches!(deltas.next().await, Some(Err(Error::Cancelled))));
+            assert!(deltas.next().await.is_none());
+        });
+    }
+}
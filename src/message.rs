@@ -0,0 +1,111 @@
+//! Signed messages, e.g. attestations made with keys of the mainchain
+//! node's wallet, in the format of `signmessage`.
+use crate::Error;
+use base64::Engine as _;
+use bitcoin::address::NetworkUnchecked;
+use bitcoin::sign_message::{signed_msg_hash, MessageSignature};
+
+/// Verifies a signature made by `signmessage` without a node, so that third
+/// parties can check attestations.
+///
+/// Like the node, only legacy P2PKH addresses are supported, and
+/// [`Error::UnsupportedMessageAddress`] is returned for any other address.
+/// Signatures that are well-formed but invalid, or made by another key,
+/// return `false`.
+pub fn verify_message_offline(
+    address: &bitcoin::Address<NetworkUnchecked>,
+    signature: &str,
+    message: &str,
+) -> Result<bool, Error> {
+    // The network does not affect the pubkey hash
+    let address = address.clone().assume_checked();
+    match address.address_type() {
+        Some(bitcoin::AddressType::P2pkh) => (),
+        address_type => return Err(Error::UnsupportedMessageAddress { address_type }),
+    }
+    let invalid = |reason: String| Error::InvalidParam {
+        name: "signature",
+        reason,
+    };
+    let signature = base64::engine::general_purpose::STANDARD
+        .decode(signature)
+        .map_err(|err| invalid(err.to_string()))?;
+    let signature =
+        MessageSignature::from_slice(&signature).map_err(|err| invalid(err.to_string()))?;
+    let secp = bitcoin::secp256k1::Secp256k1::verification_only();
+    Ok(signature
+        .is_signed_by_address(&secp, &address, signed_msg_hash(message))
+        .unwrap_or(false))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Address and signature of the `signmessage` functional test of
+    /// Bitcoin Core.
+    const ADDRESS: &str = "mpLQjfK79b7CCV4VMJWEWAj5Mpx8Up5zxB";
+    const MESSAGE: &str = "This is just a test message";
+    const SIGNATURE: &str =
+        "INbVnW4e6PeRmsv2Qgu8NuopvrVjkcxob+sX8OcZG0SALhWybUjzMLPdAsXI46YZGb0KQTRii+wWIQzRpG/U+S0=";
+
+    fn address(address: &str) -> bitcoin::Address<NetworkUnchecked> {
+        address.parse().unwrap()
+    }
+
+    #[test]
+    fn verifies_node_signature() {
+        assert!(verify_message_offline(&address(ADDRESS), SIGNATURE, MESSAGE).unwrap());
+    }
+
+    #[test]
+    fn rejects_tampered_message() {
+        let tampered = format!("{MESSAGE}!");
+        assert!(!verify_message_offline(&address(ADDRESS), SIGNATURE, &tampered).unwrap());
+    }
+
+    #[test]
+    fn rejects_other_address() {
+        use bitcoin::hashes::Hash as _;
+        let payload =
+            bitcoin::address::Payload::PubkeyHash(bitcoin::PubkeyHash::from_byte_array([1; 20]));
+        let other = address(&bitcoin::Address::new(bitcoin::Network::Regtest, payload).to_string());
+        assert!(!verify_message_offline(&other, SIGNATURE, MESSAGE).unwrap());
+    }
+
+    #[test]
+    fn segwit_addresses_are_unsupported() {
+        let segwit = address("bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080");
+        assert!(matches!(
+            verify_message_offline(&segwit, SIGNATURE, MESSAGE),
+            Err(Error::UnsupportedMessageAddress {
+                address_type: Some(bitcoin::AddressType::P2wpkh)
+            })
+        ));
+    }
+
+    #[test]
+    fn malformed_signature() {
+        assert!(matches!(
+            verify_message_offline(&address(ADDRESS), "not base64!", MESSAGE),
+            Err(Error::InvalidParam {
+                name: "signature",
+                ..
+            })
+        ));
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn node_signature_verifies_offline() {
+        use crate::client::WalletClient;
+        use crate::mock::{block_on, MockClient};
+        let client = MockClient::new(|method, params| {
+            assert_eq!(method, "signmessage");
+            assert_eq!(params, [ADDRESS, MESSAGE]);
+            Ok(serde_json::json!(SIGNATURE))
+        });
+        let signature = block_on(client.signmessage(&address(ADDRESS), MESSAGE)).unwrap();
+        assert!(verify_message_offline(&address(ADDRESS), &signature, MESSAGE).unwrap());
+    }
+}
@@ -0,0 +1,641 @@
+//! Request metrics for the mainchain RPC layer.
+//!
+//! [`MeteredClient`] reports every request to a [`MetricsSink`]. To count
+//! each attempt made by a [`crate::reconnect::ReconnectingClient`]
+//! separately, wrap the client returned by its `connect` function rather
+//! than the `ReconnectingClient` itself. Retries are then reported with the
+//! number of the attempt, see [`crate::reconnect::attempt`]. Wrapped around
+//! the `ReconnectingClient`, a retried request is reported once, as
+//! attempt 1.
+use crate::cache::RawParams;
+use jsonrpsee::core::async_trait;
+use jsonrpsee::core::client::{BatchResponse, ClientT};
+use jsonrpsee::core::params::BatchRequestBuilder;
+use jsonrpsee::core::traits::ToRpcParams;
+use serde::de::DeserializeOwned;
+use serde_json::value::RawValue;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Method name batches are recorded under.
+pub const BATCH_METHOD: &str = "batch";
+
+/// Upper bounds of the latency histogram buckets, in seconds.
+pub const LATENCY_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// How a request ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Success,
+    /// The node returned an error with this code.
+    Rpc(i32),
+    Transport,
+    /// The node's RPC work queue was full, see [`crate::throttle`].
+    ServerBusy,
+    Timeout,
+    /// The request future was dropped before it completed.
+    Cancelled,
+    Other,
+}
+
+impl Outcome {
+    fn from_result<T>(result: &Result<T, jsonrpsee::core::Error>) -> Self {
+        match result {
+            Ok(_) => Outcome::Success,
+            Err(err) if crate::throttle::is_server_busy(err) => Outcome::ServerBusy,
+            Err(jsonrpsee::core::Error::Call(err)) => Outcome::Rpc(err.code()),
+            Err(
+                jsonrpsee::core::Error::Transport(_) | jsonrpsee::core::Error::RestartNeeded(_),
+            ) => Outcome::Transport,
+            Err(jsonrpsee::core::Error::RequestTimeout) => Outcome::Timeout,
+            Err(_) => Outcome::Other,
+        }
+    }
+
+    /// Coarse class of the outcome, for use as a metric label. RPC errors
+    /// are classed by the range their code is in.
+    pub fn class(&self) -> &'static str {
+        match self {
+            Outcome::Success => "success",
+            // Reserved JSON-RPC codes, e.g. method not found
+            Outcome::Rpc(-32768..=-32000) => "rpc_protocol",
+            Outcome::Rpc(-4 | -6 | -19..=-11) => "rpc_wallet",
+            // Not connected, in initial block download, warming up, ...
+            Outcome::Rpc(-10..=-9 | -29..=-20) => "rpc_node",
+            Outcome::Rpc(_) => "rpc_general",
+            Outcome::Transport => "transport",
+            Outcome::ServerBusy => "server_busy",
+            Outcome::Timeout => "timeout",
+            Outcome::Cancelled => "cancelled",
+            Outcome::Other => "other",
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        matches!(self, Outcome::Success)
+    }
+}
+
+/// Receives request events from a [`MeteredClient`]. Every call to
+/// `request_started` is followed by exactly one call to `request_finished`
+/// for the same method and attempt. Attempts are numbered from 1.
+pub trait MetricsSink: Send + Sync {
+    fn request_started(&self, method: &str, attempt: u32);
+
+    fn request_finished(&self, method: &str, attempt: u32, outcome: Outcome, latency: Duration);
+
+    /// Called by [`crate::throttle::ThrottledClient`] whenever it changes
+    /// the number of requests it lets through at once.
+    fn concurrency_limit_changed(&self, _limit: usize) {}
+}
+
+impl<S> MetricsSink for Arc<S>
+where
+    S: MetricsSink + ?Sized,
+{
+    fn request_started(&self, method: &str, attempt: u32) {
+        (**self).request_started(method, attempt)
+    }
+
+    fn request_finished(&self, method: &str, attempt: u32, outcome: Outcome, latency: Duration) {
+        (**self).request_finished(method, attempt, outcome, latency)
+    }
+
+    fn concurrency_limit_changed(&self, limit: usize) {
+        (**self).concurrency_limit_changed(limit)
+    }
+}
+
+/// Recorded metrics of a single method.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MethodStats {
+    pub requests: u64,
+    /// Requests that were retries, i.e. attempts after the first.
+    pub retries: u64,
+    /// Failed requests by [`Outcome::class`].
+    pub errors: BTreeMap<&'static str, u64>,
+    pub in_flight: u64,
+    /// Number of requests that took at most the corresponding entry of
+    /// [`LATENCY_BUCKETS`]. Buckets are not cumulative.
+    pub latency_buckets: Vec<u64>,
+    pub latency_sum: Duration,
+}
+
+/// Sink that keeps all metrics in memory behind a lock, for reading with
+/// [`InMemorySink::snapshot`].
+#[derive(Debug, Default)]
+pub struct InMemorySink {
+    methods: Mutex<HashMap<String, MethodStats>>,
+    concurrency_limit: Mutex<Option<usize>>,
+}
+
+impl InMemorySink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Metrics of all methods, taken at a single point in time.
+    pub fn snapshot(&self) -> HashMap<String, MethodStats> {
+        self.methods.lock().unwrap().clone()
+    }
+
+    /// Last limit reported with
+    /// [`MetricsSink::concurrency_limit_changed`].
+    pub fn concurrency_limit(&self) -> Option<usize> {
+        *self.concurrency_limit.lock().unwrap()
+    }
+}
+
+impl MetricsSink for InMemorySink {
+    fn request_started(&self, method: &str, attempt: u32) {
+        let mut methods = self.methods.lock().unwrap();
+        let stats = methods
+            .entry(method.to_owned())
+            .or_insert_with(|| MethodStats {
+                latency_buckets: vec![0; LATENCY_BUCKETS.len()],
+                ..Default::default()
+            });
+        stats.requests += 1;
+        if attempt > 1 {
+            stats.retries += 1;
+        }
+        stats.in_flight += 1;
+    }
+
+    fn request_finished(&self, method: &str, _attempt: u32, outcome: Outcome, latency: Duration) {
+        let mut methods = self.methods.lock().unwrap();
+        let Some(stats) = methods.get_mut(method) else {
+            return;
+        };
+        stats.in_flight = stats.in_flight.saturating_sub(1);
+        if !outcome.is_success() {
+            *stats.errors.entry(outcome.class()).or_default() += 1;
+        }
+        let seconds = latency.as_secs_f64();
+        if let Some(bucket) = LATENCY_BUCKETS.iter().position(|bound| seconds <= *bound) {
+            stats.latency_buckets[bucket] += 1;
+        }
+        stats.latency_sum += latency;
+    }
+
+    fn concurrency_limit_changed(&self, limit: usize) {
+        *self.concurrency_limit.lock().unwrap() = Some(limit);
+    }
+}
+
+/// Sink that records to metrics registered with a `prometheus` registry.
+#[cfg(feature = "prometheus")]
+#[derive(Clone)]
+pub struct PrometheusSink {
+    requests: prometheus::IntCounterVec,
+    errors: prometheus::IntCounterVec,
+    in_flight: prometheus::IntGaugeVec,
+    latency: prometheus::HistogramVec,
+    concurrency_limit: prometheus::IntGauge,
+}
+
+#[cfg(feature = "prometheus")]
+impl PrometheusSink {
+    pub fn new(registry: &prometheus::Registry) -> Result<Self, prometheus::Error> {
+        use prometheus::{HistogramOpts, Opts};
+        let requests = prometheus::IntCounterVec::new(
+            Opts::new(
+                "mainchain_rpc_requests_total",
+                "Requests sent to the mainchain node",
+            ),
+            &["method", "attempt"],
+        )?;
+        let errors = prometheus::IntCounterVec::new(
+            Opts::new(
+                "mainchain_rpc_errors_total",
+                "Failed requests to the mainchain node",
+            ),
+            &["method", "attempt", "class"],
+        )?;
+        let in_flight = prometheus::IntGaugeVec::new(
+            Opts::new(
+                "mainchain_rpc_in_flight_requests",
+                "Requests to the mainchain node awaiting a response",
+            ),
+            &["method"],
+        )?;
+        let latency = prometheus::HistogramVec::new(
+            HistogramOpts::new(
+                "mainchain_rpc_request_duration_seconds",
+                "Time taken by requests to the mainchain node",
+            )
+            .buckets(LATENCY_BUCKETS.to_vec()),
+            &["method"],
+        )?;
+        let concurrency_limit = prometheus::IntGauge::new(
+            "mainchain_rpc_concurrency_limit",
+            "Requests let through to the mainchain node at once",
+        )?;
+        registry.register(Box::new(requests.clone()))?;
+        registry.register(Box::new(errors.clone()))?;
+        registry.register(Box::new(in_flight.clone()))?;
+        registry.register(Box::new(latency.clone()))?;
+        registry.register(Box::new(concurrency_limit.clone()))?;
+        Ok(PrometheusSink {
+            requests,
+            errors,
+            in_flight,
+            latency,
+            concurrency_limit,
+        })
+    }
+}
+
+#[cfg(feature = "prometheus")]
+impl MetricsSink for PrometheusSink {
+    fn request_started(&self, method: &str, attempt: u32) {
+        self.requests
+            .with_label_values(&[method, &attempt.to_string()])
+            .inc();
+        self.in_flight.with_label_values(&[method]).inc();
+    }
+
+    fn request_finished(&self, method: &str, attempt: u32, outcome: Outcome, latency: Duration) {
+        self.in_flight.with_label_values(&[method]).dec();
+        if !outcome.is_success() {
+            self.errors
+                .with_label_values(&[method, &attempt.to_string(), outcome.class()])
+                .inc();
+        }
+        self.latency
+            .with_label_values(&[method])
+            .observe(latency.as_secs_f64());
+    }
+
+    fn concurrency_limit_changed(&self, limit: usize) {
+        self.concurrency_limit.set(limit as i64);
+    }
+}
+
+/// Reports the request as cancelled if it is dropped before `finish`.
+struct InFlight<'a> {
+    sink: &'a dyn MetricsSink,
+    method: &'a str,
+    attempt: u32,
+    started: Instant,
+    finished: bool,
+}
+
+impl<'a> InFlight<'a> {
+    fn start(sink: &'a dyn MetricsSink, method: &'a str) -> Self {
+        let attempt = crate::reconnect::attempt();
+        sink.request_started(method, attempt);
+        InFlight {
+            sink,
+            method,
+            attempt,
+            started: Instant::now(),
+            finished: false,
+        }
+    }
+
+    fn finish<T>(mut self, result: &Result<T, jsonrpsee::core::Error>) {
+        self.finished = true;
+        self.sink.request_finished(
+            self.method,
+            self.attempt,
+            Outcome::from_result(result),
+            self.started.elapsed(),
+        );
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.sink.request_finished(
+                self.method,
+                self.attempt,
+                Outcome::Cancelled,
+                self.started.elapsed(),
+            );
+        }
+    }
+}
+
+/// Bytes of JSON sent and received for a single method. Only params and
+/// results are counted, not the JSON-RPC envelope or HTTP headers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ByteStats {
+    pub requests: u64,
+    pub request_bytes: u64,
+    /// Requests that got a successful response.
+    pub responses: u64,
+    pub response_bytes: u64,
+}
+
+impl ByteStats {
+    pub fn avg_request_bytes(&self) -> Option<u64> {
+        self.request_bytes.checked_div(self.requests)
+    }
+
+    pub fn avg_response_bytes(&self) -> Option<u64> {
+        self.response_bytes.checked_div(self.responses)
+    }
+}
+
+/// Sizes used by [`MeteredClient::estimate_sync_cost`] for methods that
+/// have not been called yet: the params of `getblockhash`, its result, and
+/// the params of `getblock` at verbosity 0.
+const DEFAULT_GETBLOCKHASH_REQUEST_BYTES: u64 = 9;
+const DEFAULT_GETBLOCKHASH_RESPONSE_BYTES: u64 = 66;
+const DEFAULT_GETBLOCK_REQUEST_BYTES: u64 = 70;
+
+/// Client wrapper that reports every request, notification and batch to a
+/// [`MetricsSink`], and counts the bytes of every request and notification.
+/// Batches are not counted in [`MeteredClient::byte_stats`].
+#[derive(Clone)]
+pub struct MeteredClient<C, S> {
+    inner: C,
+    sink: S,
+    bytes: Arc<Mutex<HashMap<String, ByteStats>>>,
+}
+
+impl<C, S> MeteredClient<C, S> {
+    pub fn new(inner: C, sink: S) -> Self {
+        MeteredClient {
+            inner,
+            sink,
+            bytes: Arc::default(),
+        }
+    }
+
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+
+    pub fn sink(&self) -> &S {
+        &self.sink
+    }
+
+    /// Cumulative byte counts of all methods. Clones of this client share
+    /// their counts.
+    pub fn byte_stats(&self) -> HashMap<String, ByteStats> {
+        self.bytes.lock().unwrap().clone()
+    }
+
+    /// Projects the bytes needed to fetch `n_blocks` blocks of
+    /// `avg_block_size` bytes with `getblockhash` and `getblock` at
+    /// verbosity 0. The sizes of everything but the block data are the
+    /// averages recorded so far.
+    pub fn estimate_sync_cost(&self, n_blocks: u64, avg_block_size: u64) -> u64 {
+        let bytes = self.bytes.lock().unwrap();
+        let stats = |method: &str| bytes.get(method).copied().unwrap_or_default();
+        let getblockhash = stats("getblockhash");
+        let getblock = stats("getblock");
+        let per_block = getblockhash
+            .avg_request_bytes()
+            .unwrap_or(DEFAULT_GETBLOCKHASH_REQUEST_BYTES)
+            + getblockhash
+                .avg_response_bytes()
+                .unwrap_or(DEFAULT_GETBLOCKHASH_RESPONSE_BYTES)
+            + getblock
+                .avg_request_bytes()
+                .unwrap_or(DEFAULT_GETBLOCK_REQUEST_BYTES)
+            // Hex encoded, in quotes
+            + 2 * avg_block_size
+            + 2;
+        n_blocks * per_block
+    }
+
+    fn record_request(&self, method: &str, params: &Option<Box<RawValue>>) {
+        let mut bytes = self.bytes.lock().unwrap();
+        let stats = bytes.entry(method.to_owned()).or_default();
+        stats.requests += 1;
+        stats.request_bytes += params.as_ref().map_or(0, |params| params.get().len()) as u64;
+    }
+
+    fn record_response(&self, method: &str, response: &RawValue) {
+        let mut bytes = self.bytes.lock().unwrap();
+        let stats = bytes.entry(method.to_owned()).or_default();
+        stats.responses += 1;
+        stats.response_bytes += response.get().len() as u64;
+    }
+}
+
+impl<C, S> MeteredClient<C, S>
+where
+    C: ClientT + Send + Sync,
+{
+    async fn notification_counted<Params>(
+        &self,
+        method: &str,
+        params: Params,
+    ) -> Result<(), jsonrpsee::core::Error>
+    where
+        Params: ToRpcParams + Send,
+    {
+        let params = params.to_rpc_params()?;
+        self.record_request(method, &params);
+        self.inner.notification(method, RawParams(params)).await
+    }
+
+    async fn request_counted<R, Params>(
+        &self,
+        method: &str,
+        params: Params,
+    ) -> Result<R, jsonrpsee::core::Error>
+    where
+        R: DeserializeOwned,
+        Params: ToRpcParams + Send,
+    {
+        let params = params.to_rpc_params()?;
+        self.record_request(method, &params);
+        let response: Box<RawValue> = self.inner.request(method, RawParams(params)).await?;
+        self.record_response(method, &response);
+        Ok(serde_json::from_str(response.get())?)
+    }
+}
+
+#[async_trait]
+impl<C, S> ClientT for MeteredClient<C, S>
+where
+    C: ClientT + Send + Sync,
+    S: MetricsSink,
+{
+    async fn notification<Params>(
+        &self,
+        method: &str,
+        params: Params,
+    ) -> Result<(), jsonrpsee::core::Error>
+    where
+        Params: ToRpcParams + Send,
+    {
+        let in_flight = InFlight::start(&self.sink, method);
+        let result = self.notification_counted(method, params).await;
+        in_flight.finish(&result);
+        result
+    }
+
+    async fn request<R, Params>(
+        &self,
+        method: &str,
+        params: Params,
+    ) -> Result<R, jsonrpsee::core::Error>
+    where
+        R: DeserializeOwned,
+        Params: ToRpcParams + Send,
+    {
+        let in_flight = InFlight::start(&self.sink, method);
+        let result = self.request_counted(method, params).await;
+        in_flight.finish(&result);
+        result
+    }
+
+    async fn batch_request<'a, R>(
+        &self,
+        batch: BatchRequestBuilder<'a>,
+    ) -> Result<BatchResponse<'a, R>, jsonrpsee::core::Error>
+    where
+        R: DeserializeOwned + fmt::Debug + 'a,
+    {
+        let in_flight = InFlight::start(&self.sink, BATCH_METHOD);
+        let result = self.inner.batch_request(batch).await;
+        in_flight.finish(&result);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{block_on, rpc_error, MockClient};
+    use crate::reconnect::ReconnectingClient;
+    use jsonrpsee::rpc_params;
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Answers `getblockcount`, fails `getblockhash` with an RPC error and
+    /// everything else with a transport error.
+    fn client<S: MetricsSink>(sink: S) -> MeteredClient<MockClient, S> {
+        let inner = MockClient::new(|method, _| match method {
+            "getblockcount" => Ok(json!(100)),
+            "getblockhash" => Err(rpc_error(-8, "Block height out of range")),
+            _ => Err(jsonrpsee::core::Error::Transport(
+                std::io::Error::from(std::io::ErrorKind::ConnectionRefused).into(),
+            )),
+        });
+        MeteredClient::new(inner, sink)
+    }
+
+    /// Sends one request of each outcome, and the successful one twice.
+    fn workload<S: MetricsSink>(client: &MeteredClient<MockClient, S>) {
+        block_on(async {
+            for _ in 0..2 {
+                let _: u64 = client
+                    .request("getblockcount", rpc_params![])
+                    .await
+                    .unwrap();
+            }
+            let result: Result<bitcoin::BlockHash, _> =
+                client.request("getblockhash", rpc_params![1000]).await;
+            assert!(result.is_err());
+            let result: Result<u64, _> = client.request("getconnectioncount", rpc_params![]).await;
+            assert!(result.is_err());
+        })
+    }
+
+    #[test]
+    fn counts_requests_by_outcome() {
+        let client = client(InMemorySink::new());
+        workload(&client);
+        let snapshot = client.sink().snapshot();
+
+        let success = &snapshot["getblockcount"];
+        assert_eq!(success.requests, 2);
+        assert!(success.errors.is_empty());
+        assert_eq!(success.in_flight, 0);
+        assert_eq!(success.latency_buckets.iter().sum::<u64>(), 2);
+
+        let rpc = &snapshot["getblockhash"];
+        assert_eq!(rpc.requests, 1);
+        assert_eq!(rpc.errors, BTreeMap::from([("rpc_general", 1)]));
+
+        let transport = &snapshot["getconnectioncount"];
+        assert_eq!(transport.requests, 1);
+        assert_eq!(transport.errors, BTreeMap::from([("transport", 1)]));
+        assert_eq!(transport.retries, 0);
+    }
+
+    #[test]
+    fn dropped_request_is_cancelled() {
+        let sink = InMemorySink::new();
+        drop(InFlight::start(&sink, "getblockcount"));
+        let stats = &sink.snapshot()["getblockcount"];
+        assert_eq!(stats.in_flight, 0);
+        assert_eq!(stats.errors, BTreeMap::from([("cancelled", 1)]));
+    }
+
+    #[test]
+    fn retries_are_separate_attempts() {
+        let sink = Arc::new(InMemorySink::new());
+        let connections = Arc::new(AtomicUsize::new(0));
+        let client = block_on(ReconnectingClient::new({
+            let sink = sink.clone();
+            move || {
+                let lost = connections.fetch_add(1, Ordering::SeqCst) == 0;
+                let inner = MockClient::new(move |_, _| {
+                    if lost {
+                        Err(jsonrpsee::core::Error::RestartNeeded(
+                            "connection closed".to_owned(),
+                        ))
+                    } else {
+                        Ok(json!(100))
+                    }
+                });
+                let client = MeteredClient::new(inner, sink.clone());
+                Box::pin(async move { Ok(client) })
+            }
+        }))
+        .unwrap();
+        let count: u64 = block_on(client.request("getblockcount", rpc_params![])).unwrap();
+        assert_eq!(count, 100);
+
+        let stats = &sink.snapshot()["getblockcount"];
+        assert_eq!(stats.requests, 2);
+        assert_eq!(stats.retries, 1);
+        assert_eq!(stats.errors, BTreeMap::from([("transport", 1)]));
+    }
+
+    #[cfg(feature = "prometheus")]
+    #[test]
+    fn prometheus_exposition() {
+        use prometheus::Encoder as _;
+        let registry = prometheus::Registry::new();
+        let client = client(PrometheusSink::new(&registry).unwrap());
+        workload(&client);
+        // Latencies differ between runs, so only the counters are compared
+        let families: Vec<_> = registry
+            .gather()
+            .into_iter()
+            .filter(|family| family.get_name().ends_with("_total"))
+            .collect();
+        let mut text = vec![];
+        prometheus::TextEncoder::new()
+            .encode(&families, &mut text)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(text).unwrap(),
+            "\
+# HELP mainchain_rpc_errors_total Failed requests to the mainchain node
+# TYPE mainchain_rpc_errors_total counter
+mainchain_rpc_errors_total{attempt=\"1\",class=\"rpc_general\",method=\"getblockhash\"} 1
+mainchain_rpc_errors_total{attempt=\"1\",class=\"transport\",method=\"getconnectioncount\"} 1
+# HELP mainchain_rpc_requests_total Requests sent to the mainchain node
+# TYPE mainchain_rpc_requests_total counter
+mainchain_rpc_requests_total{attempt=\"1\",method=\"getblockcount\"} 2
+mainchain_rpc_requests_total{attempt=\"1\",method=\"getblockhash\"} 1
+mainchain_rpc_requests_total{attempt=\"1\",method=\"getconnectioncount\"} 1
+"
+        );
+    }
+}
@@ -0,0 +1,685 @@
+use crate::client::BlockTemplate;
+#[cfg(feature = "client")]
+use crate::client::{BlockTemplateRequest, ChainClient, ConsensusEncoded, TemplateRequestMode};
+use crate::Error;
+#[cfg(feature = "client")]
+use jsonrpsee::core::client::ClientT;
+use std::fmt;
+use std::str::FromStr;
+#[cfg(feature = "client")]
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "client")]
+const RPC_DESERIALIZATION_ERROR: i32 = -22;
+#[cfg(feature = "client")]
+const RPC_VERIFY_ERROR: i32 = -25;
+
+/// Maximum length of a coinbase scriptSig.
+pub const MAX_COINBASE_SCRIPT_SIG_LEN: usize = 100;
+
+/// Start of the witness commitment output script, see BIP141.
+const WITNESS_COMMITMENT_PREFIX: [u8; 6] = [0x6a, 0x24, 0xaa, 0x21, 0xa9, 0xed];
+
+/// Prefix of the signet solution pushed after the witness commitment, see
+/// BIP325.
+pub const SIGNET_HEADER: [u8; 4] = [0xec, 0xc7, 0xda, 0xa2];
+
+/// Witness reserved value Bitcoin Core uses for `default_witness_commitment`.
+pub const DEFAULT_WITNESS_RESERVED_VALUE: [u8; 32] = [0; 32];
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum WitnessCommitmentError {
+    #[error("template has no default witness commitment")]
+    Missing,
+    #[error("witness commitment script `{script}` is malformed")]
+    Malformed { script: bitcoin::ScriptBuf },
+    #[error("witness commitment {provided} does not match the template, expected {expected}")]
+    Mismatch {
+        expected: bitcoin::hash_types::WitnessCommitment,
+        provided: bitcoin::hash_types::WitnessCommitment,
+    },
+    #[error("`coinbasetxn` of the template is invalid: {reason}")]
+    InvalidCoinbaseTxn { reason: String },
+    #[error("witness commitment {provided} in `coinbasetxn` does not match the template, expected {expected}")]
+    CoinbaseTxnMismatch {
+        expected: bitcoin::hash_types::WitnessCommitment,
+        provided: bitcoin::hash_types::WitnessCommitment,
+    },
+}
+
+/// The commitment in a witness commitment output script, see BIP141.
+fn parse_witness_commitment(
+    script: &bitcoin::Script,
+) -> Option<bitcoin::hash_types::WitnessCommitment> {
+    use bitcoin::hashes::Hash as _;
+    let bytes = script.as_bytes();
+    if bytes.len() < 38 || !bytes.starts_with(&WITNESS_COMMITMENT_PREFIX) {
+        return None;
+    }
+    Some(bitcoin::hash_types::WitnessCommitment::from_slice(&bytes[6..38]).expect("32 bytes"))
+}
+
+/// A way in which the template may be modified, from the `mutable` field.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum TemplateMutability {
+    Time,
+    Transactions,
+    PrevBlock,
+    /// `coinbase`, or a restricted coinbase mutation such as
+    /// `coinbase/append`, in which case the part after the `/` is included.
+    Coinbase(Option<String>),
+    Other(String),
+}
+
+impl From<&str> for TemplateMutability {
+    fn from(s: &str) -> Self {
+        match s {
+            "time" => TemplateMutability::Time,
+            "transactions" => TemplateMutability::Transactions,
+            "prevblock" => TemplateMutability::PrevBlock,
+            "coinbase" => TemplateMutability::Coinbase(None),
+            _ => match s.strip_prefix("coinbase/") {
+                Some(restriction) => TemplateMutability::Coinbase(Some(restriction.to_owned())),
+                None => TemplateMutability::Other(s.to_owned()),
+            },
+        }
+    }
+}
+
+impl FromStr for TemplateMutability {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(s.into())
+    }
+}
+
+impl fmt::Display for TemplateMutability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TemplateMutability::Time => f.write_str("time"),
+            TemplateMutability::Transactions => f.write_str("transactions"),
+            TemplateMutability::PrevBlock => f.write_str("prevblock"),
+            TemplateMutability::Coinbase(None) => f.write_str("coinbase"),
+            TemplateMutability::Coinbase(Some(restriction)) => {
+                write!(f, "coinbase/{restriction}")
+            }
+            TemplateMutability::Other(other) => f.write_str(other),
+        }
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for TemplateMutability {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        Ok(s.as_str().into())
+    }
+}
+
+impl serde::Serialize for TemplateMutability {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Deployment {
+    Csv,
+    Segwit,
+    Taproot,
+    Other(String),
+}
+
+impl From<&str> for Deployment {
+    fn from(s: &str) -> Self {
+        match s {
+            "csv" => Deployment::Csv,
+            "segwit" => Deployment::Segwit,
+            "taproot" => Deployment::Taproot,
+            _ => Deployment::Other(s.to_owned()),
+        }
+    }
+}
+
+impl FromStr for Deployment {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(s.into())
+    }
+}
+
+impl fmt::Display for Deployment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Deployment::Csv => f.write_str("csv"),
+            Deployment::Segwit => f.write_str("segwit"),
+            Deployment::Taproot => f.write_str("taproot"),
+            Deployment::Other(other) => f.write_str(other),
+        }
+    }
+}
+
+/// An entry of the template `rules` field.
+///
+/// Rules prefixed with `!` are required: a miner that does not understand
+/// the rule must not use the template, as blocks built from it would be
+/// invalid.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TemplateRule {
+    pub deployment: Deployment,
+    pub required: bool,
+}
+
+impl fmt::Display for TemplateRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.required {
+            f.write_str("!")?;
+        }
+        self.deployment.fmt(f)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for TemplateRule {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        let rule = match s.strip_prefix('!') {
+            Some(deployment) => TemplateRule {
+                deployment: deployment.into(),
+                required: true,
+            },
+            None => TemplateRule {
+                deployment: s.as_str().into(),
+                required: false,
+            },
+        };
+        Ok(rule)
+    }
+}
+
+impl serde::Serialize for TemplateRule {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+impl BlockTemplate {
+    /// Returns the minimum and maximum nonce, parsed big-endian per BIP22.
+    pub fn nonce_range(&self) -> (u32, u32) {
+        let [a, b, c, d, e, f, g, h] = self.nonce_range;
+        (
+            u32::from_be_bytes([a, b, c, d]),
+            u32::from_be_bytes([e, f, g, h]),
+        )
+    }
+
+    /// Returns the `coinbaseaux` values concatenated, to be included in the
+    /// coinbase scriptSig.
+    pub fn coinbase_flags(&self) -> Result<Vec<u8>, Error> {
+        let flags: Vec<u8> = self.coinbase_aux.values().flatten().copied().collect();
+        if flags.len() > MAX_COINBASE_SCRIPT_SIG_LEN {
+            return Err(Error::CoinbaseFlagsTooLong { len: flags.len() });
+        }
+        Ok(flags)
+    }
+
+    pub fn is_mutable(&self, field: &TemplateMutability) -> bool {
+        self.mutable.contains(field)
+    }
+
+    /// Returns the block version to mine with, signalling for each of the
+    /// `supported` deployments on top of the template version. Bits in
+    /// `vbrequired` are always set. Fails if a supported deployment is not
+    /// available for signalling in this template.
+    pub fn signalling_version(&self, supported: &[&str]) -> Result<bitcoin::block::Version, Error> {
+        let mut version = self.version as u32 | self.version_bits_required;
+        for deployment in supported {
+            let bit = self
+                .version_bits_available
+                .get(*deployment)
+                .ok_or_else(|| Error::UnknownDeployment {
+                    name: (*deployment).to_owned(),
+                })?;
+            version |= 1 << bit;
+        }
+        Ok(bitcoin::block::Version::from_consensus(version as i32))
+    }
+
+    /// The challenge blocks must satisfy, if the node is on signet.
+    pub fn signet_challenge_script(&self) -> Option<&bitcoin::Script> {
+        self.signet_challenge.as_deref()
+    }
+
+    /// Rules that must be understood in order to use this template.
+    pub fn required_rules(&self) -> impl Iterator<Item = &Deployment> {
+        self.rules
+            .iter()
+            .filter(|rule| rule.required)
+            .map(|rule| &rule.deployment)
+    }
+
+    /// Merkle root of the wtxids of the transactions, with the coinbase
+    /// wtxid as all zeros, see BIP141.
+    pub fn witness_root(&self) -> bitcoin::hash_types::WitnessMerkleNode {
+        use bitcoin::hashes::Hash as _;
+        let coinbase = bitcoin::Wtxid::all_zeros();
+        let wtxids = std::iter::once(coinbase)
+            .chain(self.transactions.iter().map(|tx| tx.hash))
+            .map(|wtxid| wtxid.to_raw_hash());
+        bitcoin::merkle_tree::calculate_root(wtxids)
+            .expect("there is always the coinbase")
+            .into()
+    }
+
+    /// Computes the witness commitment of the transactions, for a coinbase
+    /// with `witness_reserved_value` as its witness.
+    pub fn compute_witness_commitment(
+        &self,
+        witness_reserved_value: [u8; 32],
+    ) -> bitcoin::hash_types::WitnessCommitment {
+        bitcoin::Block::compute_witness_commitment(&self.witness_root(), &witness_reserved_value)
+    }
+
+    /// Checks `default_witness_commitment` against the transactions, so that
+    /// a block built from a bad template is not mined. If the template has a
+    /// `coinbasetxn` with a witness commitment, that is checked too, with
+    /// the witness reserved value in its witness.
+    pub fn verify_witness_commitment(&self) -> Result<(), WitnessCommitmentError> {
+        let script = self
+            .default_witness_commitment
+            .as_ref()
+            .ok_or(WitnessCommitmentError::Missing)?;
+        let provided =
+            parse_witness_commitment(script).ok_or_else(|| WitnessCommitmentError::Malformed {
+                script: script.clone(),
+            })?;
+        let expected = self.compute_witness_commitment(DEFAULT_WITNESS_RESERVED_VALUE);
+        if provided != expected {
+            return Err(WitnessCommitmentError::Mismatch { expected, provided });
+        }
+        if let Some(coinbase_txn) = self.extra.get("coinbasetxn") {
+            self.verify_coinbase_txn_commitment(coinbase_txn)?;
+        }
+        Ok(())
+    }
+
+    fn verify_coinbase_txn_commitment(
+        &self,
+        coinbase_txn: &serde_json::Value,
+    ) -> Result<(), WitnessCommitmentError> {
+        let invalid = |reason: &str| WitnessCommitmentError::InvalidCoinbaseTxn {
+            reason: reason.to_owned(),
+        };
+        let data = coinbase_txn["data"]
+            .as_str()
+            .ok_or_else(|| invalid("missing `data`"))?;
+        let bytes = hex::decode(data).map_err(|_| invalid("`data` is not hex"))?;
+        let coinbase: bitcoin::Transaction = bitcoin::consensus::deserialize(&bytes)
+            .map_err(|_| invalid("`data` is not a transaction"))?;
+        let Some(provided) = coinbase
+            .output
+            .iter()
+            .rev()
+            .find_map(|output| parse_witness_commitment(&output.script_pubkey))
+        else {
+            return Ok(());
+        };
+        // Nodes may leave the witness out, in which case it is up to the
+        // miner, who should use the default
+        let witness_reserved_value = match coinbase.input.first().map(|input| &input.witness) {
+            Some(witness) if !witness.is_empty() => {
+                let mut witness = witness.iter();
+                match (witness.next(), witness.next()) {
+                    (Some(value), None) => <[u8; 32]>::try_from(value)
+                        .map_err(|_| invalid("witness reserved value is not 32 bytes"))?,
+                    _ => return Err(invalid("coinbase witness has more than one element")),
+                }
+            }
+            _ => DEFAULT_WITNESS_RESERVED_VALUE,
+        };
+        let expected = self.compute_witness_commitment(witness_reserved_value);
+        if provided != expected {
+            return Err(WitnessCommitmentError::CoinbaseTxnMismatch { expected, provided });
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProposalResult {
+    Valid,
+    /// Reason the block was rejected, e.g. `bad-txnmrklroot`.
+    Rejected(String),
+}
+
+#[cfg(feature = "client")]
+/// Asks the node to validate `block` as if it was mined, without submitting
+/// it. Proof of work is not checked.
+pub async fn propose_block<C>(client: &C, block: &bitcoin::Block) -> Result<ProposalResult, Error>
+where
+    C: ClientT + Sync,
+{
+    let template_request = BlockTemplateRequest {
+        mode: Some(TemplateRequestMode::Proposal),
+        data: Some(ConsensusEncoded(block.clone())),
+        ..BlockTemplateRequest::default()
+    };
+    let result = match client.getblocktemplateproposal(&template_request).await? {
+        None => ProposalResult::Valid,
+        Some(reason) => ProposalResult::Rejected(reason),
+    };
+    Ok(result)
+}
+
+#[cfg(feature = "client")]
+/// Submits a consensus encoded block header, so the node can relay it
+/// before the full block.
+///
+/// Returns [`Error::MissingParentHeader`] if the node does not know the
+/// parent of the header, and [`Error::HeaderRejected`] if the header is
+/// invalid.
+pub async fn submit_header<C>(client: &C, header_hex: &str) -> Result<(), Error>
+where
+    C: ClientT + Sync,
+{
+    client
+        .submitheader(header_hex)
+        .await
+        .map_err(|err| match err {
+            jsonrpsee::core::Error::Call(err)
+                if matches!(err.code(), RPC_VERIFY_ERROR | RPC_DESERIALIZATION_ERROR) =>
+            {
+                let prev_block_hash = err
+                    .message()
+                    .strip_prefix("Must submit previous header (")
+                    .and_then(|rest| rest.strip_suffix(") first"))
+                    .and_then(|hash| hash.parse().ok());
+                match prev_block_hash {
+                    Some(prev_block_hash) => Error::MissingParentHeader { prev_block_hash },
+                    None => Error::HeaderRejected {
+                        reason: err.message().to_owned(),
+                    },
+                }
+            }
+            err => err.into(),
+        })
+}
+
+#[cfg(feature = "client")]
+/// Submits the header of `block` and then the block itself, and returns
+/// the time between the node accepting the header and the block.
+///
+/// Returns [`Error::BlockRejected`] if the header was accepted but the block
+/// was not.
+pub async fn submit_block_fast<C>(client: &C, block: &bitcoin::Block) -> Result<Duration, Error>
+where
+    C: ClientT + Sync,
+{
+    let header_hex = bitcoin::consensus::encode::serialize_hex(&block.header);
+    submit_header(client, &header_hex).await?;
+    let header_accepted = Instant::now();
+    let block_hex = bitcoin::consensus::encode::serialize_hex(block);
+    if let Some(reason) = client.submitblock(&block_hex).await? {
+        return Err(Error::BlockRejected {
+            block_hash: block.block_hash(),
+            reason,
+        });
+    }
+    let interval = header_accepted.elapsed();
+    #[cfg(feature = "tracing")]
+    jsonrpsee::tracing::debug!(
+        block_hash = %block.block_hash(),
+        ?interval,
+        "block accepted after its header"
+    );
+    Ok(interval)
+}
+
+/// Signs signet blocks, e.g. with a key held by the node wallet or an
+/// external signer.
+pub trait SignetSolver {
+    /// Returns the BIP325 solution for `block`, which does not have one yet:
+    /// the serialized scriptSig followed by the serialized witness that
+    /// satisfy the signet challenge.
+    fn sign_block(&self, block: &bitcoin::Block) -> Result<Vec<u8>, Error>;
+}
+
+/// Adds the solution from `solver` to the witness commitment of `block`, as
+/// required for blocks on signet, and updates the merkle root.
+pub fn add_signet_solution<S>(block: &mut bitcoin::Block, solver: &S) -> Result<(), Error>
+where
+    S: SignetSolver + ?Sized,
+{
+    let commitment_index = block
+        .txdata
+        .first()
+        .and_then(|coinbase| {
+            coinbase.output.iter().rposition(|output| {
+                output
+                    .script_pubkey
+                    .as_bytes()
+                    .starts_with(&WITNESS_COMMITMENT_PREFIX)
+            })
+        })
+        .ok_or(Error::MissingWitnessCommitment)?;
+    let solution = solver.sign_block(block)?;
+    let push = bitcoin::script::PushBytesBuf::try_from([&SIGNET_HEADER[..], &solution].concat())
+        .map_err(|_| Error::InvalidParam {
+            name: "solution",
+            reason: format!("{} bytes is too large to push", solution.len()),
+        })?;
+    let output = &mut block.txdata[0].output[commitment_index];
+    output.script_pubkey = bitcoin::script::Builder::from(output.script_pubkey.to_bytes())
+        .push_slice(push)
+        .into_script();
+    block.header.merkle_root = block.compute_merkle_root().expect("block has a coinbase");
+    Ok(())
+}
+
+#[cfg(feature = "client")]
+/// Fails if the node is on signet with a different challenge than the one
+/// in `template`, as blocks built from the template would then be rejected.
+pub async fn check_signet_challenge<C>(client: &C, template: &BlockTemplate) -> Result<(), Error>
+where
+    C: ClientT + Sync,
+{
+    let node = client.getblockchaininfo().await?.value.signet_challenge;
+    if node.is_some() && node != template.signet_challenge {
+        return Err(Error::SignetChallengeMismatch {
+            template: template.signet_challenge.clone(),
+            node,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hashes::Hash as _;
+
+    /// Regtest block with a coinbase and a transaction spending two
+    /// P2SH-P2WPKH outputs, from the BIP152 test vectors.
+    const SEGWIT_BLOCK: &str = "000000206c750a364035aefd5f81508a08769975116d9195312ee4520dceac39e1fdc62c4dc67473b8e354358c1e610afeaff7410858bd45df43e2940f8a62bd3d5e3ac943c2975cffff7f200000000002020000000001010000000000000000000000000000000000000000000000000000000000000000ffffffff04016b0101ffffffff020006062a0100000001510000000000000000266a24aa21a9ed4a3d9f3343dafcc0d6f6d4310f2ee5ce273ed34edca6c75db3a73e7f368734200120000000000000000000000000000000000000000000000000000000000000000000000000020000000001021fc20ba2bd745507b8e00679e3b362558f9457db374ca28ffa5243f4c23a4d5f00000000171600147c9dea14ffbcaec4b575e03f05ceb7a81cd3fcbffdffffff915d689be87b43337f42e26033df59807b768223368f189a023d0242d837768900000000171600147c9dea14ffbcaec4b575e03f05ceb7a81cd3fcbffdffffff0200cdf5050000000017a9146803c72d9154a6a20f404bed6d3dcee07986235a8700e1f5050000000017a9144e6a4c7cb5b5562904843bdf816342f4db9f5797870247304402205e9bf6e70eb0e4b495bf483fd8e6e02da64900f290ef8aaa64bb32600d973c450220670896f5d0e5f33473e5f399ab680cc1d25c2d2afd15abd722f04978f28be887012103e4e4d9312b2261af508b367d8ba9be4f01b61d6d6e78bec499845b4f410bcf2702473044022045ac80596a6ac9c8c572f94708709adaf106677221122e08daf8b9741a04f66a022003ccd52a3b78f8fd08058fc04fc0cffa5f4c196c84eae9e37e2a85babe731b57012103e4e4d9312b2261af508b367d8ba9be4f01b61d6d6e78bec499845b4f410bcf276a000000";
+
+    fn segwit_block() -> bitcoin::Block {
+        bitcoin::consensus::deserialize(&hex::decode(SEGWIT_BLOCK).unwrap()).unwrap()
+    }
+
+    /// Witness commitment output of the coinbase of `block`.
+    fn commitment_script(block: &bitcoin::Block) -> bitcoin::ScriptBuf {
+        block.txdata[0]
+            .output
+            .iter()
+            .find(|output| parse_witness_commitment(&output.script_pubkey).is_some())
+            .unwrap()
+            .script_pubkey
+            .clone()
+    }
+
+    /// The template `block` was built from, as the node would return it.
+    fn template_of(block: &bitcoin::Block) -> BlockTemplate {
+        let transactions: Vec<_> = block.txdata[1..]
+            .iter()
+            .map(|tx| {
+                serde_json::json!({
+                    "data": bitcoin::consensus::encode::serialize_hex(tx),
+                    "txid": tx.txid(),
+                    "hash": tx.wtxid(),
+                    "depends": [],
+                    "fee": 1000,
+                    "sigops": 2,
+                    "weight": tx.weight().to_wu(),
+                })
+            })
+            .collect();
+        serde_json::from_value(serde_json::json!({
+            "capabilities": ["proposal"],
+            "version": 536870912,
+            "rules": ["csv", "!segwit", "taproot"],
+            "vbavailable": {},
+            "vbrequired": 0,
+            "previousblockhash": block.header.prev_blockhash,
+            "transactions": transactions,
+            "coinbaseaux": {},
+            "coinbasevalue": 5000001000u64,
+            "target": "7fffff0000000000000000000000000000000000000000000000000000000000",
+            "mintime": 1700000001,
+            "mutable": ["time", "transactions", "prevblock"],
+            "noncerange": "00000000ffffffff",
+            "sigoplimit": 80000,
+            "sizelimit": 4000000,
+            "weightlimit": 4000000,
+            "curtime": 1700000100,
+            "bits": "207fffff",
+            "height": 107,
+            "default_witness_commitment": commitment_script(block),
+        }))
+        .unwrap()
+    }
+
+    fn mismatch(result: Result<(), WitnessCommitmentError>) -> bool {
+        matches!(result, Err(WitnessCommitmentError::Mismatch { .. }))
+    }
+
+    #[test]
+    fn commitment_of_a_segwit_block() {
+        let block = segwit_block();
+        assert!(block.check_witness_commitment());
+        let template = template_of(&block);
+        assert_eq!(template.witness_root(), block.witness_root().unwrap());
+        assert_eq!(
+            Some(template.compute_witness_commitment(DEFAULT_WITNESS_RESERVED_VALUE)),
+            parse_witness_commitment(&commitment_script(&block))
+        );
+        assert_eq!(template.verify_witness_commitment(), Ok(()));
+    }
+
+    #[test]
+    fn tampered_commitment() {
+        let mut template = template_of(&segwit_block());
+        let mut script = template
+            .default_witness_commitment
+            .take()
+            .unwrap()
+            .into_bytes();
+        assert_eq!(
+            template.verify_witness_commitment(),
+            Err(WitnessCommitmentError::Missing)
+        );
+        script[20] ^= 1;
+        template.default_witness_commitment = Some(script.clone().into());
+        assert!(mismatch(template.verify_witness_commitment()));
+        script.truncate(37);
+        template.default_witness_commitment = Some(script.into());
+        assert!(matches!(
+            template.verify_witness_commitment(),
+            Err(WitnessCommitmentError::Malformed { .. })
+        ));
+    }
+
+    #[test]
+    fn dropped_transaction() {
+        let mut template = template_of(&segwit_block());
+        template.transactions.clear();
+        assert!(mismatch(template.verify_witness_commitment()));
+    }
+
+    #[test]
+    fn coinbasetxn_with_another_reserved_value() {
+        let block = segwit_block();
+        let mut template = template_of(&block);
+        let mut coinbase = block.txdata[0].clone();
+        let coinbase_txn = |coinbase: &bitcoin::Transaction| serde_json::json!({ "data": bitcoin::consensus::encode::serialize_hex(coinbase) });
+        // The block's own coinbase, with the default reserved value
+        template
+            .extra
+            .insert("coinbasetxn".to_owned(), coinbase_txn(&coinbase));
+        assert_eq!(template.verify_witness_commitment(), Ok(()));
+
+        coinbase.input[0].witness = bitcoin::Witness::from_slice(&[[1; 32]]);
+        template
+            .extra
+            .insert("coinbasetxn".to_owned(), coinbase_txn(&coinbase));
+        let expected = template.compute_witness_commitment([1; 32]);
+        assert_eq!(
+            template.verify_witness_commitment(),
+            Err(WitnessCommitmentError::CoinbaseTxnMismatch {
+                expected,
+                provided: parse_witness_commitment(&commitment_script(&block)).unwrap(),
+            })
+        );
+
+        // Committing to the transactions with that reserved value
+        let mut script = WITNESS_COMMITMENT_PREFIX.to_vec();
+        script.extend_from_slice(expected.as_byte_array());
+        for output in &mut coinbase.output {
+            if parse_witness_commitment(&output.script_pubkey).is_some() {
+                output.script_pubkey = script.clone().into();
+            }
+        }
+        template
+            .extra
+            .insert("coinbasetxn".to_owned(), coinbase_txn(&coinbase));
+        assert_eq!(template.verify_witness_commitment(), Ok(()));
+
+        coinbase.input[0].witness = bitcoin::Witness::from_slice(&[[1; 32], [2; 32]]);
+        template
+            .extra
+            .insert("coinbasetxn".to_owned(), coinbase_txn(&coinbase));
+        assert!(matches!(
+            template.verify_witness_commitment(),
+            Err(WitnessCommitmentError::InvalidCoinbaseTxn { .. })
+        ));
+    }
+
+    #[test]
+    fn zero_transactions() {
+        let mut template = template_of(&segwit_block());
+        template.transactions.clear();
+        assert_eq!(
+            template.witness_root(),
+            bitcoin::hash_types::WitnessMerkleNode::all_zeros()
+        );
+        // As Bitcoin Core reports it for an empty template
+        template.default_witness_commitment = Some(
+            bitcoin::ScriptBuf::from_hex(
+                "6a24aa21a9ede2f61c3f71d1defd3fa999dfa36953755c690689799962b48bebd836974e8cf9",
+            )
+            .unwrap(),
+        );
+        assert_eq!(template.verify_witness_commitment(), Ok(()));
+    }
+}
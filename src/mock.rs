@@ -0,0 +1,513 @@
+//! Client answering requests from a closure, for unit tests.
+use jsonrpsee::core::async_trait;
+use jsonrpsee::core::client::{BatchResponse, ClientT};
+use jsonrpsee::core::params::BatchRequestBuilder;
+use jsonrpsee::core::traits::ToRpcParams;
+use jsonrpsee::types::ErrorObject;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+type Handler = dyn Fn(&str, &[Value]) -> Result<Value, jsonrpsee::core::Error> + Send + Sync;
+
+/// Answers every request, including those in batches, with the result of
+/// the handler for its method and params, and records the calls.
+pub(crate) struct MockClient {
+    handler: Box<Handler>,
+    calls: Mutex<Vec<(String, Vec<Value>)>>,
+    /// Number of requests and batches answered, each one round-trip.
+    round_trips: AtomicUsize,
+    /// Maximum number of responses to a batch.
+    batch_limit: Option<usize>,
+    /// Time taken to answer a request or batch.
+    delay: Option<std::time::Duration>,
+}
+
+impl MockClient {
+    pub(crate) fn new<F>(handler: F) -> Self
+    where
+        F: Fn(&str, &[Value]) -> Result<Value, jsonrpsee::core::Error> + Send + Sync + 'static,
+    {
+        MockClient {
+            handler: Box::new(handler),
+            calls: Mutex::new(vec![]),
+            round_trips: AtomicUsize::new(0),
+            batch_limit: None,
+            delay: None,
+        }
+    }
+
+    /// Answers only the first `limit` requests of a batch, like a node that
+    /// drops responses.
+    pub(crate) fn with_batch_limit(mut self, limit: usize) -> Self {
+        self.batch_limit = Some(limit);
+        self
+    }
+
+    /// Takes `delay` to answer, so that concurrent requests overlap.
+    pub(crate) fn with_delay(mut self, delay: std::time::Duration) -> Self {
+        self.delay = Some(delay);
+        self
+    }
+
+    async fn round_trip(&self) {
+        self.round_trips.fetch_add(1, Ordering::SeqCst);
+        if let Some(delay) = self.delay {
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// Methods and params of every call so far, in order.
+    pub(crate) fn calls(&self) -> Vec<(String, Vec<Value>)> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    /// Methods of every call so far, in order.
+    pub(crate) fn methods(&self) -> Vec<String> {
+        self.calls().into_iter().map(|(method, _)| method).collect()
+    }
+
+    /// Number of requests and batches so far. A batch counts once, however
+    /// many calls it has.
+    pub(crate) fn round_trips(&self) -> usize {
+        self.round_trips.load(Ordering::SeqCst)
+    }
+
+    fn call(
+        &self,
+        method: &str,
+        params: Option<&serde_json::value::RawValue>,
+    ) -> Result<Value, jsonrpsee::core::Error> {
+        let params = match params {
+            Some(params) => match serde_json::from_str(params.get())? {
+                Value::Array(params) => params,
+                params => vec![params],
+            },
+            None => vec![],
+        };
+        self.calls
+            .lock()
+            .unwrap()
+            .push((method.to_owned(), params.clone()));
+        (self.handler)(method, &params)
+    }
+
+    /// JSON-RPC response to the JSON-RPC request `request`.
+    fn respond(&self, request: &Value) -> Value {
+        let method = request["method"].as_str().unwrap();
+        let params = request
+            .get("params")
+            .map(|params| serde_json::value::to_raw_value(params).unwrap());
+        let mut response = serde_json::json!({"jsonrpc": "2.0", "id": request["id"]});
+        match self.call(method, params.as_deref()) {
+            Ok(result) => response["result"] = result,
+            Err(jsonrpsee::core::Error::Call(err)) => {
+                response["error"] = serde_json::to_value(err).unwrap()
+            }
+            Err(err) => panic!("{method}: {err}"),
+        }
+        response
+    }
+}
+
+/// Chain of blocks with distinct hashes answering the chain queries of a
+/// [`MockClient`]. Clones share the chain.
+#[derive(Clone, Default)]
+pub(crate) struct MockChain {
+    state: Arc<Mutex<ChainState>>,
+}
+
+#[derive(Default)]
+struct ChainState {
+    /// Block hashes of the main chain by height.
+    blocks: Vec<bitcoin::BlockHash>,
+    /// Number of blocks ever mined, to make hashes distinct.
+    mined: u64,
+    /// Disconnected blocks and their heights.
+    stale: Vec<(bitcoin::BlockHash, usize)>,
+    /// Transactions of blocks other than the coinbase.
+    txs: HashMap<bitcoin::BlockHash, Vec<bitcoin::Txid>>,
+}
+
+impl MockChain {
+    /// A chain with only a genesis block.
+    pub(crate) fn new() -> Self {
+        let chain = MockChain::default();
+        chain.mine(1);
+        chain
+    }
+
+    pub(crate) fn tip(&self) -> bitcoin::BlockHash {
+        *self.state.lock().unwrap().blocks.last().unwrap()
+    }
+
+    pub(crate) fn height(&self) -> usize {
+        self.state.lock().unwrap().blocks.len() - 1
+    }
+
+    /// Appends `n` blocks to the tip.
+    pub(crate) fn mine(&self, n: u64) -> Vec<bitcoin::BlockHash> {
+        (0..n).map(|_| self.mine_with(vec![])).collect()
+    }
+
+    /// Appends a block with the transactions `txids` to the tip.
+    pub(crate) fn mine_with(&self, txids: Vec<bitcoin::Txid>) -> bitcoin::BlockHash {
+        use bitcoin::hashes::Hash as _;
+        let mut state = self.state.lock().unwrap();
+        state.mined += 1;
+        let mut hash = [0; 32];
+        hash[..8].copy_from_slice(&state.mined.to_le_bytes());
+        let hash = bitcoin::BlockHash::from_byte_array(hash);
+        state.blocks.push(hash);
+        state.txs.insert(hash, txids);
+        hash
+    }
+
+    /// Disconnects the last `n` blocks.
+    pub(crate) fn disconnect(&self, n: usize) {
+        let mut state = self.state.lock().unwrap();
+        for _ in 0..n {
+            let height = state.blocks.len() - 1;
+            let hash = state.blocks.pop().unwrap();
+            state.stale.push((hash, height));
+        }
+    }
+
+    /// Disconnects `block_hash` and the blocks after it, or returns `false`
+    /// if it is not in the main chain.
+    pub(crate) fn invalidate(&self, block_hash: bitcoin::BlockHash) -> bool {
+        let n = {
+            let blocks = &self.state.lock().unwrap().blocks;
+            let Some(height) = blocks.iter().position(|block| *block == block_hash) else {
+                return false;
+            };
+            blocks.len() - height
+        };
+        self.disconnect(n);
+        true
+    }
+
+    /// Connects `block_hash` and the blocks disconnected after it again, or
+    /// returns `false` if it is not a stale block extending the tip.
+    pub(crate) fn reconsider(&self, block_hash: bitcoin::BlockHash) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let Some(position) = state
+            .stale
+            .iter()
+            .position(|(block, _)| *block == block_hash)
+        else {
+            return false;
+        };
+        if state.stale[position].1 != state.blocks.len() {
+            return false;
+        }
+        // Blocks are disconnected from the tip down, so the ones after it
+        // come before it
+        let mut start = position;
+        while start > 0 && state.stale[start - 1].1 == state.stale[start].1 + 1 {
+            start -= 1;
+        }
+        let reconnected: Vec<_> = state.stale.drain(start..=position).rev().collect();
+        state
+            .blocks
+            .extend(reconnected.into_iter().map(|(block, _)| block));
+        true
+    }
+
+    /// Main chain block containing `txid`.
+    pub(crate) fn block_of(&self, txid: &bitcoin::Txid) -> Option<bitcoin::BlockHash> {
+        let state = self.state.lock().unwrap();
+        state
+            .blocks
+            .iter()
+            .find(|hash| state.txs[*hash].contains(txid))
+            .copied()
+    }
+
+    /// Answers `getbestblockhash`, `getblockcount`, `getblockhash`,
+    /// `getblockheader`, `getblock` at verbosity 1, `generatetoaddress`,
+    /// `invalidateblock` and `reconsiderblock`, or returns `None` for other
+    /// methods.
+    pub(crate) fn handle(
+        &self,
+        method: &str,
+        params: &[Value],
+    ) -> Option<Result<Value, jsonrpsee::core::Error>> {
+        let result = match method {
+            "getbestblockhash" => serde_json::json!(self.tip()),
+            "getblockcount" => serde_json::json!(self.height()),
+            "getblockhash" => {
+                let height = params[0].as_u64().unwrap() as usize;
+                match self.state.lock().unwrap().blocks.get(height) {
+                    Some(hash) => serde_json::json!(hash),
+                    None => return Some(Err(rpc_error(-8, "Block height out of range"))),
+                }
+            }
+            "getblockheader" | "getblock" => {
+                let hash: bitcoin::BlockHash = serde_json::from_value(params[0].clone()).unwrap();
+                let Some(mut header) = self.header(hash) else {
+                    return Some(Err(rpc_error(-5, "Block not found")));
+                };
+                if method == "getblock" {
+                    let txids = &self.state.lock().unwrap().txs[&hash];
+                    let mut tx = vec![serde_json::json!(hash)];
+                    tx.extend(txids.iter().map(|txid| serde_json::json!(txid)));
+                    header["tx"] = serde_json::json!(tx);
+                    header["nTx"] = serde_json::json!(tx.len());
+                    header["strippedsize"] = serde_json::json!(250);
+                    header["size"] = serde_json::json!(250);
+                    header["weight"] = serde_json::json!(1000);
+                }
+                header
+            }
+            "generatetoaddress" => serde_json::json!(self.mine(params[0].as_u64().unwrap())),
+            "invalidateblock" | "reconsiderblock" => {
+                let hash = serde_json::from_value(params[0].clone()).unwrap();
+                let found = match method {
+                    "invalidateblock" => self.invalidate(hash),
+                    _ => self.reconsider(hash),
+                };
+                if !found {
+                    return Some(Err(rpc_error(-5, "Block not found")));
+                }
+                Value::Null
+            }
+            _ => return None,
+        };
+        Some(Ok(result))
+    }
+
+    /// `getblockheader` response for a main chain or stale block.
+    fn header(&self, hash: bitcoin::BlockHash) -> Option<Value> {
+        let state = self.state.lock().unwrap();
+        if let Some(height) = state.blocks.iter().position(|block| *block == hash) {
+            let parent = height.checked_sub(1).map(|parent| state.blocks[parent]);
+            let mut header = header(hash, height, parent);
+            header["confirmations"] = serde_json::json!(state.blocks.len() - height);
+            if let Some(next) = state.blocks.get(height + 1) {
+                header["nextblockhash"] = serde_json::json!(next);
+            }
+            return Some(header);
+        }
+        let (_, height) = state.stale.iter().find(|(block, _)| *block == hash)?;
+        let mut header = header(hash, *height, None);
+        header["confirmations"] = serde_json::json!(-1);
+        Some(header)
+    }
+}
+
+/// `getblockheader` response for a regtest block.
+pub(crate) fn header(
+    hash: bitcoin::BlockHash,
+    height: usize,
+    previousblockhash: Option<bitcoin::BlockHash>,
+) -> Value {
+    let mut header = serde_json::json!({
+        "hash": hash,
+        "confirmations": 1,
+        "height": height,
+        "version": 0x20000000,
+        "versionHex": "20000000",
+        "merkleroot": "0000000000000000000000000000000000000000000000000000000000000000",
+        "time": 1_700_000_000 + height,
+        "mediantime": 1_700_000_000 + height,
+        "nonce": 0,
+        "bits": "207fffff",
+        "difficulty": 4.656542373906925e-10,
+        "chainwork": "0000000000000000000000000000000000000000000000000000000000000002",
+        "nTx": 1,
+    });
+    if let Some(previousblockhash) = previousblockhash {
+        header["previousblockhash"] = serde_json::json!(previousblockhash);
+    }
+    header
+}
+
+/// `getblockchaininfo` response of a node on `chain`, e.g. `"regtest"`.
+pub(crate) fn blockchain_info(chain: &str, blocks: usize) -> Value {
+    serde_json::json!({
+        "chain": chain,
+        "blocks": blocks,
+        "headers": blocks,
+        "bestblockhash": "0000000000000000000000000000000000000000000000000000000000000000",
+        "difficulty": 4.656542373906925e-10,
+        "time": 1_700_000_000,
+        "mediantime": 1_700_000_000,
+        "verificationprogress": 1,
+        "initialblockdownload": false,
+        "chainwork": "0000000000000000000000000000000000000000000000000000000000000002",
+        "size_on_disk": 293,
+        "pruned": false,
+        "warnings": "",
+    })
+}
+
+/// `getnetworkinfo` response of a node with version number `version`, e.g.
+/// `230000` for 23.0.
+pub(crate) fn network_info(version: u32) -> Value {
+    serde_json::json!({
+        "version": version,
+        "subversion": "/Satoshi:23.0.0/",
+        "protocolversion": 70016,
+        "localservices": "0000000000000409",
+        "localrelay": true,
+        "timeoffset": 0,
+        "networkactive": true,
+        "connections": 0,
+        "networks": [],
+        "relayfee": 0.00001,
+        "incrementalfee": 0.00001,
+        "localaddresses": [],
+        "warnings": "",
+    })
+}
+
+/// `getmempoolentry` response for a transaction without witness.
+pub(crate) fn mempool_entry(txid: bitcoin::Txid) -> Value {
+    serde_json::json!({
+        "vsize": 141,
+        "weight": 564,
+        "time": 1_700_000_000,
+        "height": 100,
+        "descendantcount": 1,
+        "descendantsize": 141,
+        "ancestorcount": 1,
+        "ancestorsize": 141,
+        "wtxid": txid,
+        "fees": {
+            "base": 0.00000141,
+            "modified": 0.00000141,
+            "ancestor": 0.00000141,
+            "descendant": 0.00000141,
+        },
+        "depends": [],
+        "spentby": [],
+        "bip125-replaceable": true,
+        "unbroadcast": false,
+    })
+}
+
+/// An error response of the node.
+pub(crate) fn rpc_error(code: i32, message: &str) -> jsonrpsee::core::Error {
+    jsonrpsee::core::Error::Call(ErrorObject::owned(code, message, None::<()>))
+}
+
+/// Serves `client` over HTTP on its own thread, for code that connects to
+/// a node address rather than taking a client. Returns the address and the
+/// client, to inspect the calls.
+pub(crate) fn serve(client: MockClient) -> (SocketAddr, Arc<MockClient>) {
+    use hyper::service::{make_service_fn, service_fn};
+    use std::convert::Infallible;
+
+    let client = Arc::new(client);
+    let (addr_tx, addr_rx) = std::sync::mpsc::channel();
+    std::thread::spawn({
+        let client = client.clone();
+        move || {
+            block_on(async move {
+                let make_service = make_service_fn(move |_| {
+                    let client = client.clone();
+                    async move {
+                        Ok::<_, Infallible>(service_fn(
+                            move |request: hyper::Request<hyper::Body>| {
+                                let client = client.clone();
+                                async move {
+                                    client.round_trips.fetch_add(1, Ordering::SeqCst);
+                                    let body =
+                                        hyper::body::to_bytes(request.into_body()).await.unwrap();
+                                    let response = match serde_json::from_slice(&body).unwrap() {
+                                        Value::Array(requests) => Value::Array(
+                                            requests
+                                                .iter()
+                                                .map(|request| client.respond(request))
+                                                .collect(),
+                                        ),
+                                        request => client.respond(&request),
+                                    };
+                                    Ok::<_, Infallible>(hyper::Response::new(hyper::Body::from(
+                                        response.to_string(),
+                                    )))
+                                }
+                            },
+                        ))
+                    }
+                });
+                let server = hyper::Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+                addr_tx.send(server.local_addr()).unwrap();
+                server.await.unwrap();
+            })
+        }
+    });
+    (addr_rx.recv().unwrap(), client)
+}
+
+/// Runs `future` on a current-thread runtime.
+pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(future)
+}
+
+#[async_trait]
+impl ClientT for MockClient {
+    async fn notification<Params>(
+        &self,
+        method: &str,
+        params: Params,
+    ) -> Result<(), jsonrpsee::core::Error>
+    where
+        Params: ToRpcParams + Send,
+    {
+        self.round_trip().await;
+        self.call(method, params.to_rpc_params()?.as_deref())?;
+        Ok(())
+    }
+
+    async fn request<R, Params>(
+        &self,
+        method: &str,
+        params: Params,
+    ) -> Result<R, jsonrpsee::core::Error>
+    where
+        R: DeserializeOwned,
+        Params: ToRpcParams + Send,
+    {
+        self.round_trip().await;
+        let result = self.call(method, params.to_rpc_params()?.as_deref())?;
+        Ok(serde_json::from_value(result)?)
+    }
+
+    async fn batch_request<'a, R>(
+        &self,
+        batch: BatchRequestBuilder<'a>,
+    ) -> Result<BatchResponse<'a, R>, jsonrpsee::core::Error>
+    where
+        R: DeserializeOwned + fmt::Debug + 'a,
+    {
+        self.round_trip().await;
+        let mut responses = vec![];
+        let (mut successful, mut failed) = (0, 0);
+        let limit = self.batch_limit.unwrap_or(usize::MAX);
+        for (method, params) in batch.build()?.into_iter().take(limit) {
+            match self.call(method, params.as_deref()) {
+                Ok(result) => {
+                    successful += 1;
+                    responses.push(Ok(serde_json::from_value(result)?));
+                }
+                Err(jsonrpsee::core::Error::Call(err)) => {
+                    failed += 1;
+                    responses.push(Err(err));
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(BatchResponse::new(successful, responses, failed))
+    }
+}
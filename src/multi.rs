@@ -0,0 +1,392 @@
+use crate::cache::RawParams;
+use crate::client::ChainClient;
+use jsonrpsee::core::async_trait;
+use jsonrpsee::core::client::{BatchResponse, ClientT};
+use jsonrpsee::core::params::BatchRequestBuilder;
+use jsonrpsee::core::traits::ToRpcParams;
+use serde::de::DeserializeOwned;
+use serde_json::value::RawValue;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Methods that are sent to every node, succeeding if any node accepts.
+pub const BROADCAST_METHODS: &[&str] = &["sendrawtransaction", "submitblock", "submitheader"];
+
+#[derive(Debug, Clone, Copy)]
+pub struct Policy {
+    /// How often to re-evaluate which node is preferred.
+    pub reevaluate_interval: Duration,
+    /// A node with the same work as the preferred one must be faster by more
+    /// than this to become preferred.
+    pub latency_margin: Duration,
+}
+
+impl Policy {
+    pub fn new(reevaluate_interval: Duration) -> Self {
+        Policy {
+            reevaluate_interval,
+            latency_margin: Duration::from_millis(50),
+        }
+    }
+}
+
+struct Preference {
+    index: usize,
+    evaluated_at: Option<Instant>,
+}
+
+/// Client over several mainchain nodes. Requests go to the node with the
+/// most cumulative work, failing over to the other nodes on transport
+/// errors. [`BROADCAST_METHODS`] are sent to all nodes.
+///
+/// The preferred node only changes if another node has strictly more work,
+/// or the same work and is faster by more than
+/// [`Policy::latency_margin`], so that nodes with competing tips of equal
+/// work don't cause flapping.
+#[derive(Clone)]
+pub struct MultiClient<C> {
+    clients: Vec<C>,
+    policy: Policy,
+    preference: Arc<Mutex<Preference>>,
+}
+
+impl<C> MultiClient<C>
+where
+    C: ClientT + Send + Sync,
+{
+    /// Panics if `clients` is empty.
+    pub fn new(clients: Vec<C>, policy: Policy) -> Self {
+        assert!(
+            !clients.is_empty(),
+            "MultiClient requires at least one client"
+        );
+        MultiClient {
+            clients,
+            policy,
+            preference: Arc::new(Mutex::new(Preference {
+                index: 0,
+                evaluated_at: None,
+            })),
+        }
+    }
+
+    pub fn clients(&self) -> &[C] {
+        &self.clients
+    }
+
+    /// Index of the node that requests are currently sent to first.
+    pub fn preferred(&self) -> usize {
+        self.preference.lock().unwrap().index
+    }
+
+    /// Queries all nodes and updates the preferred node.
+    pub async fn reevaluate(&self) {
+        let mut candidates = Vec::with_capacity(self.clients.len());
+        for client in &self.clients {
+            let start = Instant::now();
+            let chainwork = client
+                .getblockchaininfo()
+                .await
+                .ok()
+                .map(|info| info.chainwork);
+            candidates.push(chainwork.map(|chainwork| (chainwork, start.elapsed())));
+        }
+        let mut preference = self.preference.lock().unwrap();
+        preference.evaluated_at = Some(Instant::now());
+        let best = candidates
+            .iter()
+            .enumerate()
+            .filter_map(|(index, candidate)| Some((index, (*candidate)?)))
+            .max_by(|(_, (work_a, latency_a)), (_, (work_b, latency_b))| {
+                work_a.cmp(work_b).then(latency_b.cmp(latency_a))
+            });
+        let Some((best_index, (best_work, best_latency))) = best else {
+            return;
+        };
+        let switch = match candidates[preference.index] {
+            None => true,
+            Some((work, latency)) => {
+                best_work > work
+                    || (best_work == work && best_latency + self.policy.latency_margin < latency)
+            }
+        };
+        if switch {
+            preference.index = best_index;
+        }
+    }
+
+    /// Client indexes in the order they should be tried.
+    async fn order(&self) -> Vec<usize> {
+        let due = match self.preference.lock().unwrap().evaluated_at {
+            None => true,
+            Some(evaluated_at) => evaluated_at.elapsed() >= self.policy.reevaluate_interval,
+        };
+        if due {
+            self.reevaluate().await;
+        }
+        let preferred = self.preferred();
+        std::iter::once(preferred)
+            .chain((0..self.clients.len()).filter(|index| *index != preferred))
+            .collect()
+    }
+}
+
+fn is_transport_error(err: &jsonrpsee::core::Error) -> bool {
+    matches!(
+        err,
+        jsonrpsee::core::Error::Transport(_)
+            | jsonrpsee::core::Error::RequestTimeout
+            | jsonrpsee::core::Error::RestartNeeded(_)
+    )
+}
+
+#[async_trait]
+impl<C> ClientT for MultiClient<C>
+where
+    C: ClientT + Send + Sync,
+{
+    async fn notification<Params>(
+        &self,
+        method: &str,
+        params: Params,
+    ) -> Result<(), jsonrpsee::core::Error>
+    where
+        Params: ToRpcParams + Send,
+    {
+        let preferred = self.order().await[0];
+        self.clients[preferred].notification(method, params).await
+    }
+
+    async fn request<R, Params>(
+        &self,
+        method: &str,
+        params: Params,
+    ) -> Result<R, jsonrpsee::core::Error>
+    where
+        R: DeserializeOwned,
+        Params: ToRpcParams + Send,
+    {
+        let params = params.to_rpc_params()?;
+        let order = self.order().await;
+        if BROADCAST_METHODS.contains(&method) {
+            let mut errors = Vec::new();
+            let mut response = None;
+            for index in order {
+                match self.clients[index]
+                    .request::<Box<RawValue>, _>(method, RawParams(params.clone()))
+                    .await
+                {
+                    Ok(node_response) => {
+                        response.get_or_insert(node_response);
+                    }
+                    Err(err) => errors.push(format!("node {index}: {err}")),
+                }
+            }
+            return match response {
+                Some(response) => Ok(serde_json::from_str(response.get())?),
+                None => Err(jsonrpsee::core::Error::Custom(errors.join("; "))),
+            };
+        }
+        let mut last_err = None;
+        for index in order {
+            match self.clients[index]
+                .request(method, RawParams(params.clone()))
+                .await
+            {
+                Err(err) if is_transport_error(&err) => last_err = Some(err),
+                result => return result,
+            }
+        }
+        Err(last_err.expect("MultiClient has at least one client"))
+    }
+
+    async fn batch_request<'a, R>(
+        &self,
+        batch: BatchRequestBuilder<'a>,
+    ) -> Result<BatchResponse<'a, R>, jsonrpsee::core::Error>
+    where
+        R: DeserializeOwned + fmt::Debug + 'a,
+    {
+        let mut last_err = None;
+        for index in self.order().await {
+            match self.clients[index].batch_request(batch.clone()).await {
+                Err(err) if is_transport_error(&err) => last_err = Some(err),
+                result => return result,
+            }
+        }
+        Err(last_err.expect("MultiClient has at least one client"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{block_on, blockchain_info, rpc_error, MockClient};
+    use serde_json::json;
+    use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+    /// Node with adjustable chainwork that can be taken down.
+    #[derive(Clone, Default)]
+    struct Node {
+        work: Arc<AtomicU64>,
+        down: Arc<AtomicBool>,
+    }
+
+    impl Node {
+        fn new(work: u64) -> Self {
+            let node = Node::default();
+            node.work.store(work, Ordering::SeqCst);
+            node
+        }
+
+        fn client(&self) -> MockClient {
+            let node = self.clone();
+            MockClient::new(move |method, _| {
+                if node.down.load(Ordering::SeqCst) {
+                    return Err(jsonrpsee::core::Error::Transport(
+                        jsonrpsee::http_client::transport::Error::RequestFailure {
+                            status_code: 503,
+                        }
+                        .into(),
+                    ));
+                }
+                let work = node.work.load(Ordering::SeqCst);
+                match method {
+                    "getblockchaininfo" => {
+                        let mut info = blockchain_info("regtest", work as usize);
+                        info["chainwork"] = json!(format!("{work:064x}"));
+                        Ok(info)
+                    }
+                    "getblockcount" => Ok(json!(work)),
+                    "getblockhash" => Err(rpc_error(-8, "Block height out of range")),
+                    "sendrawtransaction" => Err(rpc_error(-26, "insufficient fee")),
+                    _ => unreachable!("{method}"),
+                }
+            })
+        }
+    }
+
+    /// Requests each node got, other than `getblockchaininfo`.
+    fn requests(multi: &MultiClient<MockClient>) -> Vec<Vec<String>> {
+        multi
+            .clients()
+            .iter()
+            .map(|client| {
+                client
+                    .methods()
+                    .into_iter()
+                    .filter(|method| method != "getblockchaininfo")
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn block_count(multi: &MultiClient<MockClient>) -> Result<usize, jsonrpsee::core::Error> {
+        block_on(multi.getblockcount())
+    }
+
+    #[test]
+    fn prefers_the_node_with_most_work() {
+        let nodes = [Node::new(10), Node::new(20)];
+        let multi = MultiClient::new(
+            nodes.iter().map(Node::client).collect(),
+            Policy::new(Duration::ZERO),
+        );
+        assert_eq!(block_count(&multi).unwrap(), 20);
+        assert_eq!(multi.preferred(), 1);
+        nodes[0].work.store(30, Ordering::SeqCst);
+        assert_eq!(block_count(&multi).unwrap(), 30);
+        assert_eq!(multi.preferred(), 0);
+        assert_eq!(
+            requests(&multi),
+            [vec!["getblockcount"], vec!["getblockcount"]]
+        );
+    }
+
+    #[test]
+    fn fails_over_on_transport_errors() {
+        let nodes = [Node::new(10), Node::new(20)];
+        let multi = MultiClient::new(
+            nodes.iter().map(Node::client).collect(),
+            Policy::new(Duration::from_secs(3600)),
+        );
+        assert_eq!(block_count(&multi).unwrap(), 20);
+        // The preferred node goes down before the next evaluation
+        nodes[1].down.store(true, Ordering::SeqCst);
+        assert_eq!(block_count(&multi).unwrap(), 10);
+        assert_eq!(multi.preferred(), 1);
+        assert_eq!(
+            requests(&multi),
+            [
+                vec!["getblockcount"],
+                vec!["getblockcount", "getblockcount"]
+            ]
+        );
+        // Errors of the node itself are returned as they are
+        nodes[1].down.store(false, Ordering::SeqCst);
+        let err = block_on(multi.getblockhash(100)).unwrap_err();
+        assert!(matches!(err, jsonrpsee::core::Error::Call(err) if err.code() == -8));
+        assert_eq!(requests(&multi)[0], ["getblockcount"]);
+        // No node answers
+        nodes[0].down.store(true, Ordering::SeqCst);
+        nodes[1].down.store(true, Ordering::SeqCst);
+        assert!(matches!(
+            block_count(&multi),
+            Err(jsonrpsee::core::Error::Transport(_))
+        ));
+    }
+
+    #[test]
+    fn equal_work_does_not_flap() {
+        let nodes = [Node::new(10), Node::new(20)];
+        let multi = MultiClient::new(
+            nodes.iter().map(Node::client).collect(),
+            Policy::new(Duration::ZERO),
+        );
+        assert_eq!(block_count(&multi).unwrap(), 20);
+        assert_eq!(multi.preferred(), 1);
+        // The other node catches up with a competing tip of the same work
+        nodes[0].work.store(20, Ordering::SeqCst);
+        for _ in 0..10 {
+            block_count(&multi).unwrap();
+            assert_eq!(multi.preferred(), 1);
+        }
+        assert_eq!(requests(&multi)[0], Vec::<String>::new());
+    }
+
+    #[test]
+    fn equal_work_goes_to_a_much_faster_node() {
+        let nodes = [Node::new(20), Node::new(20)];
+        let multi = MultiClient::new(
+            vec![
+                nodes[0]
+                    .client()
+                    .with_delay(Policy::new(Duration::ZERO).latency_margin * 2),
+                nodes[1].client(),
+            ],
+            Policy::new(Duration::ZERO),
+        );
+        block_count(&multi).unwrap();
+        assert_eq!(multi.preferred(), 1);
+    }
+
+    #[test]
+    fn broadcasts_writes() {
+        let nodes = [Node::new(10), Node::new(20)];
+        let multi = MultiClient::new(
+            nodes.iter().map(Node::client).collect(),
+            Policy::new(Duration::ZERO),
+        );
+        nodes[0].down.store(true, Ordering::SeqCst);
+        let err = block_on(multi.sendrawtransaction("00")).unwrap_err();
+        let message = err.to_string();
+        assert!(message.contains("node 1: "), "{message}");
+        assert!(message.contains("insufficient fee"), "{message}");
+        assert!(message.contains("node 0: "), "{message}");
+        assert_eq!(
+            requests(&multi),
+            [vec!["sendrawtransaction"], vec!["sendrawtransaction"]]
+        );
+    }
+}
@@ -0,0 +1,193 @@
+//! Controlling the P2P connections of the mainchain node, e.g. to partition
+//! nodes in integration tests.
+use crate::client::{NodeAdminClient, PeerInfo};
+use crate::Error;
+use jsonrpsee::core::client::ClientT;
+
+/// Peer to disconnect, by address or by the id from `getpeerinfo`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum PeerSelector {
+    Address(String),
+    Id(u64),
+}
+
+pub async fn disconnect_node<C>(client: &C, peer: &PeerSelector) -> Result<(), Error>
+where
+    C: ClientT + Sync,
+{
+    match peer {
+        PeerSelector::Address(address) => client.disconnectnode(address, None).await?,
+        PeerSelector::Id(id) => client.disconnectnode("", Some(*id)).await?,
+    }
+    Ok(())
+}
+
+/// Disables networking of the node and disconnects all of its peers. See
+/// [`NetworkIsolation`] for how networking is enabled again.
+pub async fn isolate_node<C>(client: C) -> Result<NetworkIsolation<C>, Error>
+where
+    C: ClientT + Send + Sync + 'static,
+{
+    // Disabled first, so that no peers connect while the others are
+    // disconnected
+    client.setnetworkactive(false).await?;
+    let mut isolation = NetworkIsolation {
+        client: Some(client),
+        disconnected: vec![],
+    };
+    let client = isolation.client.as_ref().unwrap();
+    let peers = client.getpeerinfo().await?;
+    for peer in &peers {
+        // The peer may have been disconnected by the node in the meantime
+        match disconnect_node(client, &PeerSelector::Id(peer.id)).await {
+            Ok(()) | Err(Error::Jsonrpsee(jsonrpsee::core::Error::Call(_))) => (),
+            Err(err) => return Err(err),
+        }
+    }
+    isolation.disconnected = peers;
+    Ok(isolation)
+}
+
+/// Guard returned by [`isolate_node`]. Networking is enabled again by
+/// [`NetworkIsolation::restore`], or when the guard is dropped inside a
+/// tokio runtime. Peers that were disconnected are not reconnected, but the
+/// node will make new connections on its own.
+pub struct NetworkIsolation<C>
+where
+    C: ClientT + Send + Sync + 'static,
+{
+    /// `None` once networking was restored.
+    client: Option<C>,
+    /// Peers of the node when it was isolated.
+    pub disconnected: Vec<PeerInfo>,
+}
+
+impl<C> NetworkIsolation<C>
+where
+    C: ClientT + Send + Sync + 'static,
+{
+    pub async fn restore(mut self) -> Result<(), Error> {
+        if let Some(client) = self.client.take() {
+            client.setnetworkactive(true).await?;
+        }
+        Ok(())
+    }
+}
+
+impl<C> Drop for NetworkIsolation<C>
+where
+    C: ClientT + Send + Sync + 'static,
+{
+    fn drop(&mut self) {
+        let Some(client) = self.client.take() else {
+            return;
+        };
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            runtime.spawn(async move {
+                let _ = client.setnetworkactive(true).await;
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{block_on, rpc_error, MockClient};
+    use serde_json::{json, Value};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn peer(id: u64) -> Value {
+        json!({
+            "id": id,
+            "addr": format!("127.0.0.1:{}", 18444 + id),
+            "services": "0000000000000409",
+            "lastsend": 1_700_000_000,
+            "lastrecv": 1_700_000_000,
+            "bytessent": 1000,
+            "bytesrecv": 1000,
+            "conntime": 1_700_000_000,
+            "timeoffset": 0,
+            "version": 70016,
+            "subver": "/Satoshi:26.0.0/",
+            "inbound": false,
+        })
+    }
+
+    /// A node with peers 1 and 2. Peer 2 disconnects on its own before
+    /// `disconnectnode` gets to it.
+    fn node(active: Arc<AtomicBool>) -> MockClient {
+        MockClient::new(move |method, params| match method {
+            "setnetworkactive" => {
+                let state = params[0].as_bool().unwrap();
+                active.store(state, Ordering::SeqCst);
+                Ok(json!(state))
+            }
+            "getpeerinfo" => Ok(json!([peer(1), peer(2)])),
+            "disconnectnode" if params[1] == json!(2) => {
+                Err(rpc_error(-29, "Node not found in connected nodes"))
+            }
+            "disconnectnode" => Ok(Value::Null),
+            _ => unreachable!("{method}"),
+        })
+    }
+
+    #[test]
+    fn disconnects_by_address_or_id() {
+        let client = MockClient::new(|_, _| Ok(Value::Null));
+        block_on(async {
+            let address = PeerSelector::Address("127.0.0.1:18445".to_owned());
+            disconnect_node(&client, &address).await.unwrap();
+            disconnect_node(&client, &PeerSelector::Id(7))
+                .await
+                .unwrap();
+        });
+        // The node rejects a call that sets both
+        assert_eq!(
+            client.calls(),
+            [
+                (
+                    "disconnectnode".to_owned(),
+                    vec![json!("127.0.0.1:18445"), Value::Null]
+                ),
+                ("disconnectnode".to_owned(), vec![json!(""), json!(7)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn isolates_and_restores() {
+        let active = Arc::new(AtomicBool::new(true));
+        let client = node(active.clone());
+        block_on(async {
+            let isolation = isolate_node(client).await.unwrap();
+            assert!(!active.load(Ordering::SeqCst));
+            // Peer 2 counts as disconnected even though it was already gone
+            let ids: Vec<_> = isolation.disconnected.iter().map(|peer| peer.id).collect();
+            assert_eq!(ids, [1, 2]);
+            isolation.restore().await.unwrap();
+            assert!(active.load(Ordering::SeqCst));
+        });
+    }
+
+    #[test]
+    fn dropping_the_guard_restores_networking() {
+        let active = Arc::new(AtomicBool::new(true));
+        let client = node(active.clone());
+        block_on(async {
+            let isolation = isolate_node(client).await.unwrap();
+            assert!(!active.load(Ordering::SeqCst));
+            drop(isolation);
+            // Restored by a task spawned on drop
+            tokio::time::timeout(Duration::from_secs(5), async {
+                while !active.load(Ordering::SeqCst) {
+                    tokio::task::yield_now().await;
+                }
+            })
+            .await
+            .unwrap();
+        });
+    }
+}
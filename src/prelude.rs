@@ -0,0 +1,60 @@
+//! Everything needed to talk to a mainchain node, for glob importing.
+// The example needs the client, which is behind the `client` feature
+#![cfg_attr(
+    feature = "client",
+    doc = r#"
+```no_run
+use bip300301::prelude::*;
+
+# async fn example(drivechain: Drivechain) -> Result<(), Error> {
+let tip = drivechain.client.getbestblockhash().await?;
+let block = drivechain.get_block(&tip).await?;
+let raw_block = drivechain.get_block_raw(&tip).await?;
+assert_eq!(block.hash, raw_block.block_hash());
+let mempool = drivechain.get_raw_mempool().await?;
+# Ok(())
+# }
+```
+"#
+)]
+//!
+//! This includes the RPC client traits, such as `ChainClient` and
+//! `WalletClient`, which hold the methods of `MainClient`. The node
+//! maintenance RPCs of `NodeAdminClient` are left out, and need to be
+//! imported from the crate root.
+#[cfg(feature = "client")]
+pub use crate::batch::{Batch, BatchItemResult};
+pub use crate::client::{
+    ActiveCommand, AddNodeCommand, AddedNodeAddress, AddedNodeInfo, AddressInfo, AddressType,
+    AmountBtc, AmountSats, BalanceDetails, Balances, Bip9Info, Bip9Statistics, Bip9Status, Block,
+    BlockRef, BlockTemplate, BlockTemplateRequest, BlockTemplateTransaction, BlockchainInfo,
+    BmmAcceptedBid, BumpFee, BumpFeeOptions, ChainState, ChainStates, ConnectionDirection,
+    ConsensusEncoded, ConsensusEncodedLazy, Ctip, DecodedScript, DeploymentInfo, Deposit,
+    DepositVerbose, Difficulty, EnforcementStatus, EstimateMode, FinalizePsbt, FundRawTransaction,
+    FundRawTransactionOptions, GenerateBlock, GenerateBlockTx, Header, IndexInfo, LabelAddress,
+    LabelPurpose, ListSinceBlock, LoadTxOutSet, LocalAddress, LockedMemory, MallocInfoMode,
+    MemoryInfo, MemoryInfoMode, MemoryStatsMode, MempoolEntry, MempoolEntryFees, MempoolInfo,
+    NetTotals, NetworkInfo, NetworkReachability, PeerInfo, PrioritisedTransaction, PsbtInput,
+    PsbtOutput, RawMempoolVerbose, RawMempoolWithSequence, RawTransactionInfo, RawTransactionInput,
+    RawTransactionOutput, RawTransactionScriptPubKey, RawTransactionVerbose, ReceivedByAddress,
+    ReceivedByLabel, RpcInfo, SaveMempool, ScanAction, ScanObject, ScanStatus, ScanTxOutSetResult,
+    ScanUnspent, ScriptSig, SendToAddressOptions, SidechainId, SignRawTransaction,
+    SignRawTransactionError, SoftFork, TemplateRequestMode, UploadTarget, Vote,
+    WalletCreateFundedPsbt, WalletProcessPsbt, WalletTransaction, WalletTransactionDetail,
+    WalletTx, WalletTxCategory, WithdrawalStatus, MAX_FUTURE_BLOCK_TIME,
+};
+#[cfg(feature = "client")]
+pub use crate::client::{ChainClient, DrivechainClient, EnforcerClient, MainClient, WalletClient};
+pub use crate::deposit::{DepositAddress, DepositDestination};
+pub use crate::filters::BlockFilter;
+#[cfg(feature = "client")]
+pub use crate::health::{DegradedReason, HealthMonitor, NodeStatus, TrustReport};
+#[cfg(feature = "client")]
+pub use crate::reconnect::{Reconnected, ReconnectingClient};
+#[cfg(feature = "client")]
+pub use crate::withdrawals::{
+    BundleState, ResolvedWithdrawal, WithdrawalBundle, WithdrawalTracker,
+};
+pub use crate::Error;
+#[cfg(feature = "client")]
+pub use crate::{new_client, Drivechain};
@@ -0,0 +1,376 @@
+//! Fee deltas this process applies with `prioritisetransaction`, e.g. to its
+//! BMM requests and deposits, and removing them once they no longer matter.
+//!
+//! The node only drops a delta when the transaction is mined, and keeps it
+//! across restarts, so deltas of evicted transactions accumulate unless
+//! removed. [`PriorityManager`] tracks the deltas it applied. They can be
+//! saved with [`PriorityManager::applied`] and restored with
+//! [`PriorityManager::restore`] after a restart, and
+//! [`PriorityManager::cleanup`] reconciles them with the node using
+//! `getprioritisedtransactions`, which needs Bitcoin Core 26.
+//!
+//! Whether a transaction that is not in the mempool was evicted or never
+//! entered it is told from `getmempoolentry` when it is prioritised, earlier
+//! cleanups and `getrawtransaction`. A transaction that was broadcast and
+//! evicted between two cleanups without being seen can't be told from one
+//! that was not broadcast yet, so it is reported as
+//! [`Cleanup::unseen`] instead of having its delta removed.
+use crate::cancel::Partial;
+use crate::client::ChainClient;
+use crate::Error;
+use jsonrpsee::core::client::ClientT;
+use jsonrpsee::http_client::HttpClient;
+use std::collections::BTreeMap;
+
+const RPC_INVALID_ADDRESS_OR_KEY: i32 = -5;
+
+/// Delta applied by a [`PriorityManager`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Prioritisation {
+    /// Sum of the deltas applied, in sats.
+    pub fee_delta: i64,
+    /// Whether the transaction was in the mempool when it was prioritised
+    /// or at an earlier [`PriorityManager::cleanup`].
+    pub seen_in_mempool: bool,
+}
+
+/// Result of [`PriorityManager::cleanup`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Cleanup {
+    /// Transactions that left the mempool without being mined, or that were
+    /// mined without the node dropping the delta. Their deltas were
+    /// removed.
+    pub removed: Vec<bitcoin::Txid>,
+    /// Transactions the node no longer has a delta for, because they were
+    /// mined or the delta was removed by someone else. They are no longer
+    /// tracked.
+    pub forgotten: Vec<bitcoin::Txid>,
+    /// Transactions whose delta on the node differs from the one applied,
+    /// because someone else prioritised them too. The delta is no longer
+    /// ours alone, so it is left to the node and no longer tracked.
+    pub disowned: Vec<bitcoin::Txid>,
+    /// Transactions that were never seen in the mempool or in a block, e.g.
+    /// because they were not broadcast yet. They keep their deltas, use
+    /// [`PriorityManager::deprioritise`] for those that are abandoned.
+    pub unseen: Vec<bitcoin::Txid>,
+}
+
+/// Applies fee deltas and removes them when they are no longer needed.
+pub struct PriorityManager<C = HttpClient> {
+    client: C,
+    applied: BTreeMap<bitcoin::Txid, Prioritisation>,
+}
+
+impl<C> PriorityManager<C>
+where
+    C: ClientT + Sync,
+{
+    pub fn new(client: C) -> Self {
+        Self::restore(client, BTreeMap::new())
+    }
+
+    /// Continues with the deltas of [`Self::applied`] after a restart.
+    /// [`Self::cleanup`] drops those the node no longer has.
+    pub fn restore(client: C, applied: BTreeMap<bitcoin::Txid, Prioritisation>) -> Self {
+        PriorityManager { client, applied }
+    }
+
+    pub fn applied(&self) -> &BTreeMap<bitcoin::Txid, Prioritisation> {
+        &self.applied
+    }
+
+    /// Adds `fee_delta` sats to the fee of `txid`, which does not need to be
+    /// in the mempool yet.
+    pub async fn prioritise(&mut self, txid: bitcoin::Txid, fee_delta: i64) -> Result<(), Error> {
+        self.client
+            .prioritisetransaction(&txid, None, fee_delta)
+            .await?;
+        self.applied
+            .entry(txid)
+            .or_insert(Prioritisation {
+                fee_delta: 0,
+                seen_in_mempool: false,
+            })
+            .fee_delta += fee_delta;
+        match self.client.getmempoolentry(&txid).await {
+            Ok(_) => self.mark_seen(txid),
+            Err(jsonrpsee::core::Error::Call(err)) if err.code() == RPC_INVALID_ADDRESS_OR_KEY => {}
+            Err(err) => return Err(err.into()),
+        }
+        Ok(())
+    }
+
+    /// Removes the deltas applied to `txid`. Returns `false` if there were
+    /// none.
+    pub async fn deprioritise(&mut self, txid: bitcoin::Txid) -> Result<bool, Error> {
+        let Some(prioritisation) = self.applied.get(&txid) else {
+            return Ok(false);
+        };
+        if prioritisation.fee_delta != 0 {
+            self.client
+                .prioritisetransaction(&txid, None, -prioritisation.fee_delta)
+                .await?;
+        }
+        self.applied.remove(&txid);
+        Ok(true)
+    }
+
+    /// Reconciles the tracked deltas with those of the node, see
+    /// [`Cleanup`]. The node keeps deltas across restarts, so this also
+    /// reconciles deltas restored with [`Self::restore`].
+    ///
+    /// The tracked deltas are updated as transactions are reconciled, so if
+    /// a request fails the error is returned with the transactions
+    /// reconciled before it.
+    pub async fn cleanup(&mut self) -> Partial<Cleanup> {
+        let mut cleanup = Cleanup::default();
+        let result = self.reconcile(&mut cleanup).await;
+        Partial {
+            value: cleanup,
+            stopped: result.err(),
+        }
+    }
+
+    async fn reconcile(&mut self, cleanup: &mut Cleanup) -> Result<(), Error> {
+        let node = self.client.getprioritisedtransactions().await?;
+        let txids: Vec<_> = self.applied.keys().copied().collect();
+        for txid in txids {
+            let prioritisation = self.applied[&txid];
+            let Some(entry) = node.get(&txid) else {
+                self.applied.remove(&txid);
+                cleanup.forgotten.push(txid);
+                continue;
+            };
+            if entry.fee_delta != prioritisation.fee_delta {
+                self.applied.remove(&txid);
+                cleanup.disowned.push(txid);
+                continue;
+            }
+            if entry.in_mempool {
+                self.mark_seen(txid);
+                continue;
+            }
+            let unseen = if prioritisation.seen_in_mempool {
+                false
+            } else {
+                match self
+                    .client
+                    .getrawtransactionverbose(&txid, true, None)
+                    .await
+                {
+                    Ok(transaction) if transaction.blockhash.is_some() => false,
+                    // Entered the mempool since `getprioritisedtransactions`
+                    Ok(_) => {
+                        self.mark_seen(txid);
+                        continue;
+                    }
+                    Err(jsonrpsee::core::Error::Call(err))
+                        if err.code() == RPC_INVALID_ADDRESS_OR_KEY =>
+                    {
+                        true
+                    }
+                    Err(err) => return Err(err.into()),
+                }
+            };
+            if unseen {
+                cleanup.unseen.push(txid);
+            } else {
+                self.deprioritise(txid).await?;
+                cleanup.removed.push(txid);
+            }
+        }
+        Ok(())
+    }
+
+    fn mark_seen(&mut self, txid: bitcoin::Txid) {
+        if let Some(prioritisation) = self.applied.get_mut(&txid) {
+            prioritisation.seen_in_mempool = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{block_on, mempool_entry, rpc_error, MockClient};
+    use bitcoin::hashes::Hash as _;
+    use serde_json::{json, Value};
+
+    fn txid(n: u8) -> bitcoin::Txid {
+        bitcoin::Txid::from_byte_array([n; 32])
+    }
+
+    fn prioritisation(fee_delta: i64, seen_in_mempool: bool) -> Prioritisation {
+        Prioritisation {
+            fee_delta,
+            seen_in_mempool,
+        }
+    }
+
+    fn not_found() -> Result<Value, jsonrpsee::core::Error> {
+        Err(rpc_error(
+            -5,
+            "No such mempool transaction. Use -txindex or provide a block hash to enable blockchain transaction queries. Use gettransaction for wallet transactions.",
+        ))
+    }
+
+    /// `getrawtransaction` response of a transaction mined in a block.
+    fn mined(txid: bitcoin::Txid) -> Value {
+        json!({
+            "hex": "",
+            "txid": txid,
+            "hash": txid,
+            "version": 2,
+            "size": 100,
+            "vsize": 100,
+            "weight": 400,
+            "locktime": 0,
+            "vin": [],
+            "vout": [],
+            "blockhash": "0000000000000000000000000000000000000000000000000000000000000001",
+            "confirmations": 1,
+        })
+    }
+
+    /// Fee delta and `txid` of the `prioritisetransaction` calls.
+    fn prioritised(client: &MockClient) -> Vec<(Value, Value)> {
+        client
+            .calls()
+            .into_iter()
+            .filter(|(method, _)| method == "prioritisetransaction")
+            .map(|(_, params)| (params[0].clone(), params[2].clone()))
+            .collect()
+    }
+
+    #[test]
+    fn reconciles_after_a_restart() {
+        // Before the restart, 1 and 3 were seen in the mempool. 1, 2, 5 and
+        // 6 still have a delta on the node, the others were mined.
+        let applied = BTreeMap::from([
+            (txid(1), prioritisation(1_000, true)),
+            (txid(2), prioritisation(2_000, false)),
+            (txid(3), prioritisation(3_000, true)),
+            (txid(4), prioritisation(4_000, false)),
+            (txid(5), prioritisation(5_000, false)),
+            (txid(6), prioritisation(6_000, false)),
+            (txid(7), prioritisation(7_000, true)),
+            (txid(8), prioritisation(8_000, false)),
+        ]);
+        let client = MockClient::new(|method, params| match method {
+            "getprioritisedtransactions" => Ok(json!({
+                txid(1).to_string(): { "fee_delta": 1_000, "in_mempool": false },
+                txid(2).to_string(): { "fee_delta": 2_000, "in_mempool": false },
+                txid(5).to_string(): { "fee_delta": 5_000, "in_mempool": false },
+                txid(6).to_string(): {
+                    "fee_delta": 6_000,
+                    "in_mempool": true,
+                    "modified_fee": 6_141,
+                },
+                // Prioritised by someone else as well
+                txid(7).to_string(): { "fee_delta": 8_000, "in_mempool": false },
+                // Not ours
+                txid(9).to_string(): { "fee_delta": 9_000, "in_mempool": false },
+            })),
+            "getrawtransaction" if params[0] == json!(txid(5)) => Ok(mined(txid(5))),
+            "getrawtransaction" => not_found(),
+            "prioritisetransaction" => Ok(json!(true)),
+            _ => unreachable!("{method}"),
+        });
+        let mut manager = PriorityManager::restore(client, applied);
+        let cleanup = block_on(manager.cleanup()).into_result().unwrap();
+        assert_eq!(
+            cleanup,
+            Cleanup {
+                // 1 was evicted, 5 was mined
+                removed: vec![txid(1), txid(5)],
+                forgotten: vec![txid(3), txid(4), txid(8)],
+                disowned: vec![txid(7)],
+                unseen: vec![txid(2)],
+            }
+        );
+        assert_eq!(
+            *manager.applied(),
+            BTreeMap::from([
+                (txid(2), prioritisation(2_000, false)),
+                (txid(6), prioritisation(6_000, true)),
+            ])
+        );
+        assert_eq!(
+            prioritised(&manager.client),
+            [
+                (json!(txid(1)), json!(-1_000)),
+                (json!(txid(5)), json!(-5_000))
+            ]
+        );
+        // Only transactions never seen are looked up
+        let looked_up: Vec<_> = manager
+            .client
+            .calls()
+            .into_iter()
+            .filter(|(method, _)| method == "getrawtransaction")
+            .map(|(_, params)| params[0].clone())
+            .collect();
+        assert_eq!(looked_up, [json!(txid(2)), json!(txid(5))]);
+    }
+
+    #[test]
+    fn evicted_after_being_seen_when_prioritised() {
+        let client = MockClient::new(|method, params| match method {
+            "prioritisetransaction" => Ok(json!(true)),
+            "getmempoolentry" if params[0] == json!(txid(1)) => Ok(mempool_entry(txid(1))),
+            "getmempoolentry" => not_found(),
+            "getprioritisedtransactions" => Ok(json!({
+                txid(1).to_string(): { "fee_delta": 1_000, "in_mempool": false },
+                txid(2).to_string(): { "fee_delta": 2_000, "in_mempool": false },
+            })),
+            "getrawtransaction" => not_found(),
+            _ => unreachable!("{method}"),
+        });
+        let mut manager = PriorityManager::new(client);
+        block_on(manager.prioritise(txid(1), 1_000)).unwrap();
+        block_on(manager.prioritise(txid(2), 2_000)).unwrap();
+        assert_eq!(manager.applied()[&txid(1)], prioritisation(1_000, true));
+        assert_eq!(manager.applied()[&txid(2)], prioritisation(2_000, false));
+        let cleanup = block_on(manager.cleanup()).into_result().unwrap();
+        assert_eq!(cleanup.removed, [txid(1)]);
+        assert_eq!(cleanup.unseen, [txid(2)]);
+    }
+
+    #[test]
+    fn keeps_the_cleanup_done_before_an_error() {
+        let applied = BTreeMap::from([
+            (txid(1), prioritisation(1_000, true)),
+            (txid(2), prioritisation(2_000, true)),
+            (txid(3), prioritisation(3_000, true)),
+        ]);
+        let client = MockClient::new(|method, params| match method {
+            "getprioritisedtransactions" => Ok(json!({
+                txid(1).to_string(): { "fee_delta": 1_000, "in_mempool": false },
+                txid(2).to_string(): { "fee_delta": 2_000, "in_mempool": false },
+            })),
+            "prioritisetransaction" if params[0] == json!(txid(2)) => {
+                Err(rpc_error(-1, "node is shutting down"))
+            }
+            "prioritisetransaction" => Ok(json!(true)),
+            _ => unreachable!("{method}"),
+        });
+        let mut manager = PriorityManager::restore(client, applied);
+        let cleanup = block_on(manager.cleanup());
+        assert!(matches!(cleanup.stopped, Some(Error::Jsonrpsee(_))));
+        assert_eq!(cleanup.value.removed, [txid(1)]);
+        // 3 was not reached
+        assert!(cleanup.value.forgotten.is_empty());
+        assert_eq!(
+            manager.applied().keys().collect::<Vec<_>>(),
+            [&txid(2), &txid(3)]
+        );
+        // 2 fails again, before 3 is reached
+        let cleanup = block_on(manager.cleanup());
+        assert!(cleanup.stopped.is_some());
+        assert!(cleanup.value.removed.is_empty());
+        assert_eq!(
+            manager.applied().keys().collect::<Vec<_>>(),
+            [&txid(2), &txid(3)]
+        );
+    }
+}
@@ -0,0 +1,178 @@
+//! Tracking wallet transactions on a pruned mainchain node, which can't
+//! rescan blocks it no longer has.
+use crate::client::{ChainClient, WalletClient};
+use crate::Error;
+use jsonrpsee::core::client::ClientT;
+
+/// Code of the errors for unknown transactions and unwatched scripts, but
+/// also e.g. for unknown blocks and malformed proofs, so the message is
+/// checked too.
+const RPC_INVALID_ADDRESS_OR_KEY: i32 = -5;
+
+/// Start of the `getrawtransaction` error if the block does not contain the
+/// transaction.
+const TX_NOT_IN_BLOCK_MESSAGE: &str = "No such transaction found in the provided block";
+
+/// Start of the `importprunedfunds` error if the wallet does not watch any
+/// script of the transaction.
+const NOT_WATCHED_MESSAGE: &str = "No addresses in wallet correspond to included transaction";
+
+/// Imports `txid`, confirmed in `block_hash`, into the wallet, so that e.g.
+/// a deposit made before the wallet watched its script is tracked.
+///
+/// Fails with [`Error::BlockPruned`] if the node no longer has the block,
+/// [`Error::TxNotInBlock`] if the transaction is not in it, and
+/// [`Error::NotWatched`] if the wallet doesn't watch any of its scripts.
+pub async fn import_deposit<C>(
+    client: &C,
+    txid: &bitcoin::Txid,
+    block_hash: &bitcoin::BlockHash,
+) -> Result<(), Error>
+where
+    C: ClientT + Sync,
+{
+    let info = client.getblockchaininfo().await?;
+    if let (true, Some(prune_height)) = (info.pruned, info.pruneheight) {
+        let height = client.getblockheader(block_hash).await?.height;
+        if height < prune_height {
+            return Err(Error::BlockPruned {
+                block_hash: *block_hash,
+            });
+        }
+    }
+    let raw_tx = client
+        .getrawtransaction(txid, false, Some(block_hash))
+        .await
+        .map_err(|err| match err {
+            jsonrpsee::core::Error::Call(err)
+                if err.message().starts_with("Block not available") =>
+            {
+                Error::BlockPruned {
+                    block_hash: *block_hash,
+                }
+            }
+            jsonrpsee::core::Error::Call(err)
+                if err.code() == RPC_INVALID_ADDRESS_OR_KEY
+                    && err.message().starts_with(TX_NOT_IN_BLOCK_MESSAGE) =>
+            {
+                Error::TxNotInBlock {
+                    txid: *txid,
+                    block_hash: *block_hash,
+                }
+            }
+            err => err.into(),
+        })?;
+    let proof = client.gettxoutproof(&[*txid], Some(block_hash)).await?;
+    client
+        .importprunedfunds(&raw_tx, &proof)
+        .await
+        .map_err(|err| match err {
+            jsonrpsee::core::Error::Call(err)
+                if err.code() == RPC_INVALID_ADDRESS_OR_KEY
+                    && err.message().starts_with(NOT_WATCHED_MESSAGE) =>
+            {
+                Error::NotWatched { txid: *txid }
+            }
+            err => err.into(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{block_on, blockchain_info, header, rpc_error, MockClient};
+    use bitcoin::hashes::Hash as _;
+    use serde_json::json;
+
+    const PRUNE_HEIGHT: usize = 100;
+
+    /// Pruned node that has the block at `height`, and answers
+    /// `getrawtransaction` and `importprunedfunds` with the given results.
+    fn client(
+        height: usize,
+        getrawtransaction: Result<&'static str, (i32, &'static str)>,
+        importprunedfunds: Result<(), (i32, &'static str)>,
+    ) -> MockClient {
+        MockClient::new(move |method, params| match method {
+            "getblockchaininfo" => {
+                let mut info = blockchain_info("regtest", 200);
+                info["pruned"] = json!(true);
+                info["pruneheight"] = json!(PRUNE_HEIGHT);
+                Ok(info)
+            }
+            "getblockheader" => Ok(header(
+                serde_json::from_value(params[0].clone()).unwrap(),
+                height,
+                None,
+            )),
+            "getrawtransaction" => getrawtransaction
+                .map(|hex| json!(hex))
+                .map_err(|(code, message)| rpc_error(code, message)),
+            "gettxoutproof" => Ok(json!("00")),
+            "importprunedfunds" => importprunedfunds
+                .map(|()| json!(null))
+                .map_err(|(code, message)| rpc_error(code, message)),
+            _ => panic!("unexpected call to {method}"),
+        })
+    }
+
+    fn import(client: &MockClient) -> Result<(), Error> {
+        block_on(import_deposit(
+            client,
+            &bitcoin::Txid::all_zeros(),
+            &bitcoin::BlockHash::all_zeros(),
+        ))
+    }
+
+    #[test]
+    fn imports() {
+        let client = client(150, Ok("02"), Ok(()));
+        import(&client).unwrap();
+        assert_eq!(
+            client.calls()[4],
+            (
+                "importprunedfunds".to_owned(),
+                vec![json!("02"), json!("00")]
+            )
+        );
+    }
+
+    #[test]
+    fn block_below_prune_height() {
+        let client = client(50, Ok("02"), Ok(()));
+        assert!(matches!(import(&client), Err(Error::BlockPruned { .. })));
+        assert_eq!(client.methods(), ["getblockchaininfo", "getblockheader"]);
+    }
+
+    #[test]
+    fn block_not_available() {
+        let client = client(150, Err((-1, "Block not available (pruned data)")), Ok(()));
+        assert!(matches!(import(&client), Err(Error::BlockPruned { .. })));
+    }
+
+    #[test]
+    fn tx_not_in_block() {
+        let message = "No such transaction found in the provided block. Use gettransaction for wallet transactions.";
+        let client = client(150, Err((-5, message)), Ok(()));
+        assert!(matches!(import(&client), Err(Error::TxNotInBlock { .. })));
+    }
+
+    #[test]
+    fn unknown_block_is_not_tx_not_in_block() {
+        let client = client(150, Err((-5, "Block hash not found")), Ok(()));
+        assert!(matches!(import(&client), Err(Error::Jsonrpsee(_))));
+    }
+
+    #[test]
+    fn not_watched() {
+        let message = "No addresses in wallet correspond to included transaction";
+        let client = client(150, Ok("02"), Err((-5, message)));
+        assert!(matches!(import(&client), Err(Error::NotWatched { .. })));
+    }
+
+    #[test]
+    fn malformed_proof_is_not_unwatched() {
+        let client = client(150, Ok("02"), Err((-5, "Something wrong with merkleblock")));
+        assert!(matches!(import(&client), Err(Error::Jsonrpsee(_))));
+    }
+}
@@ -0,0 +1,493 @@
+//! Checking that the deposits and withdrawal payouts of a sidechain account
+//! for its CTIP.
+use crate::client::{BlockRef, ChainClient, ConsensusEncoded, Ctip, DrivechainClient, SidechainId};
+use crate::Error;
+use bitcoin::{Amount, SignedAmount};
+use jsonrpsee::core::client::ClientT;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EscrowEventKind {
+    Deposit,
+    WithdrawalPayout,
+}
+
+/// An escrow transaction that did not spend the escrow output left by the
+/// previous deposit or payout, e.g. because an event is missing from or
+/// listed twice by the node, or a current CTIP that was not left by the
+/// last event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Discrepancy {
+    /// Block of the last event that was accounted for, if any. The
+    /// discrepancy arose after it.
+    pub after: Option<BlockRef>,
+    /// Block the unexplained transaction is in.
+    pub at: BlockRef,
+    pub txid: bitcoin::Txid,
+    /// `None` if the discrepancy is in the current CTIP.
+    pub kind: Option<EscrowEventKind>,
+    /// Escrow balance before the transaction, from the events so far.
+    pub expected: Amount,
+    /// Value of the escrow output the transaction spent, or `None` if it
+    /// was not created by any of the listed events. For the current CTIP,
+    /// its amount.
+    pub actual: Option<Amount>,
+}
+
+impl Discrepancy {
+    /// Unexplained change in the escrow balance, if it is known.
+    pub fn delta(&self) -> Option<SignedAmount> {
+        Some(signed(self.actual?) - signed(self.expected))
+    }
+}
+
+fn signed(amount: Amount) -> SignedAmount {
+    SignedAmount::from_sat(amount.to_sat() as i64)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReconciliationReport {
+    /// Sum of all deposits.
+    pub deposits: SignedAmount,
+    /// Sum of all outputs of withdrawal bundles, excluding the escrow
+    /// output.
+    pub payouts: Amount,
+    /// Mainchain fees of withdrawal bundles, paid from the escrow.
+    pub fees: SignedAmount,
+    /// Escrow balance after the last event.
+    pub balance: Amount,
+    pub ctip: Option<Ctip>,
+    pub discrepancies: Vec<Discrepancy>,
+}
+
+impl ReconciliationReport {
+    pub fn is_balanced(&self) -> bool {
+        self.discrepancies.is_empty()
+    }
+}
+
+struct EscrowEvent {
+    block: BlockRef,
+    /// Index of the transaction in its block.
+    ntx: usize,
+    kind: EscrowEventKind,
+    transaction: bitcoin::Transaction,
+    escrow_vout: usize,
+}
+
+/// Replays the deposits and withdrawal payouts of `sidechain` in chain
+/// order, and checks that every event spends the escrow output left by the
+/// previous one and that the last one left the current CTIP.
+///
+/// If `from_block` is set, the escrow output left by the events in that
+/// block is taken as the starting balance, and only later events are
+/// checked. Otherwise all events since the first deposit are checked.
+///
+/// Withdrawal bundles are located by txid in the block `listspentwithdrawals`
+/// reports them in, and their escrow output by the script of the deposits,
+/// so there must be at least one deposit since `from_block`.
+pub async fn reconcile_sidechain_balance<C>(
+    client: &C,
+    sidechain: SidechainId,
+    from_block: Option<bitcoin::BlockHash>,
+) -> Result<ReconciliationReport, Error>
+where
+    C: ClientT + Sync,
+{
+    let mut blocks = HashMap::new();
+    let from_height = match from_block {
+        Some(block_hash) => Some(block_ref(client, &mut blocks, block_hash).await?.height),
+        None => None,
+    };
+    let mut escrow_script = None;
+    let mut events = vec![];
+    for deposit in client
+        .listsidechaindepositsbyblock(sidechain, None, from_block)
+        .await?
+    {
+        let transaction = deposit.transaction()?;
+        let Some(burn_output) = transaction.output.get(deposit.nburnindex) else {
+            return Err(Error::InvalidDeposit {
+                txid: transaction.txid(),
+                block_hash: deposit.hashblock,
+                reason: "burn index out of range",
+            });
+        };
+        escrow_script.get_or_insert_with(|| burn_output.script_pubkey.clone());
+        let block = block_ref(client, &mut blocks, deposit.hashblock).await?;
+        events.push(EscrowEvent {
+            block,
+            ntx: deposit.ntx,
+            kind: EscrowEventKind::Deposit,
+            escrow_vout: deposit.nburnindex,
+            transaction,
+        });
+    }
+    let spent_withdrawals: Vec<_> = client
+        .listspentwithdrawals()
+        .await?
+        .into_iter()
+        .filter(|spent| spent.nsidechain == sidechain)
+        .collect();
+    for spent in spent_withdrawals {
+        let block = block_ref(client, &mut blocks, spent.hashblock).await?;
+        if from_height.is_some_and(|from_height| block.height < from_height) {
+            continue;
+        }
+        let Some(escrow_script) = &escrow_script else {
+            return Err(Error::InvalidParam {
+                name: "from_block",
+                reason: "no deposit since this block to take the escrow script from".to_owned(),
+            });
+        };
+        let ConsensusEncoded(raw_block): ConsensusEncoded<bitcoin::Block> = client
+            .request("getblock", jsonrpsee::rpc_params![spent.hashblock, 0])
+            .await?;
+        let not_in_block = || Error::TxNotInBlock {
+            txid: spent.hash,
+            block_hash: spent.hashblock,
+        };
+        let ntx = raw_block
+            .txdata
+            .iter()
+            .position(|transaction| transaction.txid() == spent.hash)
+            .ok_or_else(not_in_block)?;
+        let transaction = raw_block.txdata.into_iter().nth(ntx).unwrap();
+        let escrow_vout = transaction
+            .output
+            .iter()
+            .position(|output| output.script_pubkey == *escrow_script)
+            .ok_or_else(not_in_block)?;
+        events.push(EscrowEvent {
+            block,
+            ntx,
+            kind: EscrowEventKind::WithdrawalPayout,
+            transaction,
+            escrow_vout,
+        });
+    }
+    events.sort_by_key(|event| (event.block.height, event.ntx));
+
+    let mut report = ReconciliationReport {
+        deposits: SignedAmount::ZERO,
+        payouts: Amount::ZERO,
+        fees: SignedAmount::ZERO,
+        balance: Amount::ZERO,
+        ctip: None,
+        discrepancies: vec![],
+    };
+    // Every escrow output created so far, by outpoint
+    let mut escrow_outputs = HashMap::new();
+    let mut escrow = None;
+    let mut last_explained = None;
+    for event in events {
+        let txid = event.transaction.txid();
+        let outpoint = bitcoin::OutPoint {
+            txid,
+            vout: event.escrow_vout as u32,
+        };
+        let value = Amount::from_sat(event.transaction.output[event.escrow_vout].value);
+        escrow_outputs.insert(outpoint, value);
+        if Some(event.block.hash) == from_block {
+            escrow = Some(outpoint);
+            report.balance = value;
+            last_explained = Some(event.block);
+            continue;
+        }
+        let spent = event.transaction.input.iter().find_map(|input| {
+            let value = escrow_outputs.get(&input.previous_output)?;
+            Some((input.previous_output, *value))
+        });
+        if spent.map(|(outpoint, _)| outpoint) != escrow {
+            report.discrepancies.push(Discrepancy {
+                after: last_explained,
+                at: event.block,
+                txid,
+                kind: Some(event.kind),
+                expected: report.balance,
+                actual: spent.map(|(_, value)| value),
+            });
+        }
+        let spent_value = spent.map_or(Amount::ZERO, |(_, value)| value);
+        match event.kind {
+            EscrowEventKind::Deposit => report.deposits += signed(value) - signed(spent_value),
+            EscrowEventKind::WithdrawalPayout => {
+                let payout: Amount = event
+                    .transaction
+                    .output
+                    .iter()
+                    .enumerate()
+                    .filter(|(vout, _)| *vout != event.escrow_vout)
+                    .map(|(_, output)| Amount::from_sat(output.value))
+                    .sum();
+                report.payouts += payout;
+                report.fees += signed(spent_value) - signed(value) - signed(payout);
+            }
+        }
+        escrow = Some(outpoint);
+        report.balance = value;
+        last_explained = Some(event.block);
+    }
+
+    report.ctip = crate::ctip::get_sidechain_ctip(client, sidechain).await?;
+    let ctip_outpoint = report.ctip.map(|ctip| ctip.outpoint());
+    // If the node reports no CTIP, the discrepancy is in the last event
+    if let Some(outpoint) = ctip_outpoint.or(escrow).filter(|_| ctip_outpoint != escrow) {
+        let tip = client.getbestblockhash().await?;
+        report.discrepancies.push(Discrepancy {
+            after: last_explained,
+            at: block_ref(client, &mut blocks, tip).await?,
+            txid: outpoint.txid,
+            kind: None,
+            expected: report.balance,
+            actual: report.ctip.map(|ctip| ctip.amount.0),
+        });
+    }
+    Ok(report)
+}
+
+async fn block_ref<C>(
+    client: &C,
+    blocks: &mut HashMap<bitcoin::BlockHash, BlockRef>,
+    block_hash: bitcoin::BlockHash,
+) -> Result<BlockRef, Error>
+where
+    C: ClientT + Sync,
+{
+    if let Some(block) = blocks.get(&block_hash) {
+        return Ok(*block);
+    }
+    let block = BlockRef {
+        hash: block_hash,
+        height: client.getblockheader(&block_hash).await?.height,
+    };
+    blocks.insert(block_hash, block);
+    Ok(block)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{block_on, rpc_error, MockChain, MockClient};
+    use bitcoin::hashes::Hash as _;
+    use serde_json::{json, Value};
+
+    const SIDECHAIN: SidechainId = SidechainId(1);
+
+    /// Escrow transactions of the sidechain and the node's view of them.
+    struct Fixture {
+        chain: MockChain,
+        deposits: Vec<Value>,
+        spent_withdrawals: Vec<Value>,
+        /// Raw blocks by hash.
+        blocks: HashMap<bitcoin::BlockHash, bitcoin::Block>,
+        escrow: bitcoin::OutPoint,
+        escrow_value: u64,
+    }
+
+    impl Fixture {
+        fn new() -> Self {
+            Fixture {
+                chain: MockChain::new(),
+                deposits: vec![],
+                spent_withdrawals: vec![],
+                blocks: HashMap::new(),
+                escrow: bitcoin::OutPoint::null(),
+                escrow_value: 0,
+            }
+        }
+
+        /// Mines a block with a transaction that spends the escrow output,
+        /// if any, and the other `inputs` into a new escrow output of
+        /// `escrow_value` sats and `payouts`.
+        fn mine(
+            &mut self,
+            inputs: Vec<bitcoin::OutPoint>,
+            escrow_value: u64,
+            payouts: Vec<bitcoin::TxOut>,
+        ) -> (bitcoin::Transaction, BlockRef) {
+            let escrow_input = (!self.escrow.is_null()).then_some(self.escrow);
+            let transaction = bitcoin::Transaction {
+                version: 2,
+                lock_time: bitcoin::absolute::LockTime::ZERO,
+                input: escrow_input
+                    .into_iter()
+                    .chain(inputs)
+                    .map(|previous_output| bitcoin::TxIn {
+                        previous_output,
+                        ..Default::default()
+                    })
+                    .collect(),
+                output: std::iter::once(bitcoin::TxOut {
+                    value: escrow_value,
+                    script_pubkey: crate::escrow::script_for_sidechain(SIDECHAIN),
+                })
+                .chain(payouts)
+                .collect(),
+            };
+            let txid = transaction.txid();
+            let block_hash = self.chain.mine_with(vec![txid]);
+            let genesis = bitcoin::constants::genesis_block(bitcoin::Network::Regtest);
+            let coinbase = genesis.txdata[0].clone();
+            self.blocks.insert(
+                block_hash,
+                bitcoin::Block {
+                    header: genesis.header,
+                    txdata: vec![coinbase, transaction.clone()],
+                },
+            );
+            self.escrow = bitcoin::OutPoint { txid, vout: 0 };
+            self.escrow_value = escrow_value;
+            let block = BlockRef {
+                hash: block_hash,
+                height: self.chain.height(),
+            };
+            (transaction, block)
+        }
+
+        fn deposit(&mut self, value: u64) -> (bitcoin::Txid, BlockRef) {
+            let user_input = bitcoin::OutPoint {
+                txid: bitcoin::Txid::all_zeros(),
+                vout: self.deposits.len() as u32,
+            };
+            let (transaction, block) = self.mine(
+                vec![user_input],
+                self.escrow_value + value,
+                vec![bitcoin::TxOut {
+                    value: 0,
+                    script_pubkey: bitcoin::ScriptBuf::new_op_return(b"alice"),
+                }],
+            );
+            self.deposits.push(json!({
+                "hashblock": block.hash,
+                "nburnindex": 0,
+                "ntx": 1,
+                "strdest": "alice",
+                "txhex": bitcoin::consensus::encode::serialize_hex(&transaction),
+            }));
+            (transaction.txid(), block)
+        }
+
+        fn withdraw(&mut self, payout: u64, fee: u64) -> (bitcoin::Txid, BlockRef) {
+            let (transaction, block) = self.mine(
+                vec![],
+                self.escrow_value - payout - fee,
+                vec![bitcoin::TxOut {
+                    value: payout,
+                    script_pubkey: bitcoin::ScriptBuf::new_op_return(b"payout"),
+                }],
+            );
+            self.spent_withdrawals.push(json!({
+                "nsidechain": SIDECHAIN,
+                "hash": transaction.txid(),
+                "hashblock": block.hash,
+            }));
+            (transaction.txid(), block)
+        }
+
+        fn node(self) -> MockClient {
+            let ctip = json!({
+                "txid": self.escrow.txid,
+                "n": self.escrow.vout,
+                "amount": bitcoin::Amount::from_sat(self.escrow_value).to_btc(),
+            });
+            MockClient::new(move |method, params| match method {
+                "listsidechaindepositsbyblock" => {
+                    assert_eq!(params[0], json!(SIDECHAIN));
+                    Ok(json!(self.deposits))
+                }
+                "listspentwithdrawals" => Ok(json!(self.spent_withdrawals)),
+                "listsidechainctip" => Ok(ctip.clone()),
+                "getblock" if params[1] == 0 => {
+                    let block_hash: bitcoin::BlockHash =
+                        serde_json::from_value(params[0].clone()).unwrap();
+                    match self.blocks.get(&block_hash) {
+                        Some(block) => Ok(json!(bitcoin::consensus::encode::serialize_hex(block))),
+                        None => Err(rpc_error(-5, "Block not found")),
+                    }
+                }
+                _ => self.chain.handle(method, params).unwrap(),
+            })
+        }
+    }
+
+    const BTC: u64 = 100_000_000;
+
+    /// Two deposits, a withdrawal payout and another deposit.
+    fn fixture() -> (Fixture, [(bitcoin::Txid, BlockRef); 4]) {
+        let mut fixture = Fixture::new();
+        fixture.chain.mine(2);
+        let first = fixture.deposit(BTC);
+        let second = fixture.deposit(BTC / 2);
+        fixture.chain.mine(1);
+        let payout = fixture.withdraw(29 * BTC / 100, BTC / 100);
+        let third = fixture.deposit(BTC / 2);
+        (fixture, [first, second, payout, third])
+    }
+
+    fn reconcile(fixture: Fixture) -> ReconciliationReport {
+        block_on(reconcile_sidechain_balance(
+            &fixture.node(),
+            SIDECHAIN,
+            None,
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn balanced() {
+        let (fixture, _) = fixture();
+        let report = reconcile(fixture);
+        assert!(report.is_balanced(), "{:?}", report.discrepancies);
+        assert_eq!(report.deposits, SignedAmount::from_sat(2 * BTC as i64));
+        assert_eq!(report.payouts, Amount::from_sat(29 * BTC / 100));
+        assert_eq!(report.fees, SignedAmount::from_sat(BTC as i64 / 100));
+        assert_eq!(report.balance, Amount::from_sat(170 * BTC / 100));
+        assert_eq!(report.ctip.unwrap().amount.0, report.balance);
+    }
+
+    #[test]
+    fn pinpoints_a_double_counted_deposit() {
+        let (mut fixture, [_, (second, second_block), _, _]) = fixture();
+        // The node lists the second deposit twice
+        let duplicate = fixture.deposits[1].clone();
+        fixture.deposits.insert(2, duplicate);
+        let report = reconcile(fixture);
+        assert_eq!(
+            report.discrepancies,
+            [Discrepancy {
+                after: Some(second_block),
+                at: second_block,
+                txid: second,
+                kind: Some(EscrowEventKind::Deposit),
+                expected: Amount::from_sat(3 * BTC / 2),
+                actual: Some(Amount::from_sat(BTC)),
+            }]
+        );
+        assert_eq!(
+            report.discrepancies[0].delta(),
+            Some(SignedAmount::from_sat(-(BTC as i64) / 2))
+        );
+        // It is counted twice
+        assert_eq!(report.deposits, SignedAmount::from_sat(5 * BTC as i64 / 2));
+    }
+
+    #[test]
+    fn pinpoints_a_missing_withdrawal() {
+        let (mut fixture, [_, (_, second_block), _, (third, third_block)]) = fixture();
+        // The node does not list the payout
+        fixture.spent_withdrawals.clear();
+        let report = reconcile(fixture);
+        assert_eq!(
+            report.discrepancies,
+            [Discrepancy {
+                after: Some(second_block),
+                at: third_block,
+                txid: third,
+                kind: Some(EscrowEventKind::Deposit),
+                expected: Amount::from_sat(3 * BTC / 2),
+                actual: None,
+            }]
+        );
+    }
+}
@@ -0,0 +1,298 @@
+use crate::cache::RawParams;
+use crate::Error;
+use futures::future::BoxFuture;
+use jsonrpsee::core::async_trait;
+use jsonrpsee::core::client::{BatchResponse, ClientT};
+use jsonrpsee::core::params::BatchRequestBuilder;
+use jsonrpsee::core::traits::ToRpcParams;
+use serde::de::DeserializeOwned;
+use std::fmt;
+use std::sync::Arc;
+use tokio::sync::{broadcast, RwLock};
+
+/// Read-only methods that are retried on the new connection after a
+/// reconnect. Other calls may have reached the node before the connection
+/// was lost, so they fail with the original error instead of possibly being
+/// applied twice.
+pub const RETRYABLE_METHODS: &[&str] = &[
+    "decoderawtransaction",
+    "decodescript",
+    "getaddednodeinfo",
+    "getaddressesbylabel",
+    "getaddressinfo",
+    "getbalance",
+    "getbalances",
+    "getbestblockhash",
+    "getblock",
+    "getblockchaininfo",
+    "getblockcount",
+    "getblockfilter",
+    "getblockhash",
+    "getblockheader",
+    "getblocktemplate",
+    "getchainstates",
+    "getconnectioncount",
+    "getdeploymentinfo",
+    "getenforcementstatus",
+    "getindexinfo",
+    "getmemoryinfo",
+    "getmempoolentry",
+    "getmempoolinfo",
+    "getnettotals",
+    "getnetworkinfo",
+    "getpeerinfo",
+    "getprioritisedtransactions",
+    "getrawmempool",
+    "getrawtransaction",
+    "getrpcinfo",
+    "gettransaction",
+    "gettxoutproof",
+    "getwithdrawalbundle",
+    "listfailedwithdrawals",
+    "listlabels",
+    "listreceivedbyaddress",
+    "listreceivedbylabel",
+    "listsidechainctip",
+    "listsidechaindepositsbyblock",
+    "listsinceblock",
+    "listspentwithdrawals",
+    "listwithdrawalstatus",
+    "verifybmm",
+    "verifymessage",
+];
+
+tokio::task_local! {
+    static ATTEMPT: u32;
+}
+
+/// Number of the attempt the current request is, starting from 1. Requests
+/// that a [`ReconnectingClient`] retries on a new connection are attempt 2.
+/// Client wrappers like [`crate::metrics::MeteredClient`] call this while
+/// sending a request.
+pub fn attempt() -> u32 {
+    ATTEMPT.try_with(|attempt| *attempt).unwrap_or(1)
+}
+
+/// Sent to subscribers after the connection was re-established.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reconnected;
+
+type Connect<C> = Arc<dyn Fn() -> BoxFuture<'static, Result<C, Error>> + Send + Sync>;
+
+/// Client wrapper that reconnects when the inner client reports that it
+/// needs to be restarted, as WebSocket clients do after the connection is
+/// lost. Calls to [`RETRYABLE_METHODS`] are then retried once.
+pub struct ReconnectingClient<C> {
+    connect: Connect<C>,
+    client: Arc<RwLock<Arc<C>>>,
+    reconnected: broadcast::Sender<Reconnected>,
+}
+
+impl<C> Clone for ReconnectingClient<C> {
+    fn clone(&self) -> Self {
+        ReconnectingClient {
+            connect: self.connect.clone(),
+            client: self.client.clone(),
+            reconnected: self.reconnected.clone(),
+        }
+    }
+}
+
+impl<C> ReconnectingClient<C>
+where
+    C: ClientT + Send + Sync + 'static,
+{
+    /// Connects using `connect`, which is called again on every reconnect.
+    pub async fn new<F>(connect: F) -> Result<Self, Error>
+    where
+        F: Fn() -> BoxFuture<'static, Result<C, Error>> + Send + Sync + 'static,
+    {
+        let client = connect().await?;
+        let (reconnected, _) = broadcast::channel(16);
+        Ok(ReconnectingClient {
+            connect: Arc::new(connect),
+            client: Arc::new(RwLock::new(Arc::new(client))),
+            reconnected,
+        })
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Reconnected> {
+        self.reconnected.subscribe()
+    }
+
+    async fn current(&self) -> Arc<C> {
+        self.client.read().await.clone()
+    }
+
+    /// Replaces `failed` with a new client, unless another task already did.
+    async fn reconnect(&self, failed: &Arc<C>) -> Result<Arc<C>, jsonrpsee::core::Error> {
+        let mut client = self.client.write().await;
+        if Arc::ptr_eq(&client, failed) {
+            let connected = (self.connect)().await.map_err(|err| {
+                jsonrpsee::core::Error::Custom(format!("reconnect failed: {err}"))
+            })?;
+            *client = Arc::new(connected);
+            let _ = self.reconnected.send(Reconnected);
+        }
+        Ok(client.clone())
+    }
+}
+
+#[async_trait]
+impl<C> ClientT for ReconnectingClient<C>
+where
+    C: ClientT + Send + Sync + 'static,
+{
+    async fn notification<Params>(
+        &self,
+        method: &str,
+        params: Params,
+    ) -> Result<(), jsonrpsee::core::Error>
+    where
+        Params: ToRpcParams + Send,
+    {
+        let params = params.to_rpc_params()?;
+        let client = self.current().await;
+        let err = match client.notification(method, RawParams(params.clone())).await {
+            Err(err @ jsonrpsee::core::Error::RestartNeeded(_)) => err,
+            result => return result,
+        };
+        let client = self.reconnect(&client).await?;
+        if !RETRYABLE_METHODS.contains(&method) {
+            return Err(err);
+        }
+        ATTEMPT
+            .scope(2, client.notification(method, RawParams(params)))
+            .await
+    }
+
+    async fn request<R, Params>(
+        &self,
+        method: &str,
+        params: Params,
+    ) -> Result<R, jsonrpsee::core::Error>
+    where
+        R: DeserializeOwned,
+        Params: ToRpcParams + Send,
+    {
+        let params = params.to_rpc_params()?;
+        let client = self.current().await;
+        let err = match client.request(method, RawParams(params.clone())).await {
+            Err(err @ jsonrpsee::core::Error::RestartNeeded(_)) => err,
+            result => return result,
+        };
+        let client = self.reconnect(&client).await?;
+        if !RETRYABLE_METHODS.contains(&method) {
+            return Err(err);
+        }
+        ATTEMPT
+            .scope(2, client.request(method, RawParams(params)))
+            .await
+    }
+
+    async fn batch_request<'a, R>(
+        &self,
+        batch: BatchRequestBuilder<'a>,
+    ) -> Result<BatchResponse<'a, R>, jsonrpsee::core::Error>
+    where
+        R: DeserializeOwned + fmt::Debug + 'a,
+    {
+        let retryable = batch
+            .clone()
+            .build()?
+            .iter()
+            .all(|(method, _)| RETRYABLE_METHODS.contains(method));
+        let client = self.current().await;
+        let err = match client.batch_request(batch.clone()).await {
+            Err(err @ jsonrpsee::core::Error::RestartNeeded(_)) => err,
+            result => return result,
+        };
+        let client = self.reconnect(&client).await?;
+        if !retryable {
+            return Err(err);
+        }
+        ATTEMPT.scope(2, client.batch_request(batch)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{block_on, MockClient};
+    use jsonrpsee::rpc_params;
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Connects to a client that has lost its connection the first time, and
+    /// to a working one afterwards.
+    fn lost_connection() -> (ReconnectingClient<MockClient>, Arc<AtomicUsize>) {
+        let connections = Arc::new(AtomicUsize::new(0));
+        let client = block_on(ReconnectingClient::new({
+            let connections = connections.clone();
+            move || {
+                let lost = connections.fetch_add(1, Ordering::SeqCst) == 0;
+                Box::pin(async move {
+                    Ok(MockClient::new(move |_, _| {
+                        if lost {
+                            Err(jsonrpsee::core::Error::RestartNeeded(
+                                "connection closed".to_owned(),
+                            ))
+                        } else {
+                            Ok(json!(1))
+                        }
+                    }))
+                })
+            }
+        }))
+        .unwrap();
+        (client, connections)
+    }
+
+    #[test]
+    fn read_only_request_is_retried() {
+        let (client, connections) = lost_connection();
+        let mut reconnected = client.subscribe();
+        let count: usize = block_on(client.request("getblockcount", rpc_params![])).unwrap();
+        assert_eq!(count, 1);
+        assert_eq!(connections.load(Ordering::SeqCst), 2);
+        assert_eq!(reconnected.try_recv().unwrap(), Reconnected);
+    }
+
+    #[test]
+    fn other_request_is_not_retried() {
+        let (client, connections) = lost_connection();
+        let result: Result<serde_json::Value, _> =
+            block_on(client.request("sendrawtransaction", rpc_params!["00"]));
+        assert!(matches!(
+            result,
+            Err(jsonrpsee::core::Error::RestartNeeded(_))
+        ));
+        assert_eq!(connections.load(Ordering::SeqCst), 2);
+        assert!(block_on(client.current()).methods().is_empty());
+        let count: usize = block_on(client.request("getblockcount", rpc_params![])).unwrap();
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn batch_is_retried_only_if_read_only() {
+        let (client, _) = lost_connection();
+        let mut batch = BatchRequestBuilder::new();
+        batch.insert("getblockcount", rpc_params![]).unwrap();
+        batch
+            .insert("sendrawtransaction", rpc_params!["00"])
+            .unwrap();
+        let result = block_on(client.batch_request::<serde_json::Value>(batch));
+        assert!(matches!(
+            result,
+            Err(jsonrpsee::core::Error::RestartNeeded(_))
+        ));
+
+        let (client, connections) = lost_connection();
+        let mut batch = BatchRequestBuilder::new();
+        batch.insert("getblockcount", rpc_params![]).unwrap();
+        batch.insert("getbestblockhash", rpc_params![]).unwrap();
+        let response = block_on(client.batch_request::<serde_json::Value>(batch)).unwrap();
+        assert_eq!(response.num_successful_calls(), 2);
+        assert_eq!(connections.load(Ordering::SeqCst), 2);
+    }
+}
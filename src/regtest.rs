@@ -0,0 +1,265 @@
+//! Helpers for driving a regtest mainchain node from integration tests.
+use crate::client::{ChainClient, WalletClient};
+use crate::Error;
+use jsonrpsee::core::client::ClientT;
+use std::future::Future;
+
+/// Confirmations a coinbase output needs before it can be spent.
+pub const COINBASE_MATURITY: u32 = 100;
+
+/// Blocks that were disconnected and connected by a reorg, in height order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reorg {
+    pub disconnected: Vec<bitcoin::BlockHash>,
+    pub connected: Vec<bitcoin::BlockHash>,
+}
+
+/// Reorganizes the chain onto a new branch of `new_branch_len + 1` blocks
+/// mined on top of `fork_point`, paying the coinbases to `address`.
+///
+/// The current branch is invalidated while the new branch is mined and
+/// reconsidered afterwards, so the new branch must end up with more work
+/// than the current one for the reorg to stick.
+pub async fn force_reorg<C>(
+    client: &C,
+    fork_point: bitcoin::BlockHash,
+    new_branch_len: u32,
+    address: &bitcoin::Address<bitcoin::address::NetworkUnchecked>,
+) -> Result<Reorg, Error>
+where
+    C: ClientT + Sync,
+{
+    let fork_height = client.getblockheader(&fork_point).await?.height;
+    if client.getblockhash(fork_height).await? != fork_point {
+        return Err(Error::NotInMainChain {
+            block_hash: fork_point,
+        });
+    }
+    let tip_height = client.getblockcount().await?;
+    let mut disconnected = Vec::with_capacity(tip_height - fork_height);
+    for height in fork_height + 1..=tip_height {
+        disconnected.push(client.getblockhash(height).await?);
+    }
+    if let Some(first_disconnected) = disconnected.first() {
+        client.invalidateblock(first_disconnected).await?;
+    }
+    let connected = client
+        .generatetoaddress(new_branch_len + 1, address)
+        .await?;
+    if let Some(first_disconnected) = disconnected.first() {
+        client.reconsiderblock(first_disconnected).await?;
+    }
+    let tip = client.getbestblockhash().await?;
+    if let Some(expected) = connected.last() {
+        if tip != *expected {
+            return Err(Error::UnexpectedTip {
+                expected: *expected,
+                actual: tip,
+            });
+        }
+    }
+    Ok(Reorg {
+        disconnected,
+        connected,
+    })
+}
+
+/// Mines `n_spendable_coinbases + COINBASE_MATURITY` blocks paying to
+/// `address`, after which the coinbases of the first
+/// `n_spendable_coinbases` of them are spendable.
+pub async fn mine_and_mature<C>(
+    client: &C,
+    address: &bitcoin::Address<bitcoin::address::NetworkUnchecked>,
+    n_spendable_coinbases: u32,
+) -> Result<Vec<bitcoin::BlockHash>, Error>
+where
+    C: ClientT + Sync,
+{
+    let tip = client.getbestblockhash().await?;
+    let block_hashes = client
+        .generatetoaddress(n_spendable_coinbases + COINBASE_MATURITY, address)
+        .await?;
+    check_extends(client, tip, &block_hashes).await?;
+    Ok(block_hashes)
+}
+
+/// Mines one block at a time paying to `address` until `predicate` returns
+/// `true`, e.g. until a withdrawal bundle has enough work score. The
+/// predicate is checked before every block, so nothing is mined if it
+/// already holds.
+///
+/// Fails with [`Error::MaxBlocksMined`] if the predicate is still `false`
+/// after mining `max_blocks` blocks.
+pub async fn mine_until<'a, C, F, Fut>(
+    client: &'a C,
+    address: &bitcoin::Address<bitcoin::address::NetworkUnchecked>,
+    max_blocks: u32,
+    mut predicate: F,
+) -> Result<Vec<bitcoin::BlockHash>, Error>
+where
+    C: ClientT + Sync,
+    F: FnMut(&'a C) -> Fut,
+    Fut: Future<Output = Result<bool, Error>>,
+{
+    let mut block_hashes = vec![];
+    loop {
+        if predicate(client).await? {
+            return Ok(block_hashes);
+        }
+        if block_hashes.len() >= max_blocks as usize {
+            return Err(Error::MaxBlocksMined { max_blocks });
+        }
+        let tip = client.getbestblockhash().await?;
+        let mined = client.generatetoaddress(1, address).await?;
+        check_extends(client, tip, &mined).await?;
+        block_hashes.extend(mined);
+    }
+}
+
+/// Blocks mined by [`mine_to_wallet`] and the address they pay to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MinedToWallet {
+    pub block_hashes: Vec<bitcoin::BlockHash>,
+    pub address: bitcoin::Address,
+}
+
+/// Mines `n_blocks` blocks paying to a fresh address of the wallet `wallet`
+/// is connected to, e.g. a client built with [`crate::new_wallet_client`].
+///
+/// Returns [`Error::MiningNotAllowed`] unless the node is on regtest or
+/// signet.
+pub async fn mine_to_wallet<C, W>(
+    client: &C,
+    wallet: &W,
+    n_blocks: u32,
+) -> Result<MinedToWallet, Error>
+where
+    C: ClientT + Sync,
+    W: ClientT + Sync,
+{
+    let chain = client.getblockchaininfo().await?.value.chain;
+    let network =
+        bitcoin::Network::from_core_arg(&chain).map_err(|_| Error::UnknownChain { chain })?;
+    if !matches!(
+        network,
+        bitcoin::Network::Regtest | bitcoin::Network::Signet
+    ) {
+        return Err(Error::MiningNotAllowed { network });
+    }
+    let address = wallet
+        .getnewaddress(None, None)
+        .await?
+        .require_network(network)
+        .map_err(|_| Error::InvalidParam {
+            name: "address",
+            reason: format!("node returned an address that is not valid for {network}"),
+        })?;
+    let block_hashes = client
+        .generatetoaddress(n_blocks, &crate::unchecked(&address))
+        .await?;
+    Ok(MinedToWallet {
+        block_hashes,
+        address,
+    })
+}
+
+/// Checks that `block_hashes` were mined one on top of the other, starting
+/// on `parent`, and that the last one is the tip. Otherwise another process
+/// mined meanwhile.
+async fn check_extends<C>(
+    client: &C,
+    mut parent: bitcoin::BlockHash,
+    block_hashes: &[bitcoin::BlockHash],
+) -> Result<(), Error>
+where
+    C: ClientT + Sync,
+{
+    for block_hash in block_hashes {
+        let header = client.getblockheader(block_hash).await?;
+        if header.previousblockhash != Some(parent) {
+            return Err(Error::ConcurrentMining {
+                block_hash: *block_hash,
+                expected_parent: parent,
+            });
+        }
+        parent = *block_hash;
+    }
+    let tip = client.getbestblockhash().await?;
+    if tip != parent {
+        return Err(Error::ConcurrentMining {
+            block_hash: tip,
+            expected_parent: parent,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{block_on, MockChain, MockClient};
+
+    fn address() -> bitcoin::Address<bitcoin::address::NetworkUnchecked> {
+        "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080"
+            .parse()
+            .unwrap()
+    }
+
+    fn client(chain: &MockChain) -> MockClient {
+        let chain = chain.clone();
+        MockClient::new(move |method, params| chain.handle(method, params).unwrap())
+    }
+
+    #[test]
+    fn mine_and_mature_mines_past_maturity() {
+        let chain = MockChain::new();
+        let client = client(&chain);
+        let block_hashes = block_on(mine_and_mature(&client, &address(), 2)).unwrap();
+        assert_eq!(block_hashes.len(), 2 + COINBASE_MATURITY as usize);
+        assert_eq!(block_hashes.last(), Some(&chain.tip()));
+    }
+
+    #[test]
+    fn mine_until_stops_once_predicate_holds() {
+        let chain = MockChain::new();
+        let client = client(&chain);
+        let block_hashes = block_on(mine_until(&client, &address(), 10, |client| async move {
+            Ok(client.getblockcount().await? >= 5)
+        }))
+        .unwrap();
+        assert_eq!(block_hashes.len(), 5);
+        assert_eq!(chain.height(), 5);
+
+        let block_hashes =
+            block_on(mine_until(&client, &address(), 10, |_| async { Ok(true) })).unwrap();
+        assert!(block_hashes.is_empty());
+        assert_eq!(chain.height(), 5);
+    }
+
+    #[test]
+    fn mine_until_gives_up_after_max_blocks() {
+        let chain = MockChain::new();
+        let client = client(&chain);
+        let err =
+            block_on(mine_until(&client, &address(), 3, |_| async { Ok(false) })).unwrap_err();
+        assert!(matches!(err, Error::MaxBlocksMined { max_blocks: 3 }));
+        assert_eq!(chain.height(), 3);
+    }
+
+    #[test]
+    fn concurrent_mining_is_detected() {
+        let chain = MockChain::new();
+        let other_miner = chain.clone();
+        let client = {
+            let chain = chain.clone();
+            MockClient::new(move |method, params| {
+                if method == "generatetoaddress" {
+                    other_miner.mine(1);
+                }
+                chain.handle(method, params).unwrap()
+            })
+        };
+        let err = block_on(mine_and_mature(&client, &address(), 1)).unwrap_err();
+        assert!(matches!(err, Error::ConcurrentMining { .. }));
+    }
+}
@@ -0,0 +1,415 @@
+//! Getting stuck wallet transactions, such as deposits paying less than the
+//! mempool minimum fee, confirmed.
+use crate::client::{
+    BumpFee, BumpFeeOptions, ChainClient, FundRawTransactionOptions, WalletClient,
+};
+use crate::Error;
+use jsonrpsee::core::client::ClientT;
+use std::fmt;
+
+/// Code of the errors `bumpfee` rejects a transaction with.
+const RPC_WALLET_ERROR: i32 = -4;
+
+/// Why `bumpfee` refused to replace a transaction. A child paying for the
+/// parent may still work in these cases.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BumpFeeRefusal {
+    /// Some inputs are not known to the wallet, so it can't sign a
+    /// replacement.
+    ForeignInputs,
+    NotReplaceable,
+    /// Replacing the transaction would evict its descendants.
+    HasDescendants,
+    AlreadyBumped,
+}
+
+impl BumpFeeRefusal {
+    fn from_message(message: &str) -> Option<Self> {
+        if message.starts_with("Transaction contains inputs that don't belong to this wallet") {
+            Some(BumpFeeRefusal::ForeignInputs)
+        } else if message.starts_with("Transaction is not BIP 125 replaceable") {
+            Some(BumpFeeRefusal::NotReplaceable)
+        } else if message.starts_with("Transaction has descendants") {
+            Some(BumpFeeRefusal::HasDescendants)
+        } else if message.starts_with("Transaction has already been bumped") {
+            Some(BumpFeeRefusal::AlreadyBumped)
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for BumpFeeRefusal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            BumpFeeRefusal::ForeignInputs => "it has inputs that don't belong to the wallet",
+            BumpFeeRefusal::NotReplaceable => "it does not signal replaceability",
+            BumpFeeRefusal::HasDescendants => "it has descendants",
+            BumpFeeRefusal::AlreadyBumped => "it was already bumped",
+        })
+    }
+}
+
+/// Calls `bumpfee`, returning [`Error::BumpFeeRefused`] if the node refuses
+/// to replace the transaction.
+pub async fn bump_fee<C>(
+    client: &C,
+    txid: &bitcoin::Txid,
+    options: Option<&BumpFeeOptions>,
+) -> Result<BumpFee, Error>
+where
+    C: ClientT + Sync,
+{
+    client
+        .bumpfee(txid, options)
+        .await
+        .map_err(|err| match err {
+            jsonrpsee::core::Error::Call(err) if err.code() == RPC_WALLET_ERROR => {
+                match BumpFeeRefusal::from_message(err.message()) {
+                    Some(reason) => Error::BumpFeeRefused {
+                        txid: *txid,
+                        reason,
+                    },
+                    None => Error::Jsonrpsee(jsonrpsee::core::Error::Call(err)),
+                }
+            }
+            err => err.into(),
+        })
+}
+
+/// How a stuck transaction was rescued.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Rescue {
+    /// The transaction was replaced.
+    Bumped(BumpFee),
+    /// A child spending a wallet output of the transaction was broadcast.
+    ChildPaysForParent {
+        child_txid: bitcoin::Txid,
+        fee: bitcoin::Amount,
+    },
+}
+
+/// Gets the wallet transaction `txid` to pay at least `target_feerate`,
+/// by replacing it with `bumpfee` or, if the node refuses that, by
+/// broadcasting a child that spends one of its wallet outputs and pays
+/// enough for both to reach `target_feerate` together.
+pub async fn rescue_stuck_tx<C>(
+    client: &C,
+    txid: &bitcoin::Txid,
+    target_feerate: bitcoin::FeeRate,
+) -> Result<Rescue, Error>
+where
+    C: ClientT + Sync,
+{
+    let sat_per_vb = target_feerate.to_sat_per_kwu() as f64 * 4.0 / 1000.0;
+    let options = BumpFeeOptions {
+        fee_rate: Some(sat_per_vb),
+        ..Default::default()
+    };
+    match bump_fee(client, txid, Some(&options)).await {
+        Ok(bumped) => return Ok(Rescue::Bumped(bumped)),
+        Err(Error::BumpFeeRefused { .. }) => (),
+        Err(err) => return Err(err),
+    }
+
+    let parent = client.getmempoolentry(txid).await?;
+    let parent_hex = client.getrawtransaction(txid, false, None).await?;
+    let mut wallet_vout = None;
+    for output in client.decoderawtransaction(&parent_hex, None).await?.vout {
+        let Some(address) = output.script_pub_key.address else {
+            continue;
+        };
+        if client.getaddressinfo(&address).await?.ismine {
+            wallet_vout = Some(output.n);
+            break;
+        }
+    }
+    let vout = wallet_vout.ok_or(Error::NoWalletOutput { txid: *txid })?;
+    let child = bitcoin::Transaction {
+        version: 2,
+        lock_time: bitcoin::absolute::LockTime::ZERO,
+        input: vec![bitcoin::TxIn {
+            previous_output: bitcoin::OutPoint { txid: *txid, vout },
+            ..Default::default()
+        }],
+        output: vec![],
+    };
+    let child_hex = bitcoin::consensus::encode::serialize_hex(&child);
+
+    // Funding at the target rate first tells us how large the wallet
+    // expects the child to be, which gives the rate the child needs to
+    // also cover what the parent is short of.
+    let mut options = FundRawTransactionOptions {
+        fee_rate: Some(sat_per_vb),
+        ..Default::default()
+    };
+    let mut funded = client
+        .fundrawtransaction(&child_hex, Some(&options))
+        .await?;
+    let target_parent_fee = target_feerate.to_sat_per_kwu() * parent.vsize * 4 / 1000;
+    let parent_shortfall = target_parent_fee.saturating_sub(parent.fees.modified.to_sat());
+    if parent_shortfall > 0 {
+        let child_vsize = funded.fee.to_sat() as f64 / sat_per_vb;
+        options.fee_rate = Some(sat_per_vb + parent_shortfall as f64 / child_vsize);
+        funded = client
+            .fundrawtransaction(&child_hex, Some(&options))
+            .await?;
+    }
+    let signed = client.signrawtransactionwithwallet(&funded.hex).await?;
+    if !signed.complete {
+        let errors = signed.errors.into_iter().map(|err| err.error).collect();
+        return Err(Error::IncompleteSignature { errors });
+    }
+    let child_txid = client.sendrawtransaction(&signed.hex).await?;
+    Ok(Rescue::ChildPaysForParent {
+        child_txid,
+        fee: funded.fee.0,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::SidechainId;
+    use crate::mock::{block_on, mempool_entry, rpc_error, serve, MockClient};
+    use crate::Drivechain;
+    use bitcoin::hashes::hex::FromHex as _;
+    use bitcoin::hashes::Hash as _;
+    use serde_json::{json, Value};
+    use std::str::FromStr as _;
+
+    fn txid(byte: u8) -> bitcoin::Txid {
+        bitcoin::Txid::from_byte_array([byte; 32])
+    }
+
+    /// A parent paying to an address of someone else, then to one of the
+    /// wallet.
+    struct Parent {
+        tx: bitcoin::Transaction,
+        foreign: bitcoin::Address,
+        ours: bitcoin::Address,
+    }
+
+    impl Parent {
+        fn new() -> Self {
+            let foreign =
+                bitcoin::Address::p2wsh(&bitcoin::ScriptBuf::new(), bitcoin::Network::Regtest);
+            let ours = bitcoin::Address::p2wsh(
+                &bitcoin::ScriptBuf::from(vec![0x51]),
+                bitcoin::Network::Regtest,
+            );
+            let tx = bitcoin::Transaction {
+                version: 2,
+                lock_time: bitcoin::absolute::LockTime::ZERO,
+                input: vec![],
+                output: [&foreign, &ours]
+                    .into_iter()
+                    .map(|address| bitcoin::TxOut {
+                        value: 50_000,
+                        script_pubkey: address.script_pubkey(),
+                    })
+                    .collect(),
+            };
+            Parent { tx, foreign, ours }
+        }
+
+        fn decoded(&self) -> Value {
+            let vout: Vec<_> = self
+                .tx
+                .output
+                .iter()
+                .zip([&self.foreign, &self.ours])
+                .enumerate()
+                .map(|(n, (output, address))| {
+                    json!({
+                        "value": 0.0005,
+                        "n": n,
+                        "scriptPubKey": {
+                            "asm": "",
+                            "hex": output.script_pubkey,
+                            "address": address.to_string(),
+                            "type": "witness_v0_scripthash",
+                        },
+                    })
+                })
+                .collect();
+            json!({
+                "txid": self.tx.txid(),
+                "hash": self.tx.wtxid(),
+                "version": 2,
+                "size": self.tx.size(),
+                "vsize": self.tx.vsize(),
+                "weight": self.tx.weight().to_wu(),
+                "locktime": 0,
+                "vin": [],
+                "vout": vout,
+            })
+        }
+
+        fn address_info(&self, address: &str) -> Value {
+            let ismine = address == self.ours.to_string();
+            json!({
+                "address": address,
+                "scriptPubKey": bitcoin::Address::from_str(address)
+                    .unwrap()
+                    .assume_checked()
+                    .script_pubkey(),
+                "ismine": ismine,
+                "iswatchonly": false,
+                "solvable": ismine,
+                "isscript": true,
+                "ischange": false,
+                "iswitness": true,
+            })
+        }
+    }
+
+    fn refused(txid: bitcoin::Txid, parent: Parent) -> MockClient {
+        let parent_hex = bitcoin::consensus::encode::serialize_hex(&parent.tx);
+        MockClient::new(move |method, params| match method {
+            "bumpfee" => Err(rpc_error(
+                RPC_WALLET_ERROR,
+                "Transaction is not BIP 125 replaceable",
+            )),
+            "getmempoolentry" => Ok(mempool_entry(txid)),
+            "getrawtransaction" => Ok(json!(parent_hex)),
+            "decoderawtransaction" => Ok(parent.decoded()),
+            "getaddressinfo" => Ok(parent.address_info(params[0].as_str().unwrap())),
+            // 110 vB at the requested rate
+            "fundrawtransaction" => {
+                let fee_rate = params[1]["fee_rate"].as_f64().unwrap();
+                let fee = bitcoin::Amount::from_sat((fee_rate * 110.0).round() as u64);
+                Ok(json!({
+                    "hex": params[0],
+                    "fee": fee.to_btc(),
+                    "changepos": 0,
+                }))
+            }
+            "signrawtransactionwithwallet" => Ok(json!({"hex": "signed", "complete": true})),
+            "sendrawtransaction" => Ok(json!(self::txid(2))),
+            _ => unreachable!("{method}"),
+        })
+    }
+
+    #[test]
+    fn bumps_replaceable_transactions() {
+        let client = MockClient::new(|method, _| {
+            assert_eq!(method, "bumpfee");
+            Ok(json!({
+                "txid": txid(2),
+                "origfee": 0.00000141,
+                "fee": 0.0000141,
+                "errors": [],
+            }))
+        });
+        let target = bitcoin::FeeRate::from_sat_per_vb(10).unwrap();
+        let rescue = block_on(rescue_stuck_tx(&client, &txid(1), target)).unwrap();
+        let Rescue::Bumped(bumped) = rescue else {
+            panic!("{rescue:?}");
+        };
+        assert_eq!(bumped.txid, txid(2));
+        assert_eq!(bumped.fee.0, bitcoin::Amount::from_sat(1410));
+        let calls = client.calls();
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].1[0], json!(txid(1)));
+        assert_eq!(calls[0].1[1]["fee_rate"], json!(10.0));
+    }
+
+    #[test]
+    fn child_pays_for_a_non_replaceable_parent() {
+        let parent = Parent::new();
+        let parent_txid = parent.tx.txid();
+        let client = refused(parent_txid, parent);
+        let target = bitcoin::FeeRate::from_sat_per_vb(10).unwrap();
+        let rescue = block_on(rescue_stuck_tx(&client, &parent_txid, target)).unwrap();
+        // The parent pays 141 sats of the 1410 it needs, so the child pays
+        // its own 1100 and the 1269 missing
+        assert_eq!(
+            rescue,
+            Rescue::ChildPaysForParent {
+                child_txid: txid(2),
+                fee: bitcoin::Amount::from_sat(2369),
+            }
+        );
+        assert_eq!(
+            client.methods(),
+            [
+                "bumpfee",
+                "getmempoolentry",
+                "getrawtransaction",
+                "decoderawtransaction",
+                "getaddressinfo",
+                "getaddressinfo",
+                "fundrawtransaction",
+                "fundrawtransaction",
+                "signrawtransactionwithwallet",
+                "sendrawtransaction",
+            ]
+        );
+        let calls = client.calls();
+        // The child spends the wallet output of the parent
+        let child_hex = calls[6].1[0].as_str().unwrap();
+        let child: bitcoin::Transaction =
+            bitcoin::consensus::encode::deserialize(&Vec::from_hex(child_hex).unwrap()).unwrap();
+        assert_eq!(
+            child.input[0].previous_output,
+            bitcoin::OutPoint {
+                txid: parent_txid,
+                vout: 1
+            }
+        );
+        assert_eq!(calls[6].1[1]["fee_rate"], json!(10.0));
+        assert_eq!(calls[9].1[0], json!("signed"));
+    }
+
+    #[test]
+    fn other_bumpfee_errors_are_returned() {
+        let client = MockClient::new(|_, _| Err(rpc_error(RPC_WALLET_ERROR, "Insufficient funds")));
+        let target = bitcoin::FeeRate::from_sat_per_vb(10).unwrap();
+        let err = block_on(rescue_stuck_tx(&client, &txid(1), target)).unwrap_err();
+        assert!(
+            matches!(&err, Error::Jsonrpsee(jsonrpsee::core::Error::Call(err)) if err.code() == RPC_WALLET_ERROR),
+            "{err:?}"
+        );
+        assert_eq!(client.methods(), ["bumpfee"]);
+
+        let client = MockClient::new(|_, _| {
+            Err(rpc_error(
+                RPC_WALLET_ERROR,
+                "Transaction has descendants in the wallet",
+            ))
+        });
+        let err = block_on(bump_fee(&client, &txid(1), None)).unwrap_err();
+        assert!(matches!(
+            err,
+            Error::BumpFeeRefused {
+                reason: BumpFeeRefusal::HasDescendants,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn abandons_transactions() {
+        let (addr, node) = serve(MockClient::new(|method, params| {
+            assert_eq!(method, "abandontransaction");
+            if params[0] == json!(txid(1)) {
+                Ok(Value::Null)
+            } else {
+                Err(rpc_error(-5, "Invalid or non-wallet transaction id"))
+            }
+        }));
+        block_on(async {
+            let drivechain = Drivechain::new(SidechainId(0), addr, "user", "password").unwrap();
+            drivechain.abandon_transaction(&txid(1)).await.unwrap();
+            let err = drivechain.abandon_transaction(&txid(2)).await.unwrap_err();
+            assert!(
+                matches!(&err, Error::Jsonrpsee(jsonrpsee::core::Error::Call(err)) if err.code() == -5),
+                "{err:?}"
+            );
+        });
+        let calls = node.calls();
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0].1, [json!(txid(1))]);
+    }
+}
@@ -0,0 +1,152 @@
+//! Server side adapter for building RPC proxies in front of a mainchain
+//! node.
+use crate::cache::RawParams;
+use jsonrpsee::core::client::ClientT;
+use jsonrpsee::core::server::RpcModule;
+use jsonrpsee::types::error::{ErrorObject, ErrorObjectOwned, INTERNAL_ERROR_CODE};
+use jsonrpsee::types::Params;
+use serde_json::value::RawValue;
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+/// Methods registered by [`PassthroughServer::into_rpc`]: every RPC method
+/// of the client traits, see [`crate::introspection::methods`].
+pub fn passthrough_methods() -> BTreeSet<&'static str> {
+    crate::introspection::methods()
+        .iter()
+        .map(|method| method.rpc_name)
+        .collect()
+}
+
+/// Called with the method name and raw params before a call is forwarded.
+/// Returning an error rejects the call with that error.
+type BeforeCall =
+    Arc<dyn Fn(&str, Option<&RawValue>) -> Result<(), ErrorObjectOwned> + Send + Sync>;
+/// Called with the method name and the response to be returned.
+type AfterCall = Arc<dyn Fn(&str, &Result<Box<RawValue>, ErrorObjectOwned>) + Send + Sync>;
+
+/// Forwards every method in [`passthrough_methods`] unchanged to `inner`,
+/// with optional hooks to inspect or reject calls.
+///
+/// ```no_run
+/// # async fn example(inner: jsonrpsee::http_client::HttpClient) {
+/// let module = bip300301::server::PassthroughServer::new(inner)
+///     .before_call(|method, _params| match method {
+///         "stop" => Err(jsonrpsee::types::error::ErrorObject::owned(
+///             -32601,
+///             "method disabled",
+///             None::<()>,
+///         )),
+///         _ => Ok(()),
+///     })
+///     .into_rpc();
+/// # }
+/// ```
+pub struct PassthroughServer<C> {
+    inner: C,
+    before_call: Option<BeforeCall>,
+    after_call: Option<AfterCall>,
+}
+
+impl<C> PassthroughServer<C>
+where
+    C: ClientT + Send + Sync + 'static,
+{
+    pub fn new(inner: C) -> Self {
+        PassthroughServer {
+            inner,
+            before_call: None,
+            after_call: None,
+        }
+    }
+
+    pub fn before_call<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&str, Option<&RawValue>) -> Result<(), ErrorObjectOwned> + Send + Sync + 'static,
+    {
+        self.before_call = Some(Arc::new(hook));
+        self
+    }
+
+    pub fn after_call<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&str, &Result<Box<RawValue>, ErrorObjectOwned>) + Send + Sync + 'static,
+    {
+        self.after_call = Some(Arc::new(hook));
+        self
+    }
+
+    /// Builds a module to mount on a `jsonrpsee::server::Server`.
+    pub fn into_rpc(self) -> RpcModule<Self> {
+        let mut module = RpcModule::new(self);
+        for method in passthrough_methods() {
+            module
+                .register_async_method(method, move |params, server| async move {
+                    server.forward(method, params).await
+                })
+                .expect("method names are unique");
+        }
+        module
+    }
+
+    async fn forward(
+        &self,
+        method: &str,
+        params: Params<'static>,
+    ) -> Result<Box<RawValue>, ErrorObjectOwned> {
+        let params: Option<Box<RawValue>> = params.parse()?;
+        if let Some(before_call) = &self.before_call {
+            before_call(method, params.as_deref())?;
+        }
+        let response = match self.inner.request(method, RawParams(params)).await {
+            Ok(response) => Ok(response),
+            Err(jsonrpsee::core::Error::Call(err)) => Err(err),
+            Err(err) => Err(internal_error(&err)),
+        };
+        if let Some(after_call) = &self.after_call {
+            after_call(method, &response);
+        }
+        response
+    }
+}
+
+fn internal_error(err: &dyn std::fmt::Display) -> ErrorObjectOwned {
+    ErrorObject::owned(INTERNAL_ERROR_CODE, err.to_string(), None::<()>)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{block_on, rpc_error, MockClient};
+    use jsonrpsee::rpc_params;
+    use serde_json::json;
+
+    #[test]
+    fn registers_every_client_method() {
+        let module = PassthroughServer::new(MockClient::new(|_, _| Ok(json!(null)))).into_rpc();
+        let registered: BTreeSet<_> = module.method_names().collect();
+        assert_eq!(registered, passthrough_methods());
+        assert!(registered.contains("getblockcount"));
+        assert!(registered.contains("listsidechaindepositsbyblock"));
+    }
+
+    #[test]
+    fn forwards_calls_and_errors() {
+        let module = PassthroughServer::new(MockClient::new(|method, params| match method {
+            "getblockhash" => Ok(json!(format!("hash at {}", params[0]))),
+            _ => Err(rpc_error(-5, "Block not found")),
+        }))
+        .before_call(|method, _| match method {
+            "stop" => Err(ErrorObject::owned(-32601, "method disabled", None::<()>)),
+            _ => Ok(()),
+        })
+        .into_rpc();
+        let hash: String = block_on(module.call("getblockhash", rpc_params![7])).unwrap();
+        assert_eq!(hash, "hash at 7");
+        let err =
+            block_on(module.call::<_, String>("getblockheader", rpc_params!["00"])).unwrap_err();
+        assert!(matches!(err, jsonrpsee::core::Error::Call(err) if err.code() == -5));
+        let err = block_on(module.call::<_, String>("stop", rpc_params![])).unwrap_err();
+        assert!(matches!(err, jsonrpsee::core::Error::Call(err) if err.code() == -32601));
+    }
+}
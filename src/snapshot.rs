@@ -0,0 +1,457 @@
+//! Snapshots of the drivechain state of a mainchain node, to back it up or
+//! to compare nodes.
+#[cfg(feature = "client")]
+use crate::batch::{Batch, BatchItemResult};
+#[cfg(feature = "client")]
+use crate::client::ChainClient;
+use crate::client::{Ctip, FailedWithdrawal, SidechainId, SpentWithdrawal, WithdrawalStatus};
+#[cfg(feature = "client")]
+use crate::Error;
+#[cfg(feature = "client")]
+use jsonrpsee::core::client::ClientT;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Attempts made by [`snapshot_sidechain_state`] by default.
+pub const DEFAULT_SNAPSHOT_ATTEMPTS: u32 = 3;
+
+/// Drivechain state of a mainchain node at a single block.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct SidechainStateSnapshot {
+    pub at_block: bitcoin::BlockHash,
+    /// Sidechains without a CTIP are left out.
+    pub ctips: BTreeMap<SidechainId, Ctip>,
+    /// Pending withdrawal bundles of every sidechain with a CTIP. Sidechains
+    /// without pending bundles are left out.
+    pub withdrawal_statuses: BTreeMap<SidechainId, Vec<WithdrawalStatus>>,
+    pub spent_withdrawals: Vec<SpentWithdrawal>,
+    pub failed_withdrawals: Vec<FailedWithdrawal>,
+}
+
+#[cfg(feature = "client")]
+/// Takes a snapshot, retrying up to `max_attempts` times if the tip changes
+/// while it is taken. Returns [`Error::InconsistentSnapshot`] if it changed
+/// every time.
+///
+/// The state is fetched in two batches, so the client must support batch
+/// requests.
+pub async fn snapshot_sidechain_state<C>(
+    client: &C,
+    max_attempts: u32,
+) -> Result<SidechainStateSnapshot, Error>
+where
+    C: ClientT + Sync,
+{
+    for _ in 0..max_attempts {
+        let at_block = client.getbestblockhash().await?;
+        let mut snapshot = SidechainStateSnapshot {
+            at_block,
+            ctips: BTreeMap::new(),
+            withdrawal_statuses: BTreeMap::new(),
+            spent_withdrawals: vec![],
+            failed_withdrawals: vec![],
+        };
+
+        let batch = SidechainId::ALL.into_iter().fold(
+            Batch::new()
+                .list_spent_withdrawals()
+                .list_failed_withdrawals(),
+            |batch, sidechain| batch.list_sidechain_ctip(sidechain),
+        );
+        // `send` checks that every request was answered
+        let mut responses = batch.send(client).await?.into_iter();
+        let Some(BatchItemResult::SpentWithdrawals(spent)) = responses.next().transpose()? else {
+            return Err(Error::UnexpectedBatchResult {
+                method: "listspentwithdrawals",
+            });
+        };
+        snapshot.spent_withdrawals = spent;
+        let Some(BatchItemResult::FailedWithdrawals(failed)) = responses.next().transpose()? else {
+            return Err(Error::UnexpectedBatchResult {
+                method: "listfailedwithdrawals",
+            });
+        };
+        snapshot.failed_withdrawals = failed;
+        for (sidechain, response) in SidechainId::ALL.into_iter().zip(responses) {
+            match response {
+                Ok(BatchItemResult::SidechainCtip(ctip)) => {
+                    snapshot.ctips.insert(sidechain, ctip);
+                }
+                Ok(_) => {
+                    return Err(Error::UnexpectedBatchResult {
+                        method: "listsidechainctip",
+                    })
+                }
+                Err(Error::Jsonrpsee(jsonrpsee::core::Error::Call(err)))
+                    if crate::ctip::is_no_ctip_error(&err) => {}
+                Err(err) => return Err(err),
+            }
+        }
+
+        let responses = snapshot
+            .ctips
+            .keys()
+            .fold(Batch::new(), |batch, sidechain| {
+                batch.list_withdrawal_status(*sidechain)
+            })
+            .send(client)
+            .await?;
+        for (sidechain, response) in snapshot.ctips.keys().zip(responses) {
+            match response? {
+                BatchItemResult::WithdrawalStatus(statuses) if statuses.is_empty() => {}
+                BatchItemResult::WithdrawalStatus(statuses) => {
+                    snapshot.withdrawal_statuses.insert(*sidechain, statuses);
+                }
+                _ => {
+                    return Err(Error::UnexpectedBatchResult {
+                        method: "listwithdrawalstatus",
+                    })
+                }
+            }
+        }
+
+        if client.getbestblockhash().await? == at_block {
+            return Ok(snapshot);
+        }
+    }
+    Err(Error::InconsistentSnapshot {
+        attempts: max_attempts,
+    })
+}
+
+/// A difference between two snapshots, with the value in the snapshot
+/// [`SidechainStateSnapshot::diff`] was called on first.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum SidechainStateChange {
+    Ctip {
+        sidechain: SidechainId,
+        ours: Option<Ctip>,
+        theirs: Option<Ctip>,
+    },
+    WithdrawalStatus {
+        sidechain: SidechainId,
+        bundle_txid: bitcoin::Txid,
+        ours: Option<WithdrawalStatus>,
+        theirs: Option<WithdrawalStatus>,
+    },
+    SpentWithdrawal {
+        bundle_txid: bitcoin::Txid,
+        ours: Option<SpentWithdrawal>,
+        theirs: Option<SpentWithdrawal>,
+    },
+    FailedWithdrawal {
+        bundle_txid: bitcoin::Txid,
+        ours: Option<FailedWithdrawal>,
+        theirs: Option<FailedWithdrawal>,
+    },
+}
+
+impl fmt::Display for SidechainStateChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn side<T: fmt::Debug>(value: &Option<T>) -> String {
+            match value {
+                Some(value) => format!("{value:?}"),
+                None => "missing".to_owned(),
+            }
+        }
+        match self {
+            SidechainStateChange::Ctip {
+                sidechain,
+                ours,
+                theirs,
+            } => write!(
+                f,
+                "sidechain {}: CTIP {} vs {}",
+                sidechain.0,
+                side(ours),
+                side(theirs)
+            ),
+            SidechainStateChange::WithdrawalStatus {
+                sidechain,
+                bundle_txid,
+                ours,
+                theirs,
+            } => write!(
+                f,
+                "sidechain {}: withdrawal bundle {bundle_txid} {} vs {}",
+                sidechain.0,
+                side(ours),
+                side(theirs)
+            ),
+            SidechainStateChange::SpentWithdrawal {
+                bundle_txid,
+                ours,
+                theirs,
+            } => write!(
+                f,
+                "spent withdrawal bundle {bundle_txid}: {} vs {}",
+                side(ours),
+                side(theirs)
+            ),
+            SidechainStateChange::FailedWithdrawal {
+                bundle_txid,
+                ours,
+                theirs,
+            } => write!(
+                f,
+                "failed withdrawal bundle {bundle_txid}: {} vs {}",
+                side(ours),
+                side(theirs)
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct SidechainStateDiff {
+    /// `at_block` of both snapshots. Snapshots at different blocks are
+    /// expected to differ.
+    pub at_blocks: (bitcoin::BlockHash, bitcoin::BlockHash),
+    pub changes: Vec<SidechainStateChange>,
+}
+
+impl SidechainStateDiff {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+impl fmt::Display for SidechainStateDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (ours, theirs) = self.at_blocks;
+        if ours != theirs {
+            writeln!(f, "at block {ours} vs {theirs}")?;
+        }
+        for change in &self.changes {
+            writeln!(f, "{change}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Pairs up the entries of both snapshots by key, and returns the pairs
+/// that differ.
+fn diff_entries<'a, K, V>(
+    ours: impl Iterator<Item = (K, &'a V)>,
+    theirs: impl Iterator<Item = (K, &'a V)>,
+) -> Vec<(K, Option<V>, Option<V>)>
+where
+    K: Ord + Copy,
+    V: PartialEq + Clone + 'a,
+{
+    let ours: BTreeMap<K, &V> = ours.collect();
+    let theirs: BTreeMap<K, &V> = theirs.collect();
+    let mut keys: Vec<_> = ours.keys().chain(theirs.keys()).copied().collect();
+    keys.sort();
+    keys.dedup();
+    keys.into_iter()
+        .filter_map(|key| {
+            let ours = ours.get(&key).copied();
+            let theirs = theirs.get(&key).copied();
+            (ours != theirs).then(|| (key, ours.cloned(), theirs.cloned()))
+        })
+        .collect()
+}
+
+impl SidechainStateSnapshot {
+    fn withdrawal_statuses_by_bundle(
+        &self,
+    ) -> impl Iterator<Item = ((SidechainId, bitcoin::Txid), &WithdrawalStatus)> {
+        self.withdrawal_statuses
+            .iter()
+            .flat_map(|(sidechain, statuses)| {
+                statuses
+                    .iter()
+                    .map(move |status| ((*sidechain, status.hash), status))
+            })
+    }
+
+    /// Differences between this snapshot and `other`, in the order CTIPs,
+    /// pending, spent and failed withdrawal bundles.
+    pub fn diff(&self, other: &SidechainStateSnapshot) -> SidechainStateDiff {
+        let mut changes = vec![];
+        let ctips = diff_entries(
+            self.ctips
+                .iter()
+                .map(|(sidechain, ctip)| (*sidechain, ctip)),
+            other
+                .ctips
+                .iter()
+                .map(|(sidechain, ctip)| (*sidechain, ctip)),
+        );
+        for (sidechain, ours, theirs) in ctips {
+            changes.push(SidechainStateChange::Ctip {
+                sidechain,
+                ours,
+                theirs,
+            });
+        }
+        let statuses = diff_entries(
+            self.withdrawal_statuses_by_bundle(),
+            other.withdrawal_statuses_by_bundle(),
+        );
+        for ((sidechain, bundle_txid), ours, theirs) in statuses {
+            changes.push(SidechainStateChange::WithdrawalStatus {
+                sidechain,
+                bundle_txid,
+                ours,
+                theirs,
+            });
+        }
+        let spent = diff_entries(
+            self.spent_withdrawals
+                .iter()
+                .map(|spent| (spent.hash, spent)),
+            other
+                .spent_withdrawals
+                .iter()
+                .map(|spent| (spent.hash, spent)),
+        );
+        for (bundle_txid, ours, theirs) in spent {
+            changes.push(SidechainStateChange::SpentWithdrawal {
+                bundle_txid,
+                ours,
+                theirs,
+            });
+        }
+        let failed = diff_entries(
+            self.failed_withdrawals
+                .iter()
+                .map(|failed| (failed.hash, failed)),
+            other
+                .failed_withdrawals
+                .iter()
+                .map(|failed| (failed.hash, failed)),
+        );
+        for (bundle_txid, ours, theirs) in failed {
+            changes.push(SidechainStateChange::FailedWithdrawal {
+                bundle_txid,
+                ours,
+                theirs,
+            });
+        }
+        SidechainStateDiff {
+            at_blocks: (self.at_block, other.at_block),
+            changes,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "client"))]
+mod tests {
+    use super::*;
+    use crate::mock::{block_on, rpc_error, MockClient};
+    use bitcoin::hashes::Hash as _;
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    fn txid(byte: u8) -> bitcoin::Txid {
+        bitcoin::Txid::from_byte_array([byte; 32])
+    }
+
+    /// Node with CTIPs for sidechains 0 and 1, and a pending bundle of
+    /// sidechain 1 with `work_score`. The tip moves for the first
+    /// `tip_changes` calls of `getbestblockhash`.
+    fn node(work_score: usize, tip_changes: usize) -> MockClient {
+        node_with_ctip_error(work_score, tip_changes, -1)
+    }
+
+    /// Like [`node`], but fails with `ctip_error` for other sidechains.
+    fn node_with_ctip_error(work_score: usize, tip_changes: usize, ctip_error: i32) -> MockClient {
+        let tip_calls = Arc::new(AtomicUsize::new(0));
+        MockClient::new(move |method, params| {
+            Ok(match method {
+                "getbestblockhash" => {
+                    let call = tip_calls.fetch_add(1, Ordering::SeqCst);
+                    json!(bitcoin::BlockHash::from_byte_array(
+                        [call.min(tip_changes) as u8; 32]
+                    ))
+                }
+                "listspentwithdrawals" => json!([]),
+                "listfailedwithdrawals" => json!([{ "nsidechain": 0, "hash": txid(1) }]),
+                "listsidechainctip" => match params[0].as_u64().unwrap() {
+                    sidechain @ (0 | 1) => {
+                        json!({ "txid": txid(10 + sidechain as u8), "n": 0, "amount": 1.5 })
+                    }
+                    _ => return Err(rpc_error(ctip_error, "Invalid Sidechain number")),
+                },
+                "listwithdrawalstatus" => match params[0].as_u64().unwrap() {
+                    1 => json!([{ "hash": txid(2), "nblocksleft": 100, "nworkscore": work_score }]),
+                    _ => json!([]),
+                },
+                method => panic!("unexpected {method}"),
+            })
+        })
+    }
+
+    #[test]
+    fn diff_pinpoints_work_score() {
+        let ours = block_on(snapshot_sidechain_state(&node(5, 0), 1)).unwrap();
+        let theirs = block_on(snapshot_sidechain_state(&node(6, 0), 1)).unwrap();
+        assert_eq!(ours.ctips.len(), 2);
+        assert_eq!(ours.failed_withdrawals.len(), 1);
+        assert_eq!(
+            ours.withdrawal_statuses.keys().collect::<Vec<_>>(),
+            [&SidechainId(1)]
+        );
+        assert!(ours.diff(&ours).is_empty());
+        let status = |nworkscore| WithdrawalStatus {
+            hash: txid(2),
+            nblocksleft: 100,
+            nworkscore,
+        };
+        assert_eq!(
+            ours.diff(&theirs).changes,
+            [SidechainStateChange::WithdrawalStatus {
+                sidechain: SidechainId(1),
+                bundle_txid: txid(2),
+                ours: Some(status(5)),
+                theirs: Some(status(6)),
+            }]
+        );
+        let json = serde_json::to_string(&ours).unwrap();
+        assert_eq!(
+            serde_json::from_str::<SidechainStateSnapshot>(&json).unwrap(),
+            ours
+        );
+    }
+
+    #[test]
+    fn retries_when_the_tip_moves() {
+        let client = node(5, 2);
+        let snapshot = block_on(snapshot_sidechain_state(&client, 3)).unwrap();
+        assert_eq!(
+            snapshot.at_block,
+            bitcoin::BlockHash::from_byte_array([2; 32])
+        );
+        let result = block_on(snapshot_sidechain_state(&node(5, 10), 3));
+        assert!(matches!(
+            result,
+            Err(Error::InconsistentSnapshot { attempts: 3 })
+        ));
+    }
+
+    #[test]
+    fn short_batch_response() {
+        let client = node(5, 0).with_batch_limit(10);
+        let result = block_on(snapshot_sidechain_state(&client, 1));
+        assert!(matches!(
+            result,
+            Err(Error::BatchResponseLength {
+                expected: 258,
+                actual: 10
+            })
+        ));
+    }
+
+    #[test]
+    fn other_ctip_errors_fail() {
+        let client = node_with_ctip_error(5, 0, -32603);
+        let result = block_on(snapshot_sidechain_state(&client, 1));
+        assert!(matches!(
+            result,
+            Err(Error::Jsonrpsee(jsonrpsee::core::Error::Call(err))) if err.code() == -32603
+        ));
+    }
+}
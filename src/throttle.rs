@@ -0,0 +1,445 @@
+//! Keeping the load on the node's RPC work queue below its limit.
+//!
+//! Bitcoin Core answers with HTTP 503 once more requests are queued than
+//! `-rpcworkqueue` allows. Retrying those right away, as for a network
+//! blip, only keeps the queue full. [`ThrottledClient`] instead limits how
+//! many requests are in flight at once, halves the limit when the node is
+//! busy, backs off before retrying, and raises the limit again one request
+//! at a time once requests succeed.
+use crate::cache::RawParams;
+#[cfg(feature = "metrics")]
+use crate::metrics::MetricsSink;
+use jsonrpsee::core::async_trait;
+use jsonrpsee::core::client::{BatchResponse, ClientT};
+use jsonrpsee::core::params::BatchRequestBuilder;
+use jsonrpsee::core::traits::ToRpcParams;
+use serde::de::DeserializeOwned;
+use std::fmt;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+/// HTTP status Bitcoin Core responds with when its work queue is full.
+const SERVICE_UNAVAILABLE: u16 = 503;
+
+/// Whether `err` is the HTTP 503 the node responds with when its RPC work
+/// queue is full.
+pub fn is_server_busy(err: &jsonrpsee::core::Error) -> bool {
+    match err {
+        jsonrpsee::core::Error::Transport(err) => matches!(
+            err.downcast_ref::<jsonrpsee::http_client::transport::Error>(),
+            Some(jsonrpsee::http_client::transport::Error::RequestFailure {
+                status_code: SERVICE_UNAVAILABLE
+            })
+        ),
+        _ => false,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ThrottleConfig {
+    /// Requests let through at once, and the most the limit recovers to.
+    /// Should be below the node's `-rpcworkqueue`, which defaults to 16.
+    pub max_concurrent_requests: usize,
+    /// Wait before the first retry of a request the node was too busy for.
+    /// Doubles with every further retry of the same request. The limit is
+    /// halved at most once per `busy_backoff`, so that a burst of busy
+    /// responses to requests sent at the same time only halves it once.
+    pub busy_backoff: Duration,
+    /// Retries of a request the node was too busy for, before
+    /// [`crate::Error::ServerBusy`] is returned.
+    pub max_busy_retries: u32,
+    /// Successful requests after which the limit is raised by one.
+    pub recovery_successes: u32,
+}
+
+impl Default for ThrottleConfig {
+    fn default() -> Self {
+        ThrottleConfig {
+            max_concurrent_requests: 8,
+            busy_backoff: Duration::from_millis(500),
+            max_busy_retries: 8,
+            recovery_successes: 16,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct LimiterState {
+    limit: usize,
+    in_flight: usize,
+    successes: u32,
+    last_decrease: Option<Instant>,
+}
+
+struct Limiter {
+    config: ThrottleConfig,
+    state: Mutex<LimiterState>,
+    /// Notified whenever a request finishes or the limit is raised.
+    released: Notify,
+    #[cfg(feature = "metrics")]
+    sink: Option<Arc<dyn MetricsSink>>,
+}
+
+impl Limiter {
+    fn new(config: ThrottleConfig) -> Self {
+        Limiter {
+            state: Mutex::new(LimiterState {
+                limit: config.max_concurrent_requests.max(1),
+                in_flight: 0,
+                successes: 0,
+                last_decrease: None,
+            }),
+            config,
+            released: Notify::new(),
+            #[cfg(feature = "metrics")]
+            sink: None,
+        }
+    }
+
+    async fn acquire(&self) -> Permit<'_> {
+        loop {
+            // Created before checking, so that a release in between is not
+            // missed
+            let released = self.released.notified();
+            {
+                let mut state = self.state.lock().unwrap();
+                if state.in_flight < state.limit {
+                    state.in_flight += 1;
+                    return Permit { limiter: self };
+                }
+            }
+            released.await;
+        }
+    }
+
+    fn limit_changed(&self, _limit: usize) {
+        #[cfg(feature = "metrics")]
+        if let Some(sink) = &self.sink {
+            sink.concurrency_limit_changed(_limit);
+        }
+        #[cfg(feature = "tracing")]
+        jsonrpsee::tracing::debug!(limit = _limit, "mainchain RPC concurrency limit changed");
+    }
+
+    fn record_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.limit >= self.config.max_concurrent_requests {
+            return;
+        }
+        state.successes += 1;
+        if state.successes >= self.config.recovery_successes {
+            state.successes = 0;
+            state.limit += 1;
+            let limit = state.limit;
+            drop(state);
+            self.released.notify_waiters();
+            self.limit_changed(limit);
+        }
+    }
+
+    fn record_busy(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.successes = 0;
+        let recently_decreased = state
+            .last_decrease
+            .is_some_and(|last_decrease| last_decrease.elapsed() < self.config.busy_backoff);
+        if recently_decreased || state.limit == 1 {
+            return;
+        }
+        state.limit = (state.limit / 2).max(1);
+        state.last_decrease = Some(Instant::now());
+        let limit = state.limit;
+        drop(state);
+        self.limit_changed(limit);
+    }
+}
+
+struct Permit<'a> {
+    limiter: &'a Limiter,
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        self.limiter.state.lock().unwrap().in_flight -= 1;
+        self.limiter.released.notify_waiters();
+    }
+}
+
+/// Client wrapper that adapts the number of requests in flight to what the
+/// node can handle, see the [module docs](self). Clones share their limit.
+#[derive(Clone)]
+pub struct ThrottledClient<C> {
+    inner: C,
+    limiter: Arc<Limiter>,
+}
+
+impl<C> ThrottledClient<C> {
+    pub fn new(inner: C, config: ThrottleConfig) -> Self {
+        ThrottledClient {
+            inner,
+            limiter: Arc::new(Limiter::new(config)),
+        }
+    }
+
+    /// Like [`ThrottledClient::new`], reporting the limit and every change
+    /// of it to `sink`.
+    #[cfg(feature = "metrics")]
+    pub fn with_metrics(inner: C, config: ThrottleConfig, sink: Arc<dyn MetricsSink>) -> Self {
+        let mut limiter = Limiter::new(config);
+        sink.concurrency_limit_changed(limiter.state.get_mut().unwrap().limit);
+        limiter.sink = Some(sink);
+        ThrottledClient {
+            inner,
+            limiter: Arc::new(limiter),
+        }
+    }
+
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+
+    /// Number of requests currently let through at once.
+    pub fn concurrency_limit(&self) -> usize {
+        self.limiter.state.lock().unwrap().limit
+    }
+
+    /// Sends a request with `send`, retrying with backoff while the node is
+    /// busy.
+    async fn throttled<T, F, Fut>(&self, mut send: F) -> Result<T, jsonrpsee::core::Error>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, jsonrpsee::core::Error>>,
+    {
+        let mut backoff = self.limiter.config.busy_backoff;
+        let mut retries = 0;
+        loop {
+            // Scoped so that the response, which need not be `Send`, is not
+            // held across the backoff
+            let err = {
+                let permit = self.limiter.acquire().await;
+                let result = send().await;
+                drop(permit);
+                match result {
+                    Err(err) if is_server_busy(&err) => err,
+                    result => {
+                        if result.is_ok() {
+                            self.limiter.record_success();
+                        }
+                        return result;
+                    }
+                }
+            };
+            self.limiter.record_busy();
+            if retries >= self.limiter.config.max_busy_retries {
+                return Err(err);
+            }
+            retries += 1;
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+        }
+    }
+}
+
+#[async_trait]
+impl<C> ClientT for ThrottledClient<C>
+where
+    C: ClientT + Send + Sync,
+{
+    async fn notification<Params>(
+        &self,
+        method: &str,
+        params: Params,
+    ) -> Result<(), jsonrpsee::core::Error>
+    where
+        Params: ToRpcParams + Send,
+    {
+        let params = params.to_rpc_params()?;
+        self.throttled(|| self.inner.notification(method, RawParams(params.clone())))
+            .await
+    }
+
+    async fn request<R, Params>(
+        &self,
+        method: &str,
+        params: Params,
+    ) -> Result<R, jsonrpsee::core::Error>
+    where
+        R: DeserializeOwned,
+        Params: ToRpcParams + Send,
+    {
+        let params = params.to_rpc_params()?;
+        self.throttled(|| self.inner.request(method, RawParams(params.clone())))
+            .await
+    }
+
+    async fn batch_request<'a, R>(
+        &self,
+        batch: BatchRequestBuilder<'a>,
+    ) -> Result<BatchResponse<'a, R>, jsonrpsee::core::Error>
+    where
+        R: DeserializeOwned + fmt::Debug + 'a,
+    {
+        self.throttled(|| self.inner.batch_request(batch.clone()))
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::block_on;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn busy() -> jsonrpsee::core::Error {
+        jsonrpsee::core::Error::Transport(
+            jsonrpsee::http_client::transport::Error::RequestFailure {
+                status_code: SERVICE_UNAVAILABLE,
+            }
+            .into(),
+        )
+    }
+
+    /// Node that responds with HTTP 503 to requests above `max_in_flight`,
+    /// and takes a few milliseconds for the others.
+    #[derive(Default)]
+    struct BusyNode {
+        max_in_flight: usize,
+        in_flight: AtomicUsize,
+        served: AtomicUsize,
+        rejected: AtomicUsize,
+    }
+
+    impl BusyNode {
+        async fn serve(&self) -> Result<(), jsonrpsee::core::Error> {
+            if self.in_flight.fetch_add(1, Ordering::SeqCst) >= self.max_in_flight {
+                self.in_flight.fetch_sub(1, Ordering::SeqCst);
+                self.rejected.fetch_add(1, Ordering::SeqCst);
+                return Err(busy());
+            }
+            tokio::time::sleep(Duration::from_millis(2)).await;
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            self.served.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl ClientT for BusyNode {
+        async fn notification<Params>(
+            &self,
+            _method: &str,
+            _params: Params,
+        ) -> Result<(), jsonrpsee::core::Error>
+        where
+            Params: ToRpcParams + Send,
+        {
+            self.serve().await
+        }
+
+        async fn request<R, Params>(
+            &self,
+            _method: &str,
+            _params: Params,
+        ) -> Result<R, jsonrpsee::core::Error>
+        where
+            R: DeserializeOwned,
+            Params: ToRpcParams + Send,
+        {
+            self.serve().await?;
+            Ok(serde_json::from_value(serde_json::Value::Null)?)
+        }
+
+        async fn batch_request<'a, R>(
+            &self,
+            _batch: BatchRequestBuilder<'a>,
+        ) -> Result<BatchResponse<'a, R>, jsonrpsee::core::Error>
+        where
+            R: DeserializeOwned + fmt::Debug + 'a,
+        {
+            self.serve().await?;
+            Ok(BatchResponse::new(0, vec![], 0))
+        }
+    }
+
+    fn config() -> ThrottleConfig {
+        ThrottleConfig {
+            max_concurrent_requests: 12,
+            busy_backoff: Duration::from_millis(1),
+            max_busy_retries: 16,
+            recovery_successes: 8,
+        }
+    }
+
+    #[test]
+    fn detects_server_busy() {
+        assert!(is_server_busy(&busy()));
+        assert!(matches!(
+            crate::Error::from(busy()),
+            crate::Error::ServerBusy
+        ));
+        let not_found = jsonrpsee::core::Error::Transport(
+            jsonrpsee::http_client::transport::Error::RequestFailure { status_code: 404 }.into(),
+        );
+        assert!(!is_server_busy(&not_found));
+        assert!(matches!(
+            crate::Error::from(not_found),
+            crate::Error::Jsonrpsee(_)
+        ));
+    }
+
+    #[test]
+    fn converges_below_the_node_limit() {
+        let node = BusyNode {
+            max_in_flight: 3,
+            ..Default::default()
+        };
+        let client = ThrottledClient::new(node, config());
+        block_on(async {
+            let requests = (0..200).map(|_| client.request::<(), _>("getblockcount", [(); 0]));
+            let results = futures::future::join_all(requests).await;
+            assert!(results.iter().all(Result::is_ok));
+        });
+        let node = client.inner();
+        assert_eq!(node.served.load(Ordering::SeqCst), 200);
+        // The limit drops from 12 to 3 within a few halvings, and only
+        // occasionally recovers above it
+        assert!(node.rejected.load(Ordering::SeqCst) < 100);
+        assert!(client.concurrency_limit() <= 4);
+    }
+
+    #[test]
+    fn gives_up_after_max_retries() {
+        let node = BusyNode::default();
+        let client = ThrottledClient::new(
+            node,
+            ThrottleConfig {
+                max_busy_retries: 2,
+                ..config()
+            },
+        );
+        let result = block_on(client.request::<(), _>("getblockcount", [(); 0]));
+        assert!(result.as_ref().is_err_and(is_server_busy));
+        assert_eq!(client.inner().rejected.load(Ordering::SeqCst), 3);
+        assert!(client.concurrency_limit() < 12);
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn reports_limit_changes() {
+        let sink = Arc::new(crate::metrics::InMemorySink::new());
+        let node = BusyNode {
+            max_in_flight: 2,
+            ..Default::default()
+        };
+        let client = ThrottledClient::with_metrics(node, config(), sink.clone());
+        assert_eq!(sink.concurrency_limit(), Some(12));
+        block_on(async {
+            let requests = (0..20).map(|_| client.request::<(), _>("getblockcount", [(); 0]));
+            futures::future::join_all(requests).await;
+        });
+        assert_eq!(sink.concurrency_limit(), Some(client.concurrency_limit()));
+        assert!(client.concurrency_limit() < 12);
+    }
+}
@@ -0,0 +1,340 @@
+//! Sizes and fees of the transactions of a block, without fetching it at
+//! verbosity 2.
+#[cfg(feature = "client")]
+use crate::batch::{Batch, BatchItemResult};
+#[cfg(feature = "client")]
+use crate::cancel::{Limits, Partial};
+use crate::client::Block;
+#[cfg(feature = "client")]
+use crate::Error;
+#[cfg(feature = "client")]
+use jsonrpsee::core::client::ClientT;
+#[cfg(feature = "client")]
+use std::collections::{HashMap, HashSet};
+
+/// Transactions fetched per batch by default.
+pub const DEFAULT_BATCH_SIZE: usize = 100;
+
+/// Per transaction data fetched by [`Block::enrich_txs`]. The txid and
+/// whether the transaction is the coinbase are always known.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TxFieldSet {
+    /// `vsize` and `weight`.
+    pub size: bool,
+    /// `fee`, which needs the transactions spent by every transaction.
+    pub fee: bool,
+}
+
+impl TxFieldSet {
+    pub const NONE: TxFieldSet = TxFieldSet {
+        size: false,
+        fee: false,
+    };
+    pub const ALL: TxFieldSet = TxFieldSet {
+        size: true,
+        fee: true,
+    };
+
+    #[cfg(feature = "client")]
+    fn any(&self) -> bool {
+        self.size || self.fee
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct TxSummary {
+    pub txid: bitcoin::Txid,
+    pub is_coinbase: bool,
+    pub vsize: Option<u64>,
+    pub weight: Option<u64>,
+    /// Not set for the coinbase, or if a transaction it spends could not be
+    /// fetched. Transactions spent from earlier blocks can only be fetched
+    /// with `-txindex`.
+    #[serde(default, with = "bitcoin::amount::serde::as_sat::opt")]
+    pub fee: Option<bitcoin::Amount>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct BlockWithTxSummaries {
+    pub block: Block,
+    /// In the order of [`Block::tx`].
+    pub txs: Vec<TxSummary>,
+    /// Transactions that could not be fetched, e.g. because the block was
+    /// pruned. Their summaries only have the txid and `is_coinbase` set.
+    pub unresolved: Vec<bitcoin::Txid>,
+}
+
+#[cfg(feature = "client")]
+impl Block {
+    /// Fetches the `fields` of every transaction, in batches of
+    /// `batch_size` transactions.
+    ///
+    /// Only the output values of the block's transactions are kept between
+    /// batches, to compute the fees of transactions that spend them, so
+    /// memory use does not grow with the size of the transactions.
+    pub async fn enrich_txs<C>(
+        self,
+        client: &C,
+        fields: TxFieldSet,
+        batch_size: usize,
+    ) -> Result<BlockWithTxSummaries, Error>
+    where
+        C: ClientT + Sync,
+    {
+        self.enrich_txs_with_limits(client, fields, batch_size, &Limits::default())
+            .await?
+            .into_result()
+    }
+
+    /// Like [`Self::enrich_txs`], but stops once `limits` are reached. The
+    /// transactions from the batch it stopped in onwards are then
+    /// `unresolved`.
+    ///
+    /// Progress in [`Error::DeadlineExceeded`] is counted in transactions.
+    pub async fn enrich_txs_with_limits<C>(
+        self,
+        client: &C,
+        fields: TxFieldSet,
+        batch_size: usize,
+        limits: &Limits,
+    ) -> Result<Partial<BlockWithTxSummaries>, Error>
+    where
+        C: ClientT + Sync,
+    {
+        let txs: Vec<_> = self
+            .tx
+            .iter()
+            .enumerate()
+            .map(|(index, txid)| TxSummary {
+                txid: *txid,
+                is_coinbase: index == 0,
+                vsize: None,
+                weight: None,
+                fee: None,
+            })
+            .collect();
+        let mut enriched = BlockWithTxSummaries {
+            block: self,
+            txs,
+            unresolved: vec![],
+        };
+        let mut completed = 0;
+        let result = if fields.any() {
+            enrich_batches(
+                client,
+                fields,
+                batch_size,
+                limits,
+                &mut enriched,
+                &mut completed,
+            )
+            .await
+        } else {
+            Ok(())
+        };
+        if result.is_err() {
+            let stopped = enriched.txs[completed..].iter().map(|summary| summary.txid);
+            enriched.unresolved.extend(stopped);
+        }
+        Partial::new(enriched, result)
+    }
+}
+
+/// Fills in the summaries of `enriched` batch by batch, counting the
+/// transactions of the batches that were completed in `completed`.
+#[cfg(feature = "client")]
+async fn enrich_batches<C>(
+    client: &C,
+    fields: TxFieldSet,
+    batch_size: usize,
+    limits: &Limits,
+    enriched: &mut BlockWithTxSummaries,
+    completed: &mut usize,
+) -> Result<(), Error>
+where
+    C: ClientT + Sync,
+{
+    // Output values of the transactions fetched so far, for the fees of
+    // later transactions in the block that spend them, in sats
+    let mut block_outputs = HashMap::<bitcoin::Txid, Vec<u64>>::new();
+    let running = limits.start();
+    let block_hash = enriched.block.hash;
+    let total = enriched.txs.len();
+    for chunk in enriched.txs.chunks_mut(batch_size.max(1)) {
+        let chunk_len = chunk.len();
+        let remaining = Some(total - *completed);
+        let results = running
+            .race(
+                chunk
+                    .iter()
+                    .fold(Batch::new(), |batch, summary| {
+                        batch.get_raw_transaction(summary.txid, Some(block_hash))
+                    })
+                    .send(client),
+                *completed,
+                remaining,
+            )
+            .await?;
+        let mut transactions = Vec::with_capacity(chunk.len());
+        let mut failed = vec![];
+        for (summary, result) in chunk.iter_mut().zip(results) {
+            match result {
+                Ok(BatchItemResult::RawTransaction(transaction)) => {
+                    if fields.size {
+                        summary.vsize = Some(transaction.vsize() as u64);
+                        summary.weight = Some(transaction.weight().to_wu());
+                    }
+                    transactions.push((summary, transaction));
+                }
+                Ok(_) => {
+                    return Err(Error::UnexpectedBatchResult {
+                        method: "getrawtransaction",
+                    })
+                }
+                Err(_) => failed.push(summary.txid),
+            }
+        }
+        if fields.fee {
+            for (summary, transaction) in &transactions {
+                block_outputs.insert(
+                    summary.txid,
+                    transaction
+                        .output
+                        .iter()
+                        .map(|output| output.value)
+                        .collect(),
+                );
+            }
+            let outside_block: HashSet<_> = transactions
+                .iter()
+                .filter(|(summary, _)| !summary.is_coinbase)
+                .flat_map(|(_, transaction)| &transaction.input)
+                .map(|input| input.previous_output.txid)
+                .filter(|txid| !block_outputs.contains_key(txid))
+                .collect();
+            let outside_block: Vec<_> = outside_block.into_iter().collect();
+            let mut spent_outputs = HashMap::new();
+            for txids in outside_block.chunks(batch_size.max(1)) {
+                let results = running
+                    .race(
+                        txids
+                            .iter()
+                            .fold(Batch::new(), |batch, txid| {
+                                batch.get_raw_transaction(*txid, None)
+                            })
+                            .send(client),
+                        *completed,
+                        remaining,
+                    )
+                    .await?;
+                for (txid, result) in txids.iter().zip(results) {
+                    if let Ok(BatchItemResult::RawTransaction(transaction)) = result {
+                        let values: Vec<_> = transaction
+                            .output
+                            .iter()
+                            .map(|output| output.value)
+                            .collect();
+                        spent_outputs.insert(*txid, values);
+                    }
+                }
+            }
+            for (summary, transaction) in transactions {
+                if summary.is_coinbase {
+                    continue;
+                }
+                let spent: Option<u64> = transaction
+                    .input
+                    .iter()
+                    .map(|input| {
+                        let outpoint = input.previous_output;
+                        block_outputs
+                            .get(&outpoint.txid)
+                            .or_else(|| spent_outputs.get(&outpoint.txid))?
+                            .get(outpoint.vout as usize)
+                            .copied()
+                    })
+                    .sum();
+                let created: u64 = transaction.output.iter().map(|output| output.value).sum();
+                summary.fee = spent
+                    .and_then(|spent| spent.checked_sub(created))
+                    .map(bitcoin::Amount::from_sat);
+            }
+        }
+        enriched.unresolved.append(&mut failed);
+        *completed += chunk_len;
+    }
+    Ok(())
+}
+
+#[cfg(all(test, feature = "client"))]
+mod tests {
+    use super::*;
+    use crate::cancel::CancellationToken;
+    use crate::client::ChainClient as _;
+    use crate::mock::{block_on, rpc_error, MockChain, MockClient};
+
+    fn transaction(lock_time: u32) -> bitcoin::Transaction {
+        bitcoin::Transaction {
+            version: 2,
+            lock_time: bitcoin::absolute::LockTime::from_consensus(lock_time),
+            input: vec![bitcoin::TxIn::default()],
+            output: vec![bitcoin::TxOut {
+                value: 1000,
+                script_pubkey: bitcoin::ScriptBuf::new(),
+            }],
+        }
+    }
+
+    #[test]
+    fn cancelled_batches_are_unresolved() {
+        let transactions: HashMap<_, _> = [transaction(1), transaction(2)]
+            .into_iter()
+            .map(|transaction| (transaction.txid(), transaction))
+            .collect();
+        let chain = MockChain::new();
+        let block_hash = chain.mine_with(transactions.keys().copied().collect());
+        let cancel = CancellationToken::new();
+        let client = MockClient::new({
+            let chain = chain.clone();
+            let cancel = cancel.clone();
+            let transactions = transactions.clone();
+            move |method, params| {
+                if method != "getrawtransaction" {
+                    return chain.handle(method, params).unwrap();
+                }
+                let txid: bitcoin::Txid = serde_json::from_value(params[0].clone()).unwrap();
+                match transactions.get(&txid) {
+                    Some(transaction) => {
+                        // Stops after the batch of the first transaction
+                        cancel.cancel();
+                        Ok(serde_json::json!(
+                            bitcoin::consensus::encode::serialize_hex(transaction)
+                        ))
+                    }
+                    // The coinbase
+                    None => Err(rpc_error(-5, "No such mempool or blockchain transaction")),
+                }
+            }
+        });
+        let block = block_on(client.getblock(&block_hash, None)).unwrap();
+        let limits = Limits::new().with_cancellation(cancel);
+        let fields = TxFieldSet {
+            size: true,
+            fee: false,
+        };
+        let partial = block_on(block.enrich_txs_with_limits(&client, fields, 1, &limits)).unwrap();
+        assert!(matches!(partial.stopped, Some(Error::Cancelled)));
+        let enriched = partial.value;
+        assert_eq!(enriched.txs.len(), 3);
+        assert_eq!(enriched.txs[1].vsize, Some(transaction(1).vsize() as u64));
+        assert_eq!(enriched.txs[2].vsize, None);
+        assert_eq!(
+            enriched.unresolved,
+            [enriched.txs[0].txid, enriched.txs[2].txid]
+        );
+        assert_eq!(
+            client.methods(),
+            ["getblock", "getrawtransaction", "getrawtransaction"]
+        );
+    }
+}
@@ -0,0 +1,217 @@
+//! Unlocking encrypted wallets, e.g. the BMM mining wallet, for the duration
+//! of an operation.
+use crate::client::WalletClient;
+use crate::Error;
+use jsonrpsee::core::client::ClientT;
+use std::time::Duration;
+pub use zeroize::Zeroizing;
+
+/// Error code returned by the node for an incorrect passphrase.
+const RPC_WALLET_PASSPHRASE_INCORRECT: i32 = -14;
+/// Error code returned by the node for passphrase commands on unencrypted
+/// wallets.
+const RPC_WALLET_WRONG_ENC_STATE: i32 = -15;
+
+fn wallet_error(err: jsonrpsee::core::Error) -> Error {
+    match err {
+        jsonrpsee::core::Error::Call(err) if err.code() == RPC_WALLET_PASSPHRASE_INCORRECT => {
+            Error::WrongPassphrase
+        }
+        jsonrpsee::core::Error::Call(err) if err.code() == RPC_WALLET_WRONG_ENC_STATE => {
+            Error::WalletNotEncrypted
+        }
+        err => err.into(),
+    }
+}
+
+/// Unlocks the wallet for `timeout`, after which the node locks it again.
+pub async fn wallet_passphrase<C>(
+    client: &C,
+    passphrase: &str,
+    timeout: Duration,
+) -> Result<(), Error>
+where
+    C: ClientT + Sync,
+{
+    client
+        .walletpassphrase(passphrase, timeout.as_secs().max(1))
+        .await
+        .map_err(wallet_error)
+}
+
+pub async fn wallet_lock<C>(client: &C) -> Result<(), Error>
+where
+    C: ClientT + Sync,
+{
+    client.walletlock().await.map_err(wallet_error)
+}
+
+pub async fn wallet_passphrase_change<C>(
+    client: &C,
+    old_passphrase: &str,
+    new_passphrase: &str,
+) -> Result<(), Error>
+where
+    C: ClientT + Sync,
+{
+    client
+        .walletpassphrasechange(old_passphrase, new_passphrase)
+        .await
+        .map_err(wallet_error)
+}
+
+/// Unlocks the wallet and returns a guard that locks it again, see
+/// [`UnlockedWalletGuard`]. The passphrase is zeroed once the wallet is
+/// unlocked.
+pub async fn unlock_for<C>(
+    client: C,
+    passphrase: Zeroizing<String>,
+    duration: Duration,
+) -> Result<UnlockedWalletGuard<C>, Error>
+where
+    C: ClientT + Send + Sync + 'static,
+{
+    wallet_passphrase(&client, &passphrase, duration).await?;
+    Ok(UnlockedWalletGuard {
+        client: Some(client),
+    })
+}
+
+/// Guard returned by [`unlock_for`]. The wallet is locked again by
+/// [`UnlockedWalletGuard::close`], or when the guard is dropped inside a
+/// tokio runtime. Either way, the node locks it once the duration it was
+/// unlocked for has passed.
+pub struct UnlockedWalletGuard<C>
+where
+    C: ClientT + Send + Sync + 'static,
+{
+    /// `None` once the wallet was locked.
+    client: Option<C>,
+}
+
+impl<C> UnlockedWalletGuard<C>
+where
+    C: ClientT + Send + Sync + 'static,
+{
+    pub fn client(&self) -> &C {
+        self.client.as_ref().unwrap()
+    }
+
+    pub async fn close(mut self) -> Result<(), Error> {
+        if let Some(client) = self.client.take() {
+            wallet_lock(&client).await?;
+        }
+        Ok(())
+    }
+}
+
+impl<C> Drop for UnlockedWalletGuard<C>
+where
+    C: ClientT + Send + Sync + 'static,
+{
+    fn drop(&mut self) {
+        let Some(client) = self.client.take() else {
+            return;
+        };
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            runtime.spawn(async move {
+                let _ = client.walletlock().await;
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{block_on, rpc_error, MockClient};
+    use std::sync::{Arc, Mutex};
+
+    /// Client of an encrypted wallet with passphrase `"secret"`, and the
+    /// methods called on it.
+    fn client() -> (MockClient, Arc<Mutex<Vec<String>>>) {
+        let methods = Arc::new(Mutex::new(vec![]));
+        let client = MockClient::new({
+            let methods = methods.clone();
+            move |method, params| {
+                methods.lock().unwrap().push(method.to_owned());
+                match method {
+                    "walletpassphrase" if params[0] != "secret" => Err(rpc_error(
+                        RPC_WALLET_PASSPHRASE_INCORRECT,
+                        "Error: The wallet passphrase entered was incorrect.",
+                    )),
+                    "walletpassphrase" | "walletlock" => Ok(serde_json::Value::Null),
+                    _ => Err(rpc_error(-32601, "Method not found")),
+                }
+            }
+        });
+        (client, methods)
+    }
+
+    fn passphrase(passphrase: &str) -> Zeroizing<String> {
+        Zeroizing::new(passphrase.to_owned())
+    }
+
+    #[test]
+    fn maps_wallet_errors() {
+        assert!(matches!(
+            wallet_error(rpc_error(RPC_WALLET_PASSPHRASE_INCORRECT, "")),
+            Error::WrongPassphrase
+        ));
+        assert!(matches!(
+            wallet_error(rpc_error(
+                RPC_WALLET_WRONG_ENC_STATE,
+                "Error: running with an unencrypted wallet, but walletlock was called."
+            )),
+            Error::WalletNotEncrypted
+        ));
+        assert!(matches!(
+            wallet_error(rpc_error(-4, "")),
+            Error::Jsonrpsee(_)
+        ));
+    }
+
+    #[test]
+    fn wrong_passphrase() {
+        let (client, methods) = client();
+        let result = block_on(unlock_for(
+            client,
+            passphrase("guess"),
+            Duration::from_secs(60),
+        ));
+        assert!(matches!(result, Err(Error::WrongPassphrase)));
+        assert_eq!(*methods.lock().unwrap(), ["walletpassphrase"]);
+    }
+
+    #[test]
+    fn close_locks_the_wallet() {
+        let (client, methods) = client();
+        block_on(async {
+            let guard = unlock_for(client, passphrase("secret"), Duration::from_secs(60))
+                .await
+                .unwrap();
+            guard.close().await.unwrap();
+        });
+        assert_eq!(*methods.lock().unwrap(), ["walletpassphrase", "walletlock"]);
+    }
+
+    #[test]
+    fn locks_the_wallet_when_the_operation_fails() {
+        let (client, methods) = client();
+        block_on(async {
+            let operation = async {
+                let guard =
+                    unlock_for(client, passphrase("secret"), Duration::from_secs(60)).await?;
+                guard.client().getbalances().await?;
+                guard.close().await
+            };
+            assert!(operation.await.is_err());
+            // Let the lock spawned on drop run
+            tokio::task::yield_now().await;
+        });
+        assert_eq!(
+            *methods.lock().unwrap(),
+            ["walletpassphrase", "getbalances", "walletlock"]
+        );
+    }
+}
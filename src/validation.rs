@@ -0,0 +1,205 @@
+//! Client side checks for RPC parameters, so that obviously malformed
+//! requests fail before a round-trip to the node.
+use crate::Error;
+use bitcoin::consensus::Decodable;
+
+pub(crate) fn address_network(
+    address: &bitcoin::Address<bitcoin::address::NetworkUnchecked>,
+    network: bitcoin::Network,
+) -> Result<(), Error> {
+    if !address.is_valid_for_network(network) {
+        return Err(Error::InvalidParam {
+            name: "address",
+            reason: format!("address is not valid for {network}"),
+        });
+    }
+    Ok(())
+}
+
+/// Unlike [`address_network`], testnet addresses are not accepted on signet
+/// or vice versa, even though they are encoded the same.
+pub(crate) fn checked_address_network(
+    address: &bitcoin::Address,
+    network: bitcoin::Network,
+) -> Result<(), Error> {
+    if address.network != network {
+        return Err(Error::WrongNetwork {
+            expected: network,
+            got: address.network,
+        });
+    }
+    Ok(())
+}
+
+pub(crate) fn raw_transaction(tx_hex: &str) -> Result<(), Error> {
+    let invalid = |reason: String| Error::InvalidParam {
+        name: "hexstring",
+        reason,
+    };
+    let bytes = hex::decode(tx_hex).map_err(|err| invalid(err.to_string()))?;
+    let mut cursor = std::io::Cursor::new(&bytes);
+    bitcoin::Transaction::consensus_decode(&mut cursor).map_err(|err| invalid(err.to_string()))?;
+    if cursor.position() as usize != bytes.len() {
+        return Err(invalid("trailing bytes after transaction".to_owned()));
+    }
+    Ok(())
+}
+
+#[cfg(all(test, feature = "client"))]
+mod tests {
+    use super::*;
+    use crate::bmm::PrevBytes;
+    use crate::client::SidechainId;
+    use crate::mock::{block_on, serve, MockClient};
+    use crate::Drivechain;
+    use bitcoin::hashes::Hash as _;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    /// A node that only sees the requests that get past validation.
+    fn node() -> (std::net::SocketAddr, Arc<MockClient>) {
+        serve(MockClient::new(|method, _| {
+            Ok(match method {
+                "createbmmcriticaldatatx" => json!({"txid": {"txid": bitcoin::Txid::all_zeros()}}),
+                "generatetoaddress" => json!([]),
+                "sendrawtransaction" => json!(bitcoin::Txid::all_zeros()),
+                _ => unreachable!("{method}"),
+            })
+        }))
+    }
+
+    fn mainnet_address() -> bitcoin::Address {
+        bitcoin::Address::p2wsh(&bitcoin::ScriptBuf::new(), bitcoin::Network::Bitcoin)
+    }
+
+    fn tx_hex() -> String {
+        bitcoin::consensus::encode::serialize_hex(&bitcoin::Transaction {
+            version: 2,
+            lock_time: bitcoin::absolute::LockTime::ZERO,
+            input: vec![bitcoin::TxIn::default()],
+            output: vec![bitcoin::TxOut {
+                value: 50_000,
+                script_pubkey: mainnet_address().script_pubkey(),
+            }],
+        })
+    }
+
+    #[test]
+    fn prev_bytes_of_the_wrong_length_are_refused() {
+        // The length is checked when the prevbytes are parsed, so a request
+        // with the wrong length can't be made
+        for prev_bytes in ["", "aabbcc", "aabbccddee", "aabbccdg"] {
+            assert!(prev_bytes.parse::<PrevBytes>().is_err(), "{prev_bytes}");
+        }
+        let (addr, node) = node();
+        block_on(async {
+            let drivechain = Drivechain::new(SidechainId(0), addr, "user", "password").unwrap();
+            drivechain
+                .create_bmm_critical_data_tx(
+                    bitcoin::Amount::from_sat(1000),
+                    101,
+                    &bitcoin::BlockHash::all_zeros(),
+                    "aabbccdd".parse().unwrap(),
+                )
+                .await
+                .unwrap();
+        });
+        let calls = node.calls();
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].1[4], json!("aabbccdd"));
+    }
+
+    #[test]
+    fn generate_to_address_checks_the_network() {
+        let (addr, node) = node();
+        let address = mainnet_address();
+        let unchecked = address.to_string().parse().unwrap();
+        block_on(async {
+            let drivechain = Drivechain::new(SidechainId(0), addr, "user", "password")
+                .unwrap()
+                .with_network(bitcoin::Network::Regtest);
+            let err = drivechain
+                .generate_to_address(1, &unchecked)
+                .await
+                .unwrap_err();
+            assert!(
+                matches!(
+                    err,
+                    Error::InvalidParam {
+                        name: "address",
+                        ..
+                    }
+                ),
+                "{err:?}"
+            );
+            let err = drivechain
+                .generate_to_address_checked(1, &address)
+                .await
+                .unwrap_err();
+            assert!(
+                matches!(
+                    err,
+                    Error::WrongNetwork {
+                        expected: bitcoin::Network::Regtest,
+                        got: bitcoin::Network::Bitcoin,
+                    }
+                ),
+                "{err:?}"
+            );
+        });
+        assert_eq!(node.calls().len(), 0);
+
+        // Without a network there is nothing to check against
+        block_on(async {
+            let drivechain = Drivechain::new(SidechainId(0), addr, "user", "password").unwrap();
+            drivechain.generate_to_address(1, &unchecked).await.unwrap();
+        });
+        assert_eq!(node.methods(), ["generatetoaddress"]);
+    }
+
+    #[test]
+    fn send_raw_transaction_checks_the_hex() {
+        let (addr, node) = node();
+        let valid = tx_hex();
+        let truncated = &valid[..valid.len() - 2];
+        let trailing = format!("{valid}00");
+        block_on(async {
+            let drivechain = Drivechain::new(SidechainId(0), addr, "user", "password").unwrap();
+            for tx_hex in ["", "zz", "0200", truncated, &trailing] {
+                let err = drivechain.send_raw_transaction(tx_hex).await.unwrap_err();
+                assert!(
+                    matches!(
+                        err,
+                        Error::InvalidParam {
+                            name: "hexstring",
+                            ..
+                        }
+                    ),
+                    "{tx_hex}: {err:?}"
+                );
+            }
+        });
+        assert_eq!(node.calls().len(), 0);
+
+        block_on(async {
+            let drivechain = Drivechain::new(SidechainId(0), addr, "user", "password").unwrap();
+            drivechain.send_raw_transaction(&valid).await.unwrap();
+        });
+        assert_eq!(node.methods(), ["sendrawtransaction"]);
+    }
+
+    #[test]
+    fn skipped_validation_sends_everything() {
+        let (addr, node) = node();
+        let unchecked = mainnet_address().to_string().parse().unwrap();
+        block_on(async {
+            let drivechain = Drivechain::new(SidechainId(0), addr, "user", "password")
+                .unwrap()
+                .with_network(bitcoin::Network::Regtest)
+                .danger_skip_validation();
+            drivechain.generate_to_address(1, &unchecked).await.unwrap();
+            drivechain.send_raw_transaction("zz").await.unwrap();
+        });
+        assert_eq!(node.methods(), ["generatetoaddress", "sendrawtransaction"]);
+    }
+}
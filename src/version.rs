@@ -0,0 +1,362 @@
+//! Detecting the version of the mainchain node, to refuse calls it can't
+//! serve with a clear error instead of failing on the response, and to
+//! adapt responses of older versions to the current shape.
+#[cfg(feature = "client")]
+use crate::client::ChainClient;
+use crate::Error;
+#[cfg(feature = "client")]
+use jsonrpsee::core::client::ClientT;
+use serde_json::{Map, Value};
+use std::fmt;
+
+/// Version number in the format of `getnetworkinfo`, e.g. `230100` for
+/// 23.1.0 or `160300` for 0.16.3.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CoreVersion(pub u32);
+
+impl CoreVersion {
+    pub fn major(&self) -> u32 {
+        self.0 / 10000
+    }
+
+    pub fn minor(&self) -> u32 {
+        self.0 / 100 % 100
+    }
+
+    pub fn patch(&self) -> u32 {
+        self.0 % 100
+    }
+}
+
+impl fmt::Display for CoreVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Versions before 22.0 were numbered 0.x.y
+        if self.major() < 22 {
+            write!(f, "0.{}.{}", self.major(), self.minor())?;
+        } else {
+            write!(f, "{}.{}", self.major(), self.minor())?;
+        }
+        if self.patch() != 0 {
+            write!(f, ".{}", self.patch())?;
+        }
+        Ok(())
+    }
+}
+
+/// Minimum node version of methods that are not available on all versions
+/// this crate supports. Methods not listed are assumed to be available.
+/// The [`crate::Drivechain`] wrapper of every listed method checks it.
+pub const MIN_VERSIONS: &[(&str, CoreVersion)] = &[
+    ("finalizepsbt", CoreVersion(170000)),
+    ("generateblock", CoreVersion(210000)),
+    ("generatetodescriptor", CoreVersion(200000)),
+    ("getblockfilter", CoreVersion(190000)),
+    ("getchainstates", CoreVersion(260000)),
+    ("getdeploymentinfo", CoreVersion(230000)),
+    ("getindexinfo", CoreVersion(210000)),
+    ("getprioritisedtransactions", CoreVersion(260000)),
+    ("getrpcinfo", CoreVersion(180000)),
+    ("loadtxoutset", CoreVersion(260000)),
+    ("scantxoutset", CoreVersion(170000)),
+    ("signrawtransactionwithwallet", CoreVersion(170000)),
+    ("submitheader", CoreVersion(180000)),
+    ("walletcreatefundedpsbt", CoreVersion(170000)),
+    ("walletprocesspsbt", CoreVersion(170000)),
+];
+
+/// The minimum version of `method` from [`MIN_VERSIONS`], if any.
+pub fn min_version(method: &str) -> Option<CoreVersion> {
+    MIN_VERSIONS
+        .iter()
+        .find(|(name, _)| *name == method)
+        .map(|(_, version)| *version)
+}
+
+/// Rewrites a response of `method` from a node of `version` into the shape
+/// reported by current nodes, which the response types expect. Only
+/// `getmempoolentry` differs so far.
+pub fn adapt_response(method: &str, version: CoreVersion, response: &mut Value) {
+    if let ("getmempoolentry", Value::Object(entry)) = (method, response) {
+        adapt_mempool_entry(version, entry)
+    }
+}
+
+fn adapt_mempool_entry(version: CoreVersion, entry: &mut Map<String, Value>) {
+    // Before 0.19, `size` was the virtual size, and there was no weight
+    if version < CoreVersion(190000) {
+        if let Some(size) = entry.remove("size") {
+            entry.entry("vsize").or_insert(size);
+        }
+    }
+    // Before 0.17, fees were only reported as flat fields, in BTC for the
+    // transaction itself and in sats for its ancestors and descendants.
+    // Later versions that still report them duplicate `fees`.
+    let [base, modified, ancestor, descendant] =
+        ["fee", "modifiedfee", "ancestorfees", "descendantfees"].map(|name| entry.remove(name));
+    if version < CoreVersion(170000) && !entry.contains_key("fees") {
+        let btc = |sats: Option<Value>| {
+            sats.and_then(|sats| sats.as_u64())
+                .map(|sats| bitcoin::Amount::from_sat(sats).to_btc())
+        };
+        entry.insert(
+            "fees".to_owned(),
+            serde_json::json!({
+                "base": base,
+                "modified": modified,
+                "ancestor": btc(ancestor),
+                "descendant": btc(descendant),
+            }),
+        );
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerVersion {
+    pub version: CoreVersion,
+    /// User agent of the node, e.g. `/Satoshi:23.1.0/`.
+    pub subversion: String,
+}
+
+impl ServerVersion {
+    #[cfg(feature = "client")]
+    pub async fn detect<C>(client: &C) -> Result<Self, Error>
+    where
+        C: ClientT + Sync,
+    {
+        let info = client.getnetworkinfo().await?.value;
+        Ok(ServerVersion {
+            version: CoreVersion(info.version),
+            subversion: info.subversion,
+        })
+    }
+
+    pub fn supports(&self, method: &str) -> bool {
+        min_version(method).is_none_or(|needs| self.version >= needs)
+    }
+
+    /// Returns [`Error::UnsupportedByServer`] if the node is too old for
+    /// `method`.
+    pub fn require(&self, method: &'static str) -> Result<(), Error> {
+        match min_version(method) {
+            Some(needs) if self.version < needs => Err(Error::UnsupportedByServer {
+                method,
+                needs,
+                has: self.version,
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for ServerVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.version, self.subversion)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server(version: u32) -> ServerVersion {
+        ServerVersion {
+            version: CoreVersion(version),
+            subversion: "/Satoshi:0.0.0/".to_owned(),
+        }
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(CoreVersion(160300).to_string(), "0.16.3");
+        assert_eq!(CoreVersion(230000).to_string(), "23.0");
+        assert_eq!(CoreVersion(230100).to_string(), "23.1");
+        assert_eq!(CoreVersion(260001).to_string(), "26.0.1");
+    }
+
+    #[test]
+    fn require() {
+        assert!(server(229900).require("getdeploymentinfo").is_err());
+        assert!(server(230000).require("getdeploymentinfo").is_ok());
+        // Not listed
+        assert!(server(10000).require("getblockcount").is_ok());
+        assert!(matches!(
+            server(220000).require("getdeploymentinfo"),
+            Err(Error::UnsupportedByServer {
+                method: "getdeploymentinfo",
+                needs: CoreVersion(230000),
+                has: CoreVersion(220000),
+            })
+        ));
+    }
+
+    /// Node of version `version` answering `getnetworkinfo`, and every
+    /// other method with `response`.
+    #[cfg(feature = "client")]
+    fn node(version: u32, response: Value) -> crate::mock::MockClient {
+        crate::mock::MockClient::new(move |method, _| match method {
+            "getnetworkinfo" => Ok(crate::mock::network_info(version)),
+            _ => Ok(response.clone()),
+        })
+    }
+
+    #[cfg(feature = "client")]
+    fn connect(
+        node: crate::mock::MockClient,
+    ) -> (crate::Drivechain, std::sync::Arc<crate::mock::MockClient>) {
+        let (addr, node) = crate::mock::serve(node);
+        let drivechain = crate::mock::block_on(crate::Drivechain::connect(
+            crate::client::SidechainId(0),
+            addr,
+            "user",
+            "password",
+        ))
+        .unwrap();
+        (drivechain, node)
+    }
+
+    /// Calls the [`crate::Drivechain`] wrapper of `method` with arbitrary
+    /// arguments.
+    #[cfg(feature = "client")]
+    async fn call_wrapper(drivechain: &crate::Drivechain, method: &str) -> Result<(), Error> {
+        use bitcoin::hashes::Hash as _;
+        let block_hash = bitcoin::BlockHash::all_zeros();
+        let psbt =
+            bitcoin::psbt::PartiallySignedTransaction::from_unsigned_tx(bitcoin::Transaction {
+                version: 2,
+                lock_time: bitcoin::absolute::LockTime::ZERO,
+                input: vec![],
+                output: vec![],
+            })
+            .unwrap();
+        let header = bitcoin::constants::genesis_block(bitcoin::Network::Regtest).header;
+        match method {
+            "finalizepsbt" => drivechain.finalize_psbt(&psbt, None).await.map(drop),
+            "generateblock" => drivechain
+                .generate_block("raw(51)", &[], None)
+                .await
+                .map(drop),
+            "generatetodescriptor" => drivechain
+                .generate_to_descriptor(1, "raw(51)")
+                .await
+                .map(drop),
+            "getblockfilter" => drivechain
+                .get_block_filter(&block_hash, None)
+                .await
+                .map(drop),
+            "getchainstates" => drivechain.get_chain_states().await.map(drop),
+            "getdeploymentinfo" => drivechain.get_deployment_info(None).await.map(drop),
+            "getindexinfo" => drivechain.get_index_info(None).await.map(drop),
+            "getprioritisedtransactions" => {
+                drivechain.get_prioritised_transactions().await.map(drop)
+            }
+            "getrpcinfo" => drivechain.get_rpc_info().await.map(drop),
+            "loadtxoutset" => drivechain.load_tx_out_set("utxo.dat").await.map(drop),
+            "scantxoutset" => drivechain.scan_tx_out_set(&[]).await.map(drop),
+            "signrawtransactionwithwallet" => drivechain
+                .sign_raw_transaction_with_wallet("00")
+                .await
+                .map(drop),
+            "submitheader" => drivechain.submit_header_typed(&header).await,
+            "walletcreatefundedpsbt" => drivechain
+                .wallet_create_funded_psbt(&[], &[], None, &Default::default(), None)
+                .await
+                .map(drop),
+            "walletprocesspsbt" => drivechain
+                .wallet_process_psbt(&psbt, None, None, None)
+                .await
+                .map(drop),
+            _ => panic!("{method} has no wrapper"),
+        }
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn old_server_is_refused_without_a_call() {
+        for (method, needs) in MIN_VERSIONS {
+            // The previous major version
+            let has = CoreVersion(needs.0 - 10000);
+            let (drivechain, node) = connect(node(has.0, Value::Null));
+            assert_eq!(
+                drivechain
+                    .server_version
+                    .as_ref()
+                    .map(|server| server.version),
+                Some(has)
+            );
+            let result = crate::mock::block_on(call_wrapper(&drivechain, method));
+            assert!(
+                matches!(
+                    result,
+                    Err(Error::UnsupportedByServer { method: refused, needs: refused_needs, has: refused_has })
+                        if refused == *method && refused_needs == *needs && refused_has == has
+                ),
+                "{method}: {result:?}"
+            );
+            assert_eq!(node.methods(), ["getnetworkinfo"], "{method}");
+        }
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn new_enough_server_is_called() {
+        for (method, needs) in MIN_VERSIONS {
+            let (drivechain, node) = connect(crate::mock::MockClient::new({
+                let needs = *needs;
+                move |method, _| match method {
+                    "getnetworkinfo" => Ok(crate::mock::network_info(needs.0)),
+                    _ => Err(crate::mock::rpc_error(-32601, "Method not found")),
+                }
+            }));
+            let result = crate::mock::block_on(call_wrapper(&drivechain, method));
+            assert!(
+                !matches!(result, Err(Error::UnsupportedByServer { .. })),
+                "{method}"
+            );
+            assert_eq!(node.methods(), ["getnetworkinfo", method], "{method}");
+        }
+    }
+
+    /// Shapes of a `getmempoolentry` response of the same transaction.
+    #[cfg(feature = "client")]
+    #[test]
+    fn adapts_old_mempool_entries() {
+        use bitcoin::hashes::Hash as _;
+        let txid = bitcoin::Txid::all_zeros();
+        let current = crate::mock::mempool_entry(txid);
+        // 0.18 reported the virtual size as `size`, without a weight, and
+        // the fees both as flat fields and in `fees`
+        let mut v18 = current.clone();
+        let v18_entry = v18.as_object_mut().unwrap();
+        v18_entry.remove("weight");
+        v18_entry.remove("unbroadcast");
+        let vsize = v18_entry.remove("vsize").unwrap();
+        v18_entry.insert("size".to_owned(), vsize);
+        v18_entry.insert("fee".to_owned(), serde_json::json!(0.00000141));
+        v18_entry.insert("modifiedfee".to_owned(), serde_json::json!(0.00000141));
+        v18_entry.insert("ancestorfees".to_owned(), serde_json::json!(141));
+        v18_entry.insert("descendantfees".to_owned(), serde_json::json!(141));
+        // 0.16 only reported the flat fields
+        let mut v16 = v18.clone();
+        v16.as_object_mut().unwrap().remove("fees");
+
+        let get = |version, response| {
+            let (drivechain, _) = connect(node(version, response));
+            crate::mock::block_on(drivechain.get_mempool_entry(&txid))
+        };
+        let expected: crate::MempoolEntry = serde_json::from_value(current.clone()).unwrap();
+        assert_eq!(expected.weight, Some(564));
+        assert_eq!(get(230000, current).unwrap(), expected);
+        let old = crate::MempoolEntry {
+            weight: None,
+            unbroadcast: None,
+            ..expected
+        };
+        assert_eq!(get(180000, v18.clone()).unwrap(), old);
+        assert_eq!(get(160000, v16).unwrap(), old);
+        // The shape is picked by version, not guessed from the response
+        assert!(matches!(
+            get(230000, v18),
+            Err(Error::Deserialize { method, .. }) if method == "getmempoolentry"
+        ));
+    }
+}
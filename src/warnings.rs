@@ -0,0 +1,127 @@
+//! Warnings that the node includes in otherwise successful responses.
+use std::ops::Deref;
+use std::sync::RwLock;
+
+static WARNING_HANDLER: RwLock<Option<fn(&str)>> = RwLock::new(None);
+
+/// Sets a function that is called with every warning deserialized from a
+/// node response, and with warnings about responses that had to be parsed
+/// leniently, e.g. to log them in one place.
+pub fn set_warning_handler(handler: fn(&str)) {
+    *WARNING_HANDLER.write().unwrap() = Some(handler);
+}
+
+pub fn clear_warning_handler() {
+    *WARNING_HANDLER.write().unwrap() = None;
+}
+
+/// Passes a warning about a response that was parsed leniently to the
+/// warning handler.
+pub(crate) fn warn(warning: &str) {
+    if let Some(handler) = *WARNING_HANDLER.read().unwrap() {
+        handler(warning);
+    }
+}
+
+/// Warnings sent as either a single string, which is empty if there are no
+/// warnings, or an array of strings, depending on the node version.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize)]
+#[serde(transparent)]
+pub struct Warnings(pub Vec<String>);
+
+impl Deref for Warnings {
+    type Target = [String];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Warnings {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        #[derive(serde::Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            One(String),
+            Many(Vec<String>),
+        }
+        let warnings = match Repr::deserialize(deserializer)? {
+            Repr::One(warning) if warning.is_empty() => Vec::new(),
+            Repr::One(warning) => vec![warning],
+            Repr::Many(warnings) => warnings,
+        };
+        warnings.iter().for_each(|warning| warn(warning));
+        Ok(Warnings(warnings))
+    }
+}
+
+/// A response together with the warnings the node added to it.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct WithWarnings<T> {
+    #[serde(flatten)]
+    pub value: T,
+    #[serde(default)]
+    pub warnings: Warnings,
+}
+
+impl<T> Deref for WithWarnings<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.value
+    }
+}
+
+/// Held by tests that set the warning handler.
+#[cfg(test)]
+pub(crate) static TEST_HANDLER_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Debug, PartialEq, serde::Deserialize)]
+    struct Info {
+        chain: String,
+    }
+
+    #[test]
+    fn accepts_string_and_array() {
+        let cases = [
+            (json!(""), vec![]),
+            (json!("node is pruned"), vec!["node is pruned"]),
+            (json!([]), vec![]),
+            (json!(["a", "b"]), vec!["a", "b"]),
+        ];
+        for (warnings, expected) in cases {
+            let info: WithWarnings<Info> =
+                serde_json::from_value(json!({ "chain": "main", "warnings": warnings })).unwrap();
+            assert_eq!(info.chain, "main");
+            assert_eq!(*info.warnings, expected);
+        }
+        let info: WithWarnings<Info> = serde_json::from_value(json!({ "chain": "main" })).unwrap();
+        assert!(info.warnings.is_empty());
+    }
+
+    #[test]
+    fn warnings_are_passed_to_handler() {
+        static WARNINGS: AtomicUsize = AtomicUsize::new(0);
+        let _lock = TEST_HANDLER_LOCK.lock().unwrap();
+        // Other tests deserialize warnings concurrently.
+        set_warning_handler(|warning| {
+            if warning.starts_with("handler test") {
+                WARNINGS.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+        let _: Warnings =
+            serde_json::from_value(json!(["handler test a", "handler test b"])).unwrap();
+        clear_warning_handler();
+        let _: Warnings = serde_json::from_value(json!("handler test c")).unwrap();
+        assert_eq!(WARNINGS.load(Ordering::SeqCst), 2);
+    }
+}
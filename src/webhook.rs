@@ -0,0 +1,511 @@
+//! Pushing chain events to HTTP endpoints, for consumers that can neither
+//! poll nor hold a connection open.
+//!
+//! Every event is POSTed as a JSON [`Delivery`], signed with
+//! HMAC-SHA256 over the body using a secret shared with the receiver. The
+//! signature is sent hex encoded in the [`SIGNATURE_HEADER`] header, and can
+//! be checked with [`verify_signature`]. Receivers should deduplicate
+//! deliveries by [`Delivery::id`], as a delivery may be retried after the
+//! receiver already handled it.
+//!
+//! Endpoints may use HTTP or HTTPS. HTTPS endpoints are verified against
+//! the root certificates of the platform, unless others are set with
+//! [`WebhookNotifier::with_root_certificates`].
+use crate::client::{Deposit, Header, SidechainId};
+use crate::withdrawals::BundleState;
+use crate::Error;
+use bitcoin::hashes::{hmac, sha256, Hash as _, HashEngine as _};
+use std::fs::{File, OpenOptions};
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+pub const SIGNATURE_HEADER: &str = "x-webhook-signature";
+
+/// [`Delivery::id`], so that receivers can deduplicate deliveries without
+/// parsing them.
+pub const EVENT_ID_HEADER: &str = "x-webhook-id";
+
+/// Longest delay between two attempts of a delivery.
+pub const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+type Connector = hyper_rustls::HttpsConnector<hyper::client::HttpConnector>;
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WebhookEvent {
+    NewBlock {
+        header: Header,
+    },
+    Deposit {
+        sidechain: SidechainId,
+        deposit: Deposit,
+    },
+    BundleStateChanged {
+        sidechain: SidechainId,
+        bundle_txid: bitcoin::Txid,
+        state: BundleState,
+    },
+}
+
+/// Events an endpoint is sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventFilter {
+    NewBlock,
+    Deposit(SidechainId),
+    BundleState(SidechainId),
+}
+
+impl EventFilter {
+    pub fn matches(&self, event: &WebhookEvent) -> bool {
+        match (self, event) {
+            (EventFilter::NewBlock, WebhookEvent::NewBlock { .. }) => true,
+            (EventFilter::Deposit(filter), WebhookEvent::Deposit { sidechain, .. })
+            | (
+                EventFilter::BundleState(filter),
+                WebhookEvent::BundleStateChanged { sidechain, .. },
+            ) => filter == sidechain,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Endpoint {
+    pub url: http::Uri,
+    /// The endpoint is sent events that match any of the filters.
+    pub filters: Vec<EventFilter>,
+}
+
+/// Body of a webhook request.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Delivery {
+    /// Increases by one with every event, across restarts if the notifier
+    /// has a cursor file.
+    pub id: u64,
+    /// Seconds since the UNIX epoch when the event was created.
+    pub timestamp: u64,
+    pub event: WebhookEvent,
+}
+
+/// A delivery that could not be made, as appended to the dead letter log.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct DeadLetter {
+    pub url: String,
+    pub attempts: u32,
+    pub error: String,
+    pub delivery: Delivery,
+}
+
+/// HMAC-SHA256 of `body` with `secret`, hex encoded.
+pub fn sign(secret: &[u8], body: &[u8]) -> String {
+    let mut engine = hmac::HmacEngine::<sha256::Hash>::new(secret);
+    engine.input(body);
+    hmac::Hmac::<sha256::Hash>::from_engine(engine).to_string()
+}
+
+/// Checks the [`SIGNATURE_HEADER`] of a delivery, in constant time.
+pub fn verify_signature(secret: &[u8], body: &[u8], signature: &str) -> bool {
+    let expected = sign(secret, body);
+    expected.len() == signature.len()
+        && expected
+            .bytes()
+            .zip(signature.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// POSTs events to the endpoints whose filters match them.
+///
+/// Events are fed in by the caller, e.g. from a
+/// [`crate::health::HealthMonitor`] or [`crate::withdrawals::WithdrawalTracker`],
+/// and delivered before [`WebhookNotifier::notify`] returns, so short-lived
+/// processes can exit as soon as it does.
+pub struct WebhookNotifier {
+    client: hyper::Client<Connector>,
+    endpoints: Vec<Endpoint>,
+    secret: Vec<u8>,
+    max_attempts: u32,
+    retry_delay: Duration,
+    request_timeout: Duration,
+    /// Id of the last event.
+    cursor: Mutex<u64>,
+    cursor_file: Option<PathBuf>,
+    dead_letters: Option<Mutex<File>>,
+}
+
+impl WebhookNotifier {
+    /// Root certificates of the platform that can't be loaded are skipped,
+    /// HTTPS deliveries to endpoints that need them then fail.
+    pub fn new(endpoints: Vec<Endpoint>, secret: impl Into<Vec<u8>>) -> Self {
+        let mut roots = rustls::RootCertStore::empty();
+        for cert in rustls_native_certs::load_native_certs().unwrap_or_default() {
+            let _ = roots.add(&rustls::Certificate(cert.0));
+        }
+        WebhookNotifier {
+            client: client(roots),
+            endpoints,
+            secret: secret.into(),
+            max_attempts: 5,
+            retry_delay: Duration::from_secs(1),
+            request_timeout: Duration::from_secs(10),
+            cursor: Mutex::new(0),
+            cursor_file: None,
+            dead_letters: None,
+        }
+    }
+
+    /// Verifies HTTPS endpoints against `roots` instead of the root
+    /// certificates of the platform, e.g. for a private CA.
+    pub fn with_root_certificates(mut self, roots: rustls::RootCertStore) -> Self {
+        self.client = client(roots);
+        self
+    }
+
+    /// Attempts per delivery before it is given up on. The delay between
+    /// attempts starts at `retry_delay` and doubles after every attempt, up
+    /// to [`MAX_RETRY_DELAY`].
+    pub fn with_retries(mut self, max_attempts: u32, retry_delay: Duration) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self.retry_delay = retry_delay;
+        self
+    }
+
+    pub fn with_request_timeout(mut self, request_timeout: Duration) -> Self {
+        self.request_timeout = request_timeout;
+        self
+    }
+
+    /// Persists the id of the last event to `path`, and continues from the
+    /// id in it if it exists, so that ids are not reused after a restart.
+    pub fn with_cursor_file(mut self, path: impl Into<PathBuf>) -> Result<Self, Error> {
+        let path = path.into();
+        let cursor = match std::fs::read_to_string(&path) {
+            Ok(cursor) => cursor.trim().parse().map_err(|_| Error::InvalidParam {
+                name: "path",
+                reason: format!("`{}` is not a webhook cursor", path.display()),
+            })?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(err) => return Err(err.into()),
+        };
+        self.cursor = Mutex::new(cursor);
+        self.cursor_file = Some(path);
+        Ok(self)
+    }
+
+    /// Appends deliveries that could not be made to `path`, one JSON
+    /// [`DeadLetter`] per line. Without a dead letter log, they are dropped.
+    pub fn with_dead_letter_log(mut self, path: impl AsRef<Path>) -> Result<Self, Error> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        self.dead_letters = Some(Mutex::new(file));
+        Ok(self)
+    }
+
+    /// Id of the last event.
+    pub fn cursor(&self) -> u64 {
+        *self.cursor.lock().unwrap()
+    }
+
+    fn next_id(&self) -> Result<u64, Error> {
+        let mut cursor = self.cursor.lock().unwrap();
+        let id = *cursor + 1;
+        if let Some(path) = &self.cursor_file {
+            write_cursor(path, id)?;
+        }
+        *cursor = id;
+        Ok(id)
+    }
+
+    /// Delivers `event` to every matching endpoint, and returns its id.
+    /// Deliveries that fail after all attempts are written to the dead
+    /// letter log. Errors are only returned for the cursor file, the dead
+    /// letter log and events that can't be serialized.
+    pub async fn notify(&self, event: WebhookEvent) -> Result<u64, Error> {
+        let endpoints: Vec<_> = self
+            .endpoints
+            .iter()
+            .filter(|endpoint| endpoint.filters.iter().any(|filter| filter.matches(&event)))
+            .collect();
+        let id = self.next_id()?;
+        if endpoints.is_empty() {
+            return Ok(id);
+        }
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let delivery = Delivery {
+            id,
+            timestamp,
+            event,
+        };
+        let body = serde_json::to_vec(&delivery)?;
+        let signature = sign(&self.secret, &body);
+        for endpoint in endpoints {
+            let (attempts, result) = self.deliver(endpoint, id, &body, &signature).await;
+            if let Err(error) = result {
+                self.dead_letter(DeadLetter {
+                    url: endpoint.url.to_string(),
+                    attempts,
+                    error,
+                    delivery: delivery.clone(),
+                })?;
+            }
+        }
+        Ok(id)
+    }
+
+    /// Returns the number of attempts made, and the last error if all of
+    /// them failed.
+    async fn deliver(
+        &self,
+        endpoint: &Endpoint,
+        id: u64,
+        body: &[u8],
+        signature: &str,
+    ) -> (u32, Result<(), String>) {
+        let mut retry_delay = self.retry_delay;
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let request = hyper::Request::post(endpoint.url.clone())
+                .header(http::header::CONTENT_TYPE, "application/json")
+                .header(SIGNATURE_HEADER, signature)
+                .header(EVENT_ID_HEADER, id)
+                .body(hyper::Body::from(body.to_vec()))
+                .unwrap();
+            let error = match tokio::time::timeout(
+                self.request_timeout,
+                self.client.request(request),
+            )
+            .await
+            {
+                Ok(Ok(response)) if response.status().is_success() => return (attempt, Ok(())),
+                // Retrying won't help if the receiver rejects the delivery
+                Ok(Ok(response)) if response.status().is_client_error() => {
+                    return (attempt, Err(format!("rejected with {}", response.status())))
+                }
+                Ok(Ok(response)) => format!("failed with {}", response.status()),
+                Ok(Err(err)) => err.to_string(),
+                Err(_) => "timed out".to_owned(),
+            };
+            if attempt >= self.max_attempts {
+                return (attempt, Err(error));
+            }
+            tokio::time::sleep(retry_delay).await;
+            retry_delay = next_retry_delay(retry_delay);
+        }
+    }
+
+    fn dead_letter(&self, dead_letter: DeadLetter) -> Result<(), Error> {
+        #[cfg(feature = "tracing")]
+        jsonrpsee::tracing::warn!(
+            url = %dead_letter.url,
+            id = dead_letter.delivery.id,
+            error = %dead_letter.error,
+            "webhook delivery failed"
+        );
+        if let Some(dead_letters) = &self.dead_letters {
+            let line = serde_json::to_string(&dead_letter)?;
+            writeln!(dead_letters.lock().unwrap(), "{line}")?;
+        }
+        Ok(())
+    }
+}
+
+fn next_retry_delay(retry_delay: Duration) -> Duration {
+    retry_delay.saturating_mul(2).min(MAX_RETRY_DELAY)
+}
+
+fn client(roots: rustls::RootCertStore) -> hyper::Client<Connector> {
+    let config = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let connector = hyper_rustls::HttpsConnectorBuilder::new()
+        .with_tls_config(config)
+        .https_or_http()
+        .enable_http1()
+        .build();
+    hyper::Client::builder().build(connector)
+}
+
+/// Writes `id` to a temporary file next to `path` and renames it over
+/// `path`, so that a crash leaves either the old or the new cursor.
+fn write_cursor(path: &Path, id: u64) -> Result<(), Error> {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    let mut temp_file = File::create(&temp_path)?;
+    temp_file.write_all(id.to_string().as_bytes())?;
+    temp_file.sync_all()?;
+    std::fs::rename(&temp_path, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::block_on;
+    use hyper::service::{make_service_fn, service_fn};
+    use std::convert::Infallible;
+    use std::sync::Arc;
+
+    const SECRET: &[u8] = b"secret";
+
+    type Received = Arc<Mutex<Vec<(hyper::HeaderMap, Vec<u8>)>>>;
+
+    /// Starts a server that answers with `statuses` in turn, and with 200
+    /// once they are used up.
+    fn serve(statuses: Vec<u16>) -> (http::Uri, Received) {
+        let received = Received::default();
+        let statuses = Arc::new(Mutex::new(statuses.into_iter()));
+        let make_service = make_service_fn({
+            let received = received.clone();
+            move |_| {
+                let received = received.clone();
+                let statuses = statuses.clone();
+                async move {
+                    Ok::<_, Infallible>(service_fn(move |request: hyper::Request<hyper::Body>| {
+                        let received = received.clone();
+                        let status = statuses.lock().unwrap().next().unwrap_or(200);
+                        async move {
+                            let headers = request.headers().clone();
+                            let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+                            received.lock().unwrap().push((headers, body.to_vec()));
+                            let mut response = hyper::Response::new(hyper::Body::empty());
+                            *response.status_mut() = hyper::StatusCode::from_u16(status).unwrap();
+                            Ok::<_, Infallible>(response)
+                        }
+                    }))
+                }
+            }
+        });
+        let server = hyper::Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let url = format!("http://{}/hook", server.local_addr())
+            .parse()
+            .unwrap();
+        tokio::spawn(server);
+        (url, received)
+    }
+
+    fn notifier(url: http::Uri) -> WebhookNotifier {
+        let endpoint = Endpoint {
+            url,
+            filters: vec![EventFilter::NewBlock],
+        };
+        WebhookNotifier::new(vec![endpoint], SECRET)
+            .with_retries(3, Duration::from_millis(1))
+            .with_request_timeout(Duration::from_secs(5))
+    }
+
+    fn new_block() -> WebhookEvent {
+        let mut header = crate::mock::header(bitcoin::BlockHash::all_zeros(), 0, None);
+        // Parsed back exactly, unlike the regtest difficulty
+        header["difficulty"] = serde_json::json!(1);
+        WebhookEvent::NewBlock {
+            header: serde_json::from_value(header).unwrap(),
+        }
+    }
+
+    /// Path in the temporary directory that does not exist yet.
+    fn temp_path(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("bip300301-webhook-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn deliveries_are_signed() {
+        block_on(async {
+            let (url, received) = serve(vec![]);
+            let id = notifier(url).notify(new_block()).await.unwrap();
+            let received = received.lock().unwrap();
+            assert_eq!(received.len(), 1);
+            let (headers, body) = &received[0];
+            let signature = headers[SIGNATURE_HEADER].to_str().unwrap();
+            assert!(verify_signature(SECRET, body, signature));
+            assert!(!verify_signature(b"other secret", body, signature));
+            assert_eq!(headers[EVENT_ID_HEADER].to_str().unwrap(), id.to_string());
+            let delivery: Delivery = serde_json::from_slice(body).unwrap();
+            assert_eq!((delivery.id, delivery.event), (id, new_block()));
+        })
+    }
+
+    #[test]
+    fn server_errors_are_retried() {
+        let dead_letters = temp_path("retried-dead-letters");
+        block_on(async {
+            let (url, received) = serve(vec![500, 503]);
+            let notifier = notifier(url).with_dead_letter_log(&dead_letters).unwrap();
+            notifier.notify(new_block()).await.unwrap();
+            let received = received.lock().unwrap();
+            assert_eq!(received.len(), 3);
+            // The same delivery every time
+            assert!(received.iter().all(|(_, body)| *body == received[0].1));
+        });
+        assert_eq!(std::fs::read_to_string(&dead_letters).unwrap(), "");
+    }
+
+    #[test]
+    fn undeliverable_events_are_dead_lettered() {
+        let dead_letters = temp_path("undeliverable-dead-letters");
+        block_on(async {
+            let (url, received) = serve(vec![500, 500, 500]);
+            let notifier = notifier(url.clone())
+                .with_dead_letter_log(&dead_letters)
+                .unwrap();
+            let id = notifier.notify(new_block()).await.unwrap();
+            assert_eq!(received.lock().unwrap().len(), 3);
+            let log = std::fs::read_to_string(&dead_letters).unwrap();
+            let dead_letter: DeadLetter = serde_json::from_str(log.trim()).unwrap();
+            assert_eq!(dead_letter.url, url.to_string());
+            assert_eq!(dead_letter.attempts, 3);
+            assert_eq!(dead_letter.delivery.id, id);
+        });
+    }
+
+    #[test]
+    fn client_errors_are_not_retried() {
+        block_on(async {
+            let (url, received) = serve(vec![400]);
+            notifier(url).notify(new_block()).await.unwrap();
+            assert_eq!(received.lock().unwrap().len(), 1);
+        })
+    }
+
+    #[test]
+    fn ids_continue_after_restart() {
+        let cursor = temp_path("cursor");
+        block_on(async {
+            let (url, received) = serve(vec![]);
+            let first = notifier(url.clone()).with_cursor_file(&cursor).unwrap();
+            first.notify(new_block()).await.unwrap();
+            first.notify(new_block()).await.unwrap();
+            drop(first);
+            let restarted = notifier(url).with_cursor_file(&cursor).unwrap();
+            assert_eq!(restarted.cursor(), 2);
+            restarted.notify(new_block()).await.unwrap();
+            let ids: Vec<u64> = received
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(_, body)| serde_json::from_slice::<Delivery>(body).unwrap().id)
+                .collect();
+            assert_eq!(ids, [1, 2, 3]);
+        });
+        assert_eq!(std::fs::read_to_string(&cursor).unwrap(), "3");
+        let mut temp_path = cursor.into_os_string();
+        temp_path.push(".tmp");
+        assert!(!Path::new(&temp_path).exists());
+    }
+
+    #[test]
+    fn retry_delay_is_capped() {
+        let second = Duration::from_secs(1);
+        assert_eq!(next_retry_delay(second), 2 * second);
+        assert_eq!(next_retry_delay(40 * second), MAX_RETRY_DELAY);
+        assert_eq!(next_retry_delay(Duration::MAX), MAX_RETRY_DELAY);
+    }
+}
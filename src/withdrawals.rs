@@ -0,0 +1,725 @@
+use crate::batch::{Batch, BatchItemResult};
+use crate::cancel::Limits;
+use crate::client::{ChainClient, Ctip, DrivechainClient, SidechainId, SpentWithdrawal};
+use crate::Error;
+use bitcoin::consensus::Encodable;
+use jsonrpsee::core::client::ClientT;
+use jsonrpsee::http_client::HttpClient;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Start of the error message returned by `getwithdrawalbundle` if the
+/// sidechain has no pending bundle.
+const NO_BUNDLE_ERROR_PREFIX: &str = "No withdrawal bundle";
+
+/// Response of `receivewithdrawalbundle`, which depending on the node
+/// version is the hash of the bundle, an object with it, or nothing.
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum ReceivedBundle {
+    Hash(bitcoin::Txid),
+    Object {
+        #[serde(alias = "hash")]
+        wtxid: bitcoin::Txid,
+    },
+}
+
+/// A decoded withdrawal bundle transaction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WithdrawalBundle(pub bitcoin::Transaction);
+
+impl WithdrawalBundle {
+    /// The hash the bundle is tracked under by `listwithdrawalstatus`,
+    /// `listspentwithdrawals` and `listfailedwithdrawals`.
+    pub fn txid(&self) -> bitcoin::Txid {
+        self.0.txid()
+    }
+
+    /// Destination script and amount of every output, in order.
+    pub fn outputs(&self) -> impl Iterator<Item = (&bitcoin::Script, bitcoin::Amount)> {
+        self.0.output.iter().map(|output| {
+            (
+                output.script_pubkey.as_script(),
+                bitcoin::Amount::from_sat(output.value),
+            )
+        })
+    }
+
+    pub fn total_value(&self) -> bitcoin::Amount {
+        self.outputs().map(|(_, value)| value).sum()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum BundleState {
+    /// The mainchain node doesn't know about this bundle.
+    Unknown,
+    Voting {
+        work_score: usize,
+        blocks_remaining: usize,
+    },
+    Failed,
+    Spent {
+        mainchain_block: bitcoin::BlockHash,
+    },
+}
+
+impl BundleState {
+    /// Returns `true` if the bundle can no longer change state.
+    pub fn is_terminal(&self) -> bool {
+        matches!(self, BundleState::Failed | BundleState::Spent { .. })
+    }
+}
+
+/// Follows withdrawal bundles of a single sidechain from proposal to payout.
+#[derive(Clone)]
+pub struct WithdrawalTracker<C = HttpClient> {
+    pub sidechain_number: SidechainId,
+    pub client: C,
+}
+
+impl<C> WithdrawalTracker<C>
+where
+    C: ClientT + Sync,
+{
+    pub fn new(client: C, sidechain_number: SidechainId) -> Self {
+        WithdrawalTracker {
+            sidechain_number,
+            client,
+        }
+    }
+
+    pub async fn status(&self, bundle_txid: &bitcoin::Txid) -> Result<BundleState, Error> {
+        for spent in self.client.listspentwithdrawals().await? {
+            if spent.nsidechain == self.sidechain_number && spent.hash == *bundle_txid {
+                return Ok(BundleState::Spent {
+                    mainchain_block: spent.hashblock,
+                });
+            }
+        }
+        for failed in self.client.listfailedwithdrawals().await? {
+            if failed.nsidechain == self.sidechain_number && failed.hash == *bundle_txid {
+                return Ok(BundleState::Failed);
+            }
+        }
+        for status in self
+            .client
+            .listwithdrawalstatus(self.sidechain_number)
+            .await?
+        {
+            if status.hash == *bundle_txid {
+                return Ok(BundleState::Voting {
+                    work_score: status.nworkscore,
+                    blocks_remaining: status.nblocksleft,
+                });
+            }
+        }
+        Ok(BundleState::Unknown)
+    }
+
+    /// Returns the bundle the mainchain node has pending for this sidechain,
+    /// or `None` if there is none.
+    pub async fn pending_bundle(&self) -> Result<Option<WithdrawalBundle>, Error> {
+        match self.client.getwithdrawalbundle(self.sidechain_number).await {
+            Ok(bundle) => Ok(bundle.map(|bundle| WithdrawalBundle(bundle.0))),
+            Err(jsonrpsee::core::Error::Call(err))
+                if err.message().starts_with(NO_BUNDLE_ERROR_PREFIX) =>
+            {
+                Ok(None)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Submits a withdrawal bundle to the mainchain node and returns the txid
+    /// it will be tracked under, as reported by the node. Fails with
+    /// [`Error::BundleTxidMismatch`] if the node reports a different one.
+    pub async fn submit_bundle(
+        &self,
+        transaction: &bitcoin::Transaction,
+    ) -> Result<bitcoin::Txid, Error> {
+        let mut rawtx = vec![];
+        transaction.consensus_encode(&mut rawtx)?;
+        let rawtx = hex::encode(&rawtx);
+        let response = self
+            .client
+            .receivewithdrawalbundle(self.sidechain_number, &rawtx)
+            .await?;
+        let expected = transaction.txid();
+        let response = serde_json::value::to_raw_value(&response)?;
+        let received: Option<ReceivedBundle> =
+            crate::decode_response("receivewithdrawalbundle", &response)?;
+        match received {
+            Some(ReceivedBundle::Hash(actual) | ReceivedBundle::Object { wtxid: actual })
+                if actual != expected =>
+            {
+                Err(Error::BundleTxidMismatch { expected, actual })
+            }
+            _ => Ok(expected),
+        }
+    }
+
+    /// Polls the bundle status every `poll_interval` until it is either
+    /// `Failed` or `Spent`, giving up after `timeout`.
+    pub async fn wait_for_resolution(
+        &self,
+        bundle_txid: &bitcoin::Txid,
+        poll_interval: Duration,
+        timeout: Duration,
+    ) -> Result<BundleState, Error> {
+        let limits = Limits::new().with_timeout(timeout);
+        match self
+            .wait_for_resolution_with_limits(bundle_txid, poll_interval, &limits)
+            .await
+        {
+            Err(Error::DeadlineExceeded { .. }) => Err(Error::BundleResolutionTimeout {
+                bundle_txid: *bundle_txid,
+            }),
+            result => result,
+        }
+    }
+
+    /// Like [`Self::wait_for_resolution`], but gives up with
+    /// [`Error::Cancelled`] or [`Error::DeadlineExceeded`] once `limits` are
+    /// reached, counting completed polls.
+    pub async fn wait_for_resolution_with_limits(
+        &self,
+        bundle_txid: &bitcoin::Txid,
+        poll_interval: Duration,
+        limits: &Limits,
+    ) -> Result<BundleState, Error> {
+        let running = limits.start();
+        let mut polls = 0;
+        loop {
+            let state = running.race(self.status(bundle_txid), polls, None).await?;
+            if state.is_terminal() {
+                return Ok(state);
+            }
+            polls += 1;
+            let sleep = async {
+                tokio::time::sleep(poll_interval).await;
+                Ok(())
+            };
+            running.race(sleep, polls, None).await?;
+        }
+    }
+}
+
+/// Error code returned by the node if it can't find a transaction.
+const RPC_INVALID_ADDRESS_OR_KEY: i32 = -5;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Payout {
+    pub script_pubkey: bitcoin::ScriptBuf,
+    pub amount: bitcoin::Amount,
+}
+
+impl Payout {
+    /// `None` for non-standard scripts.
+    pub fn address(&self, network: bitcoin::Network) -> Option<bitcoin::Address> {
+        bitcoin::Address::from_script(&self.script_pubkey, network).ok()
+    }
+}
+
+/// What the inputs of a withdrawal bundle spent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EscrowSpend {
+    /// An escrow output of the sidechain, i.e. its previous CTIP.
+    Escrow {
+        outpoint: bitcoin::OutPoint,
+        amount: bitcoin::Amount,
+    },
+    /// None of the inputs is an escrow output of the sidechain. Funds were
+    /// paid out as a withdrawal without coming from the escrow.
+    NotEscrow,
+    /// The spent outputs could not be looked up, e.g. because the node has
+    /// no `-txindex`.
+    Unknown,
+}
+
+/// A spent withdrawal bundle with its payouts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvedWithdrawal {
+    pub txid: bitcoin::Txid,
+    pub block_hash: bitcoin::BlockHash,
+    pub sidechain: SidechainId,
+    /// Every output except the new escrow output, in order.
+    pub payouts: Vec<Payout>,
+    /// The escrow output left by the bundle.
+    pub new_ctip: Option<Ctip>,
+    pub total_withdrawn: bitcoin::Amount,
+    pub spent_escrow: EscrowSpend,
+}
+
+impl ResolvedWithdrawal {
+    /// Mainchain fee paid from the escrow, if the spent escrow output is
+    /// known.
+    pub fn fee(&self) -> Option<bitcoin::SignedAmount> {
+        let EscrowSpend::Escrow { amount, .. } = self.spent_escrow else {
+            return None;
+        };
+        let signed =
+            |amount: bitcoin::Amount| bitcoin::SignedAmount::from_sat(amount.to_sat() as i64);
+        let remaining = self
+            .new_ctip
+            .map_or(bitcoin::Amount::ZERO, |ctip| ctip.amount.0);
+        Some(signed(amount) - signed(remaining) - signed(self.total_withdrawn))
+    }
+
+    /// Returns `true` if the bundle is known to not have spent the escrow,
+    /// which should never happen for a bundle reported by the node.
+    pub fn is_suspicious(&self) -> bool {
+        self.spent_escrow == EscrowSpend::NotEscrow
+    }
+}
+
+fn resolve_transaction(
+    spent: &SpentWithdrawal,
+    transaction: &bitcoin::Transaction,
+    spent_transactions: &HashMap<bitcoin::Txid, bitcoin::Transaction>,
+) -> ResolvedWithdrawal {
+    let sidechain = spent.nsidechain;
+    let escrow_script = crate::escrow::script_for_sidechain(sidechain);
+    let mut payouts = vec![];
+    let mut new_ctip = None;
+    for (vout, output) in transaction.output.iter().enumerate() {
+        let amount = bitcoin::Amount::from_sat(output.value);
+        if output.script_pubkey == escrow_script && new_ctip.is_none() {
+            new_ctip = Some(Ctip {
+                txid: spent.hash,
+                n: vout as u32,
+                amount: amount.into(),
+            });
+        } else {
+            payouts.push(Payout {
+                script_pubkey: output.script_pubkey.clone(),
+                amount,
+            });
+        }
+    }
+    let mut spent_escrow = EscrowSpend::NotEscrow;
+    for input in &transaction.input {
+        let outpoint = input.previous_output;
+        let Some(spent_transaction) = spent_transactions.get(&outpoint.txid) else {
+            spent_escrow = EscrowSpend::Unknown;
+            continue;
+        };
+        let output = spent_transaction.output.get(outpoint.vout as usize);
+        if let Some(output) = output.filter(|output| output.script_pubkey == escrow_script) {
+            spent_escrow = EscrowSpend::Escrow {
+                outpoint,
+                amount: bitcoin::Amount::from_sat(output.value),
+            };
+            break;
+        }
+    }
+    #[cfg(feature = "tracing")]
+    if spent_escrow == EscrowSpend::NotEscrow {
+        jsonrpsee::tracing::warn!(
+            bundle_txid = %spent.hash,
+            sidechain = %spent.nsidechain,
+            "spent withdrawal bundle does not spend the sidechain escrow"
+        );
+    }
+    ResolvedWithdrawal {
+        txid: spent.hash,
+        block_hash: spent.hashblock,
+        sidechain,
+        total_withdrawn: payouts.iter().map(|payout| payout.amount).sum(),
+        payouts,
+        new_ctip,
+        spent_escrow,
+    }
+}
+
+impl SpentWithdrawal {
+    /// Fetches and decodes the bundle. `hashblock` is passed to
+    /// `getrawtransaction`, so this works without `-txindex`, but the
+    /// outputs the bundle spent can only be looked up with it.
+    pub async fn resolve<C>(&self, client: &C) -> Result<ResolvedWithdrawal, Error>
+    where
+        C: ClientT + Sync,
+    {
+        let hex = client
+            .getrawtransaction(&self.hash, false, Some(&self.hashblock))
+            .await?;
+        let transaction: bitcoin::Transaction =
+            bitcoin::consensus::deserialize(&hex::decode(hex)?)?;
+        let mut spent_transactions = HashMap::new();
+        for input in &transaction.input {
+            let txid = input.previous_output.txid;
+            match client.getrawtransaction(&txid, false, None).await {
+                Ok(hex) => {
+                    let spent_transaction = bitcoin::consensus::deserialize(&hex::decode(hex)?)?;
+                    spent_transactions.insert(txid, spent_transaction);
+                }
+                Err(jsonrpsee::core::Error::Call(err))
+                    if err.code() == RPC_INVALID_ADDRESS_OR_KEY => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(resolve_transaction(self, &transaction, &spent_transactions))
+    }
+}
+
+/// Like [`SpentWithdrawal::resolve`] for every bundle in `spent`, in two
+/// batches. Results are in the order of `spent`. Bundles that can't be
+/// fetched fail individually, but failing to look up an output they spent
+/// for any reason other than the node not knowing it fails all of them.
+pub async fn resolve_all<C>(
+    client: &C,
+    spent: &[SpentWithdrawal],
+) -> Result<Vec<Result<ResolvedWithdrawal, Error>>, Error>
+where
+    C: ClientT + Sync,
+{
+    let bundles = spent
+        .iter()
+        .fold(Batch::new(), |batch, spent| {
+            batch.get_raw_transaction(spent.hash, Some(spent.hashblock))
+        })
+        .send(client)
+        .await?;
+    let bundles: Vec<Result<bitcoin::Transaction, Error>> = bundles
+        .into_iter()
+        .map(|result| match result? {
+            BatchItemResult::RawTransaction(transaction) => Ok(transaction),
+            _ => Err(Error::UnexpectedBatchResult {
+                method: "getrawtransaction",
+            }),
+        })
+        .collect();
+    let spent_txids: Vec<_> = bundles
+        .iter()
+        .flatten()
+        .flat_map(|transaction| &transaction.input)
+        .map(|input| input.previous_output.txid)
+        .collect::<std::collections::HashSet<_>>()
+        .into_iter()
+        .collect();
+    let results = spent_txids
+        .iter()
+        .fold(Batch::new(), |batch, txid| {
+            batch.get_raw_transaction(*txid, None)
+        })
+        .send(client)
+        .await?;
+    let mut spent_transactions = HashMap::new();
+    for (result, txid) in results.into_iter().zip(&spent_txids) {
+        match result {
+            Ok(BatchItemResult::RawTransaction(transaction)) => {
+                spent_transactions.insert(*txid, transaction);
+            }
+            Ok(_) => {
+                return Err(Error::UnexpectedBatchResult {
+                    method: "getrawtransaction",
+                })
+            }
+            Err(Error::Jsonrpsee(jsonrpsee::core::Error::Call(err)))
+                if err.code() == RPC_INVALID_ADDRESS_OR_KEY => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(spent
+        .iter()
+        .zip(bundles)
+        .map(|(spent, bundle)| Ok(resolve_transaction(spent, &bundle?, &spent_transactions)))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{block_on, rpc_error, MockClient};
+    use bitcoin::hashes::Hash as _;
+    use serde_json::{json, Value};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    fn bundle() -> bitcoin::Transaction {
+        bitcoin::Transaction {
+            version: 2,
+            lock_time: bitcoin::absolute::LockTime::ZERO,
+            input: vec![bitcoin::TxIn::default()],
+            output: vec![bitcoin::TxOut {
+                value: 1000,
+                script_pubkey: bitcoin::ScriptBuf::new(),
+            }],
+        }
+    }
+
+    fn submit(response: Value) -> Result<bitcoin::Txid, Error> {
+        let client = MockClient::new(move |method, _| {
+            assert_eq!(method, "receivewithdrawalbundle");
+            Ok(response.clone())
+        });
+        block_on(WithdrawalTracker::new(client, SidechainId(1)).submit_bundle(&bundle()))
+    }
+
+    #[test]
+    fn submit_bundle_parses_response() {
+        let txid = bundle().txid();
+        assert_eq!(submit(json!(txid)).unwrap(), txid);
+        assert_eq!(submit(json!({ "wtxid": txid })).unwrap(), txid);
+        assert_eq!(submit(json!({ "hash": txid })).unwrap(), txid);
+        assert_eq!(submit(Value::Null).unwrap(), txid);
+        let other = bitcoin::Txid::from_byte_array([1; 32]);
+        assert!(matches!(
+            submit(json!(other)),
+            Err(Error::BundleTxidMismatch { expected, actual })
+                if expected == txid && actual == other
+        ));
+        assert!(matches!(
+            submit(json!({ "unexpected": true })),
+            Err(Error::Deserialize { .. })
+        ));
+    }
+
+    #[test]
+    fn bundle_walks_from_voting_to_spent() {
+        let txid = bundle().txid();
+        let block_hash = bitcoin::BlockHash::from_byte_array([2; 32]);
+        let polls = Arc::new(AtomicUsize::new(0));
+        let client = MockClient::new({
+            let polls = polls.clone();
+            move |method, _| {
+                let poll = polls.load(Ordering::SeqCst);
+                Ok(match method {
+                    "listspentwithdrawals" if poll >= 2 => {
+                        json!([{ "nsidechain": 1, "hash": txid, "hashblock": block_hash }])
+                    }
+                    "listspentwithdrawals" | "listfailedwithdrawals" => json!([]),
+                    "listwithdrawalstatus" => {
+                        polls.fetch_add(1, Ordering::SeqCst);
+                        json!([{ "hash": txid, "nblocksleft": 10 - poll, "nworkscore": poll + 1 }])
+                    }
+                    method => panic!("unexpected {method}"),
+                })
+            }
+        });
+        let tracker = WithdrawalTracker::new(client, SidechainId(1));
+        block_on(async {
+            assert_eq!(
+                tracker.status(&txid).await.unwrap(),
+                BundleState::Voting {
+                    work_score: 1,
+                    blocks_remaining: 10
+                }
+            );
+            let state = tracker
+                .wait_for_resolution(&txid, Duration::from_millis(1), Duration::from_secs(5))
+                .await
+                .unwrap();
+            assert_eq!(
+                state,
+                BundleState::Spent {
+                    mainchain_block: block_hash
+                }
+            );
+        });
+    }
+
+    const SIDECHAIN: SidechainId = SidechainId(1);
+
+    fn transaction(
+        inputs: &[bitcoin::OutPoint],
+        outputs: &[(&bitcoin::Script, u64)],
+    ) -> bitcoin::Transaction {
+        bitcoin::Transaction {
+            version: 2,
+            lock_time: bitcoin::absolute::LockTime::ZERO,
+            input: inputs
+                .iter()
+                .map(|outpoint| bitcoin::TxIn {
+                    previous_output: *outpoint,
+                    ..Default::default()
+                })
+                .collect(),
+            output: outputs
+                .iter()
+                .map(|(script_pubkey, value)| bitcoin::TxOut {
+                    value: *value,
+                    script_pubkey: (*script_pubkey).to_owned(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Node with `-txindex` that knows the transactions `known`.
+    fn node(known: Vec<bitcoin::Transaction>) -> MockClient {
+        let known: HashMap<_, _> = known.into_iter().map(|tx| (tx.txid(), tx)).collect();
+        MockClient::new(move |method, params| {
+            assert_eq!(method, "getrawtransaction");
+            let txid: bitcoin::Txid = serde_json::from_value(params[0].clone()).unwrap();
+            match known.get(&txid) {
+                Some(tx) => Ok(json!(bitcoin::consensus::encode::serialize_hex(tx))),
+                None => Err(rpc_error(
+                    RPC_INVALID_ADDRESS_OR_KEY,
+                    "No such mempool or blockchain transaction. Use gettransaction for wallet transactions.",
+                )),
+            }
+        })
+    }
+
+    fn spent(bundle: &bitcoin::Transaction) -> SpentWithdrawal {
+        SpentWithdrawal {
+            nsidechain: SIDECHAIN,
+            hash: bundle.txid(),
+            hashblock: bitcoin::BlockHash::from_byte_array([2; 32]),
+        }
+    }
+
+    fn p2wpkh() -> bitcoin::ScriptBuf {
+        bitcoin::ScriptBuf::new_v0_p2wpkh(&bitcoin::WPubkeyHash::from_byte_array([3; 20]))
+    }
+
+    fn p2pkh() -> bitcoin::ScriptBuf {
+        bitcoin::ScriptBuf::new_p2pkh(&bitcoin::PubkeyHash::from_byte_array([4; 20]))
+    }
+
+    /// The previous CTIP with 1 BTC, and a bundle spending it.
+    fn escrow_and_bundle(payouts: &[(&bitcoin::Script, u64)]) -> [bitcoin::Transaction; 2] {
+        let escrow_script = crate::escrow::script_for_sidechain(SIDECHAIN);
+        let escrow = transaction(
+            &[bitcoin::OutPoint::null()],
+            &[(&escrow_script, 100_000_000)],
+        );
+        let mut outputs = payouts.to_vec();
+        outputs.push((&escrow_script, 69_990_000));
+        let bundle = transaction(&[bitcoin::OutPoint::new(escrow.txid(), 0)], &outputs);
+        [escrow, bundle]
+    }
+
+    #[test]
+    fn resolves_bundle_with_three_outputs() {
+        let (p2wpkh, p2pkh) = (p2wpkh(), p2pkh());
+        let [escrow, bundle] = escrow_and_bundle(&[(&p2wpkh, 20_000_000), (&p2pkh, 10_000_000)]);
+        let client = node(vec![escrow.clone(), bundle.clone()]);
+        let resolved = block_on(spent(&bundle).resolve(&client)).unwrap();
+        let network = bitcoin::Network::Regtest;
+        let addresses: Vec<_> = resolved
+            .payouts
+            .iter()
+            .map(|payout| payout.address(network).unwrap().address_type())
+            .collect();
+        assert_eq!(
+            addresses,
+            [
+                Some(bitcoin::AddressType::P2wpkh),
+                Some(bitcoin::AddressType::P2pkh)
+            ]
+        );
+        assert_eq!(
+            resolved.payouts[0].amount,
+            bitcoin::Amount::from_sat(20_000_000)
+        );
+        assert_eq!(
+            resolved.total_withdrawn,
+            bitcoin::Amount::from_sat(30_000_000)
+        );
+        assert_eq!(
+            resolved.new_ctip,
+            Some(Ctip {
+                txid: bundle.txid(),
+                n: 2,
+                amount: bitcoin::Amount::from_sat(69_990_000).into(),
+            })
+        );
+        assert_eq!(
+            resolved.spent_escrow,
+            EscrowSpend::Escrow {
+                outpoint: bitcoin::OutPoint::new(escrow.txid(), 0),
+                amount: bitcoin::Amount::from_sat(100_000_000),
+            }
+        );
+        assert_eq!(
+            resolved.fee(),
+            Some(bitcoin::SignedAmount::from_sat(10_000))
+        );
+        assert!(!resolved.is_suspicious());
+        // The block is passed, so this works without -txindex
+        assert_eq!(client.calls()[0].1[2], json!(spent(&bundle).hashblock));
+    }
+
+    #[test]
+    fn resolves_non_standard_payout() {
+        let op_true = bitcoin::ScriptBuf::from_bytes(vec![0x51]);
+        let [escrow, bundle] = escrow_and_bundle(&[(&op_true, 30_000_000)]);
+        let resolved =
+            block_on(spent(&bundle).resolve(&node(vec![escrow, bundle.clone()]))).unwrap();
+        assert_eq!(resolved.payouts.len(), 1);
+        assert_eq!(resolved.payouts[0].script_pubkey, op_true);
+        assert_eq!(resolved.payouts[0].address(bitcoin::Network::Regtest), None);
+        assert_eq!(
+            resolved.fee(),
+            Some(bitcoin::SignedAmount::from_sat(10_000))
+        );
+    }
+
+    #[test]
+    fn flags_bundles_not_spending_the_escrow() {
+        let p2wpkh = p2wpkh();
+        let funding = transaction(&[bitcoin::OutPoint::null()], &[(&p2wpkh, 100_000_000)]);
+        let bundle = transaction(
+            &[bitcoin::OutPoint::new(funding.txid(), 0)],
+            &[(&p2pkh(), 99_990_000)],
+        );
+        let resolved =
+            block_on(spent(&bundle).resolve(&node(vec![funding, bundle.clone()]))).unwrap();
+        assert_eq!(resolved.spent_escrow, EscrowSpend::NotEscrow);
+        assert!(resolved.is_suspicious());
+        assert_eq!(resolved.new_ctip, None);
+        assert_eq!(resolved.fee(), None);
+    }
+
+    #[test]
+    fn spent_escrow_is_unknown_without_txindex() {
+        let [_, bundle] = escrow_and_bundle(&[(&p2wpkh(), 20_000_000)]);
+        let resolved = block_on(spent(&bundle).resolve(&node(vec![bundle.clone()]))).unwrap();
+        assert_eq!(resolved.spent_escrow, EscrowSpend::Unknown);
+        assert!(!resolved.is_suspicious());
+        assert_eq!(resolved.fee(), None);
+    }
+
+    #[test]
+    fn resolve_all_matches_resolve() {
+        let (p2wpkh, op_true) = (p2wpkh(), bitcoin::ScriptBuf::from_bytes(vec![0x51]));
+        let [escrow, first] = escrow_and_bundle(&[(&p2wpkh, 20_000_000)]);
+        let [_, second] = escrow_and_bundle(&[(&op_true, 30_000_000)]);
+        let unknown = transaction(&[], &[(&p2wpkh, 1)]);
+        let client = node(vec![escrow, first.clone(), second.clone()]);
+        let bundles = [spent(&first), spent(&second), spent(&unknown)];
+        let resolved = block_on(resolve_all(&client, &bundles)).unwrap();
+        assert_eq!(resolved.len(), 3);
+        for (spent, resolved) in bundles.iter().zip(&resolved).take(2) {
+            let expected = block_on(spent.resolve(&client)).unwrap();
+            assert_eq!(resolved.as_ref().unwrap(), &expected);
+        }
+        assert!(resolved[2].is_err());
+    }
+
+    #[test]
+    fn resolve_all_fails_on_other_lookup_errors() {
+        let [escrow, bundle] = escrow_and_bundle(&[(&p2wpkh(), 20_000_000)]);
+        let (bundle_txid, bundle_hex) = (
+            bundle.txid(),
+            bitcoin::consensus::encode::serialize_hex(&bundle),
+        );
+        // The escrow lookup fails for a reason other than it being unknown
+        let client = MockClient::new(move |_, params| {
+            if params[0] == json!(bundle_txid) {
+                Ok(json!(bundle_hex))
+            } else {
+                Err(rpc_error(-32603, "Internal error"))
+            }
+        });
+        let err = block_on(resolve_all(&client, &[spent(&bundle)])).unwrap_err();
+        assert!(
+            matches!(&err, Error::Jsonrpsee(jsonrpsee::core::Error::Call(err)) if err.code() == -32603),
+            "{err:?}"
+        );
+        assert_eq!(client.calls()[1].1[0], json!(escrow.txid()));
+        // Like resolve
+        assert!(block_on(spent(&bundle).resolve(&client)).is_err());
+    }
+}